@@ -0,0 +1,180 @@
+// Benchmarks of the fetch and post hot paths. They go through the router, so
+// JSON (de)serialization is measured along with the SQL, against a database
+// file in the temp directory seeded with BENCH_ROWS structures (default
+// 200000):
+//   BENCH_ROWS=500000 cargo bench --bench hot_paths
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use http_body_util::BodyExt;
+use peakstranding_server::{Config, start};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+// the static auth provider takes the user id itself as the credential
+const PLAYERS: [&str; 3] = ["111", "222", "333"];
+
+fn bench_rows() -> i64 {
+    std::env::var("BENCH_ROWS")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(200_000)
+}
+
+// A server on a fresh database (left over from the last run otherwise), with
+// `settings` over the bench defaults
+fn bench_app(runtime: &Runtime, name: &str, settings: &[(&str, &str)]) -> Router {
+    let path =
+        std::env::temp_dir().join(format!("peakstranding-bench-{}.db", name.replace('/', "-")));
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{suffix}", path.display())).ok();
+    }
+    let mut env: HashMap<String, String> = [
+        (
+            "DATABASE_URL",
+            format!("sqlite://{}?mode=rwc", path.display()),
+        ),
+        ("AUTH_PROVIDER", "static".into()),
+        ("STARTUP_SELF_CHECK", "false".into()),
+        ("POST_STRUCTURE_RATE_LIMIT", "0".into()),
+        ("GET_STRUCTURE_RATE_LIMIT", "0".into()),
+        ("DUPLICATE_WINDOW_SECONDS", "0".into()),
+        ("MAX_REQUESTED_STRUCTS", "100".into()),
+        ("DEFAULT_RANDOM_LIMIT", "40".into()),
+        ("MAX_USER_STRUCTS_SAVED_PER_SCENE", "100".into()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect();
+    for (key, value) in settings {
+        env.insert(key.to_string(), value.to_string());
+    }
+    let config = Config::from_sources(toml::Table::new(), &|key| env.get(key).cloned())
+        .expect("invalid bench config");
+
+    runtime.block_on(async {
+        let (state, app) = start(Arc::new(config)).await.expect("failed to start");
+        // a quarter of the rows land in SceneBig, spread over 5000 users
+        sqlx::query(
+            r#"
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+            INSERT INTO structures (
+                user_id, username, map_id, scene, segment, prefab,
+                pos_x, pos_y, pos_z, rot_x, rot_y, rot_z, rot_w,
+                rope_start_x, rope_start_y, rope_start_z, rope_end_x, rope_end_y, rope_end_z,
+                rope_length,
+                rope_flying_rotation_x, rope_flying_rotation_y, rope_flying_rotation_z,
+                rope_anchor_rotation_x, rope_anchor_rotation_y, rope_anchor_rotation_z, rope_anchor_rotation_w,
+                antigrav, season_id, created_at
+            )
+            SELECT 1000 + i % 5000, 'bench', 1, CASE WHEN i % 4 = 0 THEN 'SceneBig' ELSE 'SceneOther' END,
+                   i % 6, 'prefab_' || (i % 10),
+                   -i, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+                   0, 1, i
+            FROM n
+            "#,
+        )
+        .bind(bench_rows())
+        .execute(&state.db)
+        .await
+        .expect("failed to seed");
+        app
+    })
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Vec<u8> {
+    let response = app.clone().oneshot(request).await.expect("request failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    response
+        .into_body()
+        .collect()
+        .await
+        .expect("failed to read body")
+        .to_bytes()
+        .to_vec()
+}
+
+fn get_random(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("failed to build runtime");
+    let strategies: [(&str, &[(&str, &str)]); 3] = [
+        ("get_random/query", &[]),
+        ("get_random/probe", &[("RANDOM_PROBE_MIN_ROWS", "1")]),
+        (
+            "get_random/sample",
+            &[("RANDOM_SAMPLE_REFRESH_SECONDS", "3600")],
+        ),
+    ];
+    for (name, settings) in strategies {
+        let app = bench_app(&runtime, name, settings);
+        c.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| {
+                send(
+                    &app,
+                    Request::builder()
+                        .method(Method::GET)
+                        .uri("/api/v1/structures?scene=SceneBig")
+                        .header("x-steam-auth", PLAYERS[2])
+                        .body(Body::empty())
+                        .expect("failed to build request"),
+                )
+            })
+        });
+    }
+}
+
+fn post_structure(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("failed to build runtime");
+    let app = bench_app(&runtime, "post_structure", &[]);
+    let round = AtomicUsize::new(0);
+    c.bench_function("post_structure", |b| {
+        b.to_async(&runtime).iter(|| {
+            let round = round.fetch_add(1, Ordering::Relaxed);
+            let payload = json!({
+                "username": "Bencher",
+                "map_id": 1,
+                "scene": "SceneBig",
+                "segment": round % 6,
+                "prefab": "prefab_0",
+                "pos_x": round as f32, "pos_y": 2.0, "pos_z": 3.0,
+                "rot_x": 0.0, "rot_y": 0.0, "rot_z": 0.0, "rot_w": 1.0,
+                "rope_start_x": 0.0, "rope_start_y": 0.0, "rope_start_z": 0.0,
+                "rope_end_x": 1.0, "rope_end_y": 1.0, "rope_end_z": 1.0,
+                "rope_length": 5.0,
+                "rope_flying_rotation_x": 0.0, "rope_flying_rotation_y": 0.0,
+                "rope_flying_rotation_z": 0.0,
+                "rope_anchor_rotation_x": 0.0, "rope_anchor_rotation_y": 0.0,
+                "rope_anchor_rotation_z": 0.0, "rope_anchor_rotation_w": 1.0,
+                "antigrav": false
+            });
+            send(
+                &app,
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/v1/structures")
+                    .header("x-steam-auth", PLAYERS[round % PLAYERS.len()])
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .expect("failed to build request"),
+            )
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(50);
+    targets = get_random, post_structure
+}
+criterion_main!(benches);
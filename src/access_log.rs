@@ -0,0 +1,167 @@
+// Access log: one line per request.
+//
+// `access_log` wraps the whole router and logs method, url, status, user and
+// duration once the response is ready: at INFO, WARN for 4xx and ERROR for
+// 5xx. The auth extractors name the user through the RequestUser slot they
+// find in the request extensions, and ApiError leaves itself in the response
+// extensions so the line can carry the error code (and, for 5xx, the message).
+// Requests slower than SLOW_REQUEST_MS are logged at WARN at least, with the
+// time spent in timed database work and in Steam calls, which the request
+// collects in a task-local while it runs. Every line names the client
+// address, resolved through TRUSTED_PROXIES by client_ip.
+
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    fmt::{Display, Write},
+    net::SocketAddr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::{
+    client_ip::{self, ClientIp},
+    error::ApiError,
+    state::AppState,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestUser(Arc<OnceLock<String>>);
+
+impl RequestUser {
+    // Names the caller in the access log; the first extractor to do so wins
+    pub fn record(parts: &Parts, user: impl Display) {
+        if let Some(slot) = parts.extensions.get::<RequestUser>() {
+            let _ = slot.0.set(user.to_string());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RequestTimings {
+    db_micros: AtomicU64,
+    db_calls: AtomicU64,
+    steam_micros: AtomicU64,
+    steam_calls: AtomicU64,
+}
+
+tokio::task_local! {
+    static TIMINGS: Arc<RequestTimings>;
+}
+
+fn note(
+    micros: fn(&RequestTimings) -> &AtomicU64,
+    calls: fn(&RequestTimings) -> &AtomicU64,
+    elapsed: Duration,
+) {
+    // background tasks run outside any request
+    let _ = TIMINGS.try_with(|timings| {
+        micros(timings).fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        calls(timings).fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+// Database work done for the current request
+pub fn note_db_time(elapsed: Duration) {
+    note(|t| &t.db_micros, |t| &t.db_calls, elapsed);
+}
+
+// A Steam Web API call made for the current request
+pub fn note_steam_time(elapsed: Duration) {
+    note(|t| &t.steam_micros, |t| &t.steam_calls, elapsed);
+}
+
+pub async fn access_log(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = req.method().clone();
+    // a tenant's router only sees the path below /t/<slug>
+    let url = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |original| &original.0)
+        .to_string();
+    let user = RequestUser::default();
+    req.extensions_mut().insert(user.clone());
+    let client = client_ip::resolve(
+        &state.config().trusted_proxies,
+        req.extensions().get::<ConnectInfo<SocketAddr>>(),
+        req.headers(),
+    );
+    if let Some(ip) = client {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    let ip = client.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+
+    let timings = Arc::new(RequestTimings::default());
+    let response = TIMINGS.scope(timings.clone(), next.run(req)).await;
+
+    let status = response.status();
+    let user_id = user.0.get().map(String::as_str).unwrap_or("-");
+    let elapsed = started.elapsed();
+    let dur = elapsed.as_millis();
+    let threshold = state.config().slow_request_threshold;
+    let slow = !threshold.is_zero() && elapsed >= threshold;
+    let mut detail = String::new();
+    if let Some(error) = response.extensions().get::<ApiError>() {
+        let _ = write!(detail, " code={}", error.code());
+        if let Some(field) = error.field() {
+            let _ = write!(detail, " field={field}");
+        }
+        if status.is_server_error() {
+            let _ = write!(detail, " error={}", error.message());
+        }
+    }
+    if slow {
+        let millis = |micros: &AtomicU64| micros.load(Ordering::Relaxed) / 1000;
+        let _ = write!(
+            detail,
+            " slow=true db_ms={} db_calls={} steam_ms={} steam_calls={}",
+            millis(&timings.db_micros),
+            timings.db_calls.load(Ordering::Relaxed),
+            millis(&timings.steam_micros),
+            timings.steam_calls.load(Ordering::Relaxed)
+        );
+    }
+    if status.is_server_error() {
+        tracing::error!(
+            "request user_id={} method={} url={} status={} duration_ms={} ip={}{}",
+            user_id,
+            method.as_str(),
+            url,
+            status.as_u16(),
+            dur,
+            ip,
+            detail
+        );
+    } else if status.is_client_error() || slow {
+        tracing::warn!(
+            "request user_id={} method={} url={} status={} duration_ms={} ip={}{}",
+            user_id,
+            method.as_str(),
+            url,
+            status.as_u16(),
+            dur,
+            ip,
+            detail
+        );
+    } else {
+        tracing::info!(
+            "request user_id={} method={} url={} status={} duration_ms={} ip={}",
+            user_id,
+            method.as_str(),
+            url,
+            status.as_u16(),
+            dur,
+            ip
+        );
+    }
+    response
+}
@@ -0,0 +1,76 @@
+// Load-based scaling of the per-user rate limits (ADAPTIVE_RATE_LIMITS).
+//
+// Every ADJUST_INTERVAL `adjust_rate_limits` looks at the mean time of the
+// database queries timed since its last look. Above
+// ADAPTIVE_RATE_LIMIT_TARGET_MS every per-user interval is stretched by one
+// STEP; below half of it, or with no queries at all, they shrink by one. The
+// factor stays between ADAPTIVE_RATE_LIMIT_MIN_FACTOR and
+// ADAPTIVE_RATE_LIMIT_MAX_FACTOR, and is 1 while the mode is off.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{config::Config, state::AppState};
+
+const ADJUST_INTERVAL: Duration = Duration::from_secs(10);
+const STEP: f64 = 1.5;
+
+#[derive(Debug)]
+pub struct RateLimitScale {
+    // f64 bits
+    factor: AtomicU64,
+}
+
+impl Default for RateLimitScale {
+    fn default() -> Self {
+        Self {
+            factor: AtomicU64::new(1.0_f64.to_bits()),
+        }
+    }
+}
+
+impl RateLimitScale {
+    pub fn factor(&self) -> f64 {
+        f64::from_bits(self.factor.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, factor: f64) {
+        let previous = f64::from_bits(self.factor.swap(factor.to_bits(), Ordering::Relaxed));
+        if previous != factor {
+            tracing::info!("rate_limit_factor {:.2} -> {:.2}", previous, factor);
+        }
+    }
+
+    // One adjustment for a window whose timed queries took `mean` on average
+    // (None: nothing ran)
+    pub fn adjust(&self, config: &Config, mean: Option<Duration>) {
+        if !config.adaptive_rate_limits {
+            self.set(1.0);
+            return;
+        }
+        let target = config.adaptive_rate_limit_target;
+        let factor = match mean {
+            Some(mean) if mean > target => self.factor() * STEP,
+            Some(mean) if mean >= target / 2 => self.factor(),
+            _ => self.factor() / STEP,
+        };
+        self.set(factor.clamp(
+            config.adaptive_rate_limit_min_factor,
+            config.adaptive_rate_limit_max_factor,
+        ));
+    }
+}
+
+pub async fn adjust_rate_limits(state: AppState) {
+    let (mut last_count, mut last_micros) = state.db_metrics.query_totals();
+    loop {
+        tokio::time::sleep(ADJUST_INTERVAL).await;
+        let (count, micros) = state.db_metrics.query_totals();
+        let mean = (count > last_count)
+            .then(|| Duration::from_micros((micros - last_micros) / (count - last_count)));
+        (last_count, last_micros) = (count, micros);
+        state.rate_limit_scale.adjust(&state.config(), mean);
+    }
+}
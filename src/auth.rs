@@ -0,0 +1,544 @@
+// Identity checks for the credential clients send in X-Steam-Auth.
+//
+// Handlers never talk to a store directly: the VerifiedUser and SteamApp
+// extractors ask the provider picked by AUTH_PROVIDER at startup and cache
+// the answer per credential. A new store only needs another AuthProvider.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use reqwest::Client;
+use std::{fmt, future::Future, pin::Pin, str::FromStr, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+use crate::{
+    access_log::{self, RequestUser},
+    config::Config,
+    db::queries::is_allowlisted,
+    error::{ApiError, AppError},
+    state::AppState,
+    steam::{self, Breaker, RejectReason, TicketCheck, Unavailable},
+};
+
+pub type AuthResult = Result<Identity, AuthError>;
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthResult> + Send + 'a>>;
+
+// Who sent the request and which app build they play
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Identity {
+    pub user_id: u64,
+    pub app_id: u64,
+}
+
+// Why a credential was not accepted. `code` is machine-readable so the mod can
+// tell "get a fresh ticket" (ticket_expired, invalid_ticket, wrong_app) apart
+// from "back off and retry later" (steam_unreachable, steam_unavailable).
+#[derive(Debug, Clone)]
+pub struct AuthError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl AuthError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn rejected(reason: RejectReason, detail: String) -> Self {
+        let code = match reason {
+            RejectReason::Expired => "ticket_expired",
+            RejectReason::WrongApp => "wrong_app",
+            RejectReason::Invalid => "invalid_ticket",
+        };
+        Self::new(StatusCode::UNAUTHORIZED, code, detail)
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        AppError::SteamAuth(self).into_response()
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> Self {
+        ApiError::new(error.status, error.message).with_code(error.code)
+    }
+}
+
+pub trait AuthProvider: Send + Sync + fmt::Debug {
+    fn verify<'a>(&'a self, credential: &'a str, config: &'a Config) -> AuthFuture<'a>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthProviderKind {
+    Steam,
+    Static,
+}
+
+impl FromStr for AuthProviderKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "steam" => Ok(AuthProviderKind::Steam),
+            "static" => Ok(AuthProviderKind::Static),
+            other => Err(format!(
+                "unknown auth provider {other:?} (expected steam or static)"
+            )),
+        }
+    }
+}
+
+pub fn provider(kind: AuthProviderKind, http: Client, steam_key: String) -> Arc<dyn AuthProvider> {
+    match kind {
+        AuthProviderKind::Steam => Arc::new(SteamTickets {
+            http,
+            steam_key,
+            breaker: Breaker::default(),
+        }),
+        AuthProviderKind::Static => Arc::new(StaticIds),
+    }
+}
+
+// Steam session tickets, checked with ISteamUserAuth/AuthenticateUserTicket (see steam.rs).
+pub struct SteamTickets {
+    http: Client,
+    steam_key: String,
+    breaker: Breaker,
+}
+
+impl fmt::Debug for SteamTickets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SteamTickets").finish_non_exhaustive()
+    }
+}
+
+impl AuthProvider for SteamTickets {
+    fn verify<'a>(&'a self, ticket: &'a str, config: &'a Config) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if !self.breaker.allow() {
+                tracing::warn!("steam_auth skipped reason=breaker_open");
+                return Err(AuthError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "steam_unavailable",
+                    "Steam authentication is temporarily unavailable",
+                ));
+            }
+
+            // a ticket is only valid for the app that issued it; when every app id
+            // rejects it, report the most telling reason (expired beats wrong app)
+            let mut rejection: Option<(RejectReason, String)> = None;
+            for &appid in &config.steam_appids {
+                match steam::authenticate_ticket(
+                    &self.http,
+                    &config.steam_api_url,
+                    &self.steam_key,
+                    appid,
+                    ticket,
+                    config.steam_partner_fallback,
+                )
+                .await
+                {
+                    Ok(TicketCheck::Valid { steamid }) => {
+                        self.breaker.record_success();
+                        return Ok(Identity {
+                            user_id: steamid,
+                            app_id: appid,
+                        });
+                    }
+                    Ok(TicketCheck::Rejected { reason, detail }) => {
+                        self.breaker.record_success();
+                        if rejection.as_ref().is_none_or(|(seen, _)| reason > *seen) {
+                            rejection = Some((reason, detail));
+                        }
+                    }
+                    Err(Unavailable { message, .. }) => {
+                        self.breaker.record_failure(
+                            config.steam_breaker_failures,
+                            config.steam_breaker_cooldown,
+                        );
+                        return Err(AuthError::new(
+                            StatusCode::BAD_GATEWAY,
+                            "steam_unreachable",
+                            message,
+                        ));
+                    }
+                }
+            }
+
+            let (reason, detail) =
+                rejection.unwrap_or((RejectReason::Invalid, "ticket rejected".to_string()));
+            Err(AuthError::rejected(reason, detail))
+        })
+    }
+}
+
+// Development and private servers: the credential is the user id itself, or
+// `<AUTH_SHARED_SECRET>:<user id>` once a secret is configured.
+#[derive(Debug)]
+pub struct StaticIds;
+
+impl AuthProvider for StaticIds {
+    fn verify<'a>(&'a self, credential: &'a str, config: &'a Config) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let user_id = match config.auth_shared_secret.as_deref() {
+                Some(secret) => match credential.split_once(':') {
+                    Some((provided, id)) if provided == secret => id,
+                    _ => {
+                        tracing::warn!("static_auth called result=rejected");
+                        return Err(AuthError::new(
+                            StatusCode::UNAUTHORIZED,
+                            "invalid_ticket",
+                            "shared secret rejected",
+                        ));
+                    }
+                },
+                None => credential,
+            };
+            let user_id = user_id.parse::<u64>().map_err(|_| {
+                AuthError::new(
+                    StatusCode::BAD_REQUEST,
+                    "bad_credential",
+                    "invalid steam ticket override",
+                )
+            })?;
+            Ok(Identity {
+                user_id,
+                app_id: config.primary_appid(),
+            })
+        })
+    }
+}
+
+pub static STEAM_HEADER: HeaderName = HeaderName::from_static("x-steam-auth"); // Header for Steam auth ticket
+pub static ADMIN_HEADER: HeaderName = HeaderName::from_static("x-admin-key"); // Header for admin API key
+pub static FEDERATION_HEADER: HeaderName = HeaderName::from_static("x-federation-key"); // Header for mirrors pulling the changes feed
+
+pub struct VerifiedUser(pub u64); // steam_id
+pub struct SteamApp(pub u64); // app id the ticket was issued for
+pub struct AdminUser; // request carried a valid X-Admin-Key
+pub struct FederationPeer; // request carried one of the FEDERATION_KEYS
+
+// Rejected credentials are answered from memory for a while instead of asking the provider again
+const REJECTED_TICKET_TTL: Duration = Duration::from_secs(60);
+// expired rejections are swept once the map grows this large
+const REJECTED_TICKETS_MAX: usize = 10_000;
+
+// Resolves the X-Steam-Auth credential to (steam_id, app_id) with the configured
+// auth provider. Results are cached per credential.
+async fn verify_ticket(
+    parts: &axum::http::request::Parts,
+    state: &AppState,
+) -> Result<(u64, u64), AuthError> {
+    let header = parts
+        .headers
+        .get(&STEAM_HEADER)
+        .ok_or_else(|| {
+            AuthError::new(
+                StatusCode::UNAUTHORIZED,
+                "missing_credential",
+                "X-Steam-Auth missing",
+            )
+        })?
+        .to_str()
+        .map_err(|_| AuthError::new(StatusCode::BAD_REQUEST, "bad_credential", "bad header"))?
+        .to_owned();
+
+    if let Some(verified) = state.cache.get(&header) {
+        RequestUser::record(parts, verified.0);
+        return Ok(*verified);
+    }
+    if let Some(entry) = state.rejected_tickets.get(&header) {
+        let (rejected_at, error) = &*entry;
+        if rejected_at.elapsed() < REJECTED_TICKET_TTL {
+            return Err(error.clone());
+        }
+    }
+
+    let config = state.config();
+    let started = Instant::now();
+    let verified = state.auth.verify(&header, &config).await;
+    state.db_metrics.auth_verified(started.elapsed());
+    if config.auth_provider == AuthProviderKind::Steam {
+        access_log::note_steam_time(started.elapsed());
+    }
+    let identity = match verified {
+        Ok(identity) => identity,
+        Err(error) if error.status == StatusCode::UNAUTHORIZED => {
+            if state.rejected_tickets.len() >= REJECTED_TICKETS_MAX {
+                state
+                    .rejected_tickets
+                    .retain(|_, (rejected_at, _)| rejected_at.elapsed() < REJECTED_TICKET_TTL);
+            }
+            state
+                .rejected_tickets
+                .insert(header, (Instant::now(), error.clone()));
+            return Err(error);
+        }
+        Err(error) => return Err(error),
+    };
+    let verified = (identity.user_id, identity.app_id);
+    state.cache.insert(header, verified);
+    RequestUser::record(parts, identity.user_id);
+    Ok(verified)
+}
+
+// WHITELIST_MODE: a valid credential is not enough, the player has to be in
+// the allowlist table too. Looked up on every request, so removals apply at once.
+async fn check_allowlist(state: &AppState, steamid: u64) -> Result<(), AuthError> {
+    if !state.config().whitelist_mode {
+        return Ok(());
+    }
+    match is_allowlisted(&state.read_db, steamid as i64).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(AuthError::new(
+            StatusCode::FORBIDDEN,
+            "not_allowlisted",
+            "This server only admits players on its allowlist.",
+        )),
+        Err(e) => {
+            tracing::error!("allowlist lookup_failed steamid={} error={}", steamid, e);
+            Err(AuthError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "Database error.",
+            ))
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for VerifiedUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let (steamid, _) = verify_ticket(parts, state).await?;
+        check_allowlist(state, steamid).await?;
+        Ok(VerifiedUser(steamid))
+    }
+}
+
+impl FromRequestParts<AppState> for SteamApp {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let (steamid, appid) = verify_ticket(parts, state).await?;
+        check_allowlist(state, steamid).await?;
+        Ok(SteamApp(appid))
+    }
+}
+
+// Failed ownership checks are retried sooner so a fresh purchase is picked up quickly
+const OWNERSHIP_NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+// Whether the user owns the app, per steam::check_app_ownership (needs a publisher key).
+pub async fn owns_app(state: &AppState, steamid: u64, appid: u64) -> Result<bool, AppError> {
+    let config = state.config();
+    if let Some(entry) = state.ownership_cache.get(&(steamid, appid)) {
+        let (owns, checked) = *entry;
+        let ttl = if owns {
+            config.ownership_cache_ttl
+        } else {
+            OWNERSHIP_NEGATIVE_TTL
+        };
+        if checked.elapsed() < ttl {
+            return Ok(owns);
+        }
+    }
+
+    if !state.steam_breaker.allow() {
+        tracing::warn!("steam_ownership skipped reason=breaker_open");
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The Steam ownership check is temporarily unavailable.",
+        )
+        .with_code("steam_unavailable")
+        .into());
+    }
+    let start = Instant::now();
+    let checked = steam::check_app_ownership(
+        &state.http,
+        &config.steam_api_url,
+        &state.steam_key,
+        steamid,
+        appid,
+    )
+    .await;
+    access_log::note_steam_time(start.elapsed());
+    let owns = match checked {
+        Ok(owns) => {
+            state.steam_breaker.record_success();
+            owns
+        }
+        Err(Unavailable { message, .. }) => {
+            state
+                .steam_breaker
+                .record_failure(config.steam_breaker_failures, config.steam_breaker_cooldown);
+            return Err(ApiError::new(StatusCode::BAD_GATEWAY, message)
+                .with_code("steam_unreachable")
+                .into());
+        }
+    };
+    state
+        .ownership_cache
+        .insert((steamid, appid), (owns, Instant::now()));
+    Ok(owns)
+}
+
+// Steam persona name for the user, from user_profiles while fresh, otherwise from
+// steam::player_name. Falls back to a stale cached name if Steam is unreachable.
+pub async fn persona_name(state: &AppState, steamid: u64) -> Option<String> {
+    let config = state.config();
+    let ttl_ms = config.profile_cache_ttl.as_millis() as i64;
+    let cached: Option<(String, bool)> = sqlx::query_as(
+        r#"SELECT persona_name, fetched_at >= strftime('%s','now')*1000 - ?
+           FROM user_profiles WHERE user_id = ?"#,
+    )
+    .bind(ttl_ms)
+    .bind(steamid as i64)
+    .fetch_optional(&state.read_db)
+    .await
+    .ok()
+    .flatten();
+    if let Some((name, true)) = &cached {
+        return Some(name.clone());
+    }
+    let stale = cached.map(|(name, _)| name);
+
+    if !state.steam_breaker.allow() {
+        tracing::warn!("steam_profile skipped reason=breaker_open");
+        return stale;
+    }
+    let start = Instant::now();
+    let fetched = steam::player_name(
+        &state.http,
+        &config.steam_api_url,
+        &state.steam_key,
+        steamid,
+    )
+    .await;
+    access_log::note_steam_time(start.elapsed());
+    let name = match fetched {
+        Ok(name) => {
+            state.steam_breaker.record_success();
+            name
+        }
+        Err(Unavailable { message, .. }) => {
+            state
+                .steam_breaker
+                .record_failure(config.steam_breaker_failures, config.steam_breaker_cooldown);
+            tracing::warn!(
+                "steam_profile called result=error steamid={} error={} duration_ms={}",
+                steamid,
+                message,
+                start.elapsed().as_millis()
+            );
+            return stale;
+        }
+    };
+    let Some(name) = name else {
+        return stale;
+    };
+    // structures.username is limited to 50 characters
+    let name: String = name.chars().take(50).collect();
+    if let Err(e) = sqlx::query(
+        r#"INSERT INTO user_profiles (user_id, persona_name, fetched_at)
+           VALUES (?, ?, strftime('%s','now')*1000)
+           ON CONFLICT(user_id) DO UPDATE SET
+               persona_name = excluded.persona_name, fetched_at = excluded.fetched_at;"#,
+    )
+    .bind(steamid as i64)
+    .bind(&name)
+    .execute(&state.db)
+    .await
+    {
+        tracing::warn!(
+            "steam_profile cache_write_failed steamid={} error={}",
+            steamid,
+            e
+        );
+    }
+    Some(name)
+}
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // Admin API stays dark unless an operator configured a key
+        let config = state.config();
+        let Some(expected) = config.admin_api_key.as_deref() else {
+            return Err(AppError::NotFound("admin API disabled").into());
+        };
+
+        let provided = parts
+            .headers
+            .get(&ADMIN_HEADER)
+            .ok_or((StatusCode::UNAUTHORIZED, "X-Admin-Key missing".into()))?
+            .to_str()
+            .map_err(|_| (StatusCode::BAD_REQUEST, "bad header".into()))?;
+
+        if provided != expected {
+            tracing::warn!(
+                "admin_auth called result=rejected path={}",
+                parts.uri.path()
+            );
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "admin key rejected"));
+        }
+
+        RequestUser::record(parts, "admin");
+        Ok(AdminUser)
+    }
+}
+
+impl FromRequestParts<AppState> for FederationPeer {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // like the admin API: not there at all until keys are configured
+        let config = state.config();
+        if config.federation_keys.is_empty() {
+            return Err(AppError::NotFound("federation disabled").into());
+        }
+
+        let provided = parts
+            .headers
+            .get(&FEDERATION_HEADER)
+            .ok_or((StatusCode::UNAUTHORIZED, "X-Federation-Key missing".into()))?
+            .to_str()
+            .map_err(|_| (StatusCode::BAD_REQUEST, "bad header".into()))?;
+
+        if !config.federation_keys.iter().any(|key| key == provided) {
+            tracing::warn!(
+                "federation_auth called result=rejected path={}",
+                parts.uri.path()
+            );
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "federation key rejected",
+            ));
+        }
+
+        RequestUser::record(parts, "federation");
+        Ok(FederationPeer)
+    }
+}
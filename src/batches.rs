@@ -0,0 +1,110 @@
+// Shared random batches.
+//
+// Players in the same lobby tend to fetch the same scene within seconds of
+// each other. With RANDOM_BATCH_TTL_SECONDS set, the first fetch of a given
+// (scene, map_id, excluded prefabs, limit) picks a batch and every matching
+// fetch within the TTL gets the same rows. Fetches arriving while the batch
+// is still being picked wait for it instead of running their own query.
+// Callers get the batch's age along with it, to reread like counts that may
+// have gone stale (LIKE_STALENESS_SECONDS).
+
+use dashmap::DashMap;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::OnceCell, time::Instant};
+
+use crate::{db::queries::RandomFilter, model::Structure};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BatchKey {
+    scene: String,
+    map_id: Option<i32>,
+    season: i64,
+    app_id: Option<i64>,
+    // sorted and deduplicated, so the order the mod lists them in doesn't matter
+    exclude_prefabs: Vec<String>,
+    limit: i64,
+    curated_limit: i64,
+    seed: Option<u32>,
+    // shadow-banned players get batches of their own
+    viewer: Option<i64>,
+}
+
+impl BatchKey {
+    // The TTL cut-off (created_after) moves every millisecond and is left out
+    pub fn new(filter: &RandomFilter<'_>) -> Self {
+        let mut exclude_prefabs = filter.exclude_prefabs.to_vec();
+        exclude_prefabs.sort();
+        exclude_prefabs.dedup();
+        Self {
+            scene: filter.scene.to_string(),
+            map_id: filter.map_id,
+            season: filter.season,
+            app_id: filter.app_id,
+            exclude_prefabs,
+            limit: filter.limit,
+            curated_limit: filter.curated_limit,
+            seed: filter.seed,
+            viewer: filter.viewer,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    picked_at: Instant,
+    rows: Arc<OnceCell<Arc<Vec<Structure>>>>,
+}
+
+impl Entry {
+    fn new() -> Self {
+        Self {
+            picked_at: Instant::now(),
+            rows: Arc::new(OnceCell::new()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BatchCache {
+    entries: DashMap<BatchKey, Entry>,
+}
+
+impl BatchCache {
+    // The batch for `key` if one was picked within `ttl`, otherwise the one
+    // `fetch` picks now, and how long ago it was picked. A failed fetch is not
+    // cached; the next caller retries.
+    pub async fn get_or_fetch<F, Fut, E>(
+        &self,
+        key: BatchKey,
+        ttl: Duration,
+        fetch: F,
+    ) -> Result<(Arc<Vec<Structure>>, Duration), E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<Structure>, E>>,
+    {
+        let mut picking = false;
+        let (cell, picked_at) = {
+            let mut entry = self.entries.entry(key).or_insert_with(|| {
+                picking = true;
+                Entry::new()
+            });
+            if entry.picked_at.elapsed() >= ttl {
+                *entry = Entry::new();
+                picking = true;
+            }
+            (entry.rows.clone(), entry.picked_at)
+        };
+        if picking {
+            // drop batches nobody can be served anymore; the map guard is gone
+            // by now, so this can't deadlock on it
+            self.entries
+                .retain(|_, entry| entry.picked_at.elapsed() < ttl);
+        }
+
+        let rows = cell
+            .get_or_try_init(|| async { fetch().await.map(Arc::new) })
+            .await?;
+        Ok((rows.clone(), picked_at.elapsed()))
+    }
+}
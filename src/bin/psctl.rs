@@ -0,0 +1,187 @@
+// Admin CLI: works on the server's database directly, with the same config
+// (environment, .env and CONFIG_PATH) and the same queries as the admin API.
+// Safe to run next to the server; SQLite queues its writes behind the server's.
+
+use anyhow::{Context, bail};
+use dotenvy::dotenv;
+use peakstranding_server::{
+    Config, MILLIS_IN_DAY,
+    db::{
+        self,
+        dump::{self, ImportSummary},
+        queries::{
+            list_allowlist, purge_user, query_global_stats, set_allowlisted, set_shadow_banned,
+            set_upload_banned,
+        },
+    },
+};
+use sqlx::SqlitePool;
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+const USAGE: &str = "usage: psctl [--tenant <slug>] <command>
+
+--tenant works on the database of a [tenants.<slug>] community instead
+
+commands:
+  ban <steamid>                      refuse further uploads from a user
+  unban <steamid>                    allow uploads again
+  shadowban <steamid>                keep a user's structures from everyone else
+  unshadowban <steamid>              serve them to others again
+  allow <steamid>                    add a user to the allowlist (WHITELIST_MODE)
+  disallow <steamid>                 remove them from it
+  allowlist                          print the allowlisted steamids
+  purge-user <steamid> [--scene S]   soft-delete a user's structures
+  stats                              print the global stats as JSON
+  backup <path>                      write a consistent copy of the database
+  export [path]                      dump everything as NDJSON (default stdout)
+  import <path|->                    load a dump in one transaction";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let tenant = match args.first().map(String::as_str) {
+        Some("--tenant") if args.len() > 1 => {
+            let slug = args[1].clone();
+            args.drain(..2);
+            Some(slug)
+        }
+        Some("--tenant") => bail!("--tenant needs a community slug"),
+        _ => None,
+    };
+    let Some(command) = args.first() else {
+        eprintln!("{USAGE}");
+        std::process::exit(2);
+    };
+    if command == "help" || command == "--help" || command == "-h" {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let config = match &tenant {
+        Some(slug) => Config::load_tenant(slug)?,
+        None => Config::load()?,
+    };
+    let db = db::open(&config).await?;
+    let args = &args[1..];
+
+    match command.as_str() {
+        "ban" | "unban" => {
+            let target = steamid(args)?;
+            set_upload_banned(&db, target, command == "ban").await?;
+            println!("{command}ned {target}");
+        }
+        "shadowban" | "unshadowban" => {
+            let target = steamid(args)?;
+            set_shadow_banned(&db, target, command == "shadowban").await?;
+            println!("{command}ned {target}");
+        }
+        "allow" | "disallow" => {
+            let target = steamid(args)?;
+            set_allowlisted(&db, target, command == "allow").await?;
+            println!("{command}ed {target}");
+        }
+        "allowlist" => {
+            for steamid in list_allowlist(&db).await? {
+                println!("{steamid}");
+            }
+        }
+        "purge-user" => {
+            let target = steamid(args)?;
+            let scene = match &args[1..] {
+                [] => None,
+                [flag, scene] if flag == "--scene" => Some(scene.as_str()),
+                _ => bail!("usage: psctl purge-user <steamid> [--scene S]"),
+            };
+            let purged = purge_user(&db, target, scene).await?;
+            println!("purged {purged} structures of {target}");
+        }
+        "stats" => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+            let stats = query_global_stats(&db, now - MILLIS_IN_DAY).await?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        "backup" => {
+            let [path] = args else {
+                bail!("usage: psctl backup <path>");
+            };
+            // VACUUM INTO reads one snapshot, so the copy is consistent even
+            // while the server keeps writing
+            sqlx::query("VACUUM INTO ?")
+                .bind(path)
+                .execute(&db)
+                .await
+                .with_context(|| format!("failed to back up to {path}"))?;
+            println!("backed up to {path}");
+        }
+        "export" => match args {
+            [] => export(&db, std::io::stdout().lock()).await?,
+            [path] => {
+                let file = std::fs::File::create(path)
+                    .with_context(|| format!("failed to create {path}"))?;
+                export(&db, std::io::BufWriter::new(file)).await?
+            }
+            _ => bail!("usage: psctl export [path]"),
+        },
+        "import" => {
+            let summary = match args {
+                [path] if path == "-" => import(&db, std::io::stdin().lock()).await?,
+                [path] => {
+                    let file = std::fs::File::open(path)
+                        .with_context(|| format!("failed to open {path}"))?;
+                    import(&db, BufReader::new(file)).await?
+                }
+                _ => bail!("usage: psctl import <path|->"),
+            };
+            println!("{}", serde_json::to_string(&summary)?);
+        }
+        other => {
+            eprintln!("unknown command {other}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    }
+
+    db.close().await;
+    Ok(())
+}
+
+fn steamid(args: &[String]) -> anyhow::Result<i64> {
+    let Some(raw) = args.first() else {
+        bail!("missing <steamid>");
+    };
+    raw.parse()
+        .with_context(|| format!("invalid steamid {raw}"))
+}
+
+async fn export(db: &SqlitePool, mut out: impl Write) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::channel(256);
+    let db = db.clone();
+    let dumping = tokio::spawn(async move { dump::export(&db, &tx).await });
+    while let Some(line) = rx.recv().await {
+        out.write_all(line?.as_bytes())?;
+    }
+    out.flush()?;
+    if !dumping.await? {
+        bail!("export stopped early");
+    }
+    Ok(())
+}
+
+// Like POST /admin/v1/import: the whole dump or nothing
+async fn import(db: &SqlitePool, input: impl BufRead) -> anyhow::Result<ImportSummary> {
+    let mut tx = db.begin().await?;
+    let mut summary = ImportSummary::default();
+    for (index, line) in input.split(b'\n').enumerate() {
+        dump::import_record(&mut tx, &line?, &mut summary)
+            .await
+            .with_context(|| format!("line {}", index + 1))?;
+    }
+    tx.commit().await?;
+    Ok(summary)
+}
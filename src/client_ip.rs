@@ -0,0 +1,109 @@
+// Client addresses behind reverse proxies.
+//
+// The socket peer is the client unless it is one of TRUSTED_PROXIES; then
+// X-Forwarded-For is walked from the right, skipping the hops that are
+// trusted proxies too, and the first one that isn't is the client. Peers on
+// the unix socket listener can only be local proxies, so they are always
+// trusted. `access_log` resolves the address once per request and leaves it
+// in the request extensions as ClientIp.
+
+use axum::{extract::ConnectInfo, http::HeaderMap};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+// An address range like 10.0.0.0/8 or fd00::/8; a bare address is a /32 or /128
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.parse().map_err(|e| format!("{e}"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("prefix must be between 0 and {max}"))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                masked(u32::from(network).into(), 32, self.prefix)
+                    == masked(u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                masked(network.into(), 128, self.prefix) == masked(ip.into(), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn masked(bits: u128, width: u8, prefix: u8) -> u128 {
+    match width - prefix {
+        0 => bits,
+        host if host >= 128 => 0,
+        host => bits >> host,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+// The client behind `peer` (None: the unix socket listener). None when there
+// is no TCP peer and no usable forwarded address either.
+pub fn resolve(
+    trusted: &[Cidr],
+    peer: Option<&ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    let mut client = peer.map(|ConnectInfo(addr)| addr.ip().to_canonical());
+    if client.is_some_and(|ip| !is_trusted(ip)) {
+        return client;
+    }
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        let Some(ip) = parse_hop(hop.trim()) else {
+            // garbage from the client side of the chain; the last good hop is all we know
+            break;
+        };
+        client = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+// "203.0.113.7", "2001:db8::1", and the "addr:port" / "[v6]:port" forms some proxies send
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
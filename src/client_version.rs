@@ -0,0 +1,100 @@
+// Minimum mod version (MIN_CLIENT_VERSION).
+//
+// Mod updates sometimes change what a payload means, so a server can refuse
+// installs older than a given release. The mod sends its version in
+// X-Mod-Version; when it is older than MIN_CLIENT_VERSION, or missing (the
+// header came with the mods that know about it), the request is answered 426
+// with code `client_outdated` before it reaches a handler. Server info stays
+// reachable so an outdated client can still find out what it needs.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use crate::{error::ApiError, state::AppState};
+
+pub static MOD_VERSION_HEADER: HeaderName = HeaderName::from_static("x-mod-version");
+
+// A dotted release number like 1.4.2; missing parts count as 0, so 1.4 is 1.4.0.
+// A pre-release suffix ("1.5.0-beta") is ignored.
+#[derive(Debug, Clone)]
+pub struct ClientVersion(Vec<u64>);
+
+impl FromStr for ClientVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let value = value.strip_prefix(['v', 'V']).unwrap_or(value);
+        let release = value.split(['-', '+']).next().unwrap_or_default();
+        release
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+            .map_err(|_| format!("{value:?} is not a version like 1.4.2"))
+    }
+}
+
+impl fmt::Display for ClientVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self.0.iter().map(u64::to_string).collect::<Vec<_>>();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+impl Ord for ClientVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        let part = |v: &Self, i: usize| v.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| part(self, i).cmp(&part(other, i)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for ClientVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ClientVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for ClientVersion {}
+
+// Middleware for the player routes
+pub async fn guard(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(min) = state.config().min_client_version.clone() else {
+        return next.run(req).await;
+    };
+    if req.uri().path().ends_with("/server-info") {
+        return next.run(req).await;
+    }
+    let sent = req
+        .headers()
+        .get(&MOD_VERSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<ClientVersion>().ok());
+    if sent.as_ref().is_some_and(|sent| *sent >= min) {
+        return next.run(req).await;
+    }
+    let message = match sent {
+        Some(sent) => format!(
+            "This server needs Peak Stranding {min} or newer, you have {sent}. Please update the mod."
+        ),
+        None => format!("This server needs Peak Stranding {min} or newer. Please update the mod."),
+    };
+    ApiError::new(StatusCode::UPGRADE_REQUIRED, message)
+        .with_code("client_outdated")
+        .into_response()
+}
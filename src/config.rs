@@ -0,0 +1,922 @@
+// Server configuration.
+//
+// Each key is read from the environment first, then from the TOML file at
+// CONFIG_PATH, then falls back to its default. SIGHUP loads the config again,
+// keeping the keys that only take effect on restart.
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::{
+    auth::AuthProviderKind,
+    client_ip::Cidr,
+    client_version::ClientVersion,
+    model::{AttributeType, PrunePolicy},
+    plausibility::PlausibilityMode,
+    steam,
+};
+
+// Where the HTTP listener binds: `host:port` or `unix:/path/to.sock`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some("") => Err("unix socket path is empty".into()),
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None if value.is_empty() => Err("listen address is empty".into()),
+            None => Ok(ListenAddr::Tcp(value.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub steam_appids: Vec<u64>,
+    pub separate_appids: bool,
+    pub read_only: bool,
+    pub startup_self_check: bool,
+    pub startup_integrity_check: bool,
+    // 0 turns slow request logging off
+    pub slow_request_threshold: Duration,
+    // route switches for fetch-only mirrors and post-only ingest nodes
+    pub enable_post: bool,
+    pub enable_likes: bool,
+    pub enable_fetch: bool,
+    pub min_client_version: Option<ClientVersion>,
+    // private servers: the shared password, and whether invite codes alone
+    // make the server private (see private_server.rs)
+    pub server_password: Option<String>,
+    pub invite_only: bool,
+    // only players in the allowlist table get past authentication
+    pub whitelist_mode: bool,
+    pub max_user_structs_saved_per_scene: i64,
+    pub prune_policy: PrunePolicy,
+    pub max_pinned_per_scene: i64,
+    // bookmarked structures per player (0 = unlimited)
+    pub max_favorites_per_user: i64,
+    // strand contracts per player, offers included (0 = unlimited)
+    pub max_contracts_per_user: i64,
+    pub max_requested_structs: i64,
+    pub post_structure_rate_limit: Duration,
+    // posts each player may make inside POST_STRUCTURE_RATE_LIMIT, once ever
+    pub new_player_burst_posts: i64,
+    // uploads per player per UTC day (0 = unlimited)
+    pub daily_upload_quota: i64,
+    pub get_structure_rate_limit: Duration,
+    pub post_like_rate_limit: Duration,
+    // players (test accounts, tooling) no per-user rate limit applies to
+    pub rate_limit_exempt_steamids: Vec<u64>,
+    // scale the per-user intervals with DB latency, between the two factors
+    pub adaptive_rate_limits: bool,
+    pub adaptive_rate_limit_target: Duration,
+    pub adaptive_rate_limit_min_factor: f64,
+    pub adaptive_rate_limit_max_factor: f64,
+    pub like_daily_budget: i64,
+    pub like_target_daily_cap: i64,
+    pub like_suspicious_threshold: i64,
+    // 0 keeps likes_ledger rows forever
+    pub likes_ledger_retention_days: u64,
+    pub usage_rate_limit: Duration,
+    pub nearby_rate_limit: Duration,
+    pub nearby_max_radius: f32,
+    pub density_radius: f32,
+    pub density_max_structures: i64,
+    pub min_own_structure_distance: f32,
+    pub plausibility_checks: PlausibilityMode,
+    pub plausibility_burst_posts: i64,
+    pub plausibility_burst_window: Duration,
+    pub newcomer_review_count: i64,
+    pub newcomer_auto_approve: Duration,
+    pub duplicate_window: Duration,
+    pub user_restore_window: Duration,
+    pub global_stats_rate_limit: Duration,
+    pub user_stats_rate_limit: Duration,
+    pub global_stats_cache_ttl: Duration,
+    pub scene_stats_cache_ttl: Duration,
+    pub default_random_limit: i64,
+    pub max_scene_length: usize,
+    pub max_segment: i32,
+    pub database_url: String,
+    pub db_max_connections: u32,
+    pub sqlite_cache_size: i64,
+    pub sqlite_mmap_size: u64,
+    pub sqlite_wal_autocheckpoint: u32,
+    // wal_checkpoint(TRUNCATE) this often (zero: off)
+    pub wal_checkpoint_interval: Duration,
+    pub listen: ListenAddr,
+    pub unix_socket_mode: Option<u32>,
+    // peers whose X-Forwarded-For is believed
+    pub trusted_proxies: Vec<Cidr>,
+    pub auth_provider: AuthProviderKind,
+    pub auth_shared_secret: Option<String>,
+    pub steam_breaker_failures: u32,
+    pub steam_breaker_cooldown: Duration,
+    pub steam_partner_fallback: bool,
+    pub steam_api_url: String,
+    pub http_pool_max_idle: usize,
+    pub http_pool_idle_timeout: Duration,
+    pub http2: bool,
+    pub require_app_ownership: bool,
+    pub ownership_cache_ttl: Duration,
+    pub resolve_steam_names: bool,
+    pub profile_cache_ttl: Duration,
+    pub admin_api_key: Option<String>,
+    // federation: this server's name in origin tags, the keys mirrors pull
+    // the changes feed with, and the upstream this server mirrors
+    pub server_id: Option<String>,
+    pub federation_keys: Vec<String>,
+    pub upstream_url: Option<String>,
+    pub upstream_key: Option<String>,
+    pub federation_pull_interval: Duration,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    pub ws_max_subscriptions: usize,
+    pub max_body_bytes: usize,
+    // 0 turns the player request timeout off
+    pub request_timeout: Duration,
+    // 0 = no cap
+    pub max_concurrent_requests: usize,
+    pub max_concurrent_fetches: usize,
+    pub random_sample_refresh: Duration,
+    pub random_probe_min_rows: i64,
+    pub random_batch_ttl: Duration,
+    // shared batches older than this get their like counts reread
+    pub like_staleness: Duration,
+    pub fetch_session_ttl: Duration,
+    pub post_queue_capacity: usize,
+    pub post_queue_batch_size: usize,
+    pub like_flush_interval: Duration,
+    pub fetch_flush_interval: Duration,
+    // FETCH_HISTORY_DAYS: random fetches prefer what the player hasn't been
+    // served in this many days; 0 keeps no history
+    pub fetch_history_days: u64,
+    pub notification_digest_window: Duration,
+    pub discord_webhook_url: Option<String>,
+    pub discord_like_milestone: i64,
+    pub like_milestones: Vec<i64>,
+    pub milestone_webhook_url: Option<String>,
+    pub curated_share_percent: i64,
+    // 0: curation ranks by raw likes
+    pub likes_decay_days: i64,
+    // prefab -> most of it one random fetch returns
+    pub prefab_fetch_quotas: BTreeMap<String, i64>,
+    // percent of a random fetch one prefab fills before other prefabs come first; 0: off
+    pub prefab_max_share_percent: i64,
+    pub current_season: i64,
+    pub structure_ttl_days: u64,
+    pub archive_cold_after_days: u64,
+    pub map_overrides: BTreeMap<i32, MapOverrides>,
+    // prefab -> attribute name -> type, from [prefab_attributes.<prefab>] file sections
+    pub prefab_attributes: BTreeMap<String, BTreeMap<String, AttributeType>>,
+    // slugs of the [tenants.<slug>] communities hosted next to this one
+    pub tenants: Vec<String>,
+}
+
+// Per-map replacements for the global limits, from [maps.<map_id>] file sections
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapOverrides {
+    pub max_user_structs_saved_per_scene: Option<i64>,
+    pub default_random_limit: Option<i64>,
+    pub structure_ttl_days: Option<u64>,
+    // corners of the playable area; uploads outside fail PLAUSIBILITY_CHECKS
+    pub bounds_min: Option<[f32; 3]>,
+    pub bounds_max: Option<[f32; 3]>,
+}
+
+// Layered lookup for config keys: environment variable, then the same key
+// lowercased in the TOML file, then the built-in default.
+struct ConfigSource<'a> {
+    file: toml::Table,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    used: std::cell::RefCell<Vec<String>>,
+}
+
+impl<'a> ConfigSource<'a> {
+    pub fn new(file: toml::Table, env: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        Self {
+            file,
+            env,
+            used: std::cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    fn raw(&self, key: &str) -> Option<String> {
+        let file_key = key.to_ascii_lowercase();
+        let file_value = self.file.get(&file_key).map(|value| match value {
+            toml::Value::String(text) => text.clone(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            other => other.to_string(),
+        });
+        self.used.borrow_mut().push(file_key);
+        (self.env)(key).or(file_value)
+    }
+
+    fn parse_with<T, E: std::fmt::Display>(
+        &self,
+        key: &str,
+        default: T,
+        parse: impl FnOnce(&str) -> Result<T, E>,
+    ) -> anyhow::Result<T> {
+        match self.raw(key) {
+            Some(value) => parse(value.trim())
+                .map_err(|e| anyhow::anyhow!("invalid value {value:?} for {key}: {e}")),
+            None => Ok(default),
+        }
+    }
+
+    pub fn get<T>(&self, key: &str, default: T) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.parse_with(key, default, str::parse::<T>)
+    }
+
+    fn get_secs(&self, key: &str, default: u64) -> anyhow::Result<Duration> {
+        self.get(key, default).map(Duration::from_secs)
+    }
+
+    fn get_opt_string(&self, key: &str) -> Option<String> {
+        self.raw(key).filter(|value| !value.is_empty())
+    }
+
+    // comma-separated list (or TOML array), blanks dropped
+    fn get_list(&self, key: &str, default: &str) -> Vec<String> {
+        self.raw(key)
+            .unwrap_or_else(|| default.to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    // File-only: nested tables have no sensible environment variable spelling.
+    fn get_map_overrides(&self) -> anyhow::Result<BTreeMap<i32, MapOverrides>> {
+        self.used.borrow_mut().push("maps".to_string());
+        let Some(maps) = self.file.get("maps") else {
+            return Ok(BTreeMap::new());
+        };
+        let maps = maps
+            .as_table()
+            .ok_or_else(|| anyhow::anyhow!("maps must be a table of [maps.<map_id>] sections"))?;
+
+        maps.iter()
+            .map(|(map_id, overrides)| {
+                let id = map_id
+                    .parse::<i32>()
+                    .map_err(|e| anyhow::anyhow!("invalid map id {map_id:?} in maps: {e}"))?;
+                let overrides = overrides
+                    .clone()
+                    .try_into::<MapOverrides>()
+                    .with_context(|| format!("invalid overrides for maps.{map_id}"))?;
+                Ok((id, overrides))
+            })
+            .collect()
+    }
+
+    // File-only, like the map overrides
+    fn get_prefab_attributes(
+        &self,
+    ) -> anyhow::Result<BTreeMap<String, BTreeMap<String, AttributeType>>> {
+        self.used.borrow_mut().push("prefab_attributes".to_string());
+        let Some(prefabs) = self.file.get("prefab_attributes") else {
+            return Ok(BTreeMap::new());
+        };
+        prefabs
+            .clone()
+            .try_into()
+            .context("invalid prefab_attributes: expected [prefab_attributes.<prefab>] sections of name = \"bool\" | \"int\" | \"float\" | \"string\"")
+    }
+
+    // Typos in the file would otherwise be silently ignored.
+    fn ensure_no_unknown_keys(&self) -> anyhow::Result<()> {
+        let used = self.used.borrow();
+        let unknown: Vec<&str> = self
+            .file
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !used.iter().any(|u| u == key))
+            .collect();
+        if !unknown.is_empty() {
+            anyhow::bail!("unknown config keys: {}", unknown.join(", "));
+        }
+        Ok(())
+    }
+}
+
+// Keys that belong to the process rather than to one community
+const PROCESS_WIDE_KEYS: &[&str] = &[
+    "server_port",
+    "listen",
+    "unix_socket_mode",
+    "tls_cert_path",
+    "tls_key_path",
+    "trusted_proxies",
+    "http_pool_max_idle",
+    "http_pool_idle_timeout_seconds",
+    "http2",
+    "tenants",
+];
+
+// [tenants.<slug>] sections of the config file
+fn tenant_sections(file: &toml::Table) -> anyhow::Result<BTreeMap<String, toml::Table>> {
+    let Some(tenants) = file.get("tenants") else {
+        return Ok(BTreeMap::new());
+    };
+    let tenants = tenants
+        .as_table()
+        .ok_or_else(|| anyhow::anyhow!("tenants must be a table of [tenants.<slug>] sections"))?;
+    tenants
+        .iter()
+        .map(|(slug, section)| {
+            let valid = (1..=32).contains(&slug.len())
+                && slug
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+            if !valid {
+                anyhow::bail!(
+                    "invalid tenant slug {slug:?}: use 1 to 32 lowercase letters, digits or dashes"
+                );
+            }
+            let section = section
+                .as_table()
+                .ok_or_else(|| anyhow::anyhow!("tenants.{slug} must be a table"))?;
+            Ok((slug.clone(), section.clone()))
+        })
+        .collect()
+}
+
+impl Config {
+    // Reads CONFIG_PATH (default peakstranding.toml, optional) and the process environment.
+    pub fn load() -> anyhow::Result<Self> {
+        Self::from_sources(Self::read_file()?, &|key| env::var(key).ok())
+    }
+
+    // The config of one [tenants.<slug>] community, read the same way
+    pub fn load_tenant(slug: &str) -> anyhow::Result<Self> {
+        Self::tenant_from_sources(&Self::read_file()?, &|key| env::var(key).ok(), slug)
+    }
+
+    // A tenant's section is laid over the rest of the file, and its keys win
+    // over the environment too, so a shared DATABASE_URL in .env can't point
+    // two communities at one database.
+    pub fn tenant_from_sources(
+        file: &toml::Table,
+        env: &dyn Fn(&str) -> Option<String>,
+        slug: &str,
+    ) -> anyhow::Result<Self> {
+        let mut sections = tenant_sections(file)?;
+        let Some(section) = sections.remove(slug) else {
+            anyhow::bail!("no [tenants.{slug}] section");
+        };
+        if let Some(key) = section
+            .keys()
+            .find(|key| PROCESS_WIDE_KEYS.contains(&key.as_str()))
+        {
+            anyhow::bail!("{key} cannot be set per tenant (tenants.{slug})");
+        }
+        if !section.contains_key("database_url") {
+            anyhow::bail!("tenants.{slug} needs its own database_url");
+        }
+        let mut merged = file.clone();
+        merged.remove("tenants");
+        merged.extend(section.clone());
+        Self::from_sources(merged, &|key| {
+            if section.contains_key(&key.to_ascii_lowercase()) {
+                None
+            } else {
+                env(key)
+            }
+        })
+        .with_context(|| format!("invalid config for tenants.{slug}"))
+    }
+
+    fn read_file() -> anyhow::Result<toml::Table> {
+        let explicit_path = env::var("CONFIG_PATH").ok();
+        let path = explicit_path
+            .clone()
+            .unwrap_or_else(|| "peakstranding.toml".to_string());
+
+        let file = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str::<toml::Table>(&text)
+                .with_context(|| format!("failed to parse config file {path}"))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && explicit_path.is_none() => {
+                toml::Table::new()
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read config file {path}")),
+        };
+        Ok(file)
+    }
+
+    pub fn from_sources(
+        file: toml::Table,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<Self> {
+        let tenants = tenant_sections(&file)?;
+        // checked below with the rest of the file, before it moves into `src`
+        let tenant_file = (!tenants.is_empty()).then(|| file.clone());
+        let src = ConfigSource::new(file, env);
+        src.used.borrow_mut().push("tenants".to_string());
+
+        let server_port = src.get("SERVER_PORT", 3000_u16)?;
+
+        let config = Self {
+            steam_appids: src
+                .get_list("STEAM_APPID", "3527290")
+                .iter()
+                .map(|appid| {
+                    appid.parse::<u64>().map_err(|e| {
+                        anyhow::anyhow!("invalid value {appid:?} for STEAM_APPID: {e}")
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            separate_appids: src.get("SEPARATE_APPIDS", false)?,
+            read_only: src.get("READ_ONLY", false)?,
+            startup_self_check: src.get("STARTUP_SELF_CHECK", true)?,
+            startup_integrity_check: src.get("STARTUP_INTEGRITY_CHECK", false)?,
+            slow_request_threshold: Duration::from_millis(src.get("SLOW_REQUEST_MS", 0)?),
+            enable_post: src.get("ENABLE_POST", true)?,
+            enable_likes: src.get("ENABLE_LIKES", true)?,
+            enable_fetch: src.get("ENABLE_FETCH", true)?,
+            min_client_version: src.parse_with("MIN_CLIENT_VERSION", None, |version| {
+                version.parse().map(Some)
+            })?,
+            server_password: src.get_opt_string("SERVER_PASSWORD"),
+            invite_only: src.get("INVITE_ONLY", false)?,
+            whitelist_mode: src.get("WHITELIST_MODE", false)?,
+            max_user_structs_saved_per_scene: src
+                .get("MAX_USER_STRUCTS_SAVED_PER_SCENE", 100_i64)?,
+            prune_policy: src.get("PRUNE_POLICY", PrunePolicy::Oldest)?,
+            max_pinned_per_scene: src.get("MAX_PINNED_PER_SCENE", 3_i64)?,
+            max_favorites_per_user: src.get("MAX_FAVORITES_PER_USER", 200_i64)?,
+            max_contracts_per_user: src.get("MAX_CONTRACTS_PER_USER", 20_i64)?,
+            max_requested_structs: src.get("MAX_REQUESTED_STRUCTS", 400_i64)?,
+            post_structure_rate_limit: src.get_secs("POST_STRUCTURE_RATE_LIMIT", 2)?,
+            new_player_burst_posts: src.get("NEW_PLAYER_BURST_POSTS", 20_i64)?,
+            daily_upload_quota: src.get("DAILY_UPLOAD_QUOTA", 0_i64)?,
+            get_structure_rate_limit: src.get_secs("GET_STRUCTURE_RATE_LIMIT", 6)?,
+            post_like_rate_limit: src.get_secs("POST_LIKE_RATE_LIMIT", 1)?,
+            rate_limit_exempt_steamids: src
+                .get_list("RATE_LIMIT_EXEMPT_STEAMIDS", "")
+                .iter()
+                .map(|steamid| {
+                    steamid.parse::<u64>().map_err(|e| {
+                        anyhow::anyhow!(
+                            "invalid value {steamid:?} for RATE_LIMIT_EXEMPT_STEAMIDS: {e}"
+                        )
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            adaptive_rate_limits: src.get("ADAPTIVE_RATE_LIMITS", false)?,
+            adaptive_rate_limit_target: Duration::from_millis(
+                src.get("ADAPTIVE_RATE_LIMIT_TARGET_MS", 50)?,
+            ),
+            adaptive_rate_limit_min_factor: src.get("ADAPTIVE_RATE_LIMIT_MIN_FACTOR", 0.5_f64)?,
+            adaptive_rate_limit_max_factor: src.get("ADAPTIVE_RATE_LIMIT_MAX_FACTOR", 4.0_f64)?,
+            like_daily_budget: src.get("LIKE_DAILY_BUDGET", 500_i64)?,
+            like_target_daily_cap: src.get("LIKE_TARGET_DAILY_CAP", 100_i64)?,
+            like_suspicious_threshold: src.get("LIKE_SUSPICIOUS_THRESHOLD", 200_i64)?,
+            likes_ledger_retention_days: src.get("LIKES_LEDGER_RETENTION_DAYS", 90_u64)?,
+            usage_rate_limit: src.get_secs("USAGE_RATE_LIMIT", 30)?,
+            nearby_rate_limit: src.get_secs("NEARBY_RATE_LIMIT", 2)?,
+            nearby_max_radius: src.get("NEARBY_MAX_RADIUS", 200.0_f32)?,
+            density_radius: src.get("DENSITY_RADIUS", 5.0_f32)?,
+            density_max_structures: src.get("DENSITY_MAX_STRUCTURES", 0_i64)?,
+            min_own_structure_distance: src.get("MIN_OWN_STRUCTURE_DISTANCE", 0.0_f32)?,
+            plausibility_checks: src.get("PLAUSIBILITY_CHECKS", PlausibilityMode::Off)?,
+            plausibility_burst_posts: src.get("PLAUSIBILITY_BURST_POSTS", 100_i64)?,
+            plausibility_burst_window: src.get_secs("PLAUSIBILITY_BURST_SECONDS", 10)?,
+            newcomer_review_count: src.get("NEWCOMER_REVIEW_COUNT", 0_i64)?,
+            newcomer_auto_approve: src.get_secs("NEWCOMER_AUTO_APPROVE_SECONDS", 86_400)?,
+            duplicate_window: src.get_secs("DUPLICATE_WINDOW_SECONDS", 300)?,
+            user_restore_window: src.get_secs("USER_RESTORE_WINDOW_SECONDS", 86_400)?,
+            global_stats_rate_limit: src.get_secs("GLOBAL_STATS_RATE_LIMIT", 6)?,
+            user_stats_rate_limit: src.get_secs("USER_STATS_RATE_LIMIT", 6)?,
+            global_stats_cache_ttl: src.get_secs("GLOBAL_STATS_CACHE_TTL_SECONDS", 600)?,
+            scene_stats_cache_ttl: src.get_secs("SCENE_STATS_CACHE_TTL_SECONDS", 60)?,
+            default_random_limit: src.get("DEFAULT_RANDOM_LIMIT", 40_i64)?,
+            max_scene_length: src.get("MAX_SCENE_LENGTH", 50_usize)?,
+            max_segment: src.get("MAX_SEGMENT", 10_i32)?,
+            database_url: src.get(
+                "DATABASE_URL",
+                "sqlite://peakstranding.db?mode=rwc".to_string(),
+            )?,
+            db_max_connections: src.get("DB_MAX_CONNECTIONS", 4_u32)?,
+            // SQLite's own defaults: 2000 KiB page cache, no mmap, checkpoint every 1000 pages
+            sqlite_cache_size: src.get("SQLITE_CACHE_SIZE", -2000_i64)?,
+            sqlite_mmap_size: src.get("SQLITE_MMAP_SIZE", 0_u64)?,
+            sqlite_wal_autocheckpoint: src.get("SQLITE_WAL_AUTOCHECKPOINT", 1000_u32)?,
+            wal_checkpoint_interval: src.get_secs("WAL_CHECKPOINT_SECONDS", 300)?,
+            listen: src.get("LISTEN", ListenAddr::Tcp(format!("0.0.0.0:{server_port}")))?,
+            // octal, like chmod: 660
+            unix_socket_mode: src.parse_with("UNIX_SOCKET_MODE", None, |mode| {
+                u32::from_str_radix(mode, 8).map(Some)
+            })?,
+            trusted_proxies: src
+                .get_list("TRUSTED_PROXIES", "")
+                .iter()
+                .map(|cidr| {
+                    cidr.parse::<Cidr>().map_err(|e| {
+                        anyhow::anyhow!("invalid value {cidr:?} for TRUSTED_PROXIES: {e}")
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            // SKIP_STEAM_TICKET_VALIDATION predates AUTH_PROVIDER and still selects static ids
+            auth_provider: src.get(
+                "AUTH_PROVIDER",
+                if src.get("SKIP_STEAM_TICKET_VALIDATION", false)? {
+                    AuthProviderKind::Static
+                } else {
+                    AuthProviderKind::Steam
+                },
+            )?,
+            auth_shared_secret: src.get_opt_string("AUTH_SHARED_SECRET"),
+            steam_breaker_failures: src.get("STEAM_BREAKER_FAILURES", 5_u32)?,
+            steam_breaker_cooldown: src.get_secs("STEAM_BREAKER_COOLDOWN_SECONDS", 30)?,
+            steam_partner_fallback: src.get("STEAM_PARTNER_FALLBACK", false)?,
+            steam_api_url: src
+                .get("STEAM_API_URL", steam::PUBLIC_HOST.to_string())?
+                .trim_end_matches('/')
+                .to_string(),
+            http_pool_max_idle: src.get("HTTP_POOL_MAX_IDLE", 8_usize)?,
+            http_pool_idle_timeout: src.get_secs("HTTP_POOL_IDLE_TIMEOUT_SECONDS", 60)?,
+            http2: src.get("HTTP2", true)?,
+            require_app_ownership: src.get("REQUIRE_APP_OWNERSHIP", false)?,
+            ownership_cache_ttl: src.get_secs("OWNERSHIP_CACHE_TTL_SECONDS", 86_400)?,
+            resolve_steam_names: src.get("RESOLVE_STEAM_NAMES", false)?,
+            profile_cache_ttl: src.get_secs("PROFILE_CACHE_TTL_SECONDS", 86_400)?,
+            admin_api_key: src.get_opt_string("ADMIN_API_KEY"),
+            server_id: src.get_opt_string("SERVER_ID"),
+            federation_keys: src.get_list("FEDERATION_KEYS", ""),
+            upstream_url: src
+                .get_opt_string("UPSTREAM_URL")
+                .map(|url| url.trim_end_matches('/').to_string()),
+            upstream_key: src.get_opt_string("UPSTREAM_KEY"),
+            federation_pull_interval: src.get_secs("FEDERATION_PULL_SECONDS", 300)?,
+            tls_cert_path: src.get_opt_string("TLS_CERT_PATH"),
+            tls_key_path: src.get_opt_string("TLS_KEY_PATH"),
+            cors_allowed_origins: src.get_list("CORS_ALLOWED_ORIGINS", ""),
+            cors_allowed_methods: src.get_list("CORS_ALLOWED_METHODS", "GET"),
+            cors_allowed_headers: src.get_list(
+                "CORS_ALLOWED_HEADERS",
+                "x-steam-auth,x-api-version,x-mod-version,x-server-password,content-type",
+            ),
+            ws_max_subscriptions: src.get("WS_MAX_SUBSCRIPTIONS", 16_usize)?,
+            max_body_bytes: src.get("MAX_BODY_BYTES", 16_384_usize)?,
+            request_timeout: src.get_secs("REQUEST_TIMEOUT_SECONDS", 30)?,
+            max_concurrent_requests: src.get("MAX_CONCURRENT_REQUESTS", 0_usize)?,
+            max_concurrent_fetches: src.get("MAX_CONCURRENT_FETCHES", 0_usize)?,
+            random_sample_refresh: src.get_secs("RANDOM_SAMPLE_REFRESH_SECONDS", 0)?,
+            random_probe_min_rows: src.get("RANDOM_PROBE_MIN_ROWS", 0_i64)?,
+            random_batch_ttl: src.get_secs("RANDOM_BATCH_TTL_SECONDS", 3)?,
+            like_staleness: src.get_secs("LIKE_STALENESS_SECONDS", 1)?,
+            fetch_session_ttl: src.get_secs("FETCH_SESSION_TTL_SECONDS", 600)?,
+            post_queue_capacity: src.get("POST_QUEUE_CAPACITY", 0_usize)?,
+            post_queue_batch_size: src.get("POST_QUEUE_BATCH_SIZE", 64_usize)?,
+            like_flush_interval: src.get_secs("LIKE_FLUSH_SECONDS", 0)?,
+            fetch_flush_interval: src.get_secs("FETCH_FLUSH_SECONDS", 60)?,
+            fetch_history_days: src.get("FETCH_HISTORY_DAYS", 0_u64)?,
+            notification_digest_window: src
+                .get_secs("NOTIFICATION_DIGEST_WINDOW_SECONDS", 86_400)?,
+            discord_webhook_url: src.get_opt_string("DISCORD_WEBHOOK_URL"),
+            discord_like_milestone: src.get("DISCORD_LIKE_MILESTONE", 100_i64)?,
+            like_milestones: src
+                .get_list("LIKE_MILESTONES", "10,100,1000")
+                .iter()
+                .map(|likes| {
+                    likes.parse::<i64>().map_err(|e| {
+                        anyhow::anyhow!("invalid value {likes:?} for LIKE_MILESTONES: {e}")
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            milestone_webhook_url: src.get_opt_string("MILESTONE_WEBHOOK_URL"),
+            curated_share_percent: src.get("CURATED_SHARE_PERCENT", 0_i64)?,
+            likes_decay_days: src.get("LIKES_DECAY_DAYS", 0_i64)?,
+            prefab_max_share_percent: src.get("PREFAB_MAX_SHARE_PERCENT", 0_i64)?,
+            prefab_fetch_quotas: src
+                .get_list("PREFAB_FETCH_QUOTAS", "")
+                .iter()
+                .map(|entry| {
+                    entry
+                        .split_once('=')
+                        .and_then(|(prefab, max)| {
+                            Some((prefab.trim().to_string(), max.trim().parse::<i64>().ok()?))
+                        })
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "invalid value {entry:?} for PREFAB_FETCH_QUOTAS: expected prefab=max"
+                            )
+                        })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            current_season: src.get("CURRENT_SEASON", 1_i64)?,
+            structure_ttl_days: src.get("STRUCTURE_TTL_DAYS", 0_u64)?,
+            archive_cold_after_days: src.get("ARCHIVE_COLD_AFTER_DAYS", 0_u64)?,
+            map_overrides: src.get_map_overrides()?,
+            prefab_attributes: src.get_prefab_attributes()?,
+            tenants: tenants.into_keys().collect(),
+        };
+
+        src.ensure_no_unknown_keys()?;
+        config.validate()?;
+        if let Some(file) = tenant_file {
+            let mut databases = vec![config.database_url.clone()];
+            for slug in &config.tenants {
+                let tenant = Self::tenant_from_sources(&file, env, slug)?;
+                if databases.contains(&tenant.database_url) {
+                    anyhow::bail!("tenants.{slug} shares its database_url with another community");
+                }
+                databases.push(tenant.database_url);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_user_structs_saved_per_scene < 1 {
+            anyhow::bail!("MAX_USER_STRUCTS_SAVED_PER_SCENE must be at least 1");
+        }
+        // pruning needs an unpinned structure to make room with
+        if !(0..self.max_user_structs_saved_per_scene).contains(&self.max_pinned_per_scene) {
+            anyhow::bail!(
+                "MAX_PINNED_PER_SCENE must be between 0 and MAX_USER_STRUCTS_SAVED_PER_SCENE - 1 ({})",
+                self.max_user_structs_saved_per_scene - 1
+            );
+        }
+        if self.max_requested_structs < 0 {
+            anyhow::bail!("MAX_REQUESTED_STRUCTS must not be negative");
+        }
+        if !(0..=self.max_requested_structs).contains(&self.default_random_limit) {
+            anyhow::bail!(
+                "DEFAULT_RANDOM_LIMIT must be between 0 and MAX_REQUESTED_STRUCTS ({})",
+                self.max_requested_structs
+            );
+        }
+        if self.post_queue_batch_size == 0 {
+            anyhow::bail!("POST_QUEUE_BATCH_SIZE must be at least 1");
+        }
+        if self.random_probe_min_rows < 0 {
+            anyhow::bail!("RANDOM_PROBE_MIN_ROWS must not be negative");
+        }
+        if self.db_max_connections == 0 {
+            anyhow::bail!("DB_MAX_CONNECTIONS must be at least 1");
+        }
+        if self.max_segment < 0 {
+            anyhow::bail!("MAX_SEGMENT must not be negative");
+        }
+        if self.max_body_bytes == 0 {
+            anyhow::bail!("MAX_BODY_BYTES must be at least 1");
+        }
+        if (self.upstream_url.is_some() || !self.federation_keys.is_empty())
+            && self.server_id.is_none()
+        {
+            anyhow::bail!("SERVER_ID must be set when UPSTREAM_URL or FEDERATION_KEYS is");
+        }
+        if self.upstream_url.is_some() && self.federation_pull_interval.is_zero() {
+            anyhow::bail!("FEDERATION_PULL_SECONDS must be at least 1");
+        }
+        if self.fetch_session_ttl.is_zero() {
+            anyhow::bail!("FETCH_SESSION_TTL_SECONDS must be at least 1");
+        }
+        if self.notification_digest_window.is_zero() {
+            anyhow::bail!("NOTIFICATION_DIGEST_WINDOW_SECONDS must be at least 1");
+        }
+        if self.max_scene_length == 0 {
+            anyhow::bail!("MAX_SCENE_LENGTH must be at least 1");
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            anyhow::bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together");
+        }
+        if self.tls_cert_path.is_some() && matches!(self.listen, ListenAddr::Unix(_)) {
+            anyhow::bail!("TLS is not supported on unix socket listeners");
+        }
+        if let Some(url) = &self.discord_webhook_url
+            && !(url.starts_with("https://") || url.starts_with("http://"))
+        {
+            anyhow::bail!("DISCORD_WEBHOOK_URL must be an http(s) URL");
+        }
+        if let Some(url) = &self.milestone_webhook_url
+            && !(url.starts_with("https://") || url.starts_with("http://"))
+        {
+            anyhow::bail!("MILESTONE_WEBHOOK_URL must be an http(s) URL");
+        }
+        if self.like_milestones.iter().any(|&likes| likes <= 0) {
+            anyhow::bail!("LIKE_MILESTONES must be positive like counts");
+        }
+        if self.steam_appids.is_empty() {
+            anyhow::bail!("STEAM_APPID must list at least one app id");
+        }
+        if self.discord_like_milestone < 0 {
+            anyhow::bail!("DISCORD_LIKE_MILESTONE must not be negative");
+        }
+        for (key, value) in [
+            ("LIKE_DAILY_BUDGET", self.like_daily_budget),
+            ("LIKE_TARGET_DAILY_CAP", self.like_target_daily_cap),
+            ("LIKE_SUSPICIOUS_THRESHOLD", self.like_suspicious_threshold),
+            ("NEW_PLAYER_BURST_POSTS", self.new_player_burst_posts),
+            ("DAILY_UPLOAD_QUOTA", self.daily_upload_quota),
+            ("MAX_FAVORITES_PER_USER", self.max_favorites_per_user),
+            ("MAX_CONTRACTS_PER_USER", self.max_contracts_per_user),
+        ] {
+            if value < 0 {
+                anyhow::bail!("{key} must not be negative");
+            }
+        }
+        if self.prefab_fetch_quotas.values().any(|&max| max < 0) {
+            anyhow::bail!("PREFAB_FETCH_QUOTAS must not be negative");
+        }
+        if !(0..=100).contains(&self.curated_share_percent) {
+            anyhow::bail!("CURATED_SHARE_PERCENT must be between 0 and 100");
+        }
+        if !(0..=100).contains(&self.prefab_max_share_percent) {
+            anyhow::bail!("PREFAB_MAX_SHARE_PERCENT must be between 0 and 100");
+        }
+        if self.likes_decay_days < 0 {
+            anyhow::bail!("LIKES_DECAY_DAYS must not be negative");
+        }
+        if !(self.nearby_max_radius.is_finite() && self.nearby_max_radius > 0.0) {
+            anyhow::bail!("NEARBY_MAX_RADIUS must be a positive number");
+        }
+        if !(self.density_radius.is_finite() && self.density_radius > 0.0) {
+            anyhow::bail!("DENSITY_RADIUS must be a positive number");
+        }
+        if !(self.min_own_structure_distance.is_finite() && self.min_own_structure_distance >= 0.0)
+        {
+            anyhow::bail!("MIN_OWN_STRUCTURE_DISTANCE must be a non-negative number");
+        }
+        if !(self.adaptive_rate_limit_min_factor.is_finite()
+            && self.adaptive_rate_limit_min_factor > 0.0
+            && self.adaptive_rate_limit_min_factor <= 1.0)
+        {
+            anyhow::bail!("ADAPTIVE_RATE_LIMIT_MIN_FACTOR must be above 0 and at most 1");
+        }
+        if !(self.adaptive_rate_limit_max_factor.is_finite()
+            && self.adaptive_rate_limit_max_factor >= 1.0)
+        {
+            anyhow::bail!("ADAPTIVE_RATE_LIMIT_MAX_FACTOR must be at least 1");
+        }
+        if self.adaptive_rate_limits && self.adaptive_rate_limit_target.is_zero() {
+            anyhow::bail!("ADAPTIVE_RATE_LIMIT_TARGET_MS must be at least 1");
+        }
+        if self.current_season < 1 {
+            anyhow::bail!("CURRENT_SEASON must be at least 1");
+        }
+        if self.plausibility_burst_posts < 0 {
+            anyhow::bail!("PLAUSIBILITY_BURST_POSTS must not be negative");
+        }
+        if self.newcomer_review_count < 0 {
+            anyhow::bail!("NEWCOMER_REVIEW_COUNT must not be negative");
+        }
+        for (map_id, overrides) in &self.map_overrides {
+            if overrides.bounds_min.is_some() != overrides.bounds_max.is_some() {
+                anyhow::bail!("maps.{map_id}.bounds_min and bounds_max must be set together");
+            }
+            if let (Some(min), Some(max)) = (overrides.bounds_min, overrides.bounds_max)
+                && min.iter().zip(max.iter()).any(|(lo, hi)| lo > hi)
+            {
+                anyhow::bail!("maps.{map_id}.bounds_min must not exceed bounds_max");
+            }
+            if overrides
+                .max_user_structs_saved_per_scene
+                .is_some_and(|cap| cap < 1)
+            {
+                anyhow::bail!("maps.{map_id}.max_user_structs_saved_per_scene must be at least 1");
+            }
+            if overrides
+                .max_user_structs_saved_per_scene
+                .is_some_and(|cap| cap <= self.max_pinned_per_scene)
+            {
+                anyhow::bail!(
+                    "maps.{map_id}.max_user_structs_saved_per_scene must be above MAX_PINNED_PER_SCENE ({})",
+                    self.max_pinned_per_scene
+                );
+            }
+            if overrides
+                .default_random_limit
+                .is_some_and(|limit| !(0..=self.max_requested_structs).contains(&limit))
+            {
+                anyhow::bail!(
+                    "maps.{map_id}.default_random_limit must be between 0 and MAX_REQUESTED_STRUCTS ({})",
+                    self.max_requested_structs
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    // First STEAM_APPID entry; structures from before app ids were recorded belong to it
+    pub fn primary_appid(&self) -> u64 {
+        self.steam_appids[0]
+    }
+
+    // Player requests need X-Server-Password (SERVER_PASSWORD or INVITE_ONLY)
+    pub fn is_private(&self) -> bool {
+        self.server_password.is_some() || self.invite_only
+    }
+
+    fn map_override<T>(
+        &self,
+        map_id: Option<i32>,
+        pick: impl Fn(&MapOverrides) -> Option<T>,
+    ) -> Option<T> {
+        map_id
+            .and_then(|id| self.map_overrides.get(&id))
+            .and_then(pick)
+    }
+
+    pub fn max_user_structs_for_map(&self, map_id: i32) -> i64 {
+        self.map_override(Some(map_id), |m| m.max_user_structs_saved_per_scene)
+            .unwrap_or(self.max_user_structs_saved_per_scene)
+    }
+
+    pub fn default_random_limit_for_map(&self, map_id: Option<i32>) -> i64 {
+        self.map_override(map_id, |m| m.default_random_limit)
+            .unwrap_or(self.default_random_limit)
+    }
+
+    // Structures older than this are no longer served; None keeps them forever.
+    pub fn structure_ttl_for_map(&self, map_id: Option<i32>) -> Option<Duration> {
+        let days = self
+            .map_override(map_id, |m| m.structure_ttl_days)
+            .unwrap_or(self.structure_ttl_days);
+        (days > 0).then(|| Duration::from_secs(days * 86_400))
+    }
+
+    // (min, max) corners of the map's playable area, when configured
+    pub fn map_bounds(&self, map_id: i32) -> Option<([f32; 3], [f32; 3])> {
+        self.map_override(Some(map_id), |m| m.bounds_min.zip(m.bounds_max))
+    }
+}
+
+impl Config {
+    // Settings bound at startup (database, listener, TLS, CORS layer) keep their running
+    // values across a reload; returns the keys whose new value was ignored.
+    pub fn keep_restart_only(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        macro_rules! keep {
+            ($key:literal, $field:ident) => {
+                if self.$field != running.$field {
+                    self.$field = running.$field.clone();
+                    ignored.push($key);
+                }
+            };
+        }
+
+        keep!("DATABASE_URL", database_url);
+        keep!("DB_MAX_CONNECTIONS", db_max_connections);
+        keep!("SQLITE_CACHE_SIZE", sqlite_cache_size);
+        keep!("SQLITE_MMAP_SIZE", sqlite_mmap_size);
+        keep!("SQLITE_WAL_AUTOCHECKPOINT", sqlite_wal_autocheckpoint);
+        keep!("LISTEN", listen);
+        keep!("AUTH_PROVIDER", auth_provider);
+        keep!("UNIX_SOCKET_MODE", unix_socket_mode);
+        keep!("TLS_CERT_PATH", tls_cert_path);
+        keep!("TLS_KEY_PATH", tls_key_path);
+        keep!("CORS_ALLOWED_ORIGINS", cors_allowed_origins);
+        keep!("CORS_ALLOWED_METHODS", cors_allowed_methods);
+        keep!("CORS_ALLOWED_HEADERS", cors_allowed_headers);
+        keep!("MAX_BODY_BYTES", max_body_bytes);
+        keep!("MAX_CONCURRENT_REQUESTS", max_concurrent_requests);
+        keep!("MAX_CONCURRENT_FETCHES", max_concurrent_fetches);
+        keep!("HTTP_POOL_MAX_IDLE", http_pool_max_idle);
+        keep!("HTTP_POOL_IDLE_TIMEOUT_SECONDS", http_pool_idle_timeout);
+        keep!("HTTP2", http2);
+        keep!("POST_QUEUE_CAPACITY", post_queue_capacity);
+        keep!("ENABLE_POST", enable_post);
+        keep!("ENABLE_LIKES", enable_likes);
+        keep!("ENABLE_FETCH", enable_fetch);
+        keep!("TENANTS", tenants);
+        ignored
+    }
+}
@@ -0,0 +1,59 @@
+// Active strand contracts (contracts table), kept in memory.
+//
+// Every unseeded random fetch and every like asks whether the player involved
+// has contract partners, and most players have none, so the pairs are read
+// whole and reread every CONTRACTS_REFRESH, or right away when this instance
+// changed them, instead of a query per request. Another instance sharing the
+// database catches up within CONTRACTS_REFRESH.
+
+use sqlx::SqlitePool;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
+
+const CONTRACTS_REFRESH: Duration = Duration::from_secs(60);
+
+type Partners = HashMap<i64, Vec<i64>>;
+
+#[derive(Debug, Default)]
+pub struct ContractCache {
+    // player -> their partners, and when they were read
+    active: RwLock<Option<(Instant, Arc<Partners>)>>,
+}
+
+impl ContractCache {
+    async fn active(&self, db: &SqlitePool) -> Result<Arc<Partners>, sqlx::Error> {
+        if let Some((read_at, active)) = &*self.active.read().await
+            && read_at.elapsed() < CONTRACTS_REFRESH
+        {
+            return Ok(active.clone());
+        }
+        let pairs: Vec<(i64, i64)> = sqlx::query_as(
+            r#"SELECT c.user_id, c.partner_id FROM contracts c
+               JOIN contracts back ON back.user_id = c.partner_id AND back.partner_id = c.user_id"#,
+        )
+        .fetch_all(db)
+        .await?;
+        let mut active = Partners::new();
+        for (user_id, partner_id) in pairs {
+            active.entry(user_id).or_default().push(partner_id);
+        }
+        let active = Arc::new(active);
+        *self.active.write().await = Some((Instant::now(), active.clone()));
+        Ok(active)
+    }
+
+    // The player's contract partners
+    pub async fn partners(&self, db: &SqlitePool, user_id: i64) -> Result<Vec<i64>, sqlx::Error> {
+        Ok(self
+            .active(db)
+            .await?
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    // After a contract was offered or cancelled
+    pub async fn invalidate(&self) {
+        *self.active.write().await = None;
+    }
+}
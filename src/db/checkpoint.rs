@@ -0,0 +1,76 @@
+// Periodic WAL checkpoints (WAL_CHECKPOINT_SECONDS).
+//
+// SQLite's automatic checkpoints (SQLITE_WAL_AUTOCHECKPOINT) run on commit and
+// give up on pages a reader still needs, and they never shrink the -wal file.
+// Under sustained fetch load some reader is nearly always open, so the file of
+// a long-running server keeps growing. This task runs
+// wal_checkpoint(TRUNCATE) on the writer every interval, which waits for the
+// readers (up to the busy timeout) and resets the file to zero bytes.
+
+use sqlx::SqlitePool;
+use std::time::{Duration, Instant};
+
+use super::sizes::file_sizes;
+use crate::state::AppState;
+
+#[derive(Debug)]
+pub struct CheckpointResult {
+    // readers or a writer kept it from finishing; the rest waits for next time
+    pub busy: bool,
+    // the -wal file before and after; None for an in-memory database. The
+    // pragma's own frame counts read zero once the file is truncated.
+    pub wal_bytes_before: Option<u64>,
+    pub wal_bytes_after: Option<u64>,
+}
+
+pub async fn checkpoint(db: &SqlitePool) -> Result<CheckpointResult, sqlx::Error> {
+    let (_, wal_bytes_before) = file_sizes(db).await?;
+    let (busy, _, _): (bool, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(db)
+        .await?;
+    let (_, wal_bytes_after) = file_sizes(db).await?;
+    Ok(CheckpointResult {
+        busy,
+        wal_bytes_before,
+        wal_bytes_after,
+    })
+}
+
+// Checkpoints every WAL_CHECKPOINT_SECONDS
+pub async fn checkpoint_wal(state: AppState) {
+    loop {
+        let interval = state.config().wal_checkpoint_interval;
+        // still polled when off, so a reload can turn it on
+        tokio::time::sleep(if interval.is_zero() {
+            Duration::from_secs(60)
+        } else {
+            interval
+        })
+        .await;
+        if state.config().wal_checkpoint_interval.is_zero() {
+            continue;
+        }
+
+        let started = Instant::now();
+        let result = state
+            .db_metrics
+            .timed("wal_checkpoint", checkpoint(&state.db))
+            .await;
+        let bytes = |bytes: Option<u64>| bytes.map_or("-".to_string(), |bytes| bytes.to_string());
+        match result {
+            Ok(result) if result.busy => tracing::warn!(
+                "wal_checkpoint result=busy wal_bytes_before={} wal_bytes_after={} duration_ms={}",
+                bytes(result.wal_bytes_before),
+                bytes(result.wal_bytes_after),
+                started.elapsed().as_millis()
+            ),
+            Ok(result) => tracing::info!(
+                "wal_checkpoint result=ok wal_bytes_before={} wal_bytes_after={} duration_ms={}",
+                bytes(result.wal_bytes_before),
+                bytes(result.wal_bytes_after),
+                started.elapsed().as_millis()
+            ),
+            Err(e) => tracing::error!("wal_checkpoint failed error={}", e),
+        }
+    }
+}
@@ -0,0 +1,456 @@
+// Newline-delimited JSON dumps of users, structures, reactions, contributors
+// and attributes.
+//
+// Shared by GET/POST /admin/v1/export and /import and by `psctl export` and
+// `psctl import`, so a dump taken one way can be loaded the other. Another
+// server's dump can also be merged into a live database (RemoteImport).
+
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+
+use crate::model::Structure;
+
+// users row as it appears in an export dump
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UserRecord {
+    pub user_id: i64,
+    pub upload_banned: bool,
+    pub likes_received: i64,
+    pub likes_send: i64,
+    #[serde(default)]
+    pub shadow_banned: bool,
+}
+
+// structures row as it appears in an export dump (includes soft-deleted rows)
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct StructureRecord {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub structure: Structure,
+    pub deleted: bool,
+    #[serde(default = "first_season")]
+    pub season_id: i64,
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
+    #[serde(default)]
+    pub deleted_by: Option<String>, // "owner" or "admin"
+    #[serde(default)]
+    pub app_id: Option<i64>,
+    #[serde(default)]
+    pub last_fetched_at: Option<i64>,
+    #[serde(default)]
+    pub origin_server: Option<String>,
+    #[serde(default)]
+    pub origin_id: Option<i64>,
+}
+
+// dumps taken before seasons existed belong to the first one
+fn first_season() -> i64 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ReactionRecord {
+    pub structure_id: i64,
+    pub kind: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ContributorRecord {
+    pub structure_id: i64,
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct AttributeRecord {
+    pub structure_id: i64,
+    pub key: String,
+    // JSON-encoded, as stored
+    pub value: String,
+}
+
+// one line of the newline-delimited JSON dump
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "table")]
+pub enum ExportRecord {
+    #[serde(rename = "users")]
+    User(UserRecord),
+    #[serde(rename = "structures")]
+    Structure(Box<StructureRecord>),
+    #[serde(rename = "structure_reactions")]
+    Reaction(ReactionRecord),
+    #[serde(rename = "structure_contributors")]
+    Contributor(ContributorRecord),
+    #[serde(rename = "structure_attributes")]
+    Attribute(AttributeRecord),
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub users: u64,
+    pub structures: u64,
+    pub reactions: u64,
+    pub contributors: u64,
+    pub attributes: u64,
+}
+
+fn export_line(record: &ExportRecord) -> Result<String, sqlx::Error> {
+    let mut line = serde_json::to_string(record).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    line.push('\n');
+    Ok(line)
+}
+
+// Streams every row of `query` into the export channel; returns false once the dump must stop.
+async fn export_rows<T>(
+    db: &SqlitePool,
+    query: &str,
+    wrap: fn(T) -> ExportRecord,
+    tx: &mpsc::Sender<Result<String, sqlx::Error>>,
+) -> bool
+where
+    T: for<'r> FromRow<'r, sqlx::sqlite::SqliteRow> + Send + Unpin,
+{
+    let mut rows = sqlx::query_as::<_, T>(query).fetch(db);
+    while let Some(row) = rows.next().await {
+        let line = row.and_then(|record| export_line(&wrap(record)));
+        if let Err(e) = &line {
+            tracing::error!("export_failed error={}", e);
+        }
+        let failed = line.is_err();
+        if tx.send(line).await.is_err() || failed {
+            return false;
+        }
+    }
+    true
+}
+
+// Sends the whole database to `tx` as dump lines, users first so an import
+// never references a missing owner. Returns false if the dump stopped early,
+// either on an error (sent down the channel) or because the receiver is gone.
+pub async fn export(db: &SqlitePool, tx: &mpsc::Sender<Result<String, sqlx::Error>>) -> bool {
+    export_rows(
+        db,
+        "SELECT user_id, upload_banned, likes_received, likes_send, shadow_banned FROM users ORDER BY user_id",
+        ExportRecord::User,
+        tx,
+    )
+    .await
+        && export_rows(
+            db,
+            "SELECT * FROM structures ORDER BY id",
+            |record| ExportRecord::Structure(Box::new(record)),
+            tx,
+        )
+        .await
+        && export_rows(
+            db,
+            "SELECT structure_id, kind, count FROM structure_reactions ORDER BY structure_id, kind",
+            ExportRecord::Reaction,
+            tx,
+        )
+        .await
+        && export_rows(
+            db,
+            "SELECT structure_id, user_id FROM structure_contributors ORDER BY structure_id, user_id",
+            ExportRecord::Contributor,
+            tx,
+        )
+        .await
+        && export_rows(
+            db,
+            "SELECT structure_id, key, value FROM structure_attributes ORDER BY structure_id, key",
+            ExportRecord::Attribute,
+            tx,
+        )
+        .await
+}
+
+// Inserts one dump line inside the caller's transaction; blank lines are skipped.
+pub async fn import_record(
+    conn: &mut sqlx::SqliteConnection,
+    line: &[u8],
+    summary: &mut ImportSummary,
+) -> anyhow::Result<()> {
+    let line = line.trim_ascii();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    match serde_json::from_slice::<ExportRecord>(line)? {
+        ExportRecord::User(u) => {
+            sqlx::query(
+                r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send, shadow_banned)
+                   VALUES (?, ?, ?, ?, ?);"#,
+            )
+            .bind(u.user_id)
+            .bind(u.upload_banned)
+            .bind(u.likes_received)
+            .bind(u.likes_send)
+            .bind(u.shadow_banned)
+            .execute(&mut *conn)
+            .await?;
+            summary.users += 1;
+        }
+        ExportRecord::Structure(record) => {
+            insert_structure(conn, *record).await?;
+            summary.structures += 1;
+        }
+        ExportRecord::Reaction(r) => {
+            sqlx::query(
+                "INSERT INTO structure_reactions (structure_id, kind, count) VALUES (?, ?, ?);",
+            )
+            .bind(r.structure_id)
+            .bind(&r.kind)
+            .bind(r.count)
+            .execute(&mut *conn)
+            .await?;
+            summary.reactions += 1;
+        }
+        ExportRecord::Contributor(c) => {
+            sqlx::query(
+                "INSERT INTO structure_contributors (structure_id, user_id) VALUES (?, ?);",
+            )
+            .bind(c.structure_id)
+            .bind(c.user_id)
+            .execute(&mut *conn)
+            .await?;
+            summary.contributors += 1;
+        }
+        ExportRecord::Attribute(a) => {
+            sqlx::query(
+                "INSERT INTO structure_attributes (structure_id, key, value) VALUES (?, ?, ?);",
+            )
+            .bind(a.structure_id)
+            .bind(&a.key)
+            .bind(&a.value)
+            .execute(&mut *conn)
+            .await?;
+            summary.attributes += 1;
+        }
+    }
+    Ok(())
+}
+
+// One structures row; returns its id (a new one when the record has none)
+async fn insert_structure(
+    conn: &mut sqlx::SqliteConnection,
+    record: StructureRecord,
+) -> Result<i64, sqlx::Error> {
+    let StructureRecord {
+        structure: s,
+        deleted,
+        season_id,
+        deleted_at,
+        deleted_by,
+        app_id,
+        last_fetched_at,
+        origin_server,
+        origin_id,
+    } = record;
+    Ok(sqlx::query(Structure::import_query())
+        .bind(s.id)
+        .bind(s.created_at)
+        .bind(s.user_id)
+        .bind(&s.username)
+        .bind(s.map_id)
+        .bind(&s.scene)
+        .bind(s.segment)
+        .bind(&s.prefab)
+        // position
+        .bind(s.pos_x)
+        .bind(s.pos_y)
+        .bind(s.pos_z)
+        // rotation
+        .bind(s.rot_x)
+        .bind(s.rot_y)
+        .bind(s.rot_z)
+        .bind(s.rot_w)
+        // rope start
+        .bind(s.rope_start_x)
+        .bind(s.rope_start_y)
+        .bind(s.rope_start_z)
+        // rope end
+        .bind(s.rope_end_x)
+        .bind(s.rope_end_y)
+        .bind(s.rope_end_z)
+        // length
+        .bind(s.rope_length)
+        // flying rot
+        .bind(s.rope_flying_rotation_x)
+        .bind(s.rope_flying_rotation_y)
+        .bind(s.rope_flying_rotation_z)
+        // anchor rot
+        .bind(s.rope_anchor_rotation_x)
+        .bind(s.rope_anchor_rotation_y)
+        .bind(s.rope_anchor_rotation_z)
+        .bind(s.rope_anchor_rotation_w)
+        // flags & counters
+        .bind(s.antigrav)
+        .bind(s.likes)
+        .bind(deleted)
+        .bind(season_id)
+        .bind(s.uses)
+        .bind(deleted_at)
+        .bind(&deleted_by)
+        .bind(app_id)
+        .bind(s.pinned)
+        .bind(last_fetched_at)
+        .bind(&origin_server)
+        .bind(origin_id)
+        .bind(s.content_hash())
+        .bind(s.updated_at)
+        // dumps from before versions existed
+        .bind(s.version.max(1))
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RemoteImportSummary {
+    pub users: u64,
+    pub structures: u64,
+    // already imported before, or uploaded here in the first place
+    pub skipped: u64,
+    pub reactions: u64,
+    pub contributors: u64,
+    pub attributes: u64,
+}
+
+// Merges another server's dump into this database, e.g. when shards are
+// combined. Structures get new ids and keep their provenance in
+// origin_server/origin_id (a structure the other server got from elsewhere
+// keeps its first origin), so running it again only adds what is new. Their
+// likes and reactions come along and are credited to their owners; structures
+// tagged with this server's own SERVER_ID are skipped.
+pub struct RemoteImport {
+    origin: String,
+    own_server_id: Option<String>,
+    // remote structure id -> id here, for the reactions and contributors after them
+    ids: HashMap<i64, i64>,
+    pub summary: RemoteImportSummary,
+}
+
+impl RemoteImport {
+    pub fn new(origin: String, own_server_id: Option<String>) -> Self {
+        Self {
+            origin,
+            own_server_id,
+            ids: HashMap::new(),
+            summary: RemoteImportSummary::default(),
+        }
+    }
+
+    // One dump line, inside the caller's transaction; blank lines are skipped
+    pub async fn record(
+        &mut self,
+        conn: &mut sqlx::SqliteConnection,
+        line: &[u8],
+    ) -> anyhow::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        match serde_json::from_slice::<ExportRecord>(line)? {
+            // totals follow the imported structures, so only bans carry over
+            ExportRecord::User(u) => {
+                sqlx::query(
+                    r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send, shadow_banned)
+                       VALUES (?, ?, 0, 0, ?)
+                       ON CONFLICT(user_id) DO UPDATE
+                       SET upload_banned = MAX(upload_banned, excluded.upload_banned),
+                           shadow_banned = MAX(shadow_banned, excluded.shadow_banned)"#,
+                )
+                .bind(u.user_id)
+                .bind(u.upload_banned)
+                .bind(u.shadow_banned)
+                .execute(&mut *conn)
+                .await?;
+                self.summary.users += 1;
+            }
+            ExportRecord::Structure(mut record) => {
+                let Some(remote_id) = record.structure.id else {
+                    anyhow::bail!("structure without an id");
+                };
+                let origin_server = record
+                    .origin_server
+                    .take()
+                    .unwrap_or_else(|| self.origin.clone());
+                let origin_id = record.origin_id.unwrap_or(remote_id);
+                let known: Option<i64> = sqlx::query_scalar(
+                    "SELECT 1 FROM structures WHERE origin_server = ? AND origin_id = ?",
+                )
+                .bind(&origin_server)
+                .bind(origin_id)
+                .fetch_optional(&mut *conn)
+                .await?;
+                if known.is_some() || self.own_server_id.as_ref() == Some(&origin_server) {
+                    self.summary.skipped += 1;
+                    return Ok(());
+                }
+
+                let (owner, likes) = (record.structure.user_id, record.structure.likes);
+                record.structure.id = None;
+                record.origin_server = Some(origin_server);
+                record.origin_id = Some(origin_id);
+                let id = insert_structure(conn, *record).await?;
+                self.ids.insert(remote_id, id);
+                sqlx::query(
+                    r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send)
+                       VALUES (?, 0, ?, 0)
+                       ON CONFLICT(user_id) DO UPDATE
+                       SET likes_received = likes_received + excluded.likes_received"#,
+                )
+                .bind(owner)
+                .bind(likes)
+                .execute(&mut *conn)
+                .await?;
+                self.summary.structures += 1;
+            }
+            ExportRecord::Reaction(r) => {
+                if let Some(id) = self.ids.get(&r.structure_id) {
+                    sqlx::query(
+                        "INSERT INTO structure_reactions (structure_id, kind, count) VALUES (?, ?, ?);",
+                    )
+                    .bind(id)
+                    .bind(&r.kind)
+                    .bind(r.count)
+                    .execute(&mut *conn)
+                    .await?;
+                    self.summary.reactions += 1;
+                }
+            }
+            ExportRecord::Contributor(c) => {
+                if let Some(id) = self.ids.get(&c.structure_id) {
+                    sqlx::query(
+                        "INSERT INTO structure_contributors (structure_id, user_id) VALUES (?, ?);",
+                    )
+                    .bind(id)
+                    .bind(c.user_id)
+                    .execute(&mut *conn)
+                    .await?;
+                    self.summary.contributors += 1;
+                }
+            }
+            ExportRecord::Attribute(a) => {
+                if let Some(id) = self.ids.get(&a.structure_id) {
+                    sqlx::query(
+                        "INSERT INTO structure_attributes (structure_id, key, value) VALUES (?, ?, ?);",
+                    )
+                    .bind(id)
+                    .bind(&a.key)
+                    .bind(&a.value)
+                    .execute(&mut *conn)
+                    .await?;
+                    self.summary.attributes += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+// Integrity checks and repair.
+//
+// SQLite files on cheap VPS storage occasionally corrupt, which otherwise only
+// shows as vague 500s. `check` runs PRAGMA integrity_check (or quick_check)
+// and foreign_key_check. Damaged indexes are the common case and REINDEX
+// rebuilds them from the tables, so `repair` runs it when the check found
+// problems and checks again. Damage to the tables themselves needs a backup.
+// POST /admin/v1/integrity-check runs it on demand, STARTUP_INTEGRITY_CHECK
+// before the server listens.
+
+use anyhow::bail;
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+
+// integrity_check stops after this many problems
+const MAX_REPORTED_PROBLEMS: i64 = 100;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    // NULL for WITHOUT ROWID tables
+    pub rowid: Option<i64>,
+    pub parent: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    // empty when integrity_check answered "ok"
+    pub problems: Vec<String>,
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+    pub reindexed: bool,
+}
+
+pub async fn check(db: &SqlitePool, quick: bool) -> Result<IntegrityReport, sqlx::Error> {
+    let pragma = if quick {
+        "quick_check"
+    } else {
+        "integrity_check"
+    };
+    let mut problems: Vec<String> =
+        sqlx::query_scalar(&format!("PRAGMA {pragma}({MAX_REPORTED_PROBLEMS})"))
+            .fetch_all(db)
+            .await?;
+    if problems.len() == 1 && problems[0] == "ok" {
+        problems.clear();
+    }
+    let foreign_key_violations: Vec<ForeignKeyViolation> =
+        sqlx::query_as("SELECT \"table\", rowid, parent FROM pragma_foreign_key_check")
+            .fetch_all(db)
+            .await?;
+    Ok(IntegrityReport {
+        ok: problems.is_empty() && foreign_key_violations.is_empty(),
+        problems,
+        foreign_key_violations,
+        reindexed: false,
+    })
+}
+
+// Checks on `reader`; on problems rebuilds every index through `writer` and
+// reports the second check
+pub async fn repair(
+    reader: &SqlitePool,
+    writer: &SqlitePool,
+    quick: bool,
+) -> Result<IntegrityReport, sqlx::Error> {
+    let report = check(reader, quick).await?;
+    if report.problems.is_empty() {
+        return Ok(report);
+    }
+    tracing::warn!(
+        "integrity_check problems={} reindexing",
+        report.problems.len()
+    );
+    sqlx::query("REINDEX").execute(writer).await?;
+    let mut report = check(reader, quick).await?;
+    report.reindexed = true;
+    Ok(report)
+}
+
+// STARTUP_INTEGRITY_CHECK: a quick check with repair; stops the start when
+// REINDEX could not fix it. Orphaned rows only warn, the handlers cope with them.
+pub async fn repair_on_start(db: &SqlitePool) -> anyhow::Result<()> {
+    let report = repair(db, db, true).await?;
+    if !report.problems.is_empty() {
+        bail!(
+            "the database at DATABASE_URL is damaged and REINDEX did not fix it; restore a backup (or set STARTUP_INTEGRITY_CHECK=false to start anyway):\n- {}",
+            report.problems.join("\n- ")
+        );
+    }
+    if !report.foreign_key_violations.is_empty() {
+        tracing::warn!(
+            "integrity_check foreign_key_violations={}",
+            report.foreign_key_violations.len()
+        );
+    }
+    tracing::info!("integrity_check passed reindexed={}", report.reindexed);
+    Ok(())
+}
@@ -0,0 +1,68 @@
+// Database access.
+//
+// `queries` holds the statements the handlers, background tasks and admin
+// tooling share; `schema` creates and migrates the tables, `integrity` checks
+// the file for corruption, `sizes` measures it and `checkpoint` keeps the WAL
+// from growing. The server keeps one writer connection and a pool of read-only connections beside it.
+
+pub mod checkpoint;
+pub mod dump;
+pub mod integrity;
+pub mod queries;
+pub mod schema;
+pub mod sizes;
+
+use sqlx::{
+    SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
+use std::{str::FromStr, time::Duration};
+
+use crate::config::Config;
+
+// WAL with relaxed fsync, plus the cache/mmap/checkpoint sizes from the config.
+// The pragmas are per connection, so every pooled connection gets them.
+pub fn sqlite_connect_options(config: &Config) -> anyhow::Result<SqliteConnectOptions> {
+    Ok(SqliteConnectOptions::from_str(&config.database_url)?
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Normal)
+        .busy_timeout(Duration::from_secs(5))
+        .pragma("cache_size", config.sqlite_cache_size.to_string())
+        .pragma("mmap_size", config.sqlite_mmap_size.to_string())
+        .pragma(
+            "wal_autocheckpoint",
+            config.sqlite_wal_autocheckpoint.to_string(),
+        ))
+}
+
+pub fn is_in_memory(config: &Config) -> bool {
+    config.database_url.contains(":memory:") || config.database_url.contains("mode=memory")
+}
+
+// The writer, with the schema created and migrated.
+// SQLite allows one writer at a time anyway; a single connection makes
+// writers queue in the pool instead of spinning on busy_timeout
+pub async fn open(config: &Config) -> anyhow::Result<SqlitePool> {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(Duration::from_secs(30))
+        .connect_with(sqlite_connect_options(config)?)
+        .await?;
+    schema::init(&db, config).await?;
+    Ok(db)
+}
+
+// Readers for the fetch and stats endpoints. Opened after migrations so they
+// never race the schema setup. An in-memory database only exists on the
+// connection that created it, so there the writer serves reads as well.
+pub async fn open_read_pool(config: &Config, writer: &SqlitePool) -> anyhow::Result<SqlitePool> {
+    if is_in_memory(config) {
+        return Ok(writer.clone());
+    }
+    let options = sqlite_connect_options(config)?;
+    Ok(SqlitePoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .idle_timeout(Duration::from_secs(30))
+        .connect_with(options.pragma("query_only", "ON"))
+        .await?)
+}
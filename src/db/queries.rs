@@ -0,0 +1,2018 @@
+// Queries shared by the handlers, the background tasks and the admin tooling.
+//
+// Functions here take a pool or an AppState and return plain rows or
+// AppError, so they can be called from anywhere that has a database.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+use crate::{
+    MILLIS_IN_DAY, SERVER_VERSION,
+    config::Config,
+    db::schema::table_columns,
+    error::{ApiError, AppError},
+    model::{CreatorStats, NewStructure, Sphere, Structure},
+    plausibility::{PlausibilityMode, burst_problem, placement_problem},
+    samples,
+    state::AppState,
+};
+
+pub const MAX_USAGE_WEIGHT: i64 = 10;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalStatsResponse {
+    total_unique_players_all_time: i64,
+    pub total_structures_uploaded_all_time: i64,
+    pub total_likes_given_all_time: i64,
+    pub total_unique_players_last_24h: i64,
+    pub total_structures_uploaded_last_24h: i64,
+    server_version: String,
+}
+
+// Attaches the reaction breakdown to structures loaded without it.
+pub async fn load_reactions(
+    db: &SqlitePool,
+    structures: &mut [Structure],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<i64> = structures.iter().filter_map(|s| s.id).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let query = format!(
+        "SELECT structure_id, kind, count FROM structure_reactions WHERE structure_id IN ({})",
+        vec!["?"; ids.len()].join(",")
+    );
+    let mut query = sqlx::query_as::<_, (i64, String, i64)>(&query);
+    for id in &ids {
+        query = query.bind(id);
+    }
+
+    for (structure_id, kind, count) in query.fetch_all(db).await? {
+        if let Some(structure) = structures.iter_mut().find(|s| s.id == Some(structure_id)) {
+            structure.reactions.insert(kind, count);
+        }
+    }
+    Ok(())
+}
+
+// Rereads the like counts and reactions of structures loaded earlier.
+pub async fn refresh_likes(
+    db: &SqlitePool,
+    structures: &mut [Structure],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<i64> = structures.iter().filter_map(|s| s.id).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let likes: HashMap<i64, i32> = sqlx::query_as(
+        "SELECT id, likes FROM structures WHERE id IN (SELECT value FROM json_each(?))",
+    )
+    .bind(serde_json::to_string(&ids).map_err(|e| sqlx::Error::Encode(Box::new(e)))?)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect();
+    for structure in structures.iter_mut() {
+        if let Some(current) = structure.id.and_then(|id| likes.get(&id)) {
+            structure.likes = *current;
+        }
+        structure.reactions.clear();
+    }
+    load_reactions(db, structures).await
+}
+
+// Attaches the co-op contributors to structures loaded without them.
+pub async fn load_contributors(
+    db: &SqlitePool,
+    structures: &mut [Structure],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<i64> = structures.iter().filter_map(|s| s.id).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let query = format!(
+        "SELECT structure_id, user_id FROM structure_contributors WHERE structure_id IN ({}) ORDER BY user_id",
+        vec!["?"; ids.len()].join(",")
+    );
+    let mut query = sqlx::query_as::<_, (i64, i64)>(&query);
+    for id in &ids {
+        query = query.bind(id);
+    }
+
+    for (structure_id, user_id) in query.fetch_all(db).await? {
+        if let Some(structure) = structures.iter_mut().find(|s| s.id == Some(structure_id)) {
+            structure.contributors.push(user_id);
+        }
+    }
+    Ok(())
+}
+
+// Attaches the PREFAB_ATTRIBUTES values to structures loaded without them.
+pub async fn load_attributes(
+    db: &SqlitePool,
+    structures: &mut [Structure],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<i64> = structures.iter().filter_map(|s| s.id).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let rows: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT structure_id, key, value FROM structure_attributes WHERE structure_id IN (SELECT value FROM json_each(?))",
+    )
+    .bind(serde_json::to_string(&ids).map_err(|e| sqlx::Error::Encode(Box::new(e)))?)
+    .fetch_all(db)
+    .await?;
+    for (structure_id, key, value) in rows {
+        let Ok(value) = serde_json::from_str(&value) else {
+            continue;
+        };
+        if let Some(structure) = structures.iter_mut().find(|s| s.id == Some(structure_id)) {
+            structure.attributes.insert(key, value);
+        }
+    }
+    Ok(())
+}
+
+// Attaches each builder's likes_received and live structure count
+// (creator_stats=true on random fetches).
+pub async fn load_creator_stats(
+    db: &SqlitePool,
+    structures: &mut [Structure],
+) -> Result<(), sqlx::Error> {
+    let mut user_ids: Vec<i64> = structures.iter().map(|s| s.user_id).collect();
+    user_ids.sort_unstable();
+    user_ids.dedup();
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+        r#"SELECT u.value,
+                  COALESCE((SELECT likes_received FROM users WHERE user_id = u.value), 0),
+                  (SELECT COUNT(*) FROM structures WHERE user_id = u.value AND deleted = 0)
+           FROM json_each(?) AS u"#,
+    )
+    .bind(serde_json::to_string(&user_ids).map_err(|e| sqlx::Error::Encode(Box::new(e)))?)
+    .fetch_all(db)
+    .await?;
+    let stats: HashMap<i64, CreatorStats> = rows
+        .into_iter()
+        .map(|(user_id, likes_received, structures)| {
+            (
+                user_id,
+                CreatorStats {
+                    likes_received,
+                    structures,
+                },
+            )
+        })
+        .collect();
+    for structure in structures {
+        structure.creator = stats.get(&structure.user_id).copied();
+    }
+    Ok(())
+}
+
+// Adds likes to the likes_received of every contributor of the liked
+// structures; `deltas` pairs structure ids with the likes they got.
+pub async fn credit_contributors(
+    conn: &mut sqlx::SqliteConnection,
+    deltas: &[(i64, i64)],
+) -> Result<(), sqlx::Error> {
+    let deltas = serde_json::to_string(deltas).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO users (user_id, upload_banned, likes_received, likes_send)
+           SELECT DISTINCT c.user_id, 0, 0, 0
+           FROM json_each(?) AS d
+           JOIN structure_contributors c ON c.structure_id = json_extract(d.value, '$[0]');"#,
+    )
+    .bind(&deltas)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"UPDATE users SET likes_received = likes_received + t.total
+           FROM (
+               SELECT c.user_id, SUM(json_extract(d.value, '$[1]')) AS total
+               FROM json_each(?) AS d
+               JOIN structure_contributors c ON c.structure_id = json_extract(d.value, '$[0]')
+               GROUP BY c.user_id
+           ) AS t
+           WHERE users.user_id = t.user_id"#,
+    )
+    .bind(&deltas)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+// Which live structures a proximity count considers, besides the sphere itself
+struct NearFilter<'a> {
+    scene: &'a str,
+    season: i64,
+    segment: Option<i32>,
+    user_id: Option<i64>,
+    prefab: Option<&'a str>,
+    // the structure being edited, which shouldn't count against itself
+    exclude_id: Option<i64>,
+}
+
+async fn count_near(
+    conn: &mut sqlx::SqliteConnection,
+    sphere: Sphere,
+    filter: NearFilter<'_>,
+) -> Result<i64, sqlx::Error> {
+    let mut conditions = vec![
+        "scene = ?",
+        "season_id = ?",
+        "deleted = 0",
+        Sphere::CONDITION,
+    ];
+    if filter.segment.is_some() {
+        conditions.push("segment = ?");
+    }
+    if filter.user_id.is_some() {
+        conditions.push("user_id = ?");
+    }
+    if filter.prefab.is_some() {
+        conditions.push("prefab = ?");
+    }
+    if filter.exclude_id.is_some() {
+        conditions.push("id != ?");
+    }
+    let sql = format!(
+        "SELECT COUNT(*) FROM structures WHERE {}",
+        conditions.join(" AND ")
+    );
+
+    let mut query = sqlx::query_scalar::<_, i64>(&sql)
+        .bind(filter.scene)
+        .bind(filter.season);
+    for value in sphere.binds() {
+        query = query.bind(value);
+    }
+    if let Some(segment) = filter.segment {
+        query = query.bind(segment);
+    }
+    if let Some(user_id) = filter.user_id {
+        query = query.bind(user_id);
+    }
+    if let Some(prefab) = filter.prefab {
+        query = query.bind(prefab);
+    }
+    if let Some(id) = filter.exclude_id {
+        query = query.bind(id);
+    }
+    query.fetch_one(conn).await
+}
+
+// Max per-axis position difference for two uploads to count as the same build
+const DUPLICATE_POSITION_EPSILON: f32 = 0.01;
+
+async fn find_duplicate(
+    conn: &mut sqlx::SqliteConnection,
+    steamid: u64,
+    season: i64,
+    s: &NewStructure,
+    window: Duration,
+) -> Result<Option<Structure>, sqlx::Error> {
+    sqlx::query_as::<_, Structure>(
+        r#"
+        SELECT * FROM structures
+        WHERE user_id = ? AND map_id = ? AND scene = ? AND prefab = ? AND season_id = ? AND deleted = 0
+          AND created_at >= strftime('%s','now')*1000 - ?
+          AND ABS(pos_x - ?) <= ? AND ABS(pos_y - ?) <= ? AND ABS(pos_z - ?) <= ?
+        ORDER BY id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(steamid as i64)
+    .bind(s.map_id)
+    .bind(&s.scene)
+    .bind(&s.prefab)
+    .bind(season)
+    .bind(window.as_millis() as i64)
+    .bind(s.pos_x)
+    .bind(DUPLICATE_POSITION_EPSILON)
+    .bind(s.pos_y)
+    .bind(DUPLICATE_POSITION_EPSILON)
+    .bind(s.pos_z)
+    .bind(DUPLICATE_POSITION_EPSILON)
+    .fetch_optional(conn)
+    .await
+}
+
+pub enum Stored {
+    New(Structure),
+    Duplicate(Structure),
+}
+
+pub enum StoreError {
+    Db(&'static str, sqlx::Error), // the failing step, for the log line
+    TooCrowded,
+    TooClose,
+    Banned,
+    Implausible(String),
+    DailyQuota,
+}
+
+impl StoreError {
+    pub fn app_error(self, s: &NewStructure, config: &Config) -> AppError {
+        match self {
+            StoreError::Db(_, e) => AppError::Db(e),
+            StoreError::TooCrowded => ApiError::new(
+                StatusCode::CONFLICT,
+                "There are already too many structures at this spot.",
+            )
+            .with_code("too_crowded")
+            .into(),
+            StoreError::TooClose => ApiError::new(
+                StatusCode::CONFLICT,
+                format!(
+                    "You already placed a {} within {} meters of this spot.",
+                    s.prefab, config.min_own_structure_distance
+                ),
+            )
+            .with_code("too_close")
+            .into(),
+            StoreError::Banned => ApiError::new(
+                StatusCode::FORBIDDEN,
+                "You are not allowed to upload structures.",
+            )
+            .with_code("upload_banned")
+            .into(),
+            StoreError::Implausible(reason) => ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("This structure could not have been placed in the game: {reason}."),
+            )
+            .with_code("implausible")
+            .into(),
+            StoreError::DailyQuota => {
+                // quotas count per UTC day
+                let now_ms = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as i64)
+                    .unwrap_or_default();
+                let until_reset = MILLIS_IN_DAY - now_ms.rem_euclid(MILLIS_IN_DAY);
+                ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!(
+                        "You reached today's upload quota of {} structures.",
+                        config.daily_upload_quota
+                    ),
+                )
+                .with_code("daily_quota_exceeded")
+                .with_retry_after(Duration::from_millis(until_reset as u64))
+                .into()
+            }
+        }
+    }
+}
+
+// The density and own-distance checks for a structure at `s`'s spot; an edit
+// passes its own id so the row it moves doesn't count against itself
+pub async fn check_spacing(
+    conn: &mut sqlx::SqliteConnection,
+    config: &Config,
+    steamid: u64,
+    s: &NewStructure,
+    exclude_id: Option<i64>,
+) -> Result<(), StoreError> {
+    // Keep popular spots from turning into a pile of identical ladders
+    if config.density_max_structures > 0 {
+        let sphere = Sphere {
+            x: s.pos_x,
+            y: s.pos_y,
+            z: s.pos_z,
+            radius: config.density_radius,
+        };
+        let filter = NearFilter {
+            scene: &s.scene,
+            season: config.current_season,
+            segment: Some(s.segment),
+            user_id: None,
+            prefab: None,
+            exclude_id,
+        };
+        let nearby = count_near(&mut *conn, sphere, filter)
+            .await
+            .map_err(|e| StoreError::Db("density_check_failed", e))?;
+        if nearby >= config.density_max_structures {
+            return Err(StoreError::TooCrowded);
+        }
+    }
+
+    // Spam guard: one of each prefab per player within MIN_OWN_STRUCTURE_DISTANCE
+    if config.min_own_structure_distance > 0.0 {
+        let sphere = Sphere {
+            x: s.pos_x,
+            y: s.pos_y,
+            z: s.pos_z,
+            radius: config.min_own_structure_distance,
+        };
+        let filter = NearFilter {
+            scene: &s.scene,
+            season: config.current_season,
+            segment: None,
+            user_id: Some(steamid as i64),
+            prefab: Some(&s.prefab),
+            exclude_id,
+        };
+        let own_nearby = count_near(&mut *conn, sphere, filter)
+            .await
+            .map_err(|e| StoreError::Db("own_distance_check_failed", e))?;
+        if own_nearby > 0 {
+            return Err(StoreError::TooClose);
+        }
+    }
+    Ok(())
+}
+
+pub const NEWCOMER_REVIEW_REASON: &str = "first uploads of a new player";
+
+// The database side of an upload, run inside the caller's transaction: the
+// duplicate, density and own-distance checks, the insert and the per-user
+// pruning. The caller commits only for Stored::New.
+pub async fn store_structure(
+    conn: &mut sqlx::SqliteConnection,
+    config: &Config,
+    steamid: u64,
+    appid: u64,
+    s: &NewStructure,
+) -> Result<Stored, StoreError> {
+    let season = config.current_season;
+
+    // 0. Ensure the posting user exists in users table
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO users (user_id, upload_banned, likes_received, likes_send)
+           VALUES (?, 0, 0, 0);"#,
+    )
+    .bind(steamid as i64)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| StoreError::Db("ensure_user_failed", e))?;
+
+    let banned: bool = sqlx::query_scalar("SELECT upload_banned FROM users WHERE user_id = ?")
+        .bind(steamid as i64)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("ban_check_failed", e))?;
+    if banned {
+        return Err(StoreError::Banned);
+    }
+
+    if !config.duplicate_window.is_zero()
+        && let Some(existing) =
+            find_duplicate(&mut *conn, steamid, season, s, config.duplicate_window)
+                .await
+                .map_err(|e| StoreError::Db("duplicate_check_failed", e))?
+    {
+        return Ok(Stored::Duplicate(existing));
+    }
+
+    // Uploads so far today, against DAILY_UPLOAD_QUOTA; earlier days are dropped
+    let uploads_today = if config.daily_upload_quota > 0 {
+        sqlx::query("DELETE FROM user_daily WHERE user_id = ? AND day < date('now')")
+            .bind(steamid as i64)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| StoreError::Db("daily_quota_check_failed", e))?;
+        let uploads: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(uploads), 0) FROM user_daily WHERE user_id = ? AND day = date('now')",
+        )
+        .bind(steamid as i64)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("daily_quota_check_failed", e))?;
+        if uploads >= config.daily_upload_quota {
+            return Err(StoreError::DailyQuota);
+        }
+        Some(uploads)
+    } else {
+        None
+    };
+
+    check_spacing(&mut *conn, config, steamid, s, None).await?;
+
+    let review_reason = match config.plausibility_checks {
+        PlausibilityMode::Off => None,
+        mode => {
+            let problem = match placement_problem(s, config) {
+                Some(problem) => Some(problem),
+                None => burst_problem(&mut *conn, config, steamid, s.segment)
+                    .await
+                    .map_err(|e| StoreError::Db("burst_check_failed", e))?,
+            };
+            match problem {
+                Some(problem) if mode == PlausibilityMode::Reject => {
+                    return Err(StoreError::Implausible(problem));
+                }
+                problem => problem,
+            }
+        }
+    };
+    // (reason, auto-approval time); implausible uploads wait for a moderator
+    let mut review = review_reason.map(|reason| (reason, None));
+
+    // New players' uploads wait until NEWCOMER_REVIEW_COUNT of theirs went live
+    if review.is_none() && config.newcomer_review_count > 0 {
+        let (approved,): (i64,) = sqlx::query_as(
+            r#"SELECT COUNT(*) FROM (
+                   SELECT 1 FROM structures
+                   WHERE user_id = ? AND NOT (deleted = 1 AND deleted_by IN ('review', 'admin'))
+                   LIMIT ?
+               )"#,
+        )
+        .bind(steamid as i64)
+        .bind(config.newcomer_review_count)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("newcomer_check_failed", e))?;
+        if approved < config.newcomer_review_count {
+            let auto_approve_at = (!config.newcomer_auto_approve.is_zero()).then(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| (d + config.newcomer_auto_approve).as_millis() as i64)
+                    .unwrap_or_default()
+            });
+            review = Some((NEWCOMER_REVIEW_REASON.to_string(), auto_approve_at));
+        }
+    }
+
+    // 1. Insert the new structure.
+    let mut rec: Structure = sqlx::query_as::<_, Structure>(Structure::insert_query())
+        .bind(steamid as i64)
+        .bind(&s.username)
+        .bind(s.map_id)
+        .bind(&s.scene)
+        .bind(s.segment)
+        .bind(&s.prefab)
+        // position
+        .bind(s.pos_x)
+        .bind(s.pos_y)
+        .bind(s.pos_z)
+        // rotation
+        .bind(s.rot_x)
+        .bind(s.rot_y)
+        .bind(s.rot_z)
+        .bind(s.rot_w)
+        // rope start
+        .bind(s.rope_start_x)
+        .bind(s.rope_start_y)
+        .bind(s.rope_start_z)
+        // rope end
+        .bind(s.rope_end_x)
+        .bind(s.rope_end_y)
+        .bind(s.rope_end_z)
+        // length
+        .bind(s.rope_length)
+        // flying rot
+        .bind(s.rope_flying_rotation_x)
+        .bind(s.rope_flying_rotation_y)
+        .bind(s.rope_flying_rotation_z)
+        // anchor rot
+        .bind(s.rope_anchor_rotation_x)
+        .bind(s.rope_anchor_rotation_y)
+        .bind(s.rope_anchor_rotation_z)
+        .bind(s.rope_anchor_rotation_w)
+        // antigrav
+        .bind(s.antigrav)
+        .bind(s.content_hash())
+        .bind(season)
+        .bind(appid as i64)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("insert_structure_failed", e))?;
+
+    // The uploader is already credited through user_id
+    for &contributor in &s.contributors {
+        if contributor == steamid || rec.contributors.contains(&(contributor as i64)) {
+            continue;
+        }
+        sqlx::query("INSERT INTO structure_contributors (structure_id, user_id) VALUES (?, ?)")
+            .bind(rec.id)
+            .bind(contributor as i64)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| StoreError::Db("insert_contributors_failed", e))?;
+        rec.contributors.push(contributor as i64);
+    }
+    rec.contributors.sort_unstable();
+
+    for (key, value) in &s.attributes {
+        sqlx::query("INSERT INTO structure_attributes (structure_id, key, value) VALUES (?, ?, ?)")
+            .bind(rec.id)
+            .bind(key)
+            .bind(value.to_string())
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| StoreError::Db("insert_attributes_failed", e))?;
+    }
+    rec.attributes = s.attributes.clone();
+
+    if let Some(uploads) = uploads_today {
+        sqlx::query(
+            r#"INSERT INTO user_daily (user_id, day, uploads) VALUES (?, date('now'), 1)
+               ON CONFLICT(user_id, day) DO UPDATE SET uploads = uploads + 1"#,
+        )
+        .bind(steamid as i64)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("daily_quota_update_failed", e))?;
+        rec.remaining_uploads_today = Some(config.daily_upload_quota - uploads - 1);
+    }
+
+    // Held back for a moderator: out of every fetch and the per-scene cap
+    if let Some((reason, auto_approve_at)) = review {
+        tracing::info!(
+            "review held structure_id={} user_id={} reason={}",
+            rec.id.unwrap_or_default(),
+            steamid,
+            reason
+        );
+        sqlx::query(
+            r#"UPDATE structures
+               SET deleted = 1, deleted_at = strftime('%s','now')*1000, deleted_by = 'review',
+                   review_reason = ?, review_auto_approve_at = ?
+               WHERE id = ?"#,
+        )
+        .bind(&reason)
+        .bind(auto_approve_at)
+        .bind(rec.id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| StoreError::Db("flag_structure_failed", e))?;
+        rec.under_review = Some(true);
+        return Ok(Stored::New(rec));
+    }
+
+    // 2. Count how many structures this user already has in this scene (this season).
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM structures WHERE user_id = ? AND scene = ? AND season_id = ? AND deleted = 0",
+    )
+            .bind(steamid as i64)
+            .bind(&s.scene)
+            .bind(season)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| StoreError::Db("count_structures_failed", e))?;
+
+    // 3. If over the limit, delete the oldest one.
+    let limit = config.max_user_structs_for_map(s.map_id);
+    if count > limit {
+        // never the upload itself, which least_liked would otherwise pick first,
+        // nor a pinned one (MAX_PINNED_PER_SCENE stays below the cap, so one is left)
+        let policy = s.prune_policy.unwrap_or(config.prune_policy);
+        let delete_query = format!(
+            r#"
+            DELETE FROM structures
+            WHERE id = (
+                SELECT id FROM structures
+                WHERE user_id = ? AND scene = ? AND season_id = ? AND deleted = 0 AND id != ?
+                  AND pinned = 0
+                ORDER BY {}
+                LIMIT 1
+            )
+            RETURNING id;
+        "#,
+            policy.order_by()
+        );
+
+        rec.pruned_structure_id = sqlx::query_scalar(&delete_query)
+            .bind(steamid as i64)
+            .bind(&s.scene)
+            .bind(season)
+            .bind(rec.id)
+            .fetch_optional(&mut *conn)
+            .await
+            .ok()
+            .flatten();
+    }
+    // Tells the client when old builds start getting recycled
+    rec.remaining_slots = Some((limit - count).max(0));
+
+    Ok(Stored::New(rec))
+}
+
+const SCENE_SIZE_TTL: Duration = Duration::from_secs(60);
+const RANDOM_PROBE_ATTEMPTS_PER_ROW: usize = 4;
+
+// Picks rows with the strategy the config selects for this scene
+pub async fn fetch_random(
+    state: &AppState,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+) -> Result<Vec<Structure>, sqlx::Error> {
+    // only the SQL ranking can be replayed from a seed
+    if filter.seed.is_some() {
+        query_random(&state.read_db, config, filter).await
+    } else if !config.random_sample_refresh.is_zero() {
+        sample_random(state, config, filter).await
+    } else if config.random_probe_min_rows > 0
+        && scene_size(state, filter).await? >= config.random_probe_min_rows
+    {
+        probe_random(&state.read_db, config, filter).await
+    } else {
+        query_random(&state.read_db, config, filter).await
+    }
+}
+
+// Pinned structures never age out of random or nearby fetches
+pub const PINNED_OR_CREATED_AFTER: &str = "(pinned = 1 OR created_at >= ?)";
+
+// Shadow-banned players' structures only reach the player themselves; binds
+// the viewer, or 0 when the requester isn't banned
+pub const NOT_SHADOW_BANNED: &str =
+    "(user_id = ? OR user_id NOT IN (SELECT user_id FROM users WHERE shadow_banned = 1))";
+
+// What a random fetch may return, shared by every random strategy
+pub struct RandomFilter<'a> {
+    pub scene: &'a str,
+    pub map_id: Option<i32>,
+    pub season: i64,
+    // requester's app when SEPARATE_APPIDS is on
+    pub app_id: Option<i64>,
+    pub exclude_prefabs: &'a [String],
+    pub created_after: Option<i64>,
+    pub limit: i64,
+    pub curated_limit: i64,
+    // same seed, same rows (as long as the scene doesn't change)
+    pub seed: Option<u32>,
+    // a shadow-banned requester, who still gets their own structures
+    pub viewer: Option<i64>,
+    // PREFAB_FETCH_QUOTAS: at most this many of a prefab per fetch
+    pub prefab_quotas: &'a BTreeMap<String, i64>,
+    // PREFAB_MAX_SHARE_PERCENT: random picks of a prefab past this many only
+    // fill slots no other prefab can
+    pub prefab_share: Option<i64>,
+    // LIKES_DECAY_DAYS: curation ranks by likes worn down with age
+    pub likes_decay: Option<LikesDecay>,
+}
+
+// How much a structure's likes count for curation as it gets older:
+// likes / (1 + age / half_life), so half after one half-life and a third
+// after two. The stored like counter is left as it is.
+#[derive(Debug, Clone, Copy)]
+pub struct LikesDecay {
+    pub now: i64,
+    pub half_life_millis: i64,
+}
+
+impl LikesDecay {
+    // Sort key for the curated share, binds `now` and `half_life_millis`
+    pub const SQL: &'static str = "likes / (1.0 + MAX(0, ? - created_at) / CAST(? AS REAL))";
+
+    pub fn weight(&self, likes: i32, created_at: i64) -> f64 {
+        let age = self.now.saturating_sub(created_at).max(0) as f64;
+        likes as f64 / (1.0 + age / self.half_life_millis.max(1) as f64)
+    }
+}
+
+// ORDER BY of the curated share: decayed likes when configured, raw likes otherwise
+fn curated_order(filter: &RandomFilter<'_>) -> String {
+    match filter.likes_decay {
+        Some(_) => format!("{} DESC, id", LikesDecay::SQL),
+        None => "likes DESC, id".to_string(),
+    }
+}
+
+// Counts a fetch's picks per prefab against its PREFAB_FETCH_QUOTAS
+pub struct QuotaCounter<'a> {
+    quotas: &'a BTreeMap<String, i64>,
+    taken: HashMap<String, i64>,
+}
+
+impl<'a> QuotaCounter<'a> {
+    pub fn new(quotas: &'a BTreeMap<String, i64>) -> Self {
+        Self {
+            quotas,
+            taken: HashMap::new(),
+        }
+    }
+
+    // Counts one more `prefab` if its quota has room
+    pub fn take(&mut self, prefab: &str) -> bool {
+        let Some(quota) = self.quotas.get(prefab) else {
+            return true;
+        };
+        let taken = self.taken.entry(prefab.to_string()).or_default();
+        if *taken >= *quota {
+            return false;
+        }
+        *taken += 1;
+        true
+    }
+}
+
+// SQL for a pseudo-random 32-bit key of `id` under `seed`: a multiply and
+// xor-shift mix, with every product kept below 2^63. SQLite has no xor, so
+// a ^ b is spelled (a | b) - (a & b).
+fn seeded_key(seed: u32) -> String {
+    let mix = |x: &str, shift: u32, multiplier: u64| {
+        let xored = format!("(({x}) | (({x}) >> {shift})) - (({x}) & (({x}) >> {shift}))");
+        format!("((({xored}) * {multiplier}) % 4294967296)")
+    };
+    let start = format!("(((id % 2147483648) * 2654435761 + {seed}) % 4294967296)");
+    mix(&mix(&start, 16, 1274126177), 13, 1911520717)
+}
+
+const RANDOM_COLUMNS: &str = r#"
+            id, created_at, user_id, username, map_id, scene, segment, prefab,
+            pos_x, pos_y, pos_z, rot_x, rot_y, rot_z, rot_w,
+            rope_start_x, rope_start_y, rope_start_z,
+            rope_end_x, rope_end_y, rope_end_z,
+            rope_length,
+            rope_flying_rotation_x, rope_flying_rotation_y, rope_flying_rotation_z,
+            rope_anchor_rotation_x, rope_anchor_rotation_y, rope_anchor_rotation_z, rope_anchor_rotation_w,
+            antigrav,
+            likes, uses, pinned, updated_at, version, content_hash
+    "#;
+
+async fn query_random(
+    db: &SqlitePool,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+) -> Result<Vec<Structure>, sqlx::Error> {
+    let random = match filter.seed {
+        Some(seed) => seeded_key(seed),
+        None => "RANDOM()".to_string(),
+    };
+    // the prefab share sorts a prefab's picks past it behind everything else
+    let (share_rank, over_share) = match filter.prefab_share {
+        Some(_) => (
+            format!(
+                ", ROW_NUMBER() OVER (PARTITION BY prefab ORDER BY {random}, id) as share_rank"
+            ),
+            "share_rank > ?, ",
+        ),
+        None => (String::new(), ""),
+    };
+    let ranked = format!(
+        r#"
+        RankedStructures AS (
+            SELECT
+                *,
+                ROW_NUMBER() OVER (PARTITION BY user_id, segment ORDER BY {random}, id) as diversity_rank{share_rank}
+            FROM Filtered
+    "#
+    );
+
+    // Random sort key divided by a usage weight (1..=MAX_USAGE_WEIGHT), so structures
+    // players actually use tend to come first without crowding out new ones.
+    let usage_weighted_random = format!(
+        "(ABS({random} % 1000000) / (1.0 + MIN(uses, {})))",
+        MAX_USAGE_WEIGHT - 1
+    );
+
+    let final_select = if filter.curated_limit > 0 {
+        // curated rows first, random ones fill the rest (including slots curation left empty)
+        let order = curated_order(filter);
+        format!(
+            r#"
+            Curated AS (
+                SELECT * FROM Filtered WHERE likes > 0 ORDER BY {order} LIMIT ?
+            ),
+            {ranked} WHERE id NOT IN (SELECT id FROM Curated)
+            )
+            SELECT {RANDOM_COLUMNS} FROM Curated
+            UNION ALL
+            SELECT {RANDOM_COLUMNS} FROM (
+                SELECT * FROM RankedStructures
+                ORDER BY {over_share}diversity_rank, {usage_weighted_random}, id
+                LIMIT ? - (SELECT COUNT(*) FROM Curated)
+            );
+            "#
+        )
+    } else {
+        format!(
+            r#"
+            {ranked}
+            )
+            SELECT {RANDOM_COLUMNS}
+            FROM RankedStructures
+            ORDER BY {over_share}diversity_rank, {usage_weighted_random}, id
+            LIMIT ?;
+            "#
+        )
+    };
+
+    let mut where_conditions = vec![
+        "scene = ?".to_string(),
+        "deleted = 0".to_string(),
+        "season_id = ?".to_string(),
+    ];
+
+    if filter.map_id.is_some() {
+        where_conditions.push("map_id = ?".to_string());
+    }
+    if filter.app_id.is_some() {
+        where_conditions.push("COALESCE(app_id, ?) = ?".to_string());
+    }
+
+    if !filter.exclude_prefabs.is_empty() {
+        let placeholders = format!("({})", vec!["?"; filter.exclude_prefabs.len()].join(","));
+        where_conditions.push(format!("prefab NOT IN {}", placeholders));
+    }
+
+    if filter.created_after.is_some() {
+        where_conditions.push(PINNED_OR_CREATED_AFTER.to_string());
+    }
+    where_conditions.push(NOT_SHADOW_BANNED.to_string());
+
+    // quotas keep a random few of each listed prefab before anything is ranked;
+    // materialized so curation and the random part see the same few
+    let filtered = if filter.prefab_quotas.is_empty() {
+        format!(
+            "Filtered AS (SELECT * FROM structures WHERE {})",
+            where_conditions.join(" AND ")
+        )
+    } else {
+        format!(
+            r#"Filtered AS MATERIALIZED (SELECT * FROM (
+                SELECT *, ROW_NUMBER() OVER (PARTITION BY prefab ORDER BY {random}, id) AS prefab_rank
+                FROM structures WHERE {}
+            )
+            WHERE prefab_rank <= COALESCE((SELECT value FROM json_each(?) WHERE key = prefab), prefab_rank))"#,
+            where_conditions.join(" AND ")
+        )
+    };
+    let full_query = format!("WITH {filtered}, {final_select}");
+
+    let mut query = sqlx::query_as::<_, Structure>(&full_query)
+        .bind(filter.scene)
+        .bind(filter.season);
+    if let Some(id) = filter.map_id {
+        query = query.bind(id);
+    }
+    if let Some(app_id) = filter.app_id {
+        query = query.bind(config.primary_appid() as i64).bind(app_id);
+    }
+    for prefab_name in filter.exclude_prefabs {
+        query = query.bind(prefab_name);
+    }
+    if let Some(created_after) = filter.created_after {
+        query = query.bind(created_after);
+    }
+    query = query.bind(filter.viewer.unwrap_or(0));
+    if !filter.prefab_quotas.is_empty() {
+        query = query.bind(serde_json::json!(filter.prefab_quotas).to_string());
+    }
+    if filter.curated_limit > 0 {
+        if let Some(decay) = filter.likes_decay {
+            query = query.bind(decay.now).bind(decay.half_life_millis);
+        }
+        query = query.bind(filter.curated_limit);
+    }
+    if let Some(share) = filter.prefab_share {
+        query = query.bind(share);
+    }
+    query = query.bind(filter.limit);
+
+    query.fetch_all(db).await
+}
+
+// Random fetch through the sample cache: ids are picked in memory, then only
+// those rows are loaded, in the order they were picked.
+async fn sample_random(
+    state: &AppState,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+) -> Result<Vec<Structure>, sqlx::Error> {
+    let candidates = state
+        .random_samples
+        .candidates(
+            &state.read_db,
+            filter.scene,
+            filter.map_id,
+            filter.season,
+            config.primary_appid() as i64,
+        )
+        .await?;
+    let ids = samples::sample(&candidates, filter);
+    // rows deleted since the last refresh simply drop out
+    load_live_structures(&state.read_db, &ids).await
+}
+
+// The given structures in the given order, minus any deleted since
+pub async fn load_live_structures(
+    db: &SqlitePool,
+    ids: &[i64],
+) -> Result<Vec<Structure>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = format!(
+        "SELECT {RANDOM_COLUMNS} FROM structures WHERE deleted = 0 AND id IN ({})",
+        vec!["?"; ids.len()].join(",")
+    );
+    let mut query = sqlx::query_as::<_, Structure>(&query);
+    for id in ids {
+        query = query.bind(id);
+    }
+    let mut rows = query.fetch_all(db).await?;
+    rows.sort_by_key(|row| ids.iter().position(|id| row.id == Some(*id)));
+    Ok(rows)
+}
+
+// Live structures of a scene in the current season, cached briefly since it
+// only decides which random strategy to use.
+async fn scene_size(state: &AppState, filter: &RandomFilter<'_>) -> Result<i64, sqlx::Error> {
+    let key = (filter.scene.to_string(), filter.season);
+    if let Some(entry) = state.scene_sizes.get(&key)
+        && entry.1.elapsed() < SCENE_SIZE_TTL
+    {
+        return Ok(entry.0);
+    }
+    let rows = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM structures WHERE scene = ? AND season_id = ? AND deleted = 0",
+    )
+    .bind(filter.scene)
+    .bind(filter.season)
+    .fetch_one(&state.read_db)
+    .await?;
+    state.scene_sizes.insert(key, (rows, Instant::now()));
+    Ok(rows)
+}
+
+// Structures a random fetch with `filter` may pick from, however many it returns.
+pub async fn count_random_matches(
+    db: &SqlitePool,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+) -> Result<i64, sqlx::Error> {
+    let mut builder =
+        sqlx::QueryBuilder::<sqlx::Sqlite>::new("SELECT COUNT(*) FROM structures WHERE ");
+    push_random_filter(&mut builder, config, filter);
+    builder.build_query_scalar().fetch_one(db).await
+}
+
+// Random picks from `user_id`'s strand contract partners and, with
+// `favorites`, the builders of their favorites: at most `limit`, spread over
+// the builders before any gets a second one. The player's own structures are
+// left out.
+pub async fn fetch_followed_builders(
+    db: &SqlitePool,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+    user_id: i64,
+    favorites: bool,
+    limit: i64,
+) -> Result<Vec<Structure>, sqlx::Error> {
+    let mut builder = sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!(
+        r#"SELECT {RANDOM_COLUMNS} FROM (
+            SELECT *, ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY RANDOM()) AS builder_rank
+            FROM structures WHERE "#
+    ));
+    push_random_filter(&mut builder, config, filter);
+    builder
+        .push(format!(" AND user_id IN ({CONTRACT_PARTNERS}"))
+        .push_bind(user_id);
+    if favorites {
+        builder
+            .push(
+                r#" UNION SELECT s.user_id FROM favorites f JOIN structures s ON s.id = f.structure_id
+                WHERE f.user_id = "#,
+            )
+            .push_bind(user_id);
+    }
+    builder
+        .push(") AND user_id != ")
+        .push_bind(user_id)
+        .push(") ORDER BY builder_rank, RANDOM() LIMIT ")
+        .push_bind(limit);
+    builder.build_query_as().fetch_all(db).await
+}
+
+// A player's strand contract partners: the players they offered a contract to
+// who offered one back. Ends in the player's id placeholder.
+const CONTRACT_PARTNERS: &str = r#"SELECT c.partner_id FROM contracts c
+    JOIN contracts back ON back.user_id = c.partner_id AND back.partner_id = c.user_id
+    WHERE c.user_id = "#;
+
+// The WHERE conditions every random strategy applies, for query builders
+fn push_random_filter<'a>(
+    builder: &mut sqlx::QueryBuilder<'a, sqlx::Sqlite>,
+    config: &Config,
+    filter: &RandomFilter<'a>,
+) {
+    builder
+        .push("deleted = 0 AND scene = ")
+        .push_bind(filter.scene)
+        .push(" AND season_id = ")
+        .push_bind(filter.season);
+    if let Some(map_id) = filter.map_id {
+        builder.push(" AND map_id = ").push_bind(map_id);
+    }
+    if let Some(app_id) = filter.app_id {
+        builder
+            .push(" AND COALESCE(app_id, ")
+            .push_bind(config.primary_appid() as i64)
+            .push(") = ")
+            .push_bind(app_id);
+    }
+    if !filter.exclude_prefabs.is_empty() {
+        builder.push(" AND prefab NOT IN (");
+        let mut prefabs = builder.separated(", ");
+        for prefab in filter.exclude_prefabs {
+            prefabs.push_bind(prefab);
+        }
+        builder.push(")");
+    }
+    if let Some(created_after) = filter.created_after {
+        builder
+            .push(" AND (pinned = 1 OR created_at >= ")
+            .push_bind(created_after)
+            .push(")");
+    }
+    builder
+        .push(" AND (user_id = ")
+        .push_bind(filter.viewer.unwrap_or(0))
+        .push(" OR user_id NOT IN (SELECT user_id FROM users WHERE shadow_banned = 1))");
+}
+
+// Random fetch for scenes too big to sort: jump to random ids between the
+// table's MIN(id) and MAX(id) and take the first matching row at or after
+// each one, which is an index seek on (scene, season_id, deleted, id).
+// Rows right after gaps in the id sequence come up more often, and there is
+// no per-user/segment spreading or usage weighting; the curated share is
+// still the scene's most-liked structures (after LIKES_DECAY_DAYS). Rows over a prefab quota are
+// skipped like duplicates.
+async fn probe_random(
+    db: &SqlitePool,
+    config: &Config,
+    filter: &RandomFilter<'_>,
+) -> Result<Vec<Structure>, sqlx::Error> {
+    let mut conditions = vec!["scene = ?", "season_id = ?", "deleted = 0"];
+    if filter.map_id.is_some() {
+        conditions.push("map_id = ?");
+    }
+    if filter.app_id.is_some() {
+        conditions.push("COALESCE(app_id, ?) = ?");
+    }
+    let prefab_condition = format!(
+        "prefab NOT IN ({})",
+        vec!["?"; filter.exclude_prefabs.len()].join(",")
+    );
+    if !filter.exclude_prefabs.is_empty() {
+        conditions.push(&prefab_condition);
+    }
+    if filter.created_after.is_some() {
+        conditions.push(PINNED_OR_CREATED_AFTER);
+    }
+    conditions.push(NOT_SHADOW_BANNED);
+    let conditions = conditions.join(" AND ");
+
+    macro_rules! bind_filter {
+        ($query:expr) => {{
+            let mut query = $query.bind(filter.scene).bind(filter.season);
+            if let Some(map_id) = filter.map_id {
+                query = query.bind(map_id);
+            }
+            if let Some(app_id) = filter.app_id {
+                query = query.bind(config.primary_appid() as i64).bind(app_id);
+            }
+            for prefab in filter.exclude_prefabs {
+                query = query.bind(prefab);
+            }
+            if let Some(created_after) = filter.created_after {
+                query = query.bind(created_after);
+            }
+            query.bind(filter.viewer.unwrap_or(0))
+        }};
+    }
+
+    let mut quotas = QuotaCounter::new(filter.prefab_quotas);
+    let mut rows: Vec<Structure> = Vec::new();
+    if filter.curated_limit > 0 {
+        let curated = format!(
+            "SELECT {RANDOM_COLUMNS} FROM structures WHERE {conditions} AND likes > 0 ORDER BY {} LIMIT ?",
+            curated_order(filter)
+        );
+        let mut query = bind_filter!(sqlx::query_as::<_, Structure>(&curated));
+        if let Some(decay) = filter.likes_decay {
+            query = query.bind(decay.now).bind(decay.half_life_millis);
+        }
+        rows = query.bind(filter.curated_limit).fetch_all(db).await?;
+        rows.retain(|row| quotas.take(&row.prefab));
+    }
+
+    let (min_id, max_id) =
+        sqlx::query_as::<_, (Option<i64>, Option<i64>)>("SELECT MIN(id), MAX(id) FROM structures")
+            .fetch_one(db)
+            .await?;
+    let (Some(min_id), Some(max_id)) = (min_id, max_id) else {
+        return Ok(rows);
+    };
+
+    let probe = format!(
+        "SELECT {RANDOM_COLUMNS} FROM structures WHERE {conditions} AND id >= ? ORDER BY id LIMIT 1"
+    );
+    let wanted = filter.limit.max(0) as usize;
+    // picks past the prefab share wait here for slots nothing else filled
+    let mut over_share: Vec<Structure> = Vec::new();
+    let mut prefab_picks: HashMap<String, i64> = HashMap::new();
+    // a scene smaller than the limit would otherwise be probed forever
+    for _ in 0..wanted * RANDOM_PROBE_ATTEMPTS_PER_ROW {
+        if rows.len() >= wanted {
+            break;
+        }
+        let start = fastrand::i64(min_id..=max_id);
+        let found = bind_filter!(sqlx::query_as::<_, Structure>(&probe))
+            .bind(start)
+            .fetch_optional(db)
+            .await?;
+        if let Some(row) = found
+            && !rows.iter().chain(&over_share).any(|seen| seen.id == row.id)
+            && quotas.take(&row.prefab)
+        {
+            let picks = prefab_picks.entry(row.prefab.clone()).or_default();
+            *picks += 1;
+            if filter.prefab_share.is_some_and(|share| *picks > share) {
+                over_share.push(row);
+            } else {
+                rows.push(row);
+            }
+        }
+    }
+    let remaining = wanted.saturating_sub(rows.len());
+    rows.extend(over_share.into_iter().take(remaining));
+    Ok(rows)
+}
+
+pub async fn query_global_stats(
+    db: &SqlitePool,
+    since_ms: i64,
+) -> Result<GlobalStatsResponse, sqlx::Error> {
+    let stats_row = sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+        r#"
+        SELECT
+            (SELECT COUNT(DISTINCT user_id) FROM structures WHERE deleted = 0) AS total_unique_players_all_time,
+            (SELECT COUNT(*) FROM structures WHERE deleted = 0) AS total_structures_uploaded_all_time,
+            (SELECT COALESCE(SUM(likes_send), 0) FROM users) AS total_likes_given_all_time,
+            (SELECT COUNT(DISTINCT user_id) FROM structures WHERE deleted = 0 AND created_at >= ?) AS total_unique_players_last_24h,
+            (SELECT COUNT(*) FROM structures WHERE deleted = 0 AND created_at >= ?) AS total_structures_uploaded_last_24h
+        "#,
+    )
+    .bind(since_ms)
+    .bind(since_ms)
+    .fetch_one(db)
+    .await?;
+
+    Ok(GlobalStatsResponse {
+        total_unique_players_all_time: stats_row.0,
+        total_structures_uploaded_all_time: stats_row.1,
+        total_likes_given_all_time: stats_row.2,
+        total_unique_players_last_24h: stats_row.3,
+        total_structures_uploaded_last_24h: stats_row.4,
+        server_version: SERVER_VERSION.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SceneStatsResponse {
+    pub scene: String,
+    pub season: i64,
+    pub total_structures: i64,
+    pub unique_builders: i64,
+    pub prefabs: BTreeMap<String, i64>,
+}
+
+// Live structures of a scene in one season, with their builders and prefabs.
+pub async fn query_scene_stats(
+    db: &SqlitePool,
+    scene: &str,
+    season: i64,
+) -> Result<SceneStatsResponse, sqlx::Error> {
+    let (total_structures, unique_builders) = sqlx::query_as::<_, (i64, i64)>(
+        r#"SELECT COUNT(*), COUNT(DISTINCT user_id) FROM structures
+           WHERE scene = ? AND season_id = ? AND deleted = 0"#,
+    )
+    .bind(scene)
+    .bind(season)
+    .fetch_one(db)
+    .await?;
+
+    let prefabs = sqlx::query_as::<_, (String, i64)>(
+        r#"SELECT prefab, COUNT(*) FROM structures
+           WHERE scene = ? AND season_id = ? AND deleted = 0
+           GROUP BY prefab"#,
+    )
+    .bind(scene)
+    .bind(season)
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect();
+
+    Ok(SceneStatsResponse {
+        scene: scene.to_string(),
+        season,
+        total_structures,
+        unique_builders,
+        prefabs,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefabStats {
+    pub structures: i64,
+    pub avg_likes: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefabStatsResponse {
+    pub season: i64,
+    pub prefabs: BTreeMap<String, PrefabStats>,
+    pub scenes: BTreeMap<String, BTreeMap<String, PrefabStats>>,
+}
+
+// Live structures and their average likes per prefab in one season, over all
+// scenes and for each scene.
+pub async fn query_prefab_stats(
+    db: &SqlitePool,
+    season: i64,
+) -> Result<PrefabStatsResponse, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (String, String, i64, i64)>(
+        r#"SELECT scene, prefab, COUNT(*), SUM(likes) FROM structures
+           WHERE season_id = ? AND deleted = 0
+           GROUP BY scene, prefab"#,
+    )
+    .bind(season)
+    .fetch_all(db)
+    .await?;
+
+    let stats = |structures: i64, likes: i64| PrefabStats {
+        structures,
+        avg_likes: likes as f64 / structures as f64,
+    };
+    let mut totals: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+    let mut scenes: BTreeMap<String, BTreeMap<String, PrefabStats>> = BTreeMap::new();
+    for (scene, prefab, structures, likes) in rows {
+        let total = totals.entry(prefab.clone()).or_default();
+        total.0 += structures;
+        total.1 += likes;
+        scenes
+            .entry(scene)
+            .or_default()
+            .insert(prefab, stats(structures, likes));
+    }
+
+    Ok(PrefabStatsResponse {
+        season,
+        prefabs: totals
+            .into_iter()
+            .map(|(prefab, (structures, likes))| (prefab, stats(structures, likes)))
+            .collect(),
+        scenes,
+    })
+}
+
+// Aggregates yesterday and today into stats_daily / stats_daily_scenes.
+// Counts only ever grow: rows pruned later must not shrink an already rolled-up day.
+// Likes have no timestamps, so each run snapshots the running total and the day's
+// value is the difference to the last snapshot of an earlier day.
+pub async fn rollup_daily_stats(db: &SqlitePool, now_ms: i64) -> Result<(), sqlx::Error> {
+    let today = now_ms.div_euclid(MILLIS_IN_DAY);
+    let mut tx = db.begin().await?;
+
+    for day in [today - 1, today] {
+        let start_ms = day * MILLIS_IN_DAY;
+        let end_ms = start_ms + MILLIS_IN_DAY;
+
+        sqlx::query(
+            r#"
+            INSERT INTO stats_daily (day, structures_posted, unique_users)
+            SELECT date(? / 1000, 'unixepoch'), COUNT(*), COUNT(DISTINCT user_id)
+            FROM structures WHERE created_at >= ? AND created_at < ?
+            ON CONFLICT(day) DO UPDATE SET
+                structures_posted = MAX(structures_posted, excluded.structures_posted),
+                unique_users = MAX(unique_users, excluded.unique_users)
+            "#,
+        )
+        .bind(start_ms)
+        .bind(start_ms)
+        .bind(end_ms)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO stats_daily_scenes (day, scene, structures_posted)
+            SELECT date(? / 1000, 'unixepoch'), scene, COUNT(*)
+            FROM structures WHERE created_at >= ? AND created_at < ?
+            GROUP BY scene
+            ON CONFLICT(day, scene) DO UPDATE SET
+                structures_posted = MAX(structures_posted, excluded.structures_posted)
+            "#,
+        )
+        .bind(start_ms)
+        .bind(start_ms)
+        .bind(end_ms)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE stats_daily SET
+            likes_given_total = t.total,
+            likes_given = t.total - COALESCE(
+                (SELECT likes_given_total FROM stats_daily
+                 WHERE day < date(? / 1000, 'unixepoch') AND likes_given_total IS NOT NULL
+                 ORDER BY day DESC LIMIT 1),
+                t.total)
+        FROM (SELECT COALESCE(SUM(likes_send), 0) AS total FROM users) AS t
+        WHERE day = date(? / 1000, 'unixepoch')
+        "#,
+    )
+    .bind(now_ms)
+    .bind(now_ms)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+// Span over which like budgets and caps are counted
+const LIKE_LEDGER_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// (owner, scene, prefab, owner's username, likes) of a live structure
+type LikeTarget = (i64, String, String, Option<String>, i64);
+
+pub async fn like_target<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    id: i64,
+) -> Result<Option<LikeTarget>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT user_id, scene, prefab, username, likes FROM structures WHERE id = ? AND deleted = 0",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn is_contributor<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    structure_id: i64,
+    user_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let found: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM structure_contributors WHERE structure_id = ? AND user_id = ?",
+    )
+    .bind(structure_id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+    Ok(found.is_some())
+}
+
+// Epoch millis where the rolling like allowances start
+pub fn like_window_start() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+        - LIKE_LEDGER_WINDOW.as_millis() as i64
+}
+
+// Drops likes_ledger rows older than LIKES_LEDGER_RETENTION_DAYS, keeping at
+// least the day the like budgets look back and the ARCHIVE_COLD_AFTER_DAYS the
+// archiver does. Returns how many went.
+pub async fn prune_likes_ledger(
+    db: &SqlitePool,
+    config: &Config,
+    now_ms: i64,
+) -> Result<u64, sqlx::Error> {
+    if config.likes_ledger_retention_days == 0 {
+        return Ok(0);
+    }
+    let days = config
+        .likes_ledger_retention_days
+        .max(config.archive_cold_after_days)
+        .max(1);
+    Ok(sqlx::query("DELETE FROM likes_ledger WHERE created_at < ?")
+        .bind(now_ms - days as i64 * MILLIS_IN_DAY)
+        .execute(db)
+        .await?
+        .rows_affected())
+}
+
+// Likes `liker` gave since `since`: in total, and to structures of `owner`
+pub async fn likes_given<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    liker: i64,
+    owner: i64,
+    since: i64,
+) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT COALESCE(SUM(count), 0),
+                  COALESCE(SUM(CASE WHEN owner_id = ? THEN count ELSE 0 END), 0)
+           FROM likes_ledger WHERE liker_id = ? AND created_at >= ?"#,
+    )
+    .bind(owner)
+    .bind(liker)
+    .bind(since)
+    .fetch_one(db)
+    .await
+}
+
+// --- private servers: invites ---
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Invite {
+    pub code: String,
+    pub note: Option<String>,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+pub async fn invite_is_live(db: &SqlitePool, code: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM invites WHERE code = ? AND revoked_at IS NULL)")
+        .bind(code)
+        .fetch_one(db)
+        .await
+}
+
+// Newest first, revoked ones included
+pub async fn list_invites(db: &SqlitePool) -> Result<Vec<Invite>, sqlx::Error> {
+    sqlx::query_as("SELECT * FROM invites ORDER BY created_at DESC, code")
+        .fetch_all(db)
+        .await
+}
+
+// A fresh random code, recorded in the audit log with its note
+pub async fn create_invite(
+    db: &SqlitePool,
+    note: Option<&str>,
+    now: i64,
+) -> Result<Invite, sqlx::Error> {
+    let invite = Invite {
+        code: format!("{:016x}", fastrand::u64(..)),
+        note: note.map(String::from),
+        created_at: now,
+        revoked_at: None,
+    };
+    let mut tx = db.begin().await?;
+    sqlx::query("INSERT INTO invites (code, note, created_at) VALUES (?, ?, ?)")
+        .bind(&invite.code)
+        .bind(&invite.note)
+        .bind(invite.created_at)
+        .execute(&mut *tx)
+        .await?;
+    record_audit(
+        &mut tx,
+        "create_invite",
+        &invite.code,
+        serde_json::json!({ "note": invite.note }),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(invite)
+}
+
+// false when there is no unrevoked invite with this code
+pub async fn revoke_invite(db: &SqlitePool, code: &str, now: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let revoked =
+        sqlx::query("UPDATE invites SET revoked_at = ? WHERE code = ? AND revoked_at IS NULL")
+            .bind(now)
+            .bind(code)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    if revoked == 0 {
+        tx.rollback().await.ok();
+        return Ok(false);
+    }
+    record_audit(&mut tx, "revoke_invite", code, serde_json::json!({})).await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+// --- private servers: allowlist ---
+
+pub async fn is_allowlisted(db: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM allowlist WHERE user_id = ?)")
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+}
+
+pub async fn list_allowlist(db: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT user_id FROM allowlist ORDER BY user_id")
+        .fetch_all(db)
+        .await
+}
+
+// Adds or removes a player; either is recorded in the audit log, even when
+// nothing changed
+pub async fn set_allowlisted(
+    db: &SqlitePool,
+    target: i64,
+    allowed: bool,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    if allowed {
+        sqlx::query(
+            r#"INSERT OR IGNORE INTO allowlist (user_id, added_at)
+               VALUES (?, strftime('%s','now')*1000);"#,
+        )
+        .bind(target)
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        sqlx::query("DELETE FROM allowlist WHERE user_id = ?")
+            .bind(target)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    record_audit(
+        &mut tx,
+        if allowed {
+            "allowlist_add"
+        } else {
+            "allowlist_remove"
+        },
+        &target.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// --- admin: bulk moderation ---
+
+// Appends a row to admin_audit_log inside the caller's transaction.
+pub async fn record_audit(
+    conn: &mut sqlx::SqliteConnection,
+    action: &str,
+    target: &str,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO admin_audit_log (action, target, details, created_at)
+           VALUES (?, ?, ?, strftime('%s','now')*1000);"#,
+    )
+    .bind(action)
+    .bind(target)
+    .bind(details.to_string())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+// Soft-deletes every live structure of a user, optionally only in one scene.
+pub async fn purge_user(
+    db: &SqlitePool,
+    target: i64,
+    scene: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let purged = sqlx::query(
+        r#"UPDATE structures
+           SET deleted = 1, deleted_at = strftime('%s','now')*1000, deleted_by = 'admin'
+           WHERE user_id = ? AND deleted = 0 AND (? IS NULL OR scene = ?)"#,
+    )
+    .bind(target)
+    .bind(scene)
+    .bind(scene)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    record_audit(
+        &mut tx,
+        "purge_user",
+        &target.to_string(),
+        serde_json::json!({ "scene": scene, "purged": purged }),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(purged)
+}
+
+// Sets users.upload_banned, creating the user if needed. Banned users can
+// still fetch and like, but their uploads are refused.
+pub async fn set_upload_banned(
+    db: &SqlitePool,
+    target: i64,
+    banned: bool,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query(
+        r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send)
+           VALUES (?, ?, 0, 0)
+           ON CONFLICT(user_id) DO UPDATE SET upload_banned = excluded.upload_banned;"#,
+    )
+    .bind(target)
+    .bind(banned)
+    .execute(&mut *tx)
+    .await?;
+
+    record_audit(
+        &mut tx,
+        if banned { "ban_user" } else { "unban_user" },
+        &target.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_shadow_banned(
+    db: &SqlitePool,
+    target: i64,
+    banned: bool,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query(
+        r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send, shadow_banned)
+           VALUES (?, 0, 0, 0, ?)
+           ON CONFLICT(user_id) DO UPDATE SET shadow_banned = excluded.shadow_banned;"#,
+    )
+    .bind(target)
+    .bind(banned)
+    .execute(&mut *tx)
+    .await?;
+
+    record_audit(
+        &mut tx,
+        if banned {
+            "shadow_ban_user"
+        } else {
+            "unshadow_ban_user"
+        },
+        &target.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn is_shadow_banned(db: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    let banned: Option<bool> =
+        sqlx::query_scalar("SELECT shadow_banned FROM users WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+    Ok(banned.unwrap_or(false))
+}
+
+// Spends one of the player's `max` burst posts; false once they are used up
+pub async fn take_burst_credit(
+    db: &SqlitePool,
+    user_id: i64,
+    max: i64,
+) -> Result<bool, sqlx::Error> {
+    if max <= 0 {
+        return Ok(false);
+    }
+    let taken = sqlx::query(
+        r#"INSERT INTO users (user_id, upload_banned, likes_received, likes_send, burst_posts_used)
+           VALUES (?, 0, 0, 0, 1)
+           ON CONFLICT(user_id) DO UPDATE SET burst_posts_used = burst_posts_used + 1
+           WHERE burst_posts_used < ?"#,
+    )
+    .bind(user_id)
+    .bind(max)
+    .execute(db)
+    .await?;
+    Ok(taken.rows_affected() > 0)
+}
+
+// Records the CURRENT_SEASON as started, closes every other season and moves their
+// structures into structures_archive. Safe to repeat; a second call archives nothing.
+pub async fn rollover_season(db: &SqlitePool, season: i64) -> Result<u64, sqlx::Error> {
+    let columns = table_columns(db, "structures")
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO seasons (id, started_at) VALUES (?, strftime('%s','now')*1000)",
+    )
+    .bind(season)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE seasons SET ended_at = strftime('%s','now')*1000 WHERE id <> ? AND ended_at IS NULL",
+    )
+    .bind(season)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO structures_archive ({columns}) SELECT {columns} FROM structures WHERE season_id <> ?"
+    ))
+    .bind(season)
+    .execute(&mut *tx)
+    .await?;
+    let archived = sqlx::query("DELETE FROM structures WHERE season_id <> ?")
+        .bind(season)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    Ok(archived)
+}
+
+// Moves up to `limit` structures nobody fetched or liked since `cutoff` (epoch
+// millis) to structures_archive, pinned ones excepted, and sets their
+// reactions, contributors and attributes aside. Returns how many moved.
+pub async fn archive_cold(db: &SqlitePool, cutoff: i64, limit: i64) -> Result<u64, sqlx::Error> {
+    let columns = table_columns(db, "structures")
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut tx = db.begin().await?;
+    let ids: Vec<i64> = sqlx::query_scalar(
+        r#"SELECT id FROM structures
+           WHERE pinned = 0 AND created_at < ?1 AND COALESCE(last_fetched_at, created_at) < ?1
+             AND NOT EXISTS (SELECT 1 FROM likes_ledger l
+                             WHERE l.structure_id = structures.id AND l.created_at >= ?1)
+           ORDER BY id LIMIT ?2"#,
+    )
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(&mut *tx)
+    .await?;
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let ids = serde_json::json!(ids).to_string();
+    const IDS: &str = "(SELECT value FROM json_each(?))";
+    sqlx::query(&format!(
+        "INSERT INTO structures_archive ({columns}) SELECT {columns} FROM structures WHERE id IN {IDS}"
+    ))
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        r#"INSERT OR REPLACE INTO structure_reactions_archive (structure_id, kind, count)
+           SELECT structure_id, kind, count FROM structure_reactions WHERE structure_id IN {IDS}"#
+    ))
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        r#"INSERT OR REPLACE INTO structure_contributors_archive (structure_id, user_id)
+           SELECT structure_id, user_id FROM structure_contributors WHERE structure_id IN {IDS}"#
+    ))
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        r#"INSERT OR REPLACE INTO structure_attributes_archive (structure_id, key, value)
+           SELECT structure_id, key, value FROM structure_attributes WHERE structure_id IN {IDS}"#
+    ))
+    .bind(&ids)
+    .execute(&mut *tx)
+    .await?;
+    // reactions, contributors and attributes go with the rows (ON DELETE CASCADE)
+    let archived = sqlx::query(&format!("DELETE FROM structures WHERE id IN {IDS}"))
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    Ok(archived)
+}
+
+// Moves an archived structure back, with its reactions, contributors and attributes, and
+// counts it as fetched `now` so the next archive run doesn't take it again.
+// False when the archive has no such structure.
+pub async fn restore_archived(db: &SqlitePool, id: i64, now: i64) -> Result<bool, sqlx::Error> {
+    let columns = table_columns(db, "structures")
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut tx = db.begin().await?;
+    let restored = sqlx::query(&format!(
+        "INSERT INTO structures ({columns}) SELECT {columns} FROM structures_archive WHERE id = ?"
+    ))
+    .bind(id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if restored == 0 {
+        return Ok(false);
+    }
+
+    for statement in [
+        r#"INSERT INTO structure_reactions (structure_id, kind, count)
+           SELECT structure_id, kind, count FROM structure_reactions_archive WHERE structure_id = ?"#,
+        r#"INSERT INTO structure_contributors (structure_id, user_id)
+           SELECT structure_id, user_id FROM structure_contributors_archive WHERE structure_id = ?"#,
+        "DELETE FROM structure_reactions_archive WHERE structure_id = ?",
+        r#"INSERT INTO structure_attributes (structure_id, key, value)
+           SELECT structure_id, key, value FROM structure_attributes_archive WHERE structure_id = ?"#,
+        "DELETE FROM structure_contributors_archive WHERE structure_id = ?",
+        "DELETE FROM structure_attributes_archive WHERE structure_id = ?",
+        "DELETE FROM structures_archive WHERE id = ?",
+    ] {
+        sqlx::query(statement).bind(id).execute(&mut *tx).await?;
+    }
+    sqlx::query("UPDATE structures SET last_fetched_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    record_audit(
+        &mut tx,
+        "unarchive_structure",
+        &id.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+// Records the milestones a structure passed going from `before` to `after`
+// likes and returns those not recorded yet, so each one fires only once.
+pub async fn claim_milestones(
+    conn: &mut sqlx::SqliteConnection,
+    structure_id: i64,
+    before: i64,
+    after: i64,
+    milestones: &[i64],
+) -> Result<Vec<i64>, sqlx::Error> {
+    let mut claimed = Vec::new();
+    for &milestone in milestones {
+        if before >= milestone || after < milestone {
+            continue;
+        }
+        let inserted = sqlx::query(
+            r#"INSERT OR IGNORE INTO like_milestones (structure_id, milestone, reached_at)
+               VALUES (?, ?, strftime('%s','now')*1000);"#,
+        )
+        .bind(structure_id)
+        .bind(milestone)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+        if inserted > 0 {
+            claimed.push(milestone);
+        }
+    }
+    Ok(claimed)
+}
+
+// Puts held uploads whose review_auto_approve_at has passed into rotation.
+// Returns how many went live.
+pub async fn auto_approve_reviews(db: &SqlitePool, now: i64) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let approved = sqlx::query(
+        r#"UPDATE structures
+           SET deleted = 0, deleted_at = NULL, deleted_by = NULL, review_auto_approve_at = NULL
+           WHERE deleted_by = 'review' AND review_auto_approve_at <= ?"#,
+    )
+    .bind(now)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if approved > 0 {
+        record_audit(
+            &mut tx,
+            "auto_approve_review",
+            "",
+            serde_json::json!({ "approved": approved }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(approved)
+}
@@ -0,0 +1,963 @@
+// Schema setup and migrations.
+//
+// `init` creates the structures table and runs `apply_migrations`, which only
+// ever adds tables, columns and indexes, so it is safe on every start.
+
+use sqlx::{Row, SqlitePool};
+
+use crate::{
+    config::Config,
+    model::{MAX_PREFAB_LENGTH, MAX_USERNAME_LENGTH, Structure},
+};
+
+pub async fn init(db: &SqlitePool, config: &Config) -> Result<(), sqlx::Error> {
+    let structures_ddl = format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS structures (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username  TEXT CHECK (length(username) <= {MAX_USERNAME_LENGTH}),
+            user_id   INTEGER NOT NULL,
+            map_id    INTEGER NOT NULL,
+            scene     TEXT NOT NULL CHECK (length(scene) <= {max_scene_length}),
+            segment   INTEGER,
+            prefab    TEXT NOT NULL CHECK (length(prefab) <= {MAX_PREFAB_LENGTH}),
+            pos_x REAL, pos_y REAL, pos_z REAL,
+            rot_x REAL, rot_y REAL, rot_z REAL, rot_w REAL,
+            rope_start_x REAL, rope_start_y REAL, rope_start_z REAL,
+            rope_end_x   REAL, rope_end_y   REAL, rope_end_z   REAL,
+            rope_length  REAL,
+            rope_flying_rotation_x REAL, rope_flying_rotation_y REAL, rope_flying_rotation_z REAL,
+            rope_anchor_rotation_x REAL, rope_anchor_rotation_y REAL, rope_anchor_rotation_z REAL, rope_anchor_rotation_w REAL,
+            antigrav BOOLEAN NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL
+        );
+        "#,
+        max_scene_length = config.max_scene_length
+    );
+
+    sqlx::query(&structures_ddl).execute(db).await?;
+
+    // apply non-destructive migrations if needed
+    apply_migrations(db).await?;
+
+    sqlx::query(
+        "INSERT OR IGNORE INTO seasons (id, started_at) VALUES (?, strftime('%s','now')*1000)",
+    )
+    .bind(config.current_season)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn apply_migrations(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Ensure users table exists
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            user_id       INTEGER PRIMARY KEY,
+            upload_banned BOOLEAN NOT NULL DEFAULT 0,
+            likes_received INTEGER NOT NULL DEFAULT 0,
+            likes_send     INTEGER NOT NULL DEFAULT 0
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Uploads per player and UTC day (DAILY_UPLOAD_QUOTA); a player's older
+    // days are dropped at their next upload
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_daily (
+            user_id INTEGER NOT NULL,
+            day     TEXT NOT NULL,
+            uploads INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (user_id, day)
+        ) WITHOUT ROWID;
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Steam persona names resolved server-side (RESOLVE_STEAM_NAMES)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_profiles (
+            user_id      INTEGER PRIMARY KEY,
+            persona_name TEXT NOT NULL,
+            fetched_at   INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Add columns to structures if missing
+    if !column_exists(db, "structures", "likes").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN likes INTEGER NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "deleted").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "app_id").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN app_id INTEGER;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "deleted_at").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN deleted_at INTEGER;")
+            .execute(db)
+            .await?;
+        sqlx::query("ALTER TABLE structures ADD COLUMN deleted_by TEXT;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "uses").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN uses INTEGER NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "pinned").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "last_fetched_at").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN last_fetched_at INTEGER;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "season_id").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN season_id INTEGER NOT NULL DEFAULT 1;")
+            .execute(db)
+            .await?;
+    }
+    // Federation: the SERVER_ID a mirrored structure came from and its id
+    // there; both NULL for structures uploaded here
+    if !column_exists(db, "structures", "origin_server").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN origin_server TEXT;")
+            .execute(db)
+            .await?;
+    }
+    if !column_exists(db, "structures", "origin_id").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN origin_id INTEGER;")
+            .execute(db)
+            .await?;
+    }
+    // Shadow-banned players keep uploading and seeing their own structures,
+    // but nobody else is served them
+    if !column_exists(db, "users", "shadow_banned").await? {
+        sqlx::query("ALTER TABLE users ADD COLUMN shadow_banned BOOLEAN NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    // Posts a player made inside POST_STRUCTURE_RATE_LIMIT on their
+    // NEW_PLAYER_BURST_POSTS credit
+    if !column_exists(db, "users", "burst_posts_used").await? {
+        sqlx::query("ALTER TABLE users ADD COLUMN burst_posts_used INTEGER NOT NULL DEFAULT 0;")
+            .execute(db)
+            .await?;
+    }
+    // Random fetches exclude these on every request; the handful of banned
+    // players is read from this partial index instead of the whole table
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_users_shadow_banned
+           ON users(user_id) WHERE shadow_banned = 1;"#,
+    )
+    .execute(db)
+    .await?;
+    // Why PLAUSIBILITY_CHECKS held an upload for review (deleted_by = 'review')
+    if !column_exists(db, "structures", "review_reason").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN review_reason TEXT;")
+            .execute(db)
+            .await?;
+    }
+    // When a held upload goes live without a moderator (NEWCOMER_AUTO_APPROVE_SECONDS);
+    // NULL waits for one
+    if !column_exists(db, "structures", "review_auto_approve_at").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN review_auto_approve_at INTEGER;")
+            .execute(db)
+            .await?;
+    }
+    // Owner edits (PATCH /structures/{id}) stamp updated_at and log the change
+    if !column_exists(db, "structures", "updated_at").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN updated_at INTEGER;")
+            .execute(db)
+            .await?;
+    }
+    // Optimistic concurrency for edits: PATCH sends it back in If-Match
+    if !column_exists(db, "structures", "version").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN version INTEGER NOT NULL DEFAULT 1;")
+            .execute(db)
+            .await?;
+    }
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_edits (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            structure_id INTEGER NOT NULL,
+            user_id      INTEGER NOT NULL,
+            changes      TEXT NOT NULL,
+            edited_at    INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structure_edits_structure
+           ON structure_edits(structure_id, edited_at);"#,
+    )
+    .execute(db)
+    .await?;
+    // Identical builds share a content_hash (see Structure::content_hash);
+    // rows from before the column are hashed once when it is added
+    if !column_exists(db, "structures", "content_hash").await? {
+        sqlx::query("ALTER TABLE structures ADD COLUMN content_hash INTEGER;")
+            .execute(db)
+            .await?;
+        let rows = sqlx::query_as::<_, Structure>("SELECT * FROM structures")
+            .fetch_all(db)
+            .await?;
+        let mut tx = db.begin().await?;
+        for row in &rows {
+            sqlx::query("UPDATE structures SET content_hash = ? WHERE id = ?")
+                .bind(row.content_hash())
+                .bind(row.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+    }
+    // Create helpful indexes (idempotent)
+    // Filter path in get_random: WHERE scene = ? AND deleted = 0 [AND map_id = ?]
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_scene_deleted_map
+           ON structures(scene, map_id, deleted);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // The review queue and its auto-approval sweep only look at held uploads
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_review
+           ON structures(review_auto_approve_at) WHERE deleted_by = 'review';"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Oldest-per-user-per-scene pruning: ORDER BY created_at, id WHERE user_id = ? AND scene = ?
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_user_scene_created
+           ON structures(user_id, scene, created_at, id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Day-range scans in the stats rollup
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_created
+           ON structures(created_at);"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stats_daily (
+            day               TEXT PRIMARY KEY,
+            structures_posted INTEGER NOT NULL DEFAULT 0,
+            unique_users      INTEGER NOT NULL DEFAULT 0,
+            likes_given       INTEGER NOT NULL DEFAULT 0,
+            likes_given_total INTEGER
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stats_daily_scenes (
+            day               TEXT NOT NULL,
+            scene             TEXT NOT NULL,
+            structures_posted INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (day, scene)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Bounding-box pre-filter of proximity queries
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_scene_pos
+           ON structures(scene, pos_x);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // get_random only looks at the current season
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_scene_season
+           ON structures(scene, season_id, deleted);"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS seasons (
+            id         INTEGER PRIMARY KEY,
+            started_at INTEGER NOT NULL,
+            ended_at   INTEGER
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Per-kind like counts. Likes given before reactions existed count as thumbs_up.
+    let backfill_reactions = !table_exists(db, "structure_reactions").await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_reactions (
+            structure_id INTEGER NOT NULL REFERENCES structures(id) ON DELETE CASCADE,
+            kind         TEXT NOT NULL,
+            count        INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (structure_id, kind)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    if backfill_reactions {
+        sqlx::query(
+            r#"INSERT INTO structure_reactions (structure_id, kind, count)
+               SELECT id, 'thumbs_up', likes FROM structures WHERE likes > 0;"#,
+        )
+        .execute(db)
+        .await?;
+    }
+
+    // Co-op builders credited next to the uploader
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_contributors (
+            structure_id INTEGER NOT NULL REFERENCES structures(id) ON DELETE CASCADE,
+            user_id      INTEGER NOT NULL,
+            PRIMARY KEY (structure_id, user_id)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structure_contributors_user
+           ON structure_contributors (user_id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // PREFAB_ATTRIBUTES values, JSON-encoded; the schema lives in the config
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_attributes (
+            structure_id INTEGER NOT NULL REFERENCES structures(id) ON DELETE CASCADE,
+            key          TEXT NOT NULL,
+            value        TEXT NOT NULL,
+            PRIMARY KEY (structure_id, key)
+        ) WITHOUT ROWID;
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_audit_log (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            action     TEXT NOT NULL,
+            target     TEXT NOT NULL,
+            details    TEXT NOT NULL DEFAULT '{}',
+            created_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // One row per accepted like request; backs the daily like budgets, the
+    // likes inbox and the like-abuse report. Rows older than
+    // LIKES_LEDGER_RETENTION_DAYS are pruned hourly (prune_likes_ledger), so
+    // unread inbox entries older than that are gone too.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS likes_ledger (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            liker_id     INTEGER NOT NULL,
+            owner_id     INTEGER NOT NULL,
+            structure_id INTEGER NOT NULL,
+            count        INTEGER NOT NULL,
+            created_at   INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_likes_ledger_liker
+           ON likes_ledger (liker_id, created_at);"#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_likes_ledger_owner
+           ON likes_ledger (owner_id, id);"#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_likes_ledger_structure
+           ON likes_ledger (structure_id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // How far each player has read their likes inbox (likes_ledger ids)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS notification_cursors (
+            user_id      INTEGER PRIMARY KEY,
+            last_read_id INTEGER NOT NULL DEFAULT 0
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // A structure is mirrored at most once, however many times it is pulled
+    sqlx::query(
+        r#"CREATE UNIQUE INDEX IF NOT EXISTS idx_structures_origin
+           ON structures(origin_server, origin_id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Like milestones each structure already reached; the primary key makes
+    // sure a milestone is announced once however the likes arrive
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS like_milestones (
+            structure_id INTEGER NOT NULL REFERENCES structures(id) ON DELETE CASCADE,
+            milestone    INTEGER NOT NULL,
+            reached_at   INTEGER NOT NULL,
+            PRIMARY KEY (structure_id, milestone)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Reactions, contributors and attributes of cold structures moved to structures_archive,
+    // kept so they come back on restore (no foreign keys: the rows are gone)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_reactions_archive (
+            structure_id INTEGER NOT NULL,
+            kind         TEXT NOT NULL,
+            count        INTEGER NOT NULL,
+            PRIMARY KEY (structure_id, kind)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_contributors_archive (
+            structure_id INTEGER NOT NULL,
+            user_id      INTEGER NOT NULL,
+            PRIMARY KEY (structure_id, user_id)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS structure_attributes_archive (
+            structure_id INTEGER NOT NULL,
+            key          TEXT NOT NULL,
+            value        TEXT NOT NULL,
+            PRIMARY KEY (structure_id, key)
+        ) WITHOUT ROWID;
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // How far the changes feed of each upstream (UPSTREAM_URL) was pulled
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS federation_cursors (
+            upstream TEXT PRIMARY KEY,
+            last_id  INTEGER NOT NULL DEFAULT 0
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Invite codes for private servers (INVITE_ONLY); revoked ones are kept for the record
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS invites (
+            code       TEXT PRIMARY KEY,
+            note       TEXT,
+            created_at INTEGER NOT NULL,
+            revoked_at INTEGER
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Players admitted while WHITELIST_MODE is on
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS allowlist (
+            user_id  INTEGER PRIMARY KEY,
+            added_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Message of the day (GET /api/v1/motd), scheduled through the admin API
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS announcements (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            message    TEXT NOT NULL,
+            starts_at  INTEGER,
+            ends_at    INTEGER,
+            created_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Community events scheduled through the admin API (see server_events.rs)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS server_events (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            name       TEXT,
+            kind       TEXT NOT NULL,
+            multiplier INTEGER,
+            scene      TEXT,
+            starts_at  INTEGER NOT NULL,
+            ends_at    INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+
+    // Which structures each player got from random fetches, and when
+    // (FETCH_HISTORY_DAYS); rows older than the window are pruned
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fetch_history (
+            user_id      INTEGER NOT NULL,
+            structure_id INTEGER NOT NULL,
+            fetched_at   INTEGER NOT NULL,
+            PRIMARY KEY (user_id, structure_id)
+        ) WITHOUT ROWID;
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_fetch_history_fetched
+           ON fetch_history(fetched_at);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Strand contracts: one row per offer, a contract once both sides offered
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS contracts (
+            user_id    INTEGER NOT NULL,
+            partner_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (user_id, partner_id)
+        ) WITHOUT ROWID;
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_contracts_partner
+           ON contracts(partner_id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Structures players bookmarked (MAX_FAVORITES_PER_USER)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS favorites (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id      INTEGER NOT NULL,
+            structure_id INTEGER NOT NULL,
+            created_at   INTEGER NOT NULL,
+            UNIQUE (user_id, structure_id)
+        );
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_favorites_user
+           ON favorites(user_id, id);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Exclusion by prefab (NOT IN ...) can benefit from an index on prefab
+    sqlx::query(
+        r#"CREATE INDEX IF NOT EXISTS idx_structures_prefab
+           ON structures(prefab);"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Substring search over usernames and prefabs for GET /admin/v1/structures/search.
+    // A trigram index over structures, kept in step by the triggers below
+    let search_is_new = !table_exists(db, "structure_search").await?;
+    sqlx::query(
+        r#"CREATE VIRTUAL TABLE IF NOT EXISTS structure_search USING fts5(
+               username, prefab,
+               content = 'structures', content_rowid = 'id', tokenize = 'trigram'
+           );"#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS structure_search_insert AFTER INSERT ON structures BEGIN
+            INSERT INTO structure_search (rowid, username, prefab)
+            VALUES (new.id, new.username, new.prefab);
+        END;
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS structure_search_delete AFTER DELETE ON structures BEGIN
+            INSERT INTO structure_search (structure_search, rowid, username, prefab)
+            VALUES ('delete', old.id, old.username, old.prefab);
+        END;
+        "#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS structure_search_update
+        AFTER UPDATE OF username, prefab ON structures BEGIN
+            INSERT INTO structure_search (structure_search, rowid, username, prefab)
+            VALUES ('delete', old.id, old.username, old.prefab);
+            INSERT INTO structure_search (rowid, username, prefab)
+            VALUES (new.id, new.username, new.prefab);
+        END;
+        "#,
+    )
+    .execute(db)
+    .await?;
+    if search_is_new {
+        // index the structures stored before the search existed
+        sqlx::query("INSERT INTO structure_search (structure_search) VALUES ('rebuild');")
+            .execute(db)
+            .await?;
+    }
+
+    sync_archive_table(db).await?;
+
+    Ok(())
+}
+
+// What the handlers rely on once migrations ran; checked at startup
+const EXPECTED_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "structures",
+        &[
+            "id",
+            "username",
+            "user_id",
+            "map_id",
+            "scene",
+            "segment",
+            "prefab",
+            "pos_x",
+            "pos_y",
+            "pos_z",
+            "rot_x",
+            "rot_y",
+            "rot_z",
+            "rot_w",
+            "rope_start_x",
+            "rope_start_y",
+            "rope_start_z",
+            "rope_end_x",
+            "rope_end_y",
+            "rope_end_z",
+            "rope_length",
+            "rope_flying_rotation_x",
+            "rope_flying_rotation_y",
+            "rope_flying_rotation_z",
+            "rope_anchor_rotation_x",
+            "rope_anchor_rotation_y",
+            "rope_anchor_rotation_z",
+            "rope_anchor_rotation_w",
+            "antigrav",
+            "created_at",
+            "likes",
+            "deleted",
+            "app_id",
+            "deleted_at",
+            "deleted_by",
+            "uses",
+            "pinned",
+            "last_fetched_at",
+            "season_id",
+            "origin_server",
+            "origin_id",
+            "review_reason",
+            "review_auto_approve_at",
+            "content_hash",
+            "updated_at",
+            "version",
+        ],
+    ),
+    (
+        "users",
+        &[
+            "user_id",
+            "upload_banned",
+            "likes_received",
+            "likes_send",
+            "shadow_banned",
+            "burst_posts_used",
+        ],
+    ),
+    (
+        "likes_ledger",
+        &[
+            "id",
+            "liker_id",
+            "owner_id",
+            "structure_id",
+            "count",
+            "created_at",
+        ],
+    ),
+    ("structure_reactions", &["structure_id", "kind", "count"]),
+    ("structure_contributors", &["structure_id", "user_id"]),
+    ("structure_attributes", &["structure_id", "key", "value"]),
+    ("notification_cursors", &["user_id", "last_read_id"]),
+    ("user_profiles", &["user_id", "persona_name", "fetched_at"]),
+    ("seasons", &["id", "started_at", "ended_at"]),
+    (
+        "admin_audit_log",
+        &["id", "action", "target", "details", "created_at"],
+    ),
+    (
+        "stats_daily",
+        &[
+            "day",
+            "structures_posted",
+            "unique_users",
+            "likes_given",
+            "likes_given_total",
+        ],
+    ),
+    ("stats_daily_scenes", &["day", "scene", "structures_posted"]),
+    ("federation_cursors", &["upstream", "last_id"]),
+    ("invites", &["code", "note", "created_at", "revoked_at"]),
+    ("allowlist", &["user_id", "added_at"]),
+    (
+        "announcements",
+        &["id", "message", "starts_at", "ends_at", "created_at"],
+    ),
+    (
+        "server_events",
+        &[
+            "id",
+            "name",
+            "kind",
+            "multiplier",
+            "scene",
+            "starts_at",
+            "ends_at",
+            "created_at",
+        ],
+    ),
+    ("fetch_history", &["user_id", "structure_id", "fetched_at"]),
+    (
+        "favorites",
+        &["id", "user_id", "structure_id", "created_at"],
+    ),
+    ("contracts", &["user_id", "partner_id", "created_at"]),
+    (
+        "like_milestones",
+        &["structure_id", "milestone", "reached_at"],
+    ),
+    (
+        "structure_reactions_archive",
+        &["structure_id", "kind", "count"],
+    ),
+    (
+        "structure_contributors_archive",
+        &["structure_id", "user_id"],
+    ),
+    (
+        "structure_attributes_archive",
+        &["structure_id", "key", "value"],
+    ),
+    ("structure_search", &["username", "prefab"]),
+    ("user_daily", &["user_id", "day", "uploads"]),
+    (
+        "structure_edits",
+        &["id", "structure_id", "user_id", "changes", "edited_at"],
+    ),
+];
+
+const EXPECTED_INDEXES: &[&str] = &[
+    "idx_structures_scene_deleted_map",
+    "idx_structures_user_scene_created",
+    "idx_structures_created",
+    "idx_structures_scene_pos",
+    "idx_structures_scene_season",
+    "idx_structures_prefab",
+    "idx_structures_origin",
+    "idx_structure_contributors_user",
+    "idx_likes_ledger_liker",
+    "idx_likes_ledger_owner",
+    "idx_likes_ledger_structure",
+    "idx_users_shadow_banned",
+    "idx_structures_review",
+    "idx_structure_edits_structure",
+    "idx_fetch_history_fetched",
+    "idx_favorites_user",
+    "idx_contracts_partner",
+];
+
+// Tables, columns and indexes from EXPECTED_COLUMNS and EXPECTED_INDEXES that
+// the database lacks, e.g. after restoring an old backup over a running
+// install or a migration that was interrupted
+pub async fn missing_schema(db: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let mut missing = Vec::new();
+    for (table, columns) in EXPECTED_COLUMNS {
+        let present = table_columns(db, table).await?;
+        if present.is_empty() {
+            missing.push(format!("table {table}"));
+            continue;
+        }
+        for column in *columns {
+            if !present
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(column))
+            {
+                missing.push(format!("column {table}.{column}"));
+            }
+        }
+    }
+    for index in EXPECTED_INDEXES {
+        let found: Option<i64> =
+            sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?")
+                .bind(index)
+                .fetch_optional(db)
+                .await?;
+        if found.is_none() {
+            missing.push(format!("index {index}"));
+        }
+    }
+    Ok(missing)
+}
+
+// Old-season rows move here on rollover. Created as a constraint-free copy of structures
+// and kept in step with it, so it must run after every structures column migration.
+async fn sync_archive_table(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS structures_archive AS SELECT * FROM structures WHERE 0;",
+    )
+    .execute(db)
+    .await?;
+
+    let archived = table_columns(db, "structures_archive").await?;
+    for (name, decl_type) in table_columns(db, "structures").await? {
+        if !archived.iter().any(|(existing, _)| *existing == name) {
+            sqlx::query(&format!(
+                "ALTER TABLE structures_archive ADD COLUMN {name} {decl_type};"
+            ))
+            .execute(db)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// (name, declared type) for each column, in table order
+pub async fn table_columns(
+    db: &SqlitePool,
+    table: &str,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({});", table))
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                row.try_get("name").unwrap_or_default(),
+                row.try_get("type").unwrap_or_default(),
+            )
+        })
+        .collect())
+}
+
+async fn table_exists(db: &SqlitePool, table: &str) -> Result<bool, sqlx::Error> {
+    let found: Option<i64> =
+        sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_optional(db)
+            .await?;
+    Ok(found.is_some())
+}
+
+async fn column_exists(db: &SqlitePool, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+    let mut rows = sqlx::query(&format!("PRAGMA table_info({});", table))
+        .fetch_all(db)
+        .await?;
+
+    // PRAGMA table_info columns: cid, name, type, notnull, dflt_value, pk
+    for row in rows.drain(..) {
+        let name: String = row.try_get("name").unwrap_or_default();
+        if name.eq_ignore_ascii_case(column) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,113 @@
+// Database size and growth.
+//
+// GET /admin/v1/db-stats reports what the file holds so operators can watch
+// growth and decide when to turn on decay or archival without shell access:
+// rows per table, the file and WAL sizes, and the bytes each table and index
+// takes. Per-object bytes come from the dbstat virtual table, which reads
+// every page, so the report costs about as much as a quick_check.
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+pub struct TableSize {
+    pub rows: i64,
+    // the table's pages; null where SQLite was built without dbstat
+    pub bytes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexSize {
+    pub table: String,
+    pub bytes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeReport {
+    // null for an in-memory database or where the file can't be read
+    pub file_bytes: Option<u64>,
+    // null while no -wal file exists
+    pub wal_bytes: Option<u64>,
+    pub page_size: i64,
+    pub page_count: i64,
+    // pages free for reuse; VACUUM gives them back to the filesystem
+    pub freelist_pages: i64,
+    pub tables: BTreeMap<String, TableSize>,
+    // the automatic ones behind UNIQUE and PRIMARY KEY constraints included
+    pub indexes: BTreeMap<String, IndexSize>,
+}
+
+// The database file and its -wal file on disk; None for an in-memory
+// database, a file that can't be read or no WAL file
+pub async fn file_sizes(db: &SqlitePool) -> Result<(Option<u64>, Option<u64>), sqlx::Error> {
+    let file: Option<String> =
+        sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_optional(db)
+            .await?;
+    let file = file.filter(|file| !file.is_empty());
+    let size_of = |path: &str| std::fs::metadata(path).ok().map(|meta| meta.len());
+    Ok((
+        file.as_deref().and_then(size_of),
+        file.as_deref()
+            .and_then(|file| size_of(&format!("{file}-wal"))),
+    ))
+}
+
+pub async fn report(db: &SqlitePool) -> Result<SizeReport, sqlx::Error> {
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(db).await?;
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+        .fetch_one(db)
+        .await?;
+    let freelist_pages: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+        .fetch_one(db)
+        .await?;
+    let (file_bytes, wal_bytes) = file_sizes(db).await?;
+
+    // one scan of dbstat for every table and index
+    let bytes: BTreeMap<String, i64> =
+        sqlx::query_as::<_, (String, i64)>("SELECT name, SUM(pgsize) FROM dbstat GROUP BY name")
+            .fetch_all(db)
+            .await
+            .map(|rows| rows.into_iter().collect())
+            .unwrap_or_default();
+    let has_dbstat = !bytes.is_empty();
+    let bytes_of = |name: &str| has_dbstat.then(|| bytes.get(name).copied().unwrap_or(0));
+
+    // SQLite's own tables are left out, the indexes behind constraints
+    // (sqlite_autoindex_*) are not
+    let objects: Vec<(String, String, String)> = sqlx::query_as(
+        r#"SELECT type, name, tbl_name FROM sqlite_master
+           WHERE type = 'index' OR (type = 'table' AND name NOT LIKE 'sqlite\_%' ESCAPE '\')
+           ORDER BY name"#,
+    )
+    .fetch_all(db)
+    .await?;
+    let mut tables = BTreeMap::new();
+    let mut indexes = BTreeMap::new();
+    for (kind, name, table) in objects {
+        if kind == "index" {
+            let bytes = bytes_of(&name);
+            indexes.insert(name, IndexSize { table, bytes });
+            continue;
+        }
+        let rows: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM \"{}\"",
+            name.replace('"', "\"\"")
+        ))
+        .fetch_one(db)
+        .await?;
+        let bytes = bytes_of(&name);
+        tables.insert(name, TableSize { rows, bytes });
+    }
+
+    Ok(SizeReport {
+        file_bytes,
+        wal_bytes,
+        page_size,
+        page_count,
+        freelist_pages,
+        tables,
+        indexes,
+    })
+}
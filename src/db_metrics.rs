@@ -0,0 +1,206 @@
+// Database timings for GET /admin/v1/stats/db.
+//
+// Keeps a latency histogram per named query (random fetch, upload, like
+// flush, ...), the time requests wait to get a connection for a transaction,
+// and how long auth ticket checks take, so a rising p99 can be pinned on DB
+// contention or on Steam. The pools' current size and idle count come with
+// every snapshot. Like the limit stats, it lives in memory since startup.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::access_log;
+
+// Upper bounds of the histogram buckets, in microseconds; a last bucket
+// catches everything slower
+const BUCKET_BOUNDS_MICROS: [u64; 14] = [
+    250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_MICROS.len() + 1],
+    count: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = BUCKET_BOUNDS_MICROS.partition_point(|bound| *bound < micros);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Timing {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max_micros = self.max_micros.load(Ordering::Relaxed);
+        // upper bound of the bucket holding the q-th sample, capped at the max seen
+        let quantile = |q: f64| {
+            let rank = ((count as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (i, bucket) in counts.iter().enumerate() {
+                seen += bucket;
+                if seen >= rank {
+                    let bound = BUCKET_BOUNDS_MICROS.get(i).copied().unwrap_or(max_micros);
+                    return millis(bound.min(max_micros));
+                }
+            }
+            millis(max_micros)
+        };
+        Timing {
+            count,
+            mean_ms: if count == 0 {
+                0.0
+            } else {
+                millis(self.total_micros.load(Ordering::Relaxed)) / count as f64
+            },
+            p50_ms: quantile(0.5),
+            p95_ms: quantile(0.95),
+            p99_ms: quantile(0.99),
+            max_ms: millis(max_micros),
+        }
+    }
+}
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timing {
+    pub count: u64,
+    pub mean_ms: f64,
+    // percentiles are bucket bounds: the real value is at most this
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+    pub acquire_wait: Timing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbMetricsResponse {
+    pub since: i64, // epoch millis
+    pub pools: BTreeMap<&'static str, PoolStats>,
+    pub queries: BTreeMap<&'static str, Timing>,
+    pub auth_verify: Timing,
+}
+
+#[derive(Debug)]
+pub struct DbMetrics {
+    started_at: i64,
+    queries: DashMap<&'static str, Histogram>,
+    // keyed by pool: "writer" or "reader"
+    acquire_waits: DashMap<&'static str, Histogram>,
+    auth_verify: Histogram,
+}
+
+impl Default for DbMetrics {
+    fn default() -> Self {
+        Self {
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default(),
+            queries: DashMap::new(),
+            acquire_waits: DashMap::new(),
+            auth_verify: Histogram::default(),
+        }
+    }
+}
+
+impl DbMetrics {
+    // Runs `work` and records how long it took under `name`, failed or not
+    pub async fn timed<T, E>(
+        &self,
+        name: &'static str,
+        work: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let started = Instant::now();
+        let result = work.await;
+        let elapsed = started.elapsed();
+        self.queries.entry(name).or_default().record(elapsed);
+        access_log::note_db_time(elapsed);
+        result
+    }
+
+    // pool.begin(), recording how long it waited for a connection
+    pub async fn begin(
+        &self,
+        pool_name: &'static str,
+        pool: &SqlitePool,
+    ) -> Result<Transaction<'static, Sqlite>, sqlx::Error> {
+        let started = Instant::now();
+        let tx = pool.begin().await;
+        let elapsed = started.elapsed();
+        self.acquire_waits
+            .entry(pool_name)
+            .or_default()
+            .record(elapsed);
+        access_log::note_db_time(elapsed);
+        tx
+    }
+
+    // (count, total microseconds) of every timed query since startup
+    pub fn query_totals(&self) -> (u64, u64) {
+        self.queries.iter().fold((0, 0), |(count, micros), entry| {
+            (
+                count + entry.count.load(Ordering::Relaxed),
+                micros + entry.total_micros.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    pub fn auth_verified(&self, elapsed: Duration) {
+        self.auth_verify.record(elapsed);
+    }
+
+    pub fn snapshot(&self, writer: &SqlitePool, reader: &SqlitePool) -> DbMetricsResponse {
+        let pool_stats = |name: &'static str, pool: &SqlitePool| PoolStats {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max_connections: pool.options().get_max_connections(),
+            acquire_wait: self
+                .acquire_waits
+                .get(name)
+                .map(|h| h.snapshot())
+                .unwrap_or_else(|| Histogram::default().snapshot()),
+        };
+        DbMetricsResponse {
+            since: self.started_at,
+            pools: BTreeMap::from([
+                ("writer", pool_stats("writer", writer)),
+                ("reader", pool_stats("reader", reader)),
+            ]),
+            queries: self
+                .queries
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().snapshot()))
+                .collect(),
+            auth_verify: self.auth_verify.snapshot(),
+        }
+    }
+}
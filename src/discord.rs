@@ -0,0 +1,212 @@
+// Background delivery of notices: embeds to DISCORD_WEBHOOK_URL, batched, and
+// like milestones to MILESTONE_WEBHOOK_URL, one POST each. Both go through one
+// bounded queue and one worker, so a burst of likes never piles up requests.
+
+use arc_swap::ArcSwap;
+use reqwest::{Client, StatusCode};
+use serde_json::{Value, json};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+
+// Notices waiting to be sent; beyond this new ones are dropped rather than blocking handlers
+const QUEUE_CAPACITY: usize = 256;
+// Discord accepts at most 10 embeds per webhook message
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+// How long the worker keeps collecting after the first notice of a batch
+const BATCH_WINDOW: Duration = Duration::from_secs(1);
+
+const COLOR_LIKES: u32 = 0xE9_1E_63;
+const COLOR_STATS: u32 = 0x34_98_DB;
+
+#[derive(Debug, Clone)]
+pub enum Notice {
+    LikeMilestone {
+        structure_id: i64,
+        username: Option<String>,
+        scene: String,
+        prefab: String,
+        likes: i64,
+    },
+    // for MILESTONE_WEBHOOK_URL rather than Discord, one POST each
+    MilestoneReached {
+        structure_id: i64,
+        owner_id: i64,
+        scene: String,
+        prefab: String,
+        milestone: i64,
+        likes: i64,
+    },
+    DailySummary {
+        unique_players_last_24h: i64,
+        structures_uploaded_last_24h: i64,
+        structures_total: i64,
+        likes_given_total: i64,
+    },
+}
+
+impl Notice {
+    fn embed(&self) -> Option<Value> {
+        Some(match self {
+            Notice::LikeMilestone {
+                structure_id,
+                username,
+                scene,
+                prefab,
+                likes,
+            } => json!({
+                "title": format!("A structure reached {likes} likes"),
+                "description": format!(
+                    "{} by {} in {}",
+                    prefab,
+                    username.as_deref().unwrap_or("an unknown climber"),
+                    scene
+                ),
+                "color": COLOR_LIKES,
+                "footer": { "text": format!("structure #{structure_id}") },
+            }),
+            Notice::MilestoneReached { .. } => return None,
+            Notice::DailySummary {
+                unique_players_last_24h,
+                structures_uploaded_last_24h,
+                structures_total,
+                likes_given_total,
+            } => json!({
+                "title": "Daily summary",
+                "color": COLOR_STATS,
+                "fields": [
+                    { "name": "Players (24h)", "value": unique_players_last_24h.to_string(), "inline": true },
+                    { "name": "Structures (24h)", "value": structures_uploaded_last_24h.to_string(), "inline": true },
+                    { "name": "Structures (total)", "value": structures_total.to_string(), "inline": true },
+                    { "name": "Likes given (total)", "value": likes_given_total.to_string(), "inline": true },
+                ],
+            }),
+        })
+    }
+
+    fn milestone_body(&self) -> Option<Value> {
+        let Notice::MilestoneReached {
+            structure_id,
+            owner_id,
+            scene,
+            prefab,
+            milestone,
+            likes,
+        } = self
+        else {
+            return None;
+        };
+        Some(json!({
+            "structure_id": structure_id,
+            "owner_id": owner_id,
+            "scene": scene,
+            "prefab": prefab,
+            "milestone": milestone,
+            "likes": likes,
+        }))
+    }
+}
+
+// Handle used by request handlers; sending never waits on Discord.
+#[derive(Debug, Clone)]
+pub struct DiscordNotifier {
+    tx: mpsc::Sender<Notice>,
+}
+
+impl DiscordNotifier {
+    // Starts the delivery worker. The webhook URL is read from the live config
+    // for every batch, so setting or clearing it takes effect on reload.
+    pub fn spawn(http: Client, config: Arc<ArcSwap<Config>>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(deliver(rx, http, config));
+        Self { tx }
+    }
+
+    pub fn notify(&self, notice: Notice) {
+        if let Err(e) = self.tx.try_send(notice) {
+            tracing::warn!("discord_notify dropped reason={}", e);
+        }
+    }
+}
+
+async fn deliver(mut rx: mpsc::Receiver<Notice>, http: Client, config: Arc<ArcSwap<Config>>) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::Instant::now() + BATCH_WINDOW;
+        while batch.len() < MAX_EMBEDS_PER_MESSAGE {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(notice)) => batch.push(notice),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let config = config.load_full();
+        if let Some(url) = &config.milestone_webhook_url {
+            for body in batch.iter().filter_map(Notice::milestone_body) {
+                post_milestone(&http, url, &body).await;
+            }
+        }
+        let embeds: Vec<Value> = batch.iter().filter_map(Notice::embed).collect();
+        if let Some(url) = &config.discord_webhook_url
+            && !embeds.is_empty()
+        {
+            let body = json!({ "embeds": embeds });
+            post_webhook(&http, url, &body, embeds.len()).await;
+        }
+    }
+}
+
+// Sent once, without retries
+async fn post_milestone(http: &Client, url: &str, body: &Value) {
+    let result = http
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        tracing::warn!(
+            "milestone_webhook result=error structure_id={} milestone={} error={}",
+            body["structure_id"],
+            body["milestone"],
+            e
+        );
+    }
+}
+
+async fn post_webhook(http: &Client, url: &str, body: &Value, embeds: usize) {
+    // one retry when Discord rate limits us, after the delay it asks for
+    for attempt in 0..2 {
+        let response = match http.post(url).json(body).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("discord_webhook result=error embeds={} error={}", embeds, e);
+                return;
+            }
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            tracing::info!("discord_webhook result=OK embeds={}", embeds);
+            return;
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS && attempt == 0 {
+            let retry_after = response
+                .json::<Value>()
+                .await
+                .ok()
+                .and_then(|v| v["retry_after"].as_f64())
+                .unwrap_or(1.0);
+            tokio::time::sleep(Duration::from_secs_f64(retry_after.clamp(0.0, 60.0))).await;
+            continue;
+        }
+
+        tracing::warn!(
+            "discord_webhook result=error embeds={} status={}",
+            embeds,
+            status.as_u16()
+        );
+        return;
+    }
+}
@@ -0,0 +1,209 @@
+// JSON error envelope shared by every handler and extractor:
+//
+//     {"code": "rate_limited", "message": "...", "retry_after": 3}
+//
+// `code` is stable for clients to branch on; `message` is for humans and may
+// change. `retry_after` (seconds) is only present on errors worth retrying and
+// is mirrored in the Retry-After header. Validation errors add the `field`
+// they are about. The access log records every error response with its
+// status and code, so handlers don't log the errors they return.
+//
+// Handlers return AppError, which names what went wrong, and use `?` on
+// database errors, auth errors and ready-made ApiErrors alike. Turning it into
+// an ApiError picks the status and code, and adds a log line of its own only
+// for what neither the client nor the access log gets to see: the database
+// error behind a 500, or why Steam could not check a ticket.
+
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::auth::AuthError;
+
+#[derive(Debug)]
+pub enum AppError {
+    Db(sqlx::Error),
+    SteamAuth(AuthError),
+    RateLimited(String, Duration), // message, retry after
+    NotFound(&'static str),
+    Validation(&'static str, String), // field, message
+    // any other status or code
+    Api(ApiError),
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    field: Option<&'static str>,
+    retry_after: Option<Duration>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    // Error with the generic code for its status; see `with_code` for specific ones
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: default_code(status),
+            message: message.into(),
+            field: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    // Names the request field that failed validation
+    pub fn with_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn field(&self) -> Option<&'static str> {
+        self.field
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+}
+
+fn default_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_body",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::BAD_GATEWAY => "upstream_error",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        s if s.is_server_error() => "internal",
+        _ => "error",
+    }
+}
+
+impl From<AppError> for ApiError {
+    fn from(error: AppError) -> Self {
+        match error {
+            AppError::Db(e) => {
+                tracing::error!("db_error error={}", e);
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Database error.")
+            }
+            AppError::SteamAuth(e) => {
+                if e.status.is_server_error() {
+                    tracing::warn!("steam_auth failed code={} error={}", e.code, e.message);
+                }
+                ApiError::from(e)
+            }
+            AppError::RateLimited(message, retry_after) => {
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, message).with_retry_after(retry_after)
+            }
+            AppError::NotFound(message) => ApiError::new(StatusCode::NOT_FOUND, message),
+            AppError::Validation(field, message) => {
+                ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+                    .with_code("invalid_field")
+                    .with_field(field)
+            }
+            AppError::Api(e) => e,
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        AppError::Db(error)
+    }
+}
+
+impl From<AuthError> for AppError {
+    fn from(error: AuthError) -> Self {
+        AppError::SteamAuth(error)
+    }
+}
+
+// Lets `?` keep working on the `(status, message)` pairs produced by map_err closures
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        Self::new(status, message)
+    }
+}
+
+impl From<(StatusCode, String)> for AppError {
+    fn from(pair: (StatusCode, String)) -> Self {
+        AppError::Api(pair.into())
+    }
+}
+
+impl From<ApiError> for AppError {
+    fn from(error: ApiError) -> Self {
+        AppError::Api(error)
+    }
+}
+
+// Lets helpers that answer ApiError use `?` on queries too
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        AppError::Db(error).into()
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // whole seconds, rounded up so clients never retry too early
+        let retry_after = self
+            .retry_after
+            .map(|d| d.as_secs() + u64::from(d.subsec_nanos() > 0));
+        let body = ErrorBody {
+            code: self.code,
+            message: &self.message,
+            field: self.field,
+            retry_after,
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        // picked up by the access log
+        response.extensions_mut().insert(self);
+        response
+    }
+}
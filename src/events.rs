@@ -0,0 +1,139 @@
+// Realtime fan-out of structure activity to connected clients.
+//
+// Handlers publish after their transaction commits; subscribers hold a
+// broadcast receiver per scene. Channels are created lazily on first
+// subscribe and dropped again once nobody listens.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::model::{Reaction, Structure};
+
+// Events a slow subscriber may fall behind by before it starts missing some
+const SCENE_CHANNEL_CAPACITY: usize = 256;
+const USER_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+// the variant names are the `type` clients see
+#[allow(clippy::enum_variant_names)]
+pub enum SceneEvent {
+    StructurePosted {
+        structure: Box<Structure>,
+    },
+    // the owner moved or re-roped it in place
+    StructureEdited {
+        structure: Box<Structure>,
+    },
+    StructureLiked {
+        id: i64,
+        scene: String,
+        likes: i64,
+        count: i32,
+        reaction: Reaction,
+    },
+}
+
+// Events addressed to one player, e.g. likes on their structures
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UserEvent {
+    LikeReceived {
+        structure_id: i64,
+        scene: String,
+        prefab: String,
+        reaction: Reaction,
+        count: i32,
+        likes: i64,
+    },
+    // a strand contract partner's structure was liked
+    PartnerLikeReceived {
+        partner_id: i64,
+        structure_id: i64,
+        scene: String,
+        prefab: String,
+        reaction: Reaction,
+        count: i32,
+        likes: i64,
+    },
+    // one of their structures reached a LIKE_MILESTONES count
+    LikeMilestone {
+        structure_id: i64,
+        scene: String,
+        prefab: String,
+        milestone: i64,
+        likes: i64,
+    },
+}
+
+impl UserEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UserEvent::LikeReceived { .. } => "like_received",
+            UserEvent::PartnerLikeReceived { .. } => "partner_like_received",
+            UserEvent::LikeMilestone { .. } => "like_milestone",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventHub {
+    scenes: DashMap<String, broadcast::Sender<SceneEvent>>,
+    users: DashMap<u64, broadcast::Sender<UserEvent>>,
+}
+
+impl EventHub {
+    pub fn subscribe_scene(&self, scene: &str) -> broadcast::Receiver<SceneEvent> {
+        self.scenes
+            .entry(scene.to_string())
+            .or_insert_with(|| broadcast::channel(SCENE_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    // Fire-and-forget: nobody listening is not an error.
+    pub fn publish_scene(&self, scene: &str, event: SceneEvent) {
+        if let Some(sender) = self.scenes.get(scene) {
+            let _ = sender.send(event);
+        }
+        self.release_scene(scene);
+    }
+
+    // Drops the scene channel once its last receiver is gone.
+    pub fn release_scene(&self, scene: &str) {
+        self.scenes
+            .remove_if(scene, |_, sender| sender.receiver_count() == 0);
+    }
+
+    pub fn subscribe_user(&self, user_id: u64) -> broadcast::Receiver<UserEvent> {
+        self.users
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(USER_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    pub fn publish_user(&self, user_id: u64, event: UserEvent) {
+        if let Some(sender) = self.users.get(&user_id) {
+            let _ = sender.send(event);
+        }
+        self.release_user(user_id);
+    }
+
+    pub fn release_user(&self, user_id: u64) {
+        self.users
+            .remove_if(&user_id, |_, sender| sender.receiver_count() == 0);
+    }
+}
+
+// Releases a user's channel when the stream holding it is dropped.
+pub struct UserSubscription {
+    pub hub: Arc<EventHub>,
+    pub user_id: u64,
+}
+
+impl Drop for UserSubscription {
+    fn drop(&mut self) {
+        self.hub.release_user(self.user_id);
+    }
+}
@@ -0,0 +1,144 @@
+// When structures were last served.
+//
+// get_random notes the ids it hands out here instead of writing on every
+// fetch; flush_fetches writes the latest time per structure to
+// structures.last_fetched_at every FETCH_FLUSH_SECONDS in one statement.
+// It feeds the least_recently_fetched prune policy and shows moderators which
+// structures nobody gets to see. Times still waiting here are lost if the
+// process stops, which only makes a structure look a little staler.
+//
+// With FETCH_HISTORY_DAYS set it also keeps who got which structure, written
+// to fetch_history with the same flush, so get_random can put what a player
+// was already served behind what they haven't seen yet.
+
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+#[derive(Debug, Default)]
+pub struct FetchLog {
+    // structure id -> epoch millis of its latest fetch
+    pending: Mutex<HashMap<i64, i64>>,
+    // (user id, structure id) -> epoch millis it was served to them
+    history: Mutex<HashMap<(i64, i64), i64>>,
+}
+
+impl FetchLog {
+    pub fn record(&self, ids: impl IntoIterator<Item = i64>, at: i64) {
+        let mut pending = self.pending.lock().unwrap();
+        for id in ids {
+            let latest = pending.entry(id).or_insert(at);
+            *latest = (*latest).max(at);
+        }
+    }
+
+    pub fn record_history(&self, user_id: i64, ids: impl IntoIterator<Item = i64>, at: i64) {
+        let mut history = self.history.lock().unwrap();
+        for id in ids {
+            let latest = history.entry((user_id, id)).or_insert(at);
+            *latest = (*latest).max(at);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty() && self.history.lock().unwrap().is_empty()
+    }
+
+    // Which of `ids` `user_id` was served since `since`, flushed or not
+    pub async fn seen(
+        &self,
+        db: &SqlitePool,
+        user_id: i64,
+        ids: &[i64],
+        since: i64,
+    ) -> Result<HashSet<i64>, sqlx::Error> {
+        let mut seen: HashSet<i64> = sqlx::query_scalar(
+            r#"SELECT structure_id FROM fetch_history
+               WHERE user_id = ? AND fetched_at >= ?
+                 AND structure_id IN (SELECT value FROM json_each(?))"#,
+        )
+        .bind(user_id)
+        .bind(since)
+        .bind(json!(ids).to_string())
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .collect();
+        let history = self.history.lock().unwrap();
+        seen.extend(
+            ids.iter()
+                .copied()
+                .filter(|id| history.get(&(user_id, *id)).is_some_and(|&at| at >= since)),
+        );
+        Ok(seen)
+    }
+
+    // Writes every pending time and returns how many structures it touched.
+    // On failure the times go back for the next flush.
+    pub async fn flush(&self, db: &SqlitePool) -> Result<usize, sqlx::Error> {
+        self.flush_history(db).await?;
+        let taken = std::mem::take(&mut *self.pending.lock().unwrap());
+        if taken.is_empty() {
+            return Ok(0);
+        }
+        let fetched: Vec<_> = taken.iter().map(|(id, at)| json!([id, at])).collect();
+        let written = sqlx::query(
+            r#"UPDATE structures
+               SET last_fetched_at = MAX(COALESCE(last_fetched_at, 0), json_extract(f.value, '$[1]'))
+               FROM json_each(?) AS f
+               WHERE structures.id = json_extract(f.value, '$[0]')"#,
+        )
+        .bind(json!(fetched).to_string())
+        .execute(db)
+        .await;
+        if let Err(e) = written {
+            for (id, at) in taken {
+                self.record([id], at);
+            }
+            return Err(e);
+        }
+        Ok(taken.len())
+    }
+
+    async fn flush_history(&self, db: &SqlitePool) -> Result<(), sqlx::Error> {
+        let taken = std::mem::take(&mut *self.history.lock().unwrap());
+        if taken.is_empty() {
+            return Ok(());
+        }
+        let served: Vec<_> = taken
+            .iter()
+            .map(|((user_id, id), at)| json!([user_id, id, at]))
+            .collect();
+        let written = sqlx::query(
+            r#"INSERT INTO fetch_history (user_id, structure_id, fetched_at)
+               SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]'), json_extract(value, '$[2]')
+               FROM json_each(?) WHERE true
+               ON CONFLICT(user_id, structure_id)
+               DO UPDATE SET fetched_at = MAX(fetched_at, excluded.fetched_at)"#,
+        )
+        .bind(json!(served).to_string())
+        .execute(db)
+        .await;
+        if let Err(e) = written {
+            for ((user_id, id), at) in taken {
+                self.record_history(user_id, [id], at);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// Drops fetch_history rows older than `before`, returning how many
+pub async fn prune_history(db: &SqlitePool, before: i64) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM fetch_history WHERE fetched_at < ?")
+            .bind(before)
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
@@ -0,0 +1,1269 @@
+// Admin endpoints under /admin/v1, all behind X-Admin-Key: export and
+// import, the structure browser, bulk moderation, the allowlist and invites,
+// announcements, community events, seasons, stats, the position heatmap and
+// like abuse telemetry.
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+
+use crate::{
+    MILLIS_IN_DAY,
+    auth::{ADMIN_HEADER, AdminUser},
+    db::{
+        dump::{self, ImportSummary, RemoteImport, RemoteImportSummary, StructureRecord},
+        integrity::{self, IntegrityReport},
+        queries::{
+            Invite, auto_approve_reviews, create_invite, list_allowlist, list_invites, purge_user,
+            record_audit, restore_archived, revoke_invite, rollover_season, set_allowlisted,
+        },
+        schema::table_columns,
+        sizes::{self, SizeReport},
+    },
+    db_metrics::DbMetricsResponse,
+    error::{ApiError, AppError},
+    extract::{JsonBody, QueryParams},
+    handlers::{
+        motd::{Announcement, MAX_ANNOUNCEMENT_LENGTH},
+        structures::now_millis,
+    },
+    limit_stats::LimitStatsResponse,
+    server_events::{EVENT_COLUMNS, EventKind, MAX_EVENT_MULTIPLIER, ServerEvent},
+    state::AppState,
+};
+
+// --- admin: export / import ---
+
+pub async fn admin_export(State(state): State<AppState>, _admin: AdminUser) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel(256);
+    let db = state.read_db.clone();
+
+    tokio::spawn(async move {
+        let completed = dump::export(&db, &tx).await;
+        tracing::info!(
+            "admin_export called result=finished completed={}",
+            completed
+        );
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+}
+
+pub async fn admin_import(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
+    // Whole dump goes in one transaction: a bad line leaves the database untouched.
+    let mut tx = state.db.begin().await?;
+
+    let mut summary = ImportSummary::default();
+    let mut stream = body.into_data_stream();
+    let mut pending: Vec<u8> = Vec::new();
+    let mut line_no = 0_u64;
+    let mut finished = false;
+
+    while !finished {
+        match stream.next().await {
+            Some(Ok(chunk)) => pending.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, e.to_string()).into());
+            }
+            None => {
+                // flush a trailing line without a newline
+                pending.push(b'\n');
+                finished = true;
+            }
+        }
+
+        while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            line_no += 1;
+            dump::import_record(&mut tx, &line, &mut summary)
+                .await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("line {line_no}: {e}")))?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(summary))
+}
+
+// the whole dump has to arrive within this, unlike Steam calls
+const REMOTE_IMPORT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Deserialize)]
+pub struct RemoteImportBody {
+    url: String, // base URL of the other server
+    key: String, // its ADMIN_API_KEY
+    // provenance for its own uploads; defaults to the URL
+    origin: Option<String>,
+}
+
+// Pulls another server's export and merges it in one transaction (see
+// dump::RemoteImport). The key travels in the body so it stays out of logs.
+pub async fn admin_import_remote(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    JsonBody(body): JsonBody<RemoteImportBody>,
+) -> Result<Json<RemoteImportSummary>, AppError> {
+    let url = body.url.trim_end_matches('/');
+    let remote_failed =
+        |e: &dyn std::fmt::Display| ApiError::new(StatusCode::BAD_GATEWAY, format!("{url}: {e}"));
+    let mut response = state
+        .http
+        .get(format!("{url}/admin/v1/export"))
+        .header(&ADMIN_HEADER, &body.key)
+        .timeout(REMOTE_IMPORT_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| remote_failed(&e))?;
+
+    let origin = body.origin.clone().unwrap_or_else(|| url.to_string());
+    let mut import = RemoteImport::new(origin, state.config().server_id.clone());
+    let mut tx = state.db.begin().await?;
+    let mut pending: Vec<u8> = Vec::new();
+    let mut line_no = 0_u64;
+    let mut finished = false;
+    while !finished {
+        match response.chunk().await.map_err(|e| remote_failed(&e))? {
+            Some(chunk) => pending.extend_from_slice(&chunk),
+            None => {
+                pending.push(b'\n');
+                finished = true;
+            }
+        }
+        while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            line_no += 1;
+            import
+                .record(&mut tx, &line)
+                .await
+                .map_err(|e| remote_failed(&format!("line {line_no}: {e}")))?;
+        }
+    }
+    tx.commit().await?;
+
+    tracing::info!(
+        "admin_import_remote called url={} structures={} skipped={}",
+        url,
+        import.summary.structures,
+        import.summary.skipped
+    );
+    Ok(Json(import.summary))
+}
+
+// 429s per route, recently rate-limited players and like trims since startup
+pub async fn admin_limit_stats(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Json<LimitStatsResponse> {
+    let stats = state.limit_stats.snapshot(state.rate_limit_scale.factor());
+    Json(stats)
+}
+
+// Query timings, connection waits and pool usage since startup
+pub async fn admin_db_stats(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Json<DbMetricsResponse> {
+    Json(state.db_metrics.snapshot(&state.db, &state.read_db))
+}
+
+// Rows per table and the bytes the file, the WAL and every table and index take
+pub async fn admin_db_sizes(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<SizeReport>, AppError> {
+    Ok(Json(sizes::report(&state.read_db).await?))
+}
+
+#[derive(Serialize)]
+pub struct ReloadResponse {
+    reloaded: bool,
+    restart_required: Vec<&'static str>,
+}
+
+pub async fn admin_reload(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<ReloadResponse>, AppError> {
+    let ignored = state.reload_config().map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("config reload failed: {e:#}"),
+        )
+    })?;
+
+    Ok(Json(ReloadResponse {
+        reloaded: true,
+        restart_required: ignored,
+    }))
+}
+
+// --- admin: maintenance ---
+
+#[derive(Serialize, Deserialize)]
+pub struct MaintenanceState {
+    read_only: bool,
+}
+
+pub async fn admin_get_maintenance(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Json<MaintenanceState> {
+    Json(MaintenanceState {
+        read_only: state.read_only(),
+    })
+}
+
+// Turns read-only mode on or off until the next restart, or until a reload
+// that changes READ_ONLY
+pub async fn admin_set_maintenance(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<MaintenanceState>,
+) -> Json<MaintenanceState> {
+    state.set_read_only(p.read_only);
+    Json(p)
+}
+
+// --- admin: rate limit exemptions ---
+
+#[derive(Serialize)]
+pub struct RateLimitExemptions {
+    steam_ids: Vec<u64>,
+}
+
+pub async fn admin_list_rate_limit_exempt(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Json<RateLimitExemptions> {
+    let mut steam_ids: Vec<u64> = state.rate_limit_exempt.iter().map(|id| *id).collect();
+    steam_ids.sort_unstable();
+    Json(RateLimitExemptions { steam_ids })
+}
+
+// Exempts a player until the next restart, or until a reload that changes
+// RATE_LIMIT_EXEMPT_STEAMIDS
+pub async fn admin_add_rate_limit_exempt(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(steamid): Path<u64>,
+) -> StatusCode {
+    if state.rate_limit_exempt.insert(steamid) {
+        tracing::info!("rate_limit_exempt added steamid={}", steamid);
+    }
+    StatusCode::NO_CONTENT
+}
+
+pub async fn admin_remove_rate_limit_exempt(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(steamid): Path<u64>,
+) -> StatusCode {
+    if state.rate_limit_exempt.remove(&steamid).is_some() {
+        tracing::info!("rate_limit_exempt removed steamid={}", steamid);
+    }
+    StatusCode::NO_CONTENT
+}
+
+// --- admin: allowlist ---
+
+#[derive(Serialize)]
+pub struct Allowlist {
+    steam_ids: Vec<i64>,
+}
+
+pub async fn admin_list_allowlist(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<Allowlist>, AppError> {
+    let steam_ids = list_allowlist(&state.read_db).await?;
+    Ok(Json(Allowlist { steam_ids }))
+}
+
+pub async fn admin_add_allowlist(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(steamid): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    set_allowlisted(&state.db, steamid, true).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn admin_remove_allowlist(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(steamid): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    set_allowlisted(&state.db, steamid, false).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- admin: announcements ---
+
+#[derive(Serialize)]
+pub struct AnnouncementList {
+    announcements: Vec<Announcement>,
+}
+
+// Every announcement, past and scheduled ones included, newest first
+pub async fn admin_list_announcements(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<AnnouncementList>, AppError> {
+    let announcements = sqlx::query_as(
+        "SELECT id, message, starts_at, ends_at FROM announcements ORDER BY id DESC",
+    )
+    .fetch_all(&state.read_db)
+    .await?;
+    Ok(Json(AnnouncementList { announcements }))
+}
+
+#[derive(Deserialize)]
+pub struct NewAnnouncement {
+    message: String,
+    starts_at: Option<i64>,
+    ends_at: Option<i64>,
+}
+
+pub async fn admin_create_announcement(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    JsonBody(body): JsonBody<NewAnnouncement>,
+) -> Result<(StatusCode, Json<Announcement>), AppError> {
+    let message = body.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_ANNOUNCEMENT_LENGTH {
+        return Err(AppError::Validation(
+            "message",
+            format!("message must be 1 to {MAX_ANNOUNCEMENT_LENGTH} characters."),
+        ));
+    }
+    if let (Some(starts_at), Some(ends_at)) = (body.starts_at, body.ends_at)
+        && ends_at <= starts_at
+    {
+        return Err(AppError::Validation(
+            "ends_at",
+            "ends_at must be after starts_at.".into(),
+        ));
+    }
+
+    let id = sqlx::query(
+        "INSERT INTO announcements (message, starts_at, ends_at, created_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(message)
+    .bind(body.starts_at)
+    .bind(body.ends_at)
+    .bind(now_millis())
+    .execute(&state.db)
+    .await?
+    .last_insert_rowid();
+    tracing::info!("announcement created id={}", id);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Announcement {
+            id,
+            message: message.to_string(),
+            starts_at: body.starts_at,
+            ends_at: body.ends_at,
+        }),
+    ))
+}
+
+pub async fn admin_delete_announcement(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query("DELETE FROM announcements WHERE id = ?")
+        .bind(id)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(AppError::NotFound("No announcement with this id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- admin: community events ---
+
+#[derive(Serialize)]
+pub struct ServerEventList {
+    events: Vec<ServerEvent>,
+}
+
+// Every event, past ones included, newest first
+pub async fn admin_list_server_events(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<ServerEventList>, AppError> {
+    let events = sqlx::query_as(&format!(
+        "SELECT {EVENT_COLUMNS} FROM server_events ORDER BY id DESC"
+    ))
+    .fetch_all(&state.read_db)
+    .await?;
+    Ok(Json(ServerEventList { events }))
+}
+
+#[derive(Deserialize)]
+pub struct NewServerEvent {
+    name: Option<String>,
+    kind: EventKind,
+    multiplier: Option<i64>,
+    scene: Option<String>,
+    starts_at: i64,
+    ends_at: i64,
+}
+
+pub async fn admin_create_server_event(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    JsonBody(body): JsonBody<NewServerEvent>,
+) -> Result<(StatusCode, Json<ServerEvent>), AppError> {
+    if body.ends_at <= body.starts_at {
+        return Err(AppError::Validation(
+            "ends_at",
+            "ends_at must be after starts_at.".into(),
+        ));
+    }
+    let (multiplier, scene) = match body.kind {
+        EventKind::LikeMultiplier | EventKind::RelaxedCaps => match body.multiplier {
+            Some(m) if (2..=MAX_EVENT_MULTIPLIER).contains(&m) => (Some(m), None),
+            _ => {
+                return Err(AppError::Validation(
+                    "multiplier",
+                    format!("multiplier must be between 2 and {MAX_EVENT_MULTIPLIER}."),
+                ));
+            }
+        },
+        EventKind::FeaturedScene => {
+            let max = state.config().max_scene_length;
+            match body.scene.as_deref().map(str::trim) {
+                Some(scene) if !scene.is_empty() && scene.len() <= max => {
+                    (None, Some(scene.to_string()))
+                }
+                _ => {
+                    return Err(AppError::Validation(
+                        "scene",
+                        format!("scene must be 1 to {max} characters."),
+                    ));
+                }
+            }
+        }
+    };
+    let name = body
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    let id = sqlx::query(
+        r#"INSERT INTO server_events (name, kind, multiplier, scene, starts_at, ends_at, created_at)
+           VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+    )
+    .bind(&name)
+    .bind(body.kind.as_str())
+    .bind(multiplier)
+    .bind(&scene)
+    .bind(body.starts_at)
+    .bind(body.ends_at)
+    .bind(now_millis())
+    .execute(&state.db)
+    .await?
+    .last_insert_rowid();
+    state.server_events.invalidate().await;
+    tracing::info!("server_event created id={} kind={}", id, body.kind.as_str());
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ServerEvent {
+            id,
+            name,
+            kind: body.kind,
+            multiplier,
+            scene,
+            starts_at: body.starts_at,
+            ends_at: body.ends_at,
+        }),
+    ))
+}
+
+// Ends a running event at once, or cancels a scheduled one
+pub async fn admin_delete_server_event(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query("DELETE FROM server_events WHERE id = ?")
+        .bind(id)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(AppError::NotFound("No event with this id"));
+    }
+    state.server_events.invalidate().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- admin: invites ---
+
+#[derive(Serialize)]
+pub struct InviteList {
+    invites: Vec<Invite>,
+}
+
+pub async fn admin_list_invites(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<InviteList>, AppError> {
+    let invites = list_invites(&state.read_db).await?;
+    Ok(Json(InviteList { invites }))
+}
+
+#[derive(Deserialize)]
+pub struct NewInvite {
+    // who it is for, so it can be found again to revoke
+    note: Option<String>,
+}
+
+// Mints a code that lets one more person in while the server is private
+pub async fn admin_create_invite(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    JsonBody(body): JsonBody<NewInvite>,
+) -> Result<(StatusCode, Json<Invite>), AppError> {
+    let invite = create_invite(&state.db, body.note.as_deref(), now_millis()).await?;
+    tracing::info!("invite created note={:?}", invite.note);
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+// Takes effect with the next request that uses it
+pub async fn admin_revoke_invite(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(code): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !revoke_invite(&state.db, &code, now_millis()).await? {
+        return Err(AppError::NotFound("No unrevoked invite with this code"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct IntegrityParams {
+    #[serde(default)]
+    quick: bool,
+    #[serde(default)]
+    reindex: bool,
+}
+
+// Checks the database file; with reindex, rebuilds the indexes if that finds
+// problems. Always 200: the report says whether it is healthy
+pub async fn admin_integrity_check(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<IntegrityParams>,
+) -> Result<Json<IntegrityReport>, AppError> {
+    let report = if p.reindex {
+        integrity::repair(&state.read_db, &state.db, p.quick).await?
+    } else {
+        integrity::check(&state.read_db, p.quick).await?
+    };
+    tracing::info!(
+        "admin_integrity_check called ok={} problems={} foreign_key_violations={} reindexed={}",
+        report.ok,
+        report.problems.len(),
+        report.foreign_key_violations.len(),
+        report.reindexed
+    );
+    Ok(Json(report))
+}
+
+// --- admin: structure browser ---
+
+const ADMIN_PAGE_DEFAULT: i64 = 50;
+const ADMIN_PAGE_MAX: i64 = 500;
+
+#[derive(Deserialize)]
+pub struct AdminStructuresParams {
+    user_id: Option<i64>,
+    scene: Option<String>,
+    prefab: Option<String>,
+    created_before: Option<i64>, // epoch millis
+    created_after: Option<i64>,
+    min_likes: Option<i64>,
+    // not served by a random fetch since then (epoch millis), never-served ones included
+    not_fetched_since: Option<i64>,
+    deleted: Option<bool>,
+    // also list structures moved to structures_archive (cold or past seasons)
+    #[serde(default)]
+    include_archived: bool,
+    before_id: Option<i64>, // cursor: pass the previous page's next_before_id
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct AdminStructureRow {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    record: StructureRecord,
+    archived: bool,
+}
+
+#[derive(Serialize)]
+pub struct AdminStructuresPage {
+    structures: Vec<AdminStructureRow>,
+    next_before_id: Option<i64>,
+}
+
+// Newest first, keyset-paginated on id.
+pub async fn admin_list_structures(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<AdminStructuresParams>,
+) -> Result<Json<AdminStructuresPage>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(ADMIN_PAGE_DEFAULT)
+        .clamp(1, ADMIN_PAGE_MAX);
+
+    let source = if p.include_archived {
+        // the archive can lag behind in column order, so name them
+        let columns = table_columns(&state.read_db, "structures")
+            .await?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "(SELECT {columns}, 0 AS archived FROM structures UNION ALL SELECT {columns}, 1 AS archived FROM structures_archive)"
+        )
+    } else {
+        "(SELECT *, 0 AS archived FROM structures)".to_string()
+    };
+    let mut builder =
+        sqlx::QueryBuilder::<sqlx::Sqlite>::new(format!("SELECT * FROM {source} WHERE 1 = 1"));
+    if let Some(user_id) = p.user_id {
+        builder.push(" AND user_id = ").push_bind(user_id);
+    }
+    if let Some(scene) = &p.scene {
+        builder.push(" AND scene = ").push_bind(scene);
+    }
+    if let Some(prefab) = &p.prefab {
+        builder.push(" AND prefab = ").push_bind(prefab);
+    }
+    if let Some(before) = p.created_before {
+        builder.push(" AND created_at < ").push_bind(before);
+    }
+    if let Some(after) = p.created_after {
+        builder.push(" AND created_at >= ").push_bind(after);
+    }
+    if let Some(min_likes) = p.min_likes {
+        builder.push(" AND likes >= ").push_bind(min_likes);
+    }
+    if let Some(since) = p.not_fetched_since {
+        builder
+            .push(" AND COALESCE(last_fetched_at, 0) < ")
+            .push_bind(since);
+    }
+    if let Some(deleted) = p.deleted {
+        builder.push(" AND deleted = ").push_bind(deleted);
+    }
+    if let Some(before_id) = p.before_id {
+        builder.push(" AND id < ").push_bind(before_id);
+    }
+    // one extra row tells whether another page exists
+    builder
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(limit + 1);
+
+    let mut structures = builder
+        .build_query_as::<AdminStructureRow>()
+        .fetch_all(&state.read_db)
+        .await?;
+
+    let next_before_id = if structures.len() as i64 > limit {
+        structures.truncate(limit as usize);
+        structures.last().and_then(|r| r.record.structure.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AdminStructuresPage {
+        structures,
+        next_before_id,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct AdminSearchParams {
+    // case-insensitive substrings
+    prefab: Option<String>,
+    username: Option<String>,
+    min_likes: Option<i64>,
+    max_likes: Option<i64>,
+    // bounding box; pass scene too so the position index is used
+    scene: Option<String>,
+    min_x: Option<f64>,
+    max_x: Option<f64>,
+    min_y: Option<f64>,
+    max_y: Option<f64>,
+    min_z: Option<f64>,
+    max_z: Option<f64>,
+    deleted: Option<bool>,
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+// The trigram index only matches substrings of three or more characters
+const SEARCH_TRIGRAM_MIN_CHARS: usize = 3;
+
+// Live structures (not the archive) matching every given filter, newest first,
+// keyset-paginated on id like the structure browser
+pub async fn admin_search_structures(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<AdminSearchParams>,
+) -> Result<Json<AdminStructuresPage>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(ADMIN_PAGE_DEFAULT)
+        .clamp(1, ADMIN_PAGE_MAX);
+
+    let mut builder = sqlx::QueryBuilder::<sqlx::Sqlite>::new(
+        "SELECT *, 0 AS archived FROM structures WHERE 1 = 1",
+    );
+    let mut phrases = Vec::new();
+    for (column, needle) in [("username", &p.username), ("prefab", &p.prefab)] {
+        let Some(needle) = needle.as_deref().filter(|n| !n.is_empty()) else {
+            continue;
+        };
+        if needle.chars().count() >= SEARCH_TRIGRAM_MIN_CHARS {
+            phrases.push(format!("{column} : \"{}\"", needle.replace('"', "\"\"")));
+        } else {
+            builder
+                .push(format!(" AND instr(lower({column}), lower("))
+                .push_bind(needle.to_string())
+                .push(")) > 0");
+        }
+    }
+    if !phrases.is_empty() {
+        builder
+            .push(" AND id IN (SELECT rowid FROM structure_search WHERE structure_search MATCH ")
+            .push_bind(phrases.join(" AND "))
+            .push(")");
+    }
+    if let Some(min_likes) = p.min_likes {
+        builder.push(" AND likes >= ").push_bind(min_likes);
+    }
+    if let Some(max_likes) = p.max_likes {
+        builder.push(" AND likes <= ").push_bind(max_likes);
+    }
+    if let Some(scene) = &p.scene {
+        builder.push(" AND scene = ").push_bind(scene);
+    }
+    let bounds = [
+        ("pos_x", p.min_x, p.max_x),
+        ("pos_y", p.min_y, p.max_y),
+        ("pos_z", p.min_z, p.max_z),
+    ];
+    for (column, min, max) in bounds {
+        if let Some(min) = min {
+            builder.push(format!(" AND {column} >= ")).push_bind(min);
+        }
+        if let Some(max) = max {
+            builder.push(format!(" AND {column} <= ")).push_bind(max);
+        }
+    }
+    if let Some(deleted) = p.deleted {
+        builder.push(" AND deleted = ").push_bind(deleted);
+    }
+    if let Some(before_id) = p.before_id {
+        builder.push(" AND id < ").push_bind(before_id);
+    }
+    builder
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(limit + 1);
+
+    let mut structures = builder
+        .build_query_as::<AdminStructureRow>()
+        .fetch_all(&state.read_db)
+        .await?;
+
+    let next_before_id = if structures.len() as i64 > limit {
+        structures.truncate(limit as usize);
+        structures.last().and_then(|r| r.record.structure.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AdminStructuresPage {
+        structures,
+        next_before_id,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    scene: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PurgeResponse {
+    purged: u64,
+}
+
+// Soft-deletes every live structure of a user, optionally only in one scene.
+pub async fn admin_purge_user(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(target): Path<i64>,
+    QueryParams(p): QueryParams<PurgeParams>,
+) -> Result<Json<PurgeResponse>, AppError> {
+    let purged = purge_user(&state.db, target, p.scene.as_deref()).await?;
+
+    Ok(Json(PurgeResponse { purged }))
+}
+
+pub async fn admin_restore_structure(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db.begin().await?;
+
+    let restored: Option<Option<String>> =
+        sqlx::query_scalar("SELECT deleted_by FROM structures WHERE id = ? AND deleted = 1")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    let Some(deleted_by) = restored else {
+        tx.rollback().await.ok();
+        return Err(AppError::NotFound("No deleted structure with this id"));
+    };
+
+    sqlx::query(
+        "UPDATE structures SET deleted = 0, deleted_at = NULL, deleted_by = NULL WHERE id = ?",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    record_audit(
+        &mut tx,
+        "restore_structure",
+        &id.to_string(),
+        serde_json::json!({ "deleted_by": deleted_by }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- admin: plausibility review queue ---
+
+#[derive(Deserialize)]
+pub struct ReviewParams {
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ReviewItem {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    record: StructureRecord,
+    review_reason: Option<String>,
+    // epoch millis; null waits for a moderator
+    review_auto_approve_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ReviewPage {
+    structures: Vec<ReviewItem>,
+    next_before_id: Option<i64>,
+}
+
+// Uploads PLAUSIBILITY_CHECKS held back, newest first, keyset-paginated like
+// the structure browser.
+pub async fn admin_list_review(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<ReviewParams>,
+) -> Result<Json<ReviewPage>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(ADMIN_PAGE_DEFAULT)
+        .clamp(1, ADMIN_PAGE_MAX);
+    let mut structures: Vec<ReviewItem> = sqlx::query_as(
+        r#"SELECT * FROM structures WHERE deleted = 1 AND deleted_by = 'review' AND id < ?
+           ORDER BY id DESC LIMIT ?"#,
+    )
+    .bind(p.before_id.unwrap_or(i64::MAX))
+    .bind(limit + 1)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    let next_before_id = if structures.len() as i64 > limit {
+        structures.truncate(limit as usize);
+        structures.last().and_then(|r| r.record.structure.id)
+    } else {
+        None
+    };
+    Ok(Json(ReviewPage {
+        structures,
+        next_before_id,
+    }))
+}
+
+// Approving puts the structure into rotation; rejecting turns it into an
+// ordinary moderator removal, which its owner cannot undo.
+pub async fn admin_approve_review(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    resolve_review(
+        &state,
+        id,
+        "approve_review",
+        r#"UPDATE structures
+           SET deleted = 0, deleted_at = NULL, deleted_by = NULL, review_auto_approve_at = NULL
+           WHERE id = ?"#,
+    )
+    .await
+}
+
+pub async fn admin_reject_review(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    resolve_review(
+        &state,
+        id,
+        "reject_review",
+        "UPDATE structures SET deleted_by = 'admin', review_auto_approve_at = NULL WHERE id = ?",
+    )
+    .await
+}
+
+async fn resolve_review(
+    state: &AppState,
+    id: i64,
+    action: &str,
+    update: &str,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db.begin().await?;
+    let reason: Option<Option<String>> = sqlx::query_scalar(
+        "SELECT review_reason FROM structures WHERE id = ? AND deleted = 1 AND deleted_by = 'review'",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(reason) = reason else {
+        tx.rollback().await.ok();
+        return Err(AppError::NotFound(
+            "No structure awaiting review with this id",
+        ));
+    };
+
+    sqlx::query(update).bind(id).execute(&mut *tx).await?;
+    record_audit(
+        &mut tx,
+        action,
+        &id.to_string(),
+        serde_json::json!({ "review_reason": reason }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Approves held uploads once their NEWCOMER_AUTO_APPROVE_SECONDS are up
+pub async fn auto_approve_held_uploads(state: AppState) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        match auto_approve_reviews(&state.db, now_millis()).await {
+            Ok(0) => {}
+            Ok(approved) => tracing::info!("review auto_approved structures={}", approved),
+            Err(e) => tracing::error!("review auto_approve failed error={}", e),
+        }
+    }
+}
+
+// Moves a structure archived as cold back to the live table
+pub async fn admin_unarchive_structure(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !restore_archived(&state.db, id, now_millis()).await? {
+        return Err(AppError::NotFound("No archived structure with this id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- admin: seasons ---
+
+#[derive(Serialize)]
+pub struct RolloverResponse {
+    season: i64,
+    archived: u64,
+}
+
+pub async fn admin_rollover_season(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Result<Json<RolloverResponse>, AppError> {
+    let season = state.config().current_season;
+
+    let archived = rollover_season(&state.db, season).await?;
+
+    Ok(Json(RolloverResponse { season, archived }))
+}
+
+// --- admin: heatmap ---
+
+const HEATMAP_CELL_DEFAULT: f64 = 10.0;
+
+#[derive(Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeatmapFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct HeatmapParams {
+    scene: Option<String>,
+    // edge length of a grid cell in world units
+    cell: Option<f64>,
+    #[serde(default)]
+    format: HeatmapFormat,
+}
+
+// Cell (x, y, z) spans x * cell up to (x + 1) * cell on each axis
+#[derive(Serialize)]
+pub struct HeatmapCell {
+    x: i64,
+    y: i64,
+    z: i64,
+    count: i64,
+}
+
+#[derive(Serialize)]
+pub struct HeatmapResponse {
+    season: i64,
+    cell: f64,
+    scenes: BTreeMap<String, Vec<HeatmapCell>>,
+}
+
+// Live structures of the current season counted per grid cell and scene,
+// so maps can be shaded without exporting the table.
+pub async fn admin_heatmap(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<HeatmapParams>,
+) -> Result<Response, AppError> {
+    let cell = p.cell.unwrap_or(HEATMAP_CELL_DEFAULT);
+    if !cell.is_finite() || cell < 1.0 {
+        return Err(AppError::Validation(
+            "cell",
+            "cell must be at least 1.".into(),
+        ));
+    }
+    let season = state.config().current_season;
+
+    // floor() needs SQLite's optional math functions; truncate and step
+    // negative fractions down instead
+    let rows: Vec<(String, i64, i64, i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT scene,
+               CAST(fx AS INTEGER) - (fx < CAST(fx AS INTEGER)) AS x,
+               CAST(fy AS INTEGER) - (fy < CAST(fy AS INTEGER)) AS y,
+               CAST(fz AS INTEGER) - (fz < CAST(fz AS INTEGER)) AS z,
+               COUNT(*)
+        FROM (
+            SELECT scene, pos_x / ?1 AS fx, pos_y / ?1 AS fy, pos_z / ?1 AS fz
+            FROM structures
+            WHERE season_id = ?2 AND deleted = 0 AND (?3 IS NULL OR scene = ?3)
+              AND pos_x IS NOT NULL AND pos_y IS NOT NULL AND pos_z IS NOT NULL
+        )
+        GROUP BY scene, x, y, z
+        ORDER BY scene, x, y, z
+        "#,
+    )
+    .bind(cell)
+    .bind(season)
+    .bind(&p.scene)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    if p.format == HeatmapFormat::Csv {
+        let mut csv = String::from("scene,x,y,z,count\n");
+        for (scene, x, y, z, count) in rows {
+            // scene names are free text
+            let scene = if scene.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", scene.replace('"', "\"\""))
+            } else {
+                scene
+            };
+            csv.push_str(&format!("{scene},{x},{y},{z},{count}\n"));
+        }
+        return Ok(([(header::CONTENT_TYPE, "text/csv")], csv).into_response());
+    }
+
+    let mut scenes: BTreeMap<String, Vec<HeatmapCell>> = BTreeMap::new();
+    for (scene, x, y, z, count) in rows {
+        scenes
+            .entry(scene)
+            .or_default()
+            .push(HeatmapCell { x, y, z, count });
+    }
+    Ok(Json(HeatmapResponse {
+        season,
+        cell,
+        scenes,
+    })
+    .into_response())
+}
+
+// --- admin: like abuse telemetry ---
+
+const LIKE_ABUSE_DAYS_DEFAULT: i64 = 7;
+const LIKE_ABUSE_DAYS_MAX: i64 = 90;
+const LIKE_ABUSE_MIN_LIKES_DEFAULT: i64 = 20;
+const LIKE_ABUSE_LIMIT_DEFAULT: i64 = 50;
+const LIKE_ABUSE_LIMIT_MAX: i64 = 500;
+
+#[derive(Deserialize)]
+pub struct LikeAbuseParams {
+    days: Option<i64>,
+    // likes each way for a pair, unfetched likes for a liker
+    min_likes: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ReciprocalPair {
+    user_a: i64,
+    user_b: i64,
+    a_to_b: i64,
+    b_to_a: i64,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct UnfetchedLiker {
+    liker_id: i64,
+    likes: i64,
+    // likes on structures no random fetch served them
+    unfetched: i64,
+}
+
+#[derive(Serialize)]
+pub struct LikeAbuseResponse {
+    since: i64,
+    reciprocal: Vec<ReciprocalPair>,
+    // null while FETCH_HISTORY_DAYS is off
+    unfetched: Option<Vec<UnfetchedLiker>>,
+    // the unfetched check looks back no further than the fetch history
+    unfetched_since: Option<i64>,
+}
+
+// Signals for like-farming rings in likes_ledger: pairs of players liking
+// each other heavily, and players liking structures the server never served
+// them (from the fetch history), i.e. ids passed around outside the game.
+// Likes still in the like buffer are not counted yet.
+pub async fn admin_like_abuse(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    QueryParams(p): QueryParams<LikeAbuseParams>,
+) -> Result<Json<LikeAbuseResponse>, AppError> {
+    let days = p
+        .days
+        .unwrap_or(LIKE_ABUSE_DAYS_DEFAULT)
+        .clamp(1, LIKE_ABUSE_DAYS_MAX);
+    let min_likes = p.min_likes.unwrap_or(LIKE_ABUSE_MIN_LIKES_DEFAULT).max(1);
+    let limit = p
+        .limit
+        .unwrap_or(LIKE_ABUSE_LIMIT_DEFAULT)
+        .clamp(1, LIKE_ABUSE_LIMIT_MAX);
+    let now = now_millis();
+    let since = now - days * MILLIS_IN_DAY;
+
+    let reciprocal = sqlx::query_as(
+        r#"
+        WITH given AS (
+            SELECT liker_id, owner_id, SUM(count) AS likes FROM likes_ledger
+            WHERE created_at >= ?
+            GROUP BY liker_id, owner_id
+        )
+        SELECT a.liker_id AS user_a, a.owner_id AS user_b, a.likes AS a_to_b, b.likes AS b_to_a
+        FROM given a JOIN given b ON b.liker_id = a.owner_id AND b.owner_id = a.liker_id
+        WHERE a.liker_id < a.owner_id AND a.likes >= ? AND b.likes >= ?
+        ORDER BY MIN(a.likes, b.likes) DESC, user_a, user_b
+        LIMIT ?
+        "#,
+    )
+    .bind(since)
+    .bind(min_likes)
+    .bind(min_likes)
+    .bind(limit)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    let history_days = state.config().fetch_history_days as i64;
+    let unfetched_since = (history_days > 0).then(|| since.max(now - history_days * MILLIS_IN_DAY));
+    let unfetched = match unfetched_since {
+        Some(unfetched_since) => Some(
+            sqlx::query_as(
+                r#"
+                SELECT l.liker_id, SUM(l.count) AS likes,
+                       SUM(CASE WHEN h.structure_id IS NULL THEN l.count ELSE 0 END) AS unfetched
+                FROM likes_ledger l
+                LEFT JOIN fetch_history h
+                  ON h.user_id = l.liker_id AND h.structure_id = l.structure_id
+                WHERE l.created_at >= ?
+                GROUP BY l.liker_id
+                HAVING unfetched >= ?
+                ORDER BY unfetched DESC, l.liker_id
+                LIMIT ?
+                "#,
+            )
+            .bind(unfetched_since)
+            .bind(min_likes)
+            .bind(limit)
+            .fetch_all(&state.read_db)
+            .await?,
+        ),
+        None => None,
+    };
+
+    Ok(Json(LikeAbuseResponse {
+        since,
+        reciprocal,
+        unfetched,
+        unfetched_since,
+    }))
+}
@@ -0,0 +1,182 @@
+// Strand contracts.
+//
+// Two players who like each other's work link up: each offers a contract to
+// the other with POST /contracts/{steamid}, and once both have, it holds.
+// Partners' structures make up part of every random fetch the other makes
+// (see random_rows), and each is told about likes the other receives
+// (partner_like_received). Either side ends it with DELETE, which also
+// withdraws or declines an open offer. Offers count toward
+// MAX_CONTRACTS_PER_USER, so nobody can collect an unbounded list.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::Serialize;
+
+use super::structures::now_millis;
+use crate::{
+    auth::VerifiedUser,
+    error::{ApiError, AppError},
+    state::AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    // both sides offered
+    Active,
+    // the player offered, the partner hasn't yet
+    Offered,
+    // the partner offered, waiting for the player
+    Received,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OfferResponse {
+    partner_id: i64,
+    status: ContractStatus,
+}
+
+pub async fn offer_contract(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(partner): Path<u64>,
+) -> Result<Json<OfferResponse>, AppError> {
+    if partner == steamid {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "You cannot sign a contract with yourself.",
+        )
+        .with_code("self_contract")
+        .into());
+    }
+    let (user_id, partner_id) = (steamid as i64, partner as i64);
+    let mut tx = state.db.begin().await?;
+
+    let max = state.config().max_contracts_per_user;
+    if max > 0 {
+        let (count, already): (i64, bool) = sqlx::query_as(
+            r#"SELECT COUNT(*), COALESCE(MAX(partner_id = ?), 0)
+               FROM contracts WHERE user_id = ?"#,
+        )
+        .bind(partner_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !already && count >= max {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("You can hold at most {max} contracts and offers."),
+            )
+            .with_code("contracts_full")
+            .into());
+        }
+    }
+
+    sqlx::query(
+        "INSERT OR IGNORE INTO contracts (user_id, partner_id, created_at) VALUES (?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(partner_id)
+    .bind(now_millis())
+    .execute(&mut *tx)
+    .await?;
+    let returned: Option<i64> =
+        sqlx::query_scalar("SELECT 1 FROM contracts WHERE user_id = ? AND partner_id = ?")
+            .bind(partner_id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    tx.commit().await?;
+    state.contracts.invalidate().await;
+    tracing::info!(
+        "contract offered user_id={} partner_id={} active={}",
+        user_id,
+        partner_id,
+        returned.is_some()
+    );
+    Ok(Json(OfferResponse {
+        partner_id,
+        status: if returned.is_some() {
+            ContractStatus::Active
+        } else {
+            ContractStatus::Offered
+        },
+    }))
+}
+
+// Ends a contract, or withdraws or declines an offer, for both sides
+pub async fn cancel_contract(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(partner): Path<u64>,
+) -> Result<StatusCode, AppError> {
+    let removed = sqlx::query(
+        r#"DELETE FROM contracts
+           WHERE (user_id = ? AND partner_id = ?) OR (user_id = ? AND partner_id = ?)"#,
+    )
+    .bind(steamid as i64)
+    .bind(partner as i64)
+    .bind(partner as i64)
+    .bind(steamid as i64)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if removed == 0 {
+        return Err(AppError::NotFound("No contract with this player"));
+    }
+    state.contracts.invalidate().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Contract {
+    partner_id: i64,
+    status: ContractStatus,
+    // epoch millis of the later offer: when it became active or was offered
+    since: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContractsResponse {
+    contracts: Vec<Contract>,
+}
+
+// The player's contracts and open offers either way, newest first
+pub async fn list_contracts(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+) -> Result<Json<ContractsResponse>, AppError> {
+    let rows: Vec<(i64, bool, bool, i64)> = sqlx::query_as(
+        r#"SELECT partner_id, MAX(offered) AS offered, MAX(received) AS received,
+                  MAX(created_at) AS since
+           FROM (
+               SELECT partner_id, 1 AS offered, 0 AS received, created_at
+               FROM contracts WHERE user_id = ?
+               UNION ALL
+               SELECT user_id, 0, 1, created_at
+               FROM contracts WHERE partner_id = ?
+           )
+           GROUP BY partner_id
+           ORDER BY since DESC, partner_id"#,
+    )
+    .bind(steamid as i64)
+    .bind(steamid as i64)
+    .fetch_all(&state.read_db)
+    .await?;
+    let contracts = rows
+        .into_iter()
+        .map(|(partner_id, offered, received, since)| Contract {
+            partner_id,
+            status: match (offered, received) {
+                (true, true) => ContractStatus::Active,
+                (true, false) => ContractStatus::Offered,
+                _ => ContractStatus::Received,
+            },
+            since,
+        })
+        .collect();
+    Ok(Json(ContractsResponse { contracts }))
+}
@@ -0,0 +1,157 @@
+// Favorites.
+//
+// Players bookmark structures they came across and list them later, with the
+// current like counts and positions, to find a good route again or show it to
+// friends. A structure deleted since drops out of the list and stops counting
+// toward MAX_FAVORITES_PER_USER; its bookmark is kept, so it comes back if the
+// structure is restored.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use super::structures::now_millis;
+use crate::{
+    auth::VerifiedUser,
+    db::queries::{load_attributes, load_contributors, load_live_structures, load_reactions},
+    error::{ApiError, AppError},
+    extract::QueryParams,
+    model::Structure,
+    state::AppState,
+};
+
+const FAVORITES_PAGE_DEFAULT: i64 = 50;
+const FAVORITES_PAGE_MAX: i64 = 200;
+
+pub async fn add_favorite(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db.begin().await?;
+    let live: Option<i64> =
+        sqlx::query_scalar("SELECT 1 FROM structures WHERE id = ? AND deleted = 0")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    if live.is_none() {
+        return Err(AppError::NotFound("Structure not found"));
+    }
+
+    let max = state.config().max_favorites_per_user;
+    if max > 0 {
+        let (count, already): (i64, bool) = sqlx::query_as(
+            r#"SELECT COUNT(*), COALESCE(MAX(f.structure_id = ?), 0)
+               FROM favorites f JOIN structures s ON s.id = f.structure_id
+               WHERE f.user_id = ? AND s.deleted = 0"#,
+        )
+        .bind(id)
+        .bind(steamid as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !already && count >= max {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("You can keep at most {max} favorites."),
+            )
+            .with_code("favorites_full")
+            .into());
+        }
+    }
+
+    // favoriting again keeps the structure where it was in the list
+    sqlx::query(
+        "INSERT OR IGNORE INTO favorites (user_id, structure_id, created_at) VALUES (?, ?, ?)",
+    )
+    .bind(steamid as i64)
+    .bind(id)
+    .bind(now_millis())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_favorite(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let removed = sqlx::query("DELETE FROM favorites WHERE user_id = ? AND structure_id = ?")
+        .bind(steamid as i64)
+        .bind(id)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+    if removed == 0 {
+        return Err(AppError::NotFound("Structure is not in your favorites"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct FavoritesParams {
+    before: Option<i64>, // cursor: pass the previous page's next_before
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FavoritesPage<T> {
+    pub structures: Vec<T>,
+    pub next_before: Option<i64>,
+}
+
+impl<T> FavoritesPage<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> FavoritesPage<U> {
+        FavoritesPage {
+            structures: self.structures.into_iter().map(f).collect(),
+            next_before: self.next_before,
+        }
+    }
+}
+
+// The player's favorites, most recently added first
+pub async fn list_favorites(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    QueryParams(p): QueryParams<FavoritesParams>,
+) -> Result<Json<FavoritesPage<Structure>>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(FAVORITES_PAGE_DEFAULT)
+        .clamp(1, FAVORITES_PAGE_MAX);
+
+    // one extra row tells whether another page exists
+    let mut page: Vec<(i64, i64)> = sqlx::query_as(
+        r#"SELECT f.id, f.structure_id
+           FROM favorites f JOIN structures s ON s.id = f.structure_id
+           WHERE f.user_id = ? AND s.deleted = 0 AND (? IS NULL OR f.id < ?)
+           ORDER BY f.id DESC LIMIT ?"#,
+    )
+    .bind(steamid as i64)
+    .bind(p.before)
+    .bind(p.before)
+    .bind(limit + 1)
+    .fetch_all(&state.read_db)
+    .await?;
+    let next_before = if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        page.last().map(|(favorite_id, _)| *favorite_id)
+    } else {
+        None
+    };
+
+    let ids: Vec<i64> = page.iter().map(|(_, structure_id)| *structure_id).collect();
+    let mut rows = load_live_structures(&state.read_db, &ids).await?;
+    load_reactions(&state.read_db, &mut rows).await?;
+    load_contributors(&state.read_db, &mut rows).await?;
+    load_attributes(&state.read_db, &mut rows).await?;
+    state.like_buffer.overlay(&mut rows);
+    Ok(Json(FavoritesPage {
+        structures: rows,
+        next_before,
+    }))
+}
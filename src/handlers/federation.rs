@@ -0,0 +1,226 @@
+// Federation: community servers mirroring another server's structures.
+//
+// A server with FEDERATION_KEYS serves its live structures of the current
+// season, oldest first, at /federation/v1/changes. A mirror with UPSTREAM_URL
+// pulls that feed every FEDERATION_PULL_SECONDS and stores new structures as
+// ordinary rows tagged with origin_server/origin_id, so random and nearby
+// fetches serve them next to local uploads. Rows tagged with the mirror's own
+// SERVER_ID are skipped, which stops a structure from travelling around a
+// ring of servers that mirror each other, and the unique origin index keeps
+// it from being stored twice. Likes, pins and deletions stay local.
+
+use anyhow::Context;
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use std::time::Duration;
+
+use crate::{
+    auth::{FEDERATION_HEADER, FederationPeer},
+    db::queries::NOT_SHADOW_BANNED,
+    error::AppError,
+    extract::QueryParams,
+    model::Structure,
+    state::AppState,
+};
+
+const CHANGES_PAGE_DEFAULT: i64 = 500;
+const CHANGES_PAGE_MAX: i64 = 1000;
+
+// copied from the feed as is; the rest starts fresh on the mirror
+const MIRRORED_COLUMNS: &[&str] = &[
+    "created_at",
+    "user_id",
+    "username",
+    "map_id",
+    "scene",
+    "segment",
+    "prefab",
+    "pos_x",
+    "pos_y",
+    "pos_z",
+    "rot_x",
+    "rot_y",
+    "rot_z",
+    "rot_w",
+    "rope_start_x",
+    "rope_start_y",
+    "rope_start_z",
+    "rope_end_x",
+    "rope_end_y",
+    "rope_end_z",
+    "rope_length",
+    "rope_flying_rotation_x",
+    "rope_flying_rotation_y",
+    "rope_flying_rotation_z",
+    "rope_anchor_rotation_x",
+    "rope_anchor_rotation_y",
+    "rope_anchor_rotation_z",
+    "rope_anchor_rotation_w",
+    "antigrav",
+    "likes",
+    "origin_server",
+    "origin_id",
+];
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct FederatedStructure {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub structure: Structure,
+    // where the structure was first uploaded; set on every feed entry
+    pub origin_server: Option<String>,
+    pub origin_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangesPage {
+    pub server_id: String,
+    pub structures: Vec<FederatedStructure>,
+    // pass as `after` for the next page
+    pub cursor: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ChangesParams {
+    #[serde(default)]
+    after: i64, // feed ids are this server's structure ids
+    limit: Option<i64>,
+}
+
+pub async fn get_changes(
+    State(state): State<AppState>,
+    _peer: FederationPeer,
+    QueryParams(p): QueryParams<ChangesParams>,
+) -> Result<Json<ChangesPage>, AppError> {
+    let config = state.config();
+    let server_id = config.server_id.clone().unwrap_or_default();
+    let limit = p
+        .limit
+        .unwrap_or(CHANGES_PAGE_DEFAULT)
+        .clamp(1, CHANGES_PAGE_MAX);
+
+    // mirrors show what they get to everyone, so shadow bans hold back the feed too
+    let mut structures: Vec<FederatedStructure> = sqlx::query_as(&format!(
+        r#"SELECT * FROM structures
+           WHERE id > ? AND deleted = 0 AND season_id = ? AND {NOT_SHADOW_BANNED}
+           ORDER BY id LIMIT ?"#
+    ))
+    .bind(p.after)
+    .bind(config.current_season)
+    .bind(0_i64) // no viewer
+    .bind(limit)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    let cursor = structures
+        .last()
+        .and_then(|s| s.structure.id)
+        .unwrap_or(p.after);
+    // structures uploaded here carry no origin yet
+    for s in &mut structures {
+        if s.origin_server.is_none() {
+            s.origin_server = Some(server_id.clone());
+            s.origin_id = s.structure.id;
+        }
+    }
+
+    Ok(Json(ChangesPage {
+        server_id,
+        structures,
+        cursor,
+    }))
+}
+
+// Pulls UPSTREAM_URL every FEDERATION_PULL_SECONDS
+pub async fn pull_upstream(state: AppState) {
+    loop {
+        let config = state.config();
+        // still polled when off, so a reload can turn mirroring on
+        let interval = match config.upstream_url {
+            Some(_) => config.federation_pull_interval,
+            None => Duration::from_secs(60),
+        };
+        tokio::time::sleep(interval).await;
+        if state.config().upstream_url.is_none() {
+            continue;
+        }
+        match pull_once(&state).await {
+            Ok(0) => {}
+            Ok(stored) => tracing::info!("federation pulled stored={}", stored),
+            Err(e) => tracing::warn!("federation pull failed error={:#}", e),
+        }
+    }
+}
+
+// Follows the upstream feed to its end; returns how many structures were new.
+// The cursor moves in the same transaction as the rows it covers.
+pub async fn pull_once(state: &AppState) -> anyhow::Result<usize> {
+    let config = state.config();
+    let (Some(upstream), Some(server_id)) = (&config.upstream_url, &config.server_id) else {
+        return Ok(0);
+    };
+
+    let mut after: i64 =
+        sqlx::query_scalar("SELECT last_id FROM federation_cursors WHERE upstream = ?")
+            .bind(upstream)
+            .fetch_optional(&state.db)
+            .await?
+            .unwrap_or(0);
+    let mut stored = 0;
+    loop {
+        let mut request = state
+            .http
+            .get(format!("{upstream}/federation/v1/changes"))
+            .query(&[("after", after), ("limit", CHANGES_PAGE_DEFAULT)]);
+        if let Some(key) = &config.upstream_key {
+            request = request.header(&FEDERATION_HEADER, key);
+        }
+        let page: ChangesPage = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("fetching {upstream}"))?
+            .json()
+            .await
+            .with_context(|| format!("reading the feed of {upstream}"))?;
+
+        let fresh: Vec<_> = page
+            .structures
+            .iter()
+            .filter(|s| s.origin_server.as_ref() != Some(server_id))
+            .collect();
+        let mut tx = state.db.begin().await?;
+        if !fresh.is_empty() {
+            let selected = MIRRORED_COLUMNS
+                .iter()
+                .map(|column| format!("json_extract(f.value, '$.{column}')"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            stored += sqlx::query(&format!(
+                "INSERT OR IGNORE INTO structures ({}, season_id) SELECT {selected}, ? FROM json_each(?) AS f",
+                MIRRORED_COLUMNS.join(", ")
+            ))
+            .bind(config.current_season)
+            .bind(json!(fresh).to_string())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() as usize;
+        }
+        sqlx::query(
+            r#"INSERT INTO federation_cursors (upstream, last_id) VALUES (?, ?)
+               ON CONFLICT(upstream) DO UPDATE SET last_id = excluded.last_id"#,
+        )
+        .bind(upstream)
+        .bind(page.cursor)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if page.structures.len() < CHANGES_PAGE_DEFAULT as usize || page.cursor <= after {
+            return Ok(stored);
+        }
+        after = page.cursor;
+    }
+}
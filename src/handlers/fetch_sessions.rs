@@ -0,0 +1,83 @@
+// Shared fetch sessions.
+//
+// One lobby member picks the scene's structures once; the server keeps the
+// chosen ids for FETCH_SESSION_TTL_SECONDS and everyone else loads exactly
+// those rows by session id. Unlike seeds this survives the scene changing in
+// between, and the lobby runs one random pick instead of one per player.
+// Sessions live in memory only and are gone after a restart.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use super::structures::{RandomParams, check_random_rate_limit, note_fetched, random_rows};
+use crate::{
+    auth::{SteamApp, VerifiedUser},
+    db::queries::{load_attributes, load_contributors, load_live_structures, load_reactions},
+    error::AppError,
+    extract::JsonBody,
+    model::Structure,
+    state::{AppState, CacheEntry},
+};
+
+#[derive(Debug, Serialize)]
+pub struct FetchSessionCreated {
+    session_id: String,
+    size: usize,
+    expires_in: u64, // seconds
+}
+
+// Takes the same fields as GET /structures, as a JSON body
+pub async fn create_fetch_session(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    SteamApp(appid): SteamApp,
+    JsonBody(p): JsonBody<RandomParams>,
+) -> Result<Json<FetchSessionCreated>, AppError> {
+    check_random_rate_limit(&state, steamid)?;
+    // the whole lobby sees the pick, so it is what anyone else would get
+    let (rows, _) = random_rows(&state, appid, &p, false, None).await?;
+
+    let ttl = state.config().fetch_session_ttl;
+    let now = Instant::now();
+    state
+        .fetch_sessions
+        .retain(|_, session| session.expires_at > now);
+    let session_id = format!("{:032x}", fastrand::u128(..));
+    state.fetch_sessions.insert(
+        session_id.clone(),
+        CacheEntry {
+            value: rows.iter().filter_map(|s| s.id).collect(),
+            expires_at: now + ttl,
+        },
+    );
+
+    Ok(Json(FetchSessionCreated {
+        session_id,
+        size: rows.len(),
+        expires_in: ttl.as_secs(),
+    }))
+}
+
+// The session's structures in the order they were picked. Structures deleted
+// since drop out; likes and reactions are current.
+pub async fn get_fetch_session(
+    State(state): State<AppState>,
+    VerifiedUser(_): VerifiedUser,
+    Path(session_id): Path<String>,
+) -> Result<Json<Vec<Structure>>, AppError> {
+    let ids = match state.fetch_sessions.get(&session_id) {
+        Some(session) if session.expires_at > Instant::now() => session.value.clone(),
+        _ => return Err(AppError::NotFound("Fetch session not found or expired.")),
+    };
+    let mut rows = load_live_structures(&state.read_db, &ids).await?;
+    load_reactions(&state.read_db, &mut rows).await?;
+    load_contributors(&state.read_db, &mut rows).await?;
+    load_attributes(&state.read_db, &mut rows).await?;
+    state.like_buffer.overlay(&mut rows);
+    note_fetched(&state, &rows);
+    Ok(Json(rows))
+}
@@ -0,0 +1,612 @@
+// Likes and reactions.
+//
+// like_structure checks the daily budgets and either writes the like at once
+// or, with LIKE_FLUSH_SECONDS set, leaves it to the like buffer. Either way
+// LIKE_MILESTONES and DISCORD_LIKE_MILESTONE are claimed in like_milestones
+// when the new total passes them, so each fires once to the owner (and
+// MILESTONE_WEBHOOK_URL) or to Discord.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::{
+    MILLIS_IN_DAY,
+    auth::VerifiedUser,
+    config::Config,
+    db::queries::{
+        claim_milestones, credit_contributors, is_contributor, like_target, like_window_start,
+        likes_given,
+    },
+    discord::Notice,
+    error::{ApiError, AppError},
+    events::{SceneEvent, UserEvent},
+    extract::JsonBody,
+    handlers::structures::now_millis,
+    like_buffer::PendingLike,
+    model::Reaction,
+    state::AppState,
+};
+
+// Writes buffered likes every LIKE_FLUSH_SECONDS and sends the notifications
+// the direct path sends per like, once per structure and reaction.
+pub async fn flush_likes(state: AppState) {
+    loop {
+        let interval = state.config().like_flush_interval;
+        // still polled when off, so likes buffered before a reload turned it off get written
+        tokio::time::sleep(if interval.is_zero() {
+            Duration::from_secs(1)
+        } else {
+            interval
+        })
+        .await;
+        if state.like_buffer.is_empty() {
+            continue;
+        }
+
+        let started = Instant::now();
+        let flushed = state
+            .db_metrics
+            .timed("flush_likes", state.like_buffer.flush(&state.db))
+            .await;
+        let flushed = match flushed {
+            Ok(flushed) => flushed,
+            Err(e) => {
+                tracing::error!("like_buffer flush failed error={}", e);
+                continue;
+            }
+        };
+        tracing::info!(
+            "like_buffer flushed structures={} duration_ms={}",
+            flushed.len(),
+            started.elapsed().as_millis()
+        );
+
+        let config = state.config();
+        let milestones = claimable_milestones(&config);
+        let mut milestones_claimed = std::collections::HashSet::new();
+        for like in flushed {
+            // every reaction of the structure reports the same totals; claim once
+            if milestones_claimed.insert(like.structure_id) {
+                let claimed = match state.db.acquire().await {
+                    Ok(mut conn) => {
+                        claim_milestones(
+                            &mut conn,
+                            like.structure_id,
+                            like.likes - like.added,
+                            like.likes,
+                            &milestones,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                match claimed {
+                    Ok(claimed) => announce_milestones(
+                        &state,
+                        &config,
+                        Liked {
+                            structure_id: like.structure_id,
+                            owner_id: like.owner_id,
+                            owner_username: like.owner_username.as_deref(),
+                            scene: &like.scene,
+                            prefab: &like.prefab,
+                            likes: like.likes,
+                        },
+                        claimed,
+                    ),
+                    Err(e) => tracing::error!(
+                        "like_milestones claim failed structure_id={} error={}",
+                        like.structure_id,
+                        e
+                    ),
+                }
+            }
+            let count = like.count as i32;
+            state.events.publish_scene(
+                &like.scene,
+                SceneEvent::StructureLiked {
+                    id: like.structure_id,
+                    scene: like.scene.clone(),
+                    likes: like.likes,
+                    count,
+                    reaction: like.reaction,
+                },
+            );
+            let event = UserEvent::LikeReceived {
+                structure_id: like.structure_id,
+                scene: like.scene,
+                prefab: like.prefab,
+                reaction: like.reaction,
+                count,
+                likes: like.likes,
+            };
+            notify_partners(&state, like.owner_id, &event).await;
+            state.events.publish_user(like.owner_id as u64, event);
+        }
+    }
+}
+
+// LIKE_MILESTONES and DISCORD_LIKE_MILESTONE. Both are claimed in
+// like_milestones, so each is announced once however the likes arrive.
+fn claimable_milestones(config: &Config) -> Vec<i64> {
+    let mut milestones = config.like_milestones.clone();
+    let discord = config.discord_like_milestone;
+    if discord > 0 && !milestones.contains(&discord) {
+        milestones.push(discord);
+    }
+    milestones
+}
+
+// The structure whose like total just passed some milestones
+struct Liked<'a> {
+    structure_id: i64,
+    owner_id: i64,
+    owner_username: Option<&'a str>,
+    scene: &'a str,
+    prefab: &'a str,
+    likes: i64,
+}
+
+// Announces each milestone just claimed: a LIKE_MILESTONES one as a
+// like_milestone event to the owner and, when configured, a POST to
+// MILESTONE_WEBHOOK_URL for reward systems; DISCORD_LIKE_MILESTONE as a
+// Discord notice. Webhooks go out through the notifier's queue.
+fn announce_milestones(state: &AppState, config: &Config, liked: Liked<'_>, milestones: Vec<i64>) {
+    let Liked {
+        structure_id,
+        owner_id,
+        owner_username,
+        scene,
+        prefab,
+        likes,
+    } = liked;
+    for milestone in milestones {
+        if milestone == config.discord_like_milestone {
+            state.discord.notify(Notice::LikeMilestone {
+                structure_id,
+                username: owner_username.map(String::from),
+                scene: scene.to_string(),
+                prefab: prefab.to_string(),
+                likes,
+            });
+        }
+        if !config.like_milestones.contains(&milestone) {
+            continue;
+        }
+        tracing::info!(
+            "like_milestone reached structure_id={} owner_id={} milestone={}",
+            structure_id,
+            owner_id,
+            milestone
+        );
+        state.events.publish_user(
+            owner_id as u64,
+            UserEvent::LikeMilestone {
+                structure_id,
+                scene: scene.to_string(),
+                prefab: prefab.to_string(),
+                milestone,
+                likes,
+            },
+        );
+        if config.milestone_webhook_url.is_some() {
+            state.discord.notify(Notice::MilestoneReached {
+                structure_id,
+                owner_id,
+                scene: scene.to_string(),
+                prefab: prefab.to_string(),
+                milestone,
+                likes,
+            });
+        }
+    }
+}
+
+// Passes a like_received on to the owner's strand contract partners
+async fn notify_partners(state: &AppState, owner_id: i64, event: &UserEvent) {
+    let UserEvent::LikeReceived {
+        structure_id,
+        scene,
+        prefab,
+        reaction,
+        count,
+        likes,
+    } = event
+    else {
+        return;
+    };
+    let partners = match state.contracts.partners(&state.read_db, owner_id).await {
+        Ok(partners) => partners,
+        Err(e) => {
+            tracing::warn!(
+                "contract_partners lookup failed owner_id={} error={}",
+                owner_id,
+                e
+            );
+            return;
+        }
+    };
+    for partner in partners {
+        state.events.publish_user(
+            partner as u64,
+            UserEvent::PartnerLikeReceived {
+                partner_id: owner_id,
+                structure_id: *structure_id,
+                scene: scene.clone(),
+                prefab: prefab.clone(),
+                reaction: *reaction,
+                count: *count,
+                likes: *likes,
+            },
+        );
+    }
+}
+
+// A like on a structure no random fetch served the liker suggests ids passed
+// around outside the game. Only known with FETCH_HISTORY_DAYS; nearby fetches
+// and favorites aren't in the history, so this is a signal, not proof.
+async fn note_if_unfetched(state: &AppState, config: &Config, liker: i64, id: i64) {
+    if config.fetch_history_days == 0 {
+        return;
+    }
+    let since = now_millis() - config.fetch_history_days as i64 * MILLIS_IN_DAY;
+    match state
+        .fetch_log
+        .seen(&state.read_db, liker, &[id], since)
+        .await
+    {
+        Ok(seen) if seen.is_empty() => tracing::info!(
+            "like_suspicious pattern=unfetched user_id={} structure_id={}",
+            liker,
+            id
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::warn!("fetch_history lookup failed user_id={} error={}", liker, e),
+    }
+}
+
+// Rolling 24h allowances; a request over the limit is trimmed to what is left.
+// The second value names the limit that trimmed it.
+fn like_allowance(
+    config: &Config,
+    count: i32,
+    given_today: i64,
+    given_to_owner: i64,
+) -> (i64, Option<&'static str>) {
+    let mut allowance = i64::from(count);
+    let mut limited_by = None;
+    if config.like_daily_budget > 0 && config.like_daily_budget - given_today < allowance {
+        allowance = config.like_daily_budget - given_today;
+        limited_by = Some("daily_budget");
+    }
+    if config.like_target_daily_cap > 0 && config.like_target_daily_cap - given_to_owner < allowance
+    {
+        allowance = config.like_target_daily_cap - given_to_owner;
+        limited_by = Some("target_cap");
+    }
+    (allowance, limited_by)
+}
+
+// Pairs of accounts liking each other heavily are the usual way to farm likes_received
+async fn warn_if_reciprocal<'e>(
+    db: impl sqlx::SqliteExecutor<'e>,
+    config: &Config,
+    liker: i64,
+    owner: i64,
+    since: i64,
+    given_to_owner: i64,
+) {
+    let threshold = config.like_suspicious_threshold;
+    if threshold <= 0 || given_to_owner < threshold {
+        return;
+    }
+    let returned: i64 = sqlx::query_scalar(
+        r#"SELECT COALESCE(SUM(count), 0) FROM likes_ledger
+           WHERE liker_id = ? AND owner_id = ? AND created_at >= ?"#,
+    )
+    .bind(owner)
+    .bind(liker)
+    .bind(since)
+    .fetch_one(db)
+    .await
+    .unwrap_or_default();
+    if returned >= threshold {
+        tracing::warn!(
+            "like_suspicious pattern=reciprocal user_id={} owner_id={} given_24h={} returned_24h={}",
+            liker,
+            owner,
+            given_to_owner,
+            returned
+        );
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LikeBody {
+    count: Option<i32>,
+    reaction: Option<Reaction>,
+    // answer 200 with LikeTotals instead of 204; v2 always does
+    #[serde(default)]
+    return_totals: bool,
+}
+
+// The structure's like count after the like, and what the liker has left
+#[derive(Debug, Serialize)]
+pub struct LikeTotals {
+    likes: i64,
+    // null while the limit is off
+    remaining_daily_budget: Option<i64>,
+    remaining_for_owner: Option<i64>,
+}
+
+pub async fn like_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+    JsonBody(body): JsonBody<LikeBody>,
+) -> Result<Response, AppError> {
+    let return_totals = body.return_totals;
+    let totals = apply_like(&state, steamid, id, body).await?;
+    Ok(if return_totals {
+        Json(totals).into_response()
+    } else {
+        StatusCode::NO_CONTENT.into_response()
+    })
+}
+
+pub async fn apply_like(
+    state: &AppState,
+    steamid: u64,
+    id: i64,
+    body: LikeBody,
+) -> Result<LikeTotals, AppError> {
+    let requested = body.count.unwrap_or(1); // log before clamp
+    let reaction = body.reaction.unwrap_or_default();
+
+    // Per-user rate limit for likes (configurable)
+    let interval = state.rate_limit(state.config().post_like_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last) = state.post_like_rate_limiter.get(&steamid)
+        && last.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("like", steamid);
+        return Err(AppError::RateLimited(
+            "You are liking too frequently.".into(),
+            interval.saturating_sub(last.elapsed()),
+        ));
+    }
+    state.post_like_rate_limiter.insert(steamid, Instant::now());
+
+    // running events may relax the budgets and multiply what a like is worth
+    let events = state.server_events.active(&state.read_db).await?;
+    let config = events.relax(state.config());
+
+    // Buffered likes (LIKE_FLUSH_SECONDS) are only checked here; flush_likes writes them
+    let buffered = !config.like_flush_interval.is_zero();
+    let (pool_name, pool) = if buffered {
+        ("reader", &state.read_db)
+    } else {
+        ("writer", &state.db)
+    };
+    let mut tx = state.db_metrics.begin(pool_name, pool).await?;
+
+    // Validate structure and get owner
+    let owner = like_target(&mut *tx, id).await?;
+
+    let Some((owner_user_id, scene, prefab, owner_username, stored_likes)) = owner else {
+        tx.rollback().await.ok();
+        return Err(AppError::NotFound("Structure not found"));
+    };
+
+    // Forbid self-like attempts, co-op builders included
+    if owner_user_id == steamid as i64 || is_contributor(&mut *tx, id, steamid as i64).await? {
+        tx.rollback().await.ok();
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "Cannot like your own structure.")
+                .with_code("self_like")
+                .into(),
+        );
+    }
+
+    // Normalize count AFTER logging requested
+    let mut count = requested.clamp(1, 100);
+    if count != requested {
+        state.limit_stats.like_clamped("count");
+    }
+
+    let window_start = like_window_start();
+    let (given_today, given_to_owner) =
+        likes_given(&mut *tx, steamid as i64, owner_user_id, window_start).await?;
+
+    // likes still waiting in the buffer count toward the allowances too
+    let (pending_today, pending_to_owner) = state
+        .like_buffer
+        .pending_from(steamid as i64, owner_user_id);
+    let given_today = given_today + pending_today;
+    let given_to_owner = given_to_owner + pending_to_owner;
+    let (allowance, limited_by) = like_allowance(&config, count, given_today, given_to_owner);
+    if allowance <= 0 {
+        tx.rollback().await.ok();
+        state.limit_stats.rate_limited("like", steamid);
+        if let Some(reason) = limited_by {
+            state.limit_stats.like_clamped(reason);
+        }
+        return Err(
+            ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Daily like limit reached.")
+                .with_code("like_limit")
+                .into(),
+        );
+    }
+    count = allowance as i32;
+    if let Some(reason) = limited_by {
+        state.limit_stats.like_clamped(reason);
+    }
+    // budgets count the likes given, the structure and its owner get the multiplied ones
+    let credited = count.saturating_mul(events.like_multiplier() as i32);
+    let remaining = |limit: i64, given: i64| (limit > 0).then(|| limit - given - i64::from(count));
+    let remaining_daily_budget = remaining(config.like_daily_budget, given_today);
+    let remaining_for_owner = remaining(config.like_target_daily_cap, given_to_owner);
+
+    if buffered {
+        warn_if_reciprocal(
+            &mut *tx,
+            &config,
+            steamid as i64,
+            owner_user_id,
+            window_start,
+            given_to_owner + i64::from(count),
+        )
+        .await;
+        tx.rollback().await.ok();
+        note_if_unfetched(state, &config, steamid as i64, id).await;
+        state.like_buffer.add(
+            steamid as i64,
+            id,
+            reaction,
+            PendingLike {
+                owner_id: owner_user_id,
+                scene,
+                prefab,
+                owner_username,
+                count: i64::from(count),
+                credited: i64::from(credited),
+            },
+        );
+
+        return Ok(LikeTotals {
+            likes: stored_likes + state.like_buffer.pending_on(id),
+            remaining_daily_budget,
+            remaining_for_owner,
+        });
+    }
+
+    // Ensure liker and owner exist in users
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO users (user_id, upload_banned, likes_received, likes_send)
+           VALUES (?, 0, 0, 0);"#,
+    )
+    .bind(steamid as i64)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO users (user_id, upload_banned, likes_received, likes_send)
+           VALUES (?, 0, 0, 0);"#,
+    )
+    .bind(owner_user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // Update structure likes
+    let updated: Option<i64> = sqlx::query_scalar(
+        "UPDATE structures SET likes = likes + ? WHERE id = ? AND deleted = 0 RETURNING likes",
+    )
+    .bind(credited)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(likes) = updated else {
+        tx.rollback().await.ok();
+        return Err(AppError::NotFound("Structure not found"));
+    };
+
+    sqlx::query(
+        r#"INSERT INTO structure_reactions (structure_id, kind, count) VALUES (?, ?, ?)
+           ON CONFLICT(structure_id, kind) DO UPDATE SET count = count + excluded.count;"#,
+    )
+    .bind(id)
+    .bind(reaction.as_str())
+    .bind(credited)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"INSERT INTO likes_ledger (liker_id, owner_id, structure_id, count, created_at)
+           VALUES (?, ?, ?, ?, strftime('%s','now')*1000);"#,
+    )
+    .bind(steamid as i64)
+    .bind(owner_user_id)
+    .bind(id)
+    .bind(count)
+    .execute(&mut *tx)
+    .await?;
+
+    warn_if_reciprocal(
+        &mut *tx,
+        &config,
+        steamid as i64,
+        owner_user_id,
+        window_start,
+        given_to_owner + i64::from(count),
+    )
+    .await;
+
+    // Update users metrics
+    sqlx::query("UPDATE users SET likes_send = likes_send + ? WHERE user_id = ?")
+        .bind(count)
+        .bind(steamid as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE users SET likes_received = likes_received + ? WHERE user_id = ?")
+        .bind(credited)
+        .bind(owner_user_id)
+        .execute(&mut *tx)
+        .await?;
+    credit_contributors(&mut tx, &[(id, i64::from(credited))]).await?;
+    let milestones = claim_milestones(
+        &mut tx,
+        id,
+        likes - i64::from(credited),
+        likes,
+        &claimable_milestones(&config),
+    )
+    .await?;
+
+    tx.commit().await?;
+    note_if_unfetched(state, &config, steamid as i64, id).await;
+    announce_milestones(
+        state,
+        &config,
+        Liked {
+            structure_id: id,
+            owner_id: owner_user_id,
+            owner_username: owner_username.as_deref(),
+            scene: &scene,
+            prefab: &prefab,
+            likes,
+        },
+        milestones,
+    );
+
+    let event = SceneEvent::StructureLiked {
+        id,
+        scene: scene.clone(),
+        likes,
+        count: credited,
+        reaction,
+    };
+    state.events.publish_scene(&scene, event);
+
+    let event = UserEvent::LikeReceived {
+        structure_id: id,
+        scene,
+        prefab,
+        reaction,
+        count: credited,
+        likes,
+    };
+    notify_partners(state, owner_user_id, &event).await;
+    state.events.publish_user(owner_user_id as u64, event);
+
+    Ok(LikeTotals {
+        likes,
+        remaining_daily_budget,
+        remaining_for_owner,
+    })
+}
@@ -0,0 +1,371 @@
+// HTTP routes.
+//
+// One module per area of the API. `build_router` nests the versioned routers
+// under /api/v1 and /api/v2, the admin routes under /admin/v1 and the
+// federation feed under /federation/v1, and adds the layers every route
+// shares: CORS, body limit, concurrency caps, the request timeout and the
+// access log.
+
+pub mod admin;
+pub mod contracts;
+pub mod favorites;
+pub mod federation;
+pub mod fetch_sessions;
+pub mod likes;
+pub mod motd;
+pub mod notifications;
+pub mod realtime;
+pub mod server_info;
+pub mod stats;
+pub mod structures;
+pub mod v2;
+
+use axum::{
+    Router,
+    http::{HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
+    routing::{MethodRouter, delete, get, patch, post, put},
+};
+use std::{str::FromStr, time::Duration};
+use tower_http::{
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
+    limit::RequestBodyLimitLayer,
+};
+
+use crate::{
+    access_log, client_version, config::Config, error::ApiError, extract, load_shed::LoadCap,
+    maintenance, private_server, request_timeout, state::AppState, versioning,
+    versioning::ApiVersion,
+};
+use admin::{
+    admin_add_allowlist, admin_add_rate_limit_exempt, admin_approve_review,
+    admin_create_announcement, admin_create_invite, admin_create_server_event, admin_db_sizes,
+    admin_db_stats, admin_delete_announcement, admin_delete_server_event, admin_export,
+    admin_get_maintenance, admin_heatmap, admin_import, admin_import_remote, admin_integrity_check,
+    admin_like_abuse, admin_limit_stats, admin_list_allowlist, admin_list_announcements,
+    admin_list_invites, admin_list_rate_limit_exempt, admin_list_review, admin_list_server_events,
+    admin_list_structures, admin_purge_user, admin_reject_review, admin_reload,
+    admin_remove_allowlist, admin_remove_rate_limit_exempt, admin_restore_structure,
+    admin_revoke_invite, admin_rollover_season, admin_search_structures, admin_set_maintenance,
+    admin_unarchive_structure,
+};
+use contracts::{cancel_contract, list_contracts, offer_contract};
+use favorites::{add_favorite, list_favorites, remove_favorite};
+use federation::get_changes;
+use fetch_sessions::{create_fetch_session, get_fetch_session};
+use likes::like_structure;
+use motd::{get_motd, get_server_events};
+use notifications::{get_notification_digest, get_notifications, mark_notifications_read};
+use realtime::{user_events, ws_connect};
+use server_info::get_server_info;
+use stats::{
+    get_daily_stats, get_global_stats, get_prefab_stats, get_scene_stats, get_user_stats,
+    list_scenes,
+};
+use structures::{
+    delete_structure, edit_structure, get_nearby, get_queued_post, get_random, get_user_structures,
+    pin_structure, post_structure, report_usage, restore_structure, unpin_structure,
+};
+
+// CORS stays off unless at least one origin is configured ("*" allows any).
+fn cors_layer(config: &Config) -> Option<CorsLayer> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+
+    let origins = if config.cors_allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.cors_allowed_origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .inspect_err(|_| tracing::warn!("ignoring invalid CORS origin {}", origin))
+                .ok()
+        }))
+    };
+    let methods = config.cors_allowed_methods.iter().filter_map(|method| {
+        Method::from_str(&method.to_ascii_uppercase())
+            .inspect_err(|_| tracing::warn!("ignoring invalid CORS method {}", method))
+            .ok()
+    });
+    let headers = config.cors_allowed_headers.iter().filter_map(|name| {
+        HeaderName::from_str(name)
+            .inspect_err(|_| tracing::warn!("ignoring invalid CORS header {}", name))
+            .ok()
+    });
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(AllowMethods::list(methods))
+            .allow_headers(AllowHeaders::list(headers))
+            .max_age(Duration::from_secs(600)),
+    )
+}
+
+// The method routes of one path. Those switched off with ENABLE_* are left
+// out, and the path answers 404 with code `feature_disabled` in their place.
+fn switched(
+    routes: impl IntoIterator<Item = (bool, MethodRouter<AppState>)>,
+) -> MethodRouter<AppState> {
+    let mut router = MethodRouter::new();
+    let mut disabled = false;
+    for (enabled, route) in routes {
+        if enabled {
+            router = router.merge(route);
+        } else {
+            disabled = true;
+        }
+    }
+    if disabled {
+        router.fallback(feature_disabled)
+    } else {
+        router
+    }
+}
+
+async fn feature_disabled() -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "This feature is disabled on this server.",
+    )
+    .with_code("feature_disabled")
+}
+
+// Routes whose payloads are the same in every API version
+fn shared_api_routes(config: &Config, fetches: &LoadCap) -> Router<AppState> {
+    Router::new()
+        .route("/structures/{id}/usage", post(report_usage))
+        .route("/structures/{id}", delete(delete_structure))
+        .route("/structures/{id}/restore", post(restore_structure))
+        .route(
+            "/structures/{id}/pin",
+            post(pin_structure).delete(unpin_structure),
+        )
+        .route(
+            "/fetch-sessions",
+            switched([(
+                config.enable_fetch,
+                fetches.route(post(create_fetch_session)),
+            )]),
+        )
+        .route("/server-info", get(get_server_info))
+        .route("/motd", get(get_motd))
+        .route("/server-events", get(get_server_events))
+        .route("/stats/global", get(get_global_stats))
+        .route("/stats/me", get(get_user_stats))
+        .route("/stats/daily", get(get_daily_stats))
+        .route("/stats/prefabs", get(get_prefab_stats))
+        .route("/scenes", get(list_scenes))
+        .route("/scenes/{scene}/stats", get(get_scene_stats))
+        .route("/ws", get(ws_connect))
+        .route("/users/me/events", get(user_events))
+        .route(
+            "/users/me/favorites/{id}",
+            post(add_favorite).delete(remove_favorite),
+        )
+        .route("/contracts", get(list_contracts))
+        .route(
+            "/contracts/{steamid}",
+            post(offer_contract).delete(cancel_contract),
+        )
+        .route("/users/me/notifications", get(get_notifications))
+        .route(
+            "/users/me/notifications/digest",
+            get(get_notification_digest),
+        )
+        .route(
+            "/users/me/notifications/read",
+            post(mark_notifications_read),
+        )
+}
+
+fn api_v1_routes(config: &Config, fetches: &LoadCap) -> Router<AppState> {
+    shared_api_routes(config, fetches)
+        .route(
+            "/structures/{id}",
+            switched([(config.enable_post, patch(edit_structure))]),
+        )
+        .route(
+            "/structures/{id}/like",
+            switched([(config.enable_likes, post(like_structure))]),
+        )
+        .route("/users/{steamid}/structures", get(get_user_structures))
+        .route("/users/me/favorites", get(list_favorites))
+        .route(
+            "/structures",
+            switched([
+                (config.enable_fetch, fetches.route(get(get_random))),
+                (config.enable_post, post(post_structure)),
+            ]),
+        )
+        .route(
+            "/structures/nearby",
+            switched([(config.enable_fetch, fetches.route(get(get_nearby)))]),
+        )
+        .route(
+            "/structures/queued/{client_guid}",
+            switched([(config.enable_post, get(get_queued_post))]),
+        )
+        .route(
+            "/fetch-sessions/{session_id}",
+            switched([(config.enable_fetch, fetches.route(get(get_fetch_session)))]),
+        )
+        .layer(middleware::from_fn_with_state(
+            ApiVersion::V1,
+            versioning::negotiate,
+        ))
+}
+
+fn api_v2_routes(config: &Config, fetches: &LoadCap) -> Router<AppState> {
+    shared_api_routes(config, fetches)
+        .route(
+            "/structures/{id}",
+            switched([(config.enable_post, patch(v2::edit_structure))]),
+        )
+        .route(
+            "/structures/{id}/like",
+            switched([(config.enable_likes, post(v2::like_structure))]),
+        )
+        .route("/users/{steamid}/structures", get(v2::get_user_structures))
+        .route("/users/me/favorites", get(v2::list_favorites))
+        .route(
+            "/structures",
+            switched([
+                (config.enable_fetch, fetches.route(get(v2::get_random))),
+                (config.enable_post, post(v2::post_structure)),
+            ]),
+        )
+        .route(
+            "/structures/nearby",
+            switched([(config.enable_fetch, fetches.route(get(v2::get_nearby)))]),
+        )
+        .route(
+            "/structures/queued/{client_guid}",
+            switched([(config.enable_post, get(v2::get_queued_post))]),
+        )
+        .route(
+            "/fetch-sessions/{session_id}",
+            switched([(
+                config.enable_fetch,
+                fetches.route(get(v2::get_fetch_session)),
+            )]),
+        )
+        .layer(middleware::from_fn_with_state(
+            ApiVersion::V2,
+            versioning::negotiate,
+        ))
+}
+
+pub fn build_router(state: AppState) -> Router {
+    // admin imports stream whole dumps, so only player routes get the body limit
+    let config = state.config();
+    let max_body_bytes = config.max_body_bytes;
+    let fetches = LoadCap::new(config.max_concurrent_fetches);
+    let api = Router::new()
+        .nest("/api/v1", api_v1_routes(&config, &fetches))
+        .nest("/api/v2", api_v2_routes(&config, &fetches))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::guard,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            client_version::guard,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            private_server::guard,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_timeout::limit,
+        ))
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            max_body_bytes,
+            extract::limit_body,
+        ));
+    let api = LoadCap::new(config.max_concurrent_requests).router(api);
+
+    let router = api
+        .route("/admin/v1/export", get(admin_export))
+        .route("/admin/v1/import", post(admin_import))
+        .route("/admin/v1/import-remote", post(admin_import_remote))
+        .route("/admin/v1/reload", post(admin_reload))
+        .route(
+            "/admin/v1/maintenance",
+            get(admin_get_maintenance).put(admin_set_maintenance),
+        )
+        .route("/admin/v1/stats/limits", get(admin_limit_stats))
+        .route("/admin/v1/stats/db", get(admin_db_stats))
+        .route("/admin/v1/db-stats", get(admin_db_sizes))
+        .route("/admin/v1/stats/heatmap", get(admin_heatmap))
+        .route("/admin/v1/likes/suspicious", get(admin_like_abuse))
+        .route("/admin/v1/integrity-check", post(admin_integrity_check))
+        .route(
+            "/admin/v1/rate-limit-exempt",
+            get(admin_list_rate_limit_exempt),
+        )
+        .route(
+            "/admin/v1/rate-limit-exempt/{steamid}",
+            put(admin_add_rate_limit_exempt).delete(admin_remove_rate_limit_exempt),
+        )
+        .route(
+            "/admin/v1/announcements",
+            get(admin_list_announcements).post(admin_create_announcement),
+        )
+        .route(
+            "/admin/v1/announcements/{id}",
+            delete(admin_delete_announcement),
+        )
+        .route(
+            "/admin/v1/server-events",
+            get(admin_list_server_events).post(admin_create_server_event),
+        )
+        .route(
+            "/admin/v1/server-events/{id}",
+            delete(admin_delete_server_event),
+        )
+        .route("/admin/v1/allowlist", get(admin_list_allowlist))
+        .route(
+            "/admin/v1/allowlist/{steamid}",
+            put(admin_add_allowlist).delete(admin_remove_allowlist),
+        )
+        .route(
+            "/admin/v1/invites",
+            get(admin_list_invites).post(admin_create_invite),
+        )
+        .route("/admin/v1/invites/{code}", delete(admin_revoke_invite))
+        .route("/admin/v1/structures", get(admin_list_structures))
+        .route("/admin/v1/structures/search", get(admin_search_structures))
+        .route("/admin/v1/users/{steamid}/purge", post(admin_purge_user))
+        .route(
+            "/admin/v1/structures/{id}/restore",
+            post(admin_restore_structure),
+        )
+        .route(
+            "/admin/v1/structures/{id}/unarchive",
+            post(admin_unarchive_structure),
+        )
+        .route("/admin/v1/review", get(admin_list_review))
+        .route("/admin/v1/review/{id}/approve", post(admin_approve_review))
+        .route("/admin/v1/review/{id}/reject", post(admin_reject_review))
+        .route("/admin/v1/seasons/rollover", post(admin_rollover_season))
+        .route("/federation/v1/changes", get(get_changes));
+
+    #[cfg(feature = "mock-steam")]
+    let router = router.nest("/mock/steam", crate::mock_steam::router(Default::default()));
+
+    let router = match cors_layer(&config) {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+
+    // outermost, so rejected and preflight requests get their line too
+    router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            access_log::access_log,
+        ))
+        .with_state(state)
+}
@@ -0,0 +1,61 @@
+// Message of the day: announcements the operator schedules through the admin
+// API (maintenance windows, events) for the mod to show players, and the
+// community events (server_events.rs) running or coming up. Public, like
+// server info, so the mod can show them before it has a ticket.
+
+use axum::{Json, extract::State};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::{
+    error::AppError, handlers::structures::now_millis, server_events::ServerEvent, state::AppState,
+};
+
+// Longest announcement the admin API accepts
+pub const MAX_ANNOUNCEMENT_LENGTH: usize = 1000;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Announcement {
+    pub id: i64,
+    pub message: String,
+    // epoch millis; null: shown from when it was created
+    pub starts_at: Option<i64>,
+    // epoch millis; null: shown until deleted
+    pub ends_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MotdResponse {
+    announcements: Vec<Announcement>,
+}
+
+// The announcements showing right now, earliest first
+pub async fn get_motd(State(state): State<AppState>) -> Result<Json<MotdResponse>, AppError> {
+    let now = now_millis();
+    let announcements = sqlx::query_as(
+        r#"SELECT id, message, starts_at, ends_at FROM announcements
+           WHERE COALESCE(starts_at, 0) <= ? AND (ends_at IS NULL OR ends_at > ?)
+           ORDER BY COALESCE(starts_at, created_at), id"#,
+    )
+    .bind(now)
+    .bind(now)
+    .fetch_all(&state.read_db)
+    .await?;
+    Ok(Json(MotdResponse { announcements }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerEventsResponse {
+    events: Vec<ServerEvent>,
+}
+
+// Events that haven't ended yet, earliest first; the running ones have
+// started_at in the past
+pub async fn get_server_events(
+    State(state): State<AppState>,
+) -> Result<Json<ServerEventsResponse>, AppError> {
+    let events = state.server_events.upcoming(&state.read_db).await?;
+    Ok(Json(ServerEventsResponse {
+        events: events.to_vec(),
+    }))
+}
@@ -0,0 +1,223 @@
+// Likes inbox.
+//
+// Every accepted like is already kept in likes_ledger for the daily budgets;
+// the inbox reads it back for the owner (and co-op contributors) of the liked
+// structure, so the mod can sum up what came in while the player was away.
+// The read cursor is stored server-side and only moves when the client
+// acknowledges a page, so a crash before showing it loses nothing.
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{
+    auth::VerifiedUser,
+    error::AppError,
+    extract::{JsonBody, QueryParams},
+    state::AppState,
+};
+
+const NOTIFICATIONS_PAGE_DEFAULT: i64 = 50;
+const NOTIFICATIONS_PAGE_MAX: i64 = 200;
+
+// likes on the player's own structures and on those they helped build
+const INBOX_CONDITION: &str = r#"l.id > ? AND (l.owner_id = ? OR l.structure_id IN (
+        SELECT structure_id FROM structure_contributors WHERE user_id = ?))"#;
+
+async fn read_cursor(state: &AppState, user: i64, after: Option<i64>) -> Result<i64, AppError> {
+    if let Some(after) = after {
+        return Ok(after);
+    }
+    let stored =
+        sqlx::query_scalar("SELECT last_read_id FROM notification_cursors WHERE user_id = ?")
+            .bind(user)
+            .fetch_optional(&state.db)
+            .await?;
+    Ok(stored.unwrap_or(0))
+}
+
+// (entries, likes) past the cursor
+async fn unread_totals(state: &AppState, user: i64, after: i64) -> Result<(i64, i64), AppError> {
+    Ok(sqlx::query_as(&format!(
+        "SELECT COUNT(*), COALESCE(SUM(l.count), 0) FROM likes_ledger l WHERE {INBOX_CONDITION}"
+    ))
+    .bind(after)
+    .bind(user)
+    .bind(user)
+    .fetch_one(&state.read_db)
+    .await?)
+}
+
+#[derive(Deserialize)]
+pub struct NotificationsParams {
+    after: Option<i64>, // defaults to the stored read cursor
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct LikeNotification {
+    id: i64,
+    structure_id: i64,
+    // gone once the structure was pruned
+    scene: Option<String>,
+    prefab: Option<String>,
+    liker_id: i64,
+    liker_name: Option<String>, // only with RESOLVE_STEAM_NAMES
+    count: i64,
+    created_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct NotificationsPage {
+    notifications: Vec<LikeNotification>,
+    // everything past `after`, not just this page
+    unread: i64,
+    unread_likes: i64,
+    // acknowledge this to mark the page as read
+    cursor: i64,
+    has_more: bool,
+}
+
+pub async fn get_notifications(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    QueryParams(p): QueryParams<NotificationsParams>,
+) -> Result<Json<NotificationsPage>, AppError> {
+    let user = steamid as i64;
+    let limit = p
+        .limit
+        .unwrap_or(NOTIFICATIONS_PAGE_DEFAULT)
+        .clamp(1, NOTIFICATIONS_PAGE_MAX);
+    let after = read_cursor(&state, user, p.after).await?;
+    let (unread, unread_likes) = unread_totals(&state, user, after).await?;
+
+    let notifications: Vec<LikeNotification> = sqlx::query_as(&format!(
+        r#"SELECT l.id, l.structure_id, s.scene, s.prefab, l.liker_id,
+                  p.persona_name AS liker_name, l.count, l.created_at
+           FROM likes_ledger l
+           LEFT JOIN structures s ON s.id = l.structure_id
+           LEFT JOIN user_profiles p ON p.user_id = l.liker_id
+           WHERE {INBOX_CONDITION}
+           ORDER BY l.id LIMIT ?"#
+    ))
+    .bind(after)
+    .bind(user)
+    .bind(user)
+    .bind(limit)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    Ok(Json(NotificationsPage {
+        cursor: notifications.last().map_or(after, |n| n.id),
+        has_more: unread > notifications.len() as i64,
+        notifications,
+        unread,
+        unread_likes,
+    }))
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct LikeDigest {
+    structure_id: i64,
+    scene: Option<String>,
+    prefab: Option<String>,
+    window_start: i64, // epoch millis
+    count: i64,
+    likers: i64,
+    last_at: i64,
+    #[serde(skip)]
+    first_id: i64,
+    #[serde(skip)]
+    last_id: i64,
+}
+
+#[derive(Serialize)]
+pub struct DigestPage {
+    digests: Vec<LikeDigest>,
+    unread: i64,
+    unread_likes: i64,
+    cursor: i64,
+    has_more: bool,
+}
+
+// The inbox rolled up into one entry per structure and digest window
+// (NOTIFICATION_DIGEST_WINDOW_SECONDS), so a structure liked 500 times
+// overnight is one line. Shares the read cursor with the plain inbox.
+pub async fn get_notification_digest(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    QueryParams(p): QueryParams<NotificationsParams>,
+) -> Result<Json<DigestPage>, AppError> {
+    let user = steamid as i64;
+    let limit = p
+        .limit
+        .unwrap_or(NOTIFICATIONS_PAGE_DEFAULT)
+        .clamp(1, NOTIFICATIONS_PAGE_MAX);
+    let window_ms = state.config().notification_digest_window.as_millis() as i64;
+    let after = read_cursor(&state, user, p.after).await?;
+    let (unread, unread_likes) = unread_totals(&state, user, after).await?;
+
+    // one extra digest tells whether another page exists
+    let mut digests: Vec<LikeDigest> = sqlx::query_as(&format!(
+        r#"SELECT l.structure_id, s.scene, s.prefab,
+                  (l.created_at / ?) * ? AS window_start,
+                  SUM(l.count) AS count, COUNT(DISTINCT l.liker_id) AS likers,
+                  MAX(l.created_at) AS last_at, MIN(l.id) AS first_id, MAX(l.id) AS last_id
+           FROM likes_ledger l
+           LEFT JOIN structures s ON s.id = l.structure_id
+           WHERE {INBOX_CONDITION}
+           GROUP BY l.structure_id, window_start
+           ORDER BY first_id LIMIT ?"#
+    ))
+    .bind(window_ms)
+    .bind(window_ms)
+    .bind(after)
+    .bind(user)
+    .bind(user)
+    .bind(limit + 1)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    // Digests interleave, so a cursor past the whole page could skip likes of
+    // the next one; stop just before it instead. Digests on this page with
+    // likes beyond that come back next time with only those.
+    let (cursor, has_more) = if digests.len() as i64 > limit {
+        let next = digests.pop().map_or(after, |d| d.first_id - 1);
+        (next, true)
+    } else {
+        let last = digests.iter().map(|d| d.last_id).max().unwrap_or(after);
+        (last, false)
+    };
+
+    Ok(Json(DigestPage {
+        digests,
+        unread,
+        unread_likes,
+        cursor,
+        has_more,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ReadBody {
+    cursor: i64,
+}
+
+// Marks everything up to `cursor` as read. The cursor never moves back, so a
+// late acknowledgement from another session can't resurface old likes.
+pub async fn mark_notifications_read(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    JsonBody(body): JsonBody<ReadBody>,
+) -> Result<StatusCode, AppError> {
+    sqlx::query(
+        r#"INSERT INTO notification_cursors (user_id, last_read_id) VALUES (?, ?)
+           ON CONFLICT(user_id) DO UPDATE
+           SET last_read_id = MAX(last_read_id, excluded.last_read_id)"#,
+    )
+    .bind(steamid as i64)
+    .bind(body.cursor)
+    .execute(&state.db)
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
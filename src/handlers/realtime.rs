@@ -0,0 +1,168 @@
+// Live updates: websocket scene subscriptions and per-user server-sent events,
+// both fed by the EventHub.
+
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::{
+        Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use serde::Deserialize;
+use std::convert::Infallible;
+use tokio::time::Instant;
+use tokio_stream::{
+    Stream, StreamExt, StreamMap,
+    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+};
+
+use crate::{
+    auth::VerifiedUser,
+    events::{SceneEvent, UserSubscription},
+    state::AppState,
+};
+
+// --- realtime: websocket scene subscriptions ---
+
+// Client -> server messages on /api/v1/ws
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum WsCommand {
+    Subscribe { scenes: Vec<String> },
+    Unsubscribe { scenes: Vec<String> },
+}
+
+pub async fn ws_connect(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| ws_session(state, steamid, socket))
+}
+
+async fn ws_session(state: AppState, steamid: u64, mut socket: WebSocket) {
+    let started = Instant::now();
+    let mut subscriptions: StreamMap<String, BroadcastStream<SceneEvent>> = StreamMap::new();
+
+    loop {
+        let reply = tokio::select! {
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue, // pings are answered by axum
+                };
+                ws_apply_command(&state, &mut subscriptions, &text)
+            }
+            Some((scene, event)) = subscriptions.next(), if !subscriptions.is_empty() => {
+                match event {
+                    Ok(event) => serde_json::to_value(&event).unwrap_or_default(),
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        tracing::warn!(
+                            "ws_session user_id={} scene={} lagged missed={}",
+                            steamid,
+                            scene,
+                            missed
+                        );
+                        serde_json::json!({ "type": "lagged", "scene": scene, "missed": missed })
+                    }
+                }
+            }
+        };
+
+        if socket
+            .send(Message::Text(reply.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    let scenes: Vec<String> = subscriptions.keys().cloned().collect();
+    drop(subscriptions);
+    for scene in &scenes {
+        state.events.release_scene(scene);
+    }
+    tracing::info!(
+        "ws_session user_id={} closed duration_ms={} scenes={}",
+        steamid,
+        started.elapsed().as_millis(),
+        scenes.len()
+    );
+}
+
+fn ws_apply_command(
+    state: &AppState,
+    subscriptions: &mut StreamMap<String, BroadcastStream<SceneEvent>>,
+    text: &str,
+) -> serde_json::Value {
+    let config = state.config();
+    match serde_json::from_str::<WsCommand>(text) {
+        Ok(WsCommand::Subscribe { scenes }) => {
+            for scene in scenes {
+                if scene.len() > config.max_scene_length {
+                    return serde_json::json!({
+                        "type": "error",
+                        "message": format!("scene must be <= {} characters", config.max_scene_length),
+                    });
+                }
+                if subscriptions.contains_key(&scene) {
+                    continue;
+                }
+                if subscriptions.len() >= config.ws_max_subscriptions {
+                    return serde_json::json!({
+                        "type": "error",
+                        "message": format!("at most {} scenes per connection", config.ws_max_subscriptions),
+                    });
+                }
+                let receiver = state.events.subscribe_scene(&scene);
+                subscriptions.insert(scene, BroadcastStream::new(receiver));
+            }
+            let scenes: Vec<&String> = subscriptions.keys().collect();
+            serde_json::json!({ "type": "subscribed", "scenes": scenes })
+        }
+        Ok(WsCommand::Unsubscribe { scenes }) => {
+            for scene in scenes {
+                subscriptions.remove(&scene);
+                state.events.release_scene(&scene);
+            }
+            let scenes: Vec<&String> = subscriptions.keys().collect();
+            serde_json::json!({ "type": "subscribed", "scenes": scenes })
+        }
+        Err(e) => serde_json::json!({ "type": "error", "message": e.to_string() }),
+    }
+}
+
+// --- realtime: per-user server-sent events ---
+
+pub async fn user_events(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.events.subscribe_user(steamid);
+    let subscription = UserSubscription {
+        hub: state.events.clone(),
+        user_id: steamid,
+    };
+
+    let stream = BroadcastStream::new(receiver).filter_map(move |event| {
+        let _keep_alive = &subscription; // releases the channel when the client disconnects
+        match event {
+            Ok(event) => Event::default()
+                .event(event.name())
+                .json_data(&event)
+                .ok()
+                .map(Ok),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                tracing::warn!("user_events user_id={} lagged missed={}", steamid, missed);
+                None
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
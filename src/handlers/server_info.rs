@@ -0,0 +1,94 @@
+// What this server offers, for mods talking to community servers that are
+// configured differently. Public, so a client can look before it has a ticket.
+
+use axum::{Json, extract::State};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{SERVER_VERSION, model::AttributeType, state::AppState, versioning::ApiVersion};
+
+#[derive(Debug, Serialize)]
+pub struct ServerInfoResponse {
+    server_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_id: Option<String>,
+    api_versions: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_client_version: Option<String>,
+    read_only: bool,
+    // requests need X-Server-Password (SERVER_PASSWORD or INVITE_ONLY)
+    private: bool,
+    features: Features,
+    limits: Limits,
+    // PREFAB_ATTRIBUTES: the extra properties uploads may carry per prefab
+    prefab_attributes: BTreeMap<String, BTreeMap<String, AttributeType>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Features {
+    uploads: bool,
+    likes: bool,
+    fetch: bool,
+    edits: bool,
+    websocket: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Limits {
+    max_structures_per_fetch: i64,
+    default_structures_per_fetch: i64,
+    max_structures_per_scene: i64,
+    max_pinned_per_scene: i64,
+    // 0: unlimited
+    daily_upload_quota: i64,
+    max_body_bytes: usize,
+    nearby_max_radius: f32,
+    // seconds between requests, ADAPTIVE_RATE_LIMITS scaling included
+    rate_limits: RateLimits,
+}
+
+#[derive(Debug, Serialize)]
+struct RateLimits {
+    post_structure: f64,
+    get_structure: f64,
+    post_like: f64,
+    nearby: f64,
+    usage: f64,
+}
+
+pub async fn get_server_info(State(state): State<AppState>) -> Json<ServerInfoResponse> {
+    let config = state.config();
+    let seconds = |interval| state.rate_limit(interval).as_secs_f64();
+    Json(ServerInfoResponse {
+        server_version: SERVER_VERSION,
+        server_id: config.server_id.clone(),
+        api_versions: ApiVersion::ALL.iter().map(|v| v.number()).collect(),
+        min_client_version: config.min_client_version.as_ref().map(|v| v.to_string()),
+        read_only: state.read_only(),
+        private: config.is_private(),
+        features: Features {
+            uploads: config.enable_post,
+            likes: config.enable_likes,
+            fetch: config.enable_fetch,
+            edits: config.enable_post,
+            websocket: true,
+        },
+        limits: Limits {
+            max_structures_per_fetch: config.max_requested_structs,
+            default_structures_per_fetch: config.default_random_limit,
+            max_structures_per_scene: config.max_user_structs_saved_per_scene,
+            max_pinned_per_scene: config.max_pinned_per_scene,
+            daily_upload_quota: config.daily_upload_quota,
+            max_body_bytes: config.max_body_bytes,
+            nearby_max_radius: config.nearby_max_radius,
+            rate_limits: RateLimits {
+                post_structure: seconds(config.post_structure_rate_limit),
+                get_structure: seconds(config.get_structure_rate_limit),
+                post_like: seconds(config.post_like_rate_limit),
+                nearby: seconds(config.nearby_rate_limit),
+                usage: seconds(config.usage_rate_limit),
+            },
+        },
+        prefab_attributes: config.prefab_attributes.clone(),
+    })
+}
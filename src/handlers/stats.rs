@@ -0,0 +1,405 @@
+// Player and global statistics, plus the daily summary and the rollup of
+// daily stats that run in the background.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+use crate::{
+    MILLIS_IN_DAY,
+    auth::VerifiedUser,
+    db::queries::{
+        GlobalStatsResponse, PrefabStatsResponse, SceneStatsResponse, prune_likes_ledger,
+        query_global_stats, query_prefab_stats, query_scene_stats, rollup_daily_stats,
+    },
+    discord::Notice,
+    error::AppError,
+    extract::QueryParams,
+    state::{AppState, CacheEntry},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserStatsResponse {
+    total_structures_uploaded: i64,
+    structures_uploaded_last_24h: i64,
+    total_likes_received: i64,
+    total_likes_sent: i64,
+}
+
+pub async fn get_global_stats(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+) -> Result<Json<GlobalStatsResponse>, AppError> {
+    let interval = state.rate_limit(state.config().global_stats_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last) = state.global_stats_rate_limiter.get(&steamid)
+        && last.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("global_stats", steamid);
+        return Err(AppError::RateLimited(
+            "You are requesting stats too frequently.".into(),
+            interval.saturating_sub(last.elapsed()),
+        ));
+    }
+    state
+        .global_stats_rate_limiter
+        .insert(steamid, Instant::now());
+
+    let cache_now = Instant::now();
+    if let Some(cached) = {
+        let guard = state.global_stats_cache.read().await;
+        guard
+            .as_ref()
+            .filter(|entry| entry.expires_at > cache_now)
+            .map(|entry| entry.value.clone())
+    } {
+        return Ok(Json(cached));
+    }
+
+    let now_duration = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "System clock error".into(),
+        )
+    })?;
+    let now_ms = i64::try_from(now_duration.as_millis()).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "System clock overflow".into(),
+        )
+    })?;
+    let since_ms = now_ms.saturating_sub(MILLIS_IN_DAY);
+
+    let stats = query_global_stats(&state.read_db, since_ms).await?;
+
+    {
+        let mut cache = state.global_stats_cache.write().await;
+        *cache = Some(CacheEntry {
+            value: stats.clone(),
+            expires_at: Instant::now() + state.config().global_stats_cache_ttl,
+        });
+    }
+
+    Ok(Json(stats))
+}
+
+pub async fn get_user_stats(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+) -> Result<Json<UserStatsResponse>, AppError> {
+    let interval = state.rate_limit(state.config().user_stats_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last) = state.user_stats_rate_limiter.get(&steamid)
+        && last.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("user_stats", steamid);
+        return Err(AppError::RateLimited(
+            "You are requesting stats too frequently.".into(),
+            interval.saturating_sub(last.elapsed()),
+        ));
+    }
+    state
+        .user_stats_rate_limiter
+        .insert(steamid, Instant::now());
+
+    let now_duration = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "System clock error".into(),
+        )
+    })?;
+    let now_ms = i64::try_from(now_duration.as_millis()).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "System clock overflow".into(),
+        )
+    })?;
+    let since_ms = now_ms.saturating_sub(MILLIS_IN_DAY);
+
+    let total_structures_uploaded = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM structures WHERE user_id = ? AND deleted = 0",
+    )
+    .bind(steamid as i64)
+    .fetch_one(&state.read_db)
+    .await?;
+
+    let structures_uploaded_last_24h = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM structures WHERE user_id = ? AND deleted = 0 AND created_at >= ?",
+    )
+    .bind(steamid as i64)
+    .bind(since_ms)
+    .fetch_one(&state.read_db)
+    .await?;
+
+    let likes = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT likes_received, likes_send FROM users WHERE user_id = ?",
+    )
+    .bind(steamid as i64)
+    .fetch_optional(&state.read_db)
+    .await?;
+    let (total_likes_received, total_likes_sent) = likes.unwrap_or((0, 0));
+
+    let stats = UserStatsResponse {
+        total_structures_uploaded,
+        structures_uploaded_last_24h,
+        total_likes_received,
+        total_likes_sent,
+    };
+
+    Ok(Json(stats))
+}
+
+// Counts for one scene in the current season, cached for SCENE_STATS_CACHE_TTL_SECONDS
+pub async fn get_scene_stats(
+    State(state): State<AppState>,
+    VerifiedUser(_): VerifiedUser,
+    Path(scene): Path<String>,
+) -> Result<Json<SceneStatsResponse>, AppError> {
+    let config = state.config();
+    let key = (scene, config.current_season);
+    if let Some(cached) = state.scene_stats_cache.get(&key)
+        && cached.expires_at > Instant::now()
+    {
+        return Ok(Json(cached.value.clone()));
+    }
+
+    let stats = query_scene_stats(&state.read_db, &key.0, key.1).await?;
+    if stats.total_structures > 0 {
+        state.scene_stats_cache.insert(
+            key,
+            CacheEntry {
+                value: stats.clone(),
+                expires_at: Instant::now() + config.scene_stats_cache_ttl,
+            },
+        );
+    }
+
+    Ok(Json(stats))
+}
+
+// Structures and average likes per prefab in the current season, overall and
+// per scene. Public (no Steam ticket) so the mod team can see which buildables
+// get used; cached for SCENE_STATS_CACHE_TTL_SECONDS.
+pub async fn get_prefab_stats(
+    State(state): State<AppState>,
+) -> Result<Json<PrefabStatsResponse>, AppError> {
+    let config = state.config();
+    let cache_now = Instant::now();
+    if let Some(cached) = {
+        let guard = state.prefab_stats_cache.read().await;
+        guard
+            .as_ref()
+            .filter(|entry| {
+                entry.expires_at > cache_now && entry.value.season == config.current_season
+            })
+            .map(|entry| entry.value.clone())
+    } {
+        return Ok(Json(cached));
+    }
+
+    let stats = query_prefab_stats(&state.read_db, config.current_season).await?;
+
+    {
+        let mut cache = state.prefab_stats_cache.write().await;
+        *cache = Some(CacheEntry {
+            value: stats.clone(),
+            expires_at: Instant::now() + config.scene_stats_cache_ttl,
+        });
+    }
+
+    Ok(Json(stats))
+}
+
+const SCENES_PAGE_DEFAULT: i64 = 100;
+const SCENES_PAGE_MAX: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ScenesParams {
+    after: Option<String>, // cursor: pass the previous page's next_after
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SceneSummary {
+    scene: String,
+    structures: i64,
+    last_activity: i64, // epoch millis of the newest live structure
+    // a featured_scene event is running for it
+    #[sqlx(skip)]
+    featured: bool,
+}
+
+#[derive(Serialize)]
+pub struct ScenesPage {
+    scenes: Vec<SceneSummary>,
+    next_after: Option<String>,
+}
+
+// Scenes with live structures in the current season, by name. Public (no
+// Steam ticket) so community tools can enumerate a server.
+pub async fn list_scenes(
+    State(state): State<AppState>,
+    QueryParams(p): QueryParams<ScenesParams>,
+) -> Result<Json<ScenesPage>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(SCENES_PAGE_DEFAULT)
+        .clamp(1, SCENES_PAGE_MAX);
+
+    // one extra row tells whether another page exists
+    let mut scenes: Vec<SceneSummary> = sqlx::query_as(
+        r#"SELECT scene, COUNT(*) AS structures, MAX(created_at) AS last_activity
+           FROM structures
+           WHERE season_id = ? AND deleted = 0 AND (? IS NULL OR scene > ?)
+           GROUP BY scene ORDER BY scene LIMIT ?"#,
+    )
+    .bind(state.config().current_season)
+    .bind(&p.after)
+    .bind(&p.after)
+    .bind(limit + 1)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    let next_after = if scenes.len() as i64 > limit {
+        scenes.truncate(limit as usize);
+        scenes.last().map(|s| s.scene.clone())
+    } else {
+        None
+    };
+    let events = state.server_events.active(&state.read_db).await?;
+    for scene in &mut scenes {
+        scene.featured = events.is_featured(&scene.scene);
+    }
+
+    Ok(Json(ScenesPage { scenes, next_after }))
+}
+
+// --- daily stats rollup ---
+
+// How often the running day (and the one before it) is re-aggregated
+const STATS_ROLLUP_INTERVAL: Duration = Duration::from_secs(3600);
+const MAX_DAILY_STATS_DAYS: i64 = 365;
+
+#[derive(Debug, Serialize)]
+struct DailyStats {
+    day: String, // UTC, YYYY-MM-DD
+    structures_posted: i64,
+    unique_users: i64,
+    likes_given: i64,
+    scenes: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailyStatsResponse {
+    days: Vec<DailyStats>,
+}
+
+#[derive(Deserialize)]
+pub struct DailyStatsParams {
+    days: Option<i64>,
+}
+
+// Rolls up the daily stats every hour and prunes likes_ledger along with them
+pub async fn stats_rollup(state: AppState) {
+    let mut ticker = tokio::time::interval(STATS_ROLLUP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        match rollup_daily_stats(&state.db, now_ms).await {
+            Ok(()) => tracing::info!("stats_rollup result=OK"),
+            Err(e) => tracing::error!("stats_rollup result=error error={}", e),
+        }
+        match prune_likes_ledger(&state.db, &state.config(), now_ms).await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!("likes_ledger pruned rows={}", pruned),
+            Err(e) => tracing::error!("likes_ledger prune failed error={}", e),
+        }
+    }
+}
+
+// Public (no Steam ticket) so community sites can chart activity.
+pub async fn get_daily_stats(
+    State(state): State<AppState>,
+    QueryParams(p): QueryParams<DailyStatsParams>,
+) -> Result<Json<DailyStatsResponse>, AppError> {
+    let days = p.days.unwrap_or(30).clamp(1, MAX_DAILY_STATS_DAYS);
+
+    let rows: Vec<(String, i64, i64, i64)> = sqlx::query_as(
+        r#"SELECT day, structures_posted, unique_users, likes_given
+           FROM stats_daily ORDER BY day DESC LIMIT ?"#,
+    )
+    .bind(days)
+    .fetch_all(&state.read_db)
+    .await?;
+
+    let mut stats: Vec<DailyStats> = rows
+        .into_iter()
+        .rev()
+        .map(
+            |(day, structures_posted, unique_users, likes_given)| DailyStats {
+                day,
+                structures_posted,
+                unique_users,
+                likes_given,
+                scenes: BTreeMap::new(),
+            },
+        )
+        .collect();
+
+    if let Some(first) = stats.first() {
+        let scene_rows: Vec<(String, String, i64)> = sqlx::query_as(
+            "SELECT day, scene, structures_posted FROM stats_daily_scenes WHERE day >= ?",
+        )
+        .bind(&first.day)
+        .fetch_all(&state.read_db)
+        .await?;
+
+        for (day, scene, count) in scene_rows {
+            if let Some(entry) = stats.iter_mut().find(|s| s.day == day) {
+                entry.scenes.insert(scene, count);
+            }
+        }
+    }
+
+    Ok(Json(DailyStatsResponse { days: stats }))
+}
+
+// Posts the global stats to Discord once a day while a webhook is configured.
+pub async fn daily_summary(state: AppState) {
+    let mut ticker = tokio::time::interval(Duration::from_millis(MILLIS_IN_DAY as u64));
+    ticker.tick().await; // first tick fires immediately; wait a full day instead
+
+    loop {
+        ticker.tick().await;
+        if state.config().discord_webhook_url.is_none() {
+            continue;
+        }
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        match query_global_stats(&state.read_db, now_ms.saturating_sub(MILLIS_IN_DAY)).await {
+            Ok(stats) => state.discord.notify(Notice::DailySummary {
+                unique_players_last_24h: stats.total_unique_players_last_24h,
+                structures_uploaded_last_24h: stats.total_structures_uploaded_last_24h,
+                structures_total: stats.total_structures_uploaded_all_time,
+                likes_given_total: stats.total_likes_given_all_time,
+            }),
+            Err(e) => tracing::error!("daily_summary result=error error={}", e),
+        }
+    }
+}
@@ -0,0 +1,1353 @@
+// Structure uploads, fetches and the per-structure endpoints.
+//
+// Also home to the background tasks feeding them: the random sample refresh
+// and the writer behind POST_QUEUE_CAPACITY.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderName, StatusCode, header::IF_MATCH},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::Acquire;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    MILLIS_IN_DAY,
+    auth::{SteamApp, VerifiedUser, owns_app, persona_name},
+    batches::BatchKey,
+    db::queries::{
+        LikesDecay, NOT_SHADOW_BANNED, PINNED_OR_CREATED_AFTER, RandomFilter, StoreError, Stored,
+        archive_cold, check_spacing, count_random_matches, fetch_followed_builders, fetch_random,
+        is_shadow_banned, load_attributes, load_contributors, load_creator_stats, load_reactions,
+        refresh_likes, store_structure, take_burst_credit,
+    },
+    error::{ApiError, AppError},
+    events::SceneEvent,
+    extract::{JsonBody, QueryParams},
+    fetch_log::prune_history,
+    model::{NewStructure, Sphere, Structure, StructureEdit},
+    plausibility::{PlausibilityMode, placement_problem},
+    post_queue::{PostStatus, QueuedPost},
+    state::AppState,
+};
+
+pub async fn post_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    SteamApp(appid): SteamApp,
+    JsonBody(mut s): JsonBody<NewStructure>,
+) -> Result<PostResponse, AppError> {
+    s.validate(&state.config())?;
+
+    // Rate limiting check for posting structures (configurable). New players
+    // syncing a save spend their one-time burst credit instead.
+    let interval = state.rate_limit(state.config().post_structure_rate_limit);
+    let since_last_post = state
+        .post_structure_rate_limiter
+        .get(&steamid)
+        .map(|last_post_time| last_post_time.elapsed());
+    if !state.rate_limit_exempt(steamid)
+        && let Some(elapsed) = since_last_post
+        && elapsed < interval
+        && !take_burst_credit(
+            &state.db,
+            steamid as i64,
+            state.config().new_player_burst_posts,
+        )
+        .await?
+    {
+        state.limit_stats.rate_limited("post_structure", steamid);
+        return Err(AppError::RateLimited(
+            "You are posting structures too frequently.".into(),
+            interval.saturating_sub(elapsed),
+        ));
+    }
+    state
+        .post_structure_rate_limiter
+        .insert(steamid, Instant::now());
+
+    if state.config().require_app_ownership && !owns_app(&state, steamid, appid).await? {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "Posting requires owning the game on this Steam account.",
+        )
+        .with_code("app_not_owned")
+        .into());
+    }
+
+    // The submitted username is only used when Steam can't tell us the real one
+    if state.config().resolve_steam_names
+        && let Some(name) = persona_name(&state, steamid).await
+    {
+        s.username = name;
+    }
+
+    if let Some(queue) = &state.post_queue {
+        let guid = queue.enqueue(steamid, appid, s)?;
+        return Ok(PostResponse::Queued(guid));
+    }
+
+    // a relaxed_caps event raises the per-scene cap and the daily quota
+    let config = state
+        .server_events
+        .active(&state.read_db)
+        .await?
+        .relax(state.config());
+
+    // Begin a transaction to perform all database operations at once.
+    let mut tx = state.db_metrics.begin("writer", &state.db).await?;
+
+    let stored = state
+        .db_metrics
+        .timed(
+            "store_structure",
+            store_structure(&mut tx, &config, steamid, appid, &s),
+        )
+        .await;
+    let rec = match stored {
+        Ok(Stored::New(rec)) => rec,
+        // Client retries re-send the same build; hand back the stored row instead of a copy.
+        Ok(Stored::Duplicate(existing)) => {
+            tx.rollback().await.ok();
+            return Ok(PostResponse::Stored(Box::new(existing)));
+        }
+        Err(e) => {
+            tx.rollback().await.ok();
+            return Err(e.app_error(&s, &config));
+        }
+    };
+
+    // Commit the transaction to finalize all changes.
+    tx.commit().await?;
+
+    if rec.under_review.is_none() {
+        state.events.publish_scene(
+            &rec.scene,
+            SceneEvent::StructurePosted {
+                structure: Box::new(rec.without_upload_info()),
+            },
+        );
+    }
+
+    Ok(PostResponse::Stored(Box::new(rec)))
+}
+
+// What post_structure answers: the stored row, or where to poll for it
+pub enum PostResponse {
+    Stored(Box<Structure>),
+    Queued(String), // client_guid
+}
+
+#[derive(Serialize)]
+struct QueuedBody<'a> {
+    client_guid: &'a str,
+    status: &'static str,
+}
+
+impl IntoResponse for PostResponse {
+    fn into_response(self) -> Response {
+        match self {
+            PostResponse::Stored(structure) => Json(structure).into_response(),
+            PostResponse::Queued(guid) => (
+                StatusCode::ACCEPTED,
+                Json(QueuedBody {
+                    client_guid: &guid,
+                    status: "queued",
+                }),
+            )
+                .into_response(),
+        }
+    }
+}
+
+// Outcome of a queued upload, as post_structure would have answered it
+pub async fn get_queued_post(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(guid): Path<String>,
+) -> Result<PostResponse, AppError> {
+    let status = state
+        .post_queue
+        .as_ref()
+        .and_then(|queue| queue.status(&guid, steamid));
+    match status {
+        Some(PostStatus::Queued) => Ok(PostResponse::Queued(guid)),
+        Some(PostStatus::Done(result)) => result.map(PostResponse::Stored).map_err(AppError::from),
+        None => Err(AppError::NotFound("No such upload.")),
+    }
+}
+
+// The single writer behind POST_QUEUE_CAPACITY: takes whatever uploads are
+// waiting, up to POST_QUEUE_BATCH_SIZE, and stores them in one transaction.
+pub async fn write_queued_posts(state: AppState, mut receiver: mpsc::Receiver<QueuedPost>) {
+    let Some(queue) = state.post_queue.clone() else {
+        return;
+    };
+    let mut batch = Vec::new();
+    loop {
+        let batch_size = state.config().post_queue_batch_size;
+        if receiver.recv_many(&mut batch, batch_size).await == 0 {
+            return;
+        }
+        let started = Instant::now();
+        let size = batch.len();
+        let results = store_post_batch(&state, &batch).await;
+        let stored = results.iter().filter(|r| r.is_ok()).count();
+        for (post, result) in batch.drain(..).zip(results) {
+            if let Ok((true, rec)) = &result
+                && rec.under_review.is_none()
+            {
+                state.events.publish_scene(
+                    &rec.scene,
+                    SceneEvent::StructurePosted {
+                        structure: Box::new(rec.without_upload_info()),
+                    },
+                );
+            }
+            queue.finish(&post.guid, result.map(|(_, rec)| Box::new(rec)));
+        }
+        queue.sweep();
+        tracing::info!(
+            "post_queue stored batch size={} ok={} duration_ms={}",
+            size,
+            stored,
+            started.elapsed().as_millis()
+        );
+    }
+}
+
+// One result per upload: the row (and whether it is new) or the error the
+// client would have got from a direct post
+async fn store_post_batch(
+    state: &AppState,
+    batch: &[QueuedPost],
+) -> Vec<Result<(bool, Structure), ApiError>> {
+    let config = match state.server_events.active(&state.read_db).await {
+        Ok(events) => events.relax(state.config()),
+        Err(e) => {
+            tracing::warn!("server_events lookup_failed error={}", e);
+            state.config()
+        }
+    };
+    let internal = |step: &str, e: sqlx::Error| {
+        tracing::error!(
+            "post_queue batch failed size={} error={}",
+            batch.len(),
+            step
+        );
+        ApiError::from(e)
+    };
+    let mut tx = match state.db_metrics.begin("writer", &state.db).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            let error = internal("tx_begin_failed", e);
+            return batch.iter().map(|_| Err(error.clone())).collect();
+        }
+    };
+
+    let mut results = Vec::with_capacity(batch.len());
+    for post in batch {
+        // a savepoint per upload, so a rejected one leaves the rest of the batch alone
+        let mut savepoint = match tx.begin().await {
+            Ok(savepoint) => savepoint,
+            Err(e) => {
+                results.push(Err(internal("savepoint_failed", e)));
+                continue;
+            }
+        };
+        let stored = store_structure(
+            &mut savepoint,
+            &config,
+            post.steamid,
+            post.appid,
+            &post.structure,
+        )
+        .await;
+        results.push(match stored {
+            Ok(Stored::New(rec)) => match savepoint.commit().await {
+                Ok(()) => Ok((true, rec)),
+                Err(e) => Err(internal("savepoint_release_failed", e)),
+            },
+            Ok(Stored::Duplicate(existing)) => {
+                savepoint.rollback().await.ok();
+                Ok((false, existing))
+            }
+            Err(e) => {
+                savepoint.rollback().await.ok();
+                if let StoreError::Db(step, _) = &e {
+                    tracing::error!(
+                        "post_queue upload failed user_id={} error={}",
+                        post.steamid,
+                        step
+                    );
+                }
+                Err(e.app_error(&post.structure, &config).into())
+            }
+        });
+    }
+
+    if let Err(e) = tx.commit().await {
+        let error = internal("tx_commit_failed", e);
+        // nothing new made it; duplicates and rejections still stand
+        for result in &mut results {
+            if matches!(result, Ok((true, _))) {
+                *result = Err(error.clone());
+            }
+        }
+    }
+    results
+}
+
+// Opt-in header asking get_random to wrap its rows with the number of matches
+pub static INCLUDE_TOTAL_HEADER: HeaderName = HeaderName::from_static("x-include-total");
+
+// Plain rows by default; with X-Include-Total: true, the rows plus how many
+// structures matched the filter, so clients can tell a sparse scene from a
+// filtered-away one.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RandomResponse<T> {
+    Items(Vec<T>),
+    WithTotal { items: Vec<T>, total_matching: i64 },
+}
+
+impl<T> RandomResponse<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> RandomResponse<U> {
+        match self {
+            RandomResponse::Items(items) => {
+                RandomResponse::Items(items.into_iter().map(f).collect())
+            }
+            RandomResponse::WithTotal {
+                items,
+                total_matching,
+            } => RandomResponse::WithTotal {
+                items: items.into_iter().map(f).collect(),
+                total_matching,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RandomParams {
+    pub scene: String,
+    pub map_id: Option<i32>,
+    pub limit: Option<i64>,
+    pub exclude_prefabs: Option<String>,
+    // lobby members passing the same seed get the same rows
+    pub seed: Option<String>,
+    // serve one of each set of identical builds (same content_hash)
+    #[serde(default)]
+    pub dedupe: bool,
+    // attach each builder's likes_received and structure count
+    #[serde(default)]
+    pub creator_stats: bool,
+    // always include structures by builders of the requester's favorites
+    #[serde(default)]
+    pub favorite_builders: bool,
+}
+
+// Any string works as a seed (lobby ids are too long for the SQL key); FNV-1a
+fn seed_from(value: &str) -> u32 {
+    value.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+pub async fn get_random(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    SteamApp(appid): SteamApp,
+    headers: HeaderMap,
+    QueryParams(p): QueryParams<RandomParams>,
+) -> Result<Json<RandomResponse<Structure>>, AppError> {
+    check_random_rate_limit(&state, steamid)?;
+
+    let include_total = headers
+        .get(&INCLUDE_TOTAL_HEADER)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"));
+    let (rows, total_matching) =
+        random_rows(&state, appid, &p, include_total, Some(steamid)).await?;
+    note_fetched(&state, &rows);
+    if p.seed.is_none() {
+        note_served_to(&state, steamid, &rows);
+    }
+
+    Ok(Json(match total_matching {
+        None => RandomResponse::Items(rows),
+        Some(total_matching) => RandomResponse::WithTotal {
+            items: rows,
+            total_matching,
+        },
+    }))
+}
+
+// GET_STRUCTURE_RATE_LIMIT, shared by everything that runs a random pick
+pub fn check_random_rate_limit(state: &AppState, steamid: u64) -> Result<(), AppError> {
+    let interval = state.rate_limit(state.config().get_structure_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last_get_time) = state.get_structure_rate_limiter.get(&steamid)
+        && last_get_time.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("get_random", steamid);
+        return Err(AppError::RateLimited(
+            "You are requesting structures too frequently.".into(),
+            interval.saturating_sub(last_get_time.elapsed()),
+        ));
+    }
+    state
+        .get_structure_rate_limiter
+        .insert(steamid, Instant::now());
+    Ok(())
+}
+
+// With a fetch history, a fetch picks this many times its limit
+const HISTORY_OVERFETCH: i64 = 2;
+
+// Contract partners (and favorite builders, on request) fill at most this
+// percent of a fetch, so strangers still show up
+const FOLLOWED_BUILDERS_MAX_SHARE: i64 = 50;
+
+// The random pick for `p`, with reactions and contributors attached, and the
+// number of structures it could have picked from when `include_total` is set.
+// A shadow-banned `requester` also gets their own structures.
+pub async fn random_rows(
+    state: &AppState,
+    appid: u64,
+    p: &RandomParams,
+    include_total: bool,
+    requester: Option<u64>,
+) -> Result<(Vec<Structure>, Option<i64>), AppError> {
+    let config = state.config();
+    if p.scene.len() > config.max_scene_length {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("scene must be <= {} characters", config.max_scene_length),
+        )
+        .into());
+    }
+    let limit = p
+        .limit
+        .unwrap_or(config.default_random_limit_for_map(p.map_id))
+        .clamp(0, config.max_requested_structs);
+    // Auto-curation: this many slots go to the scene's most-liked structures
+    let curated_limit = limit * config.curated_share_percent / 100;
+    // FETCH_HISTORY_DAYS: pick extra, so what the player already got can make
+    // room for what they haven't. Seeded picks stay the same for the lobby.
+    let history_since = match requester {
+        Some(steamid) if config.fetch_history_days > 0 && p.seed.is_none() => Some((
+            steamid as i64,
+            now_millis() - config.fetch_history_days as i64 * MILLIS_IN_DAY,
+        )),
+        _ => None,
+    };
+    let picked_limit = if history_since.is_some() {
+        limit.saturating_mul(HISTORY_OVERFETCH)
+    } else {
+        limit
+    };
+
+    let exclude_prefabs: Vec<String> = p
+        .exclude_prefabs
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+
+    let created_after = config
+        .structure_ttl_for_map(p.map_id)
+        .map(|ttl| now_millis().saturating_sub(ttl.as_millis() as i64));
+
+    let viewer = match requester {
+        Some(steamid) if is_shadow_banned(&state.read_db, steamid as i64).await? => {
+            Some(steamid as i64)
+        }
+        _ => None,
+    };
+
+    let filter = RandomFilter {
+        scene: &p.scene,
+        map_id: p.map_id,
+        season: config.current_season,
+        app_id: config.separate_appids.then_some(appid as i64),
+        exclude_prefabs: &exclude_prefabs,
+        created_after,
+        limit: picked_limit,
+        curated_limit,
+        seed: p.seed.as_deref().map(seed_from),
+        viewer,
+        prefab_quotas: &config.prefab_fetch_quotas,
+        prefab_share: (config.prefab_max_share_percent > 0)
+            .then(|| (picked_limit * config.prefab_max_share_percent / 100).max(1)),
+        likes_decay: (config.likes_decay_days > 0).then(|| LikesDecay {
+            now: now_millis(),
+            half_life_millis: config.likes_decay_days * MILLIS_IN_DAY,
+        }),
+    };
+
+    let fetch = || {
+        state.db_metrics.timed("random_fetch", async {
+            let mut rows = fetch_random(state, &config, &filter).await?;
+            load_reactions(&state.read_db, &mut rows).await?;
+            load_contributors(&state.read_db, &mut rows).await?;
+            load_attributes(&state.read_db, &mut rows).await?;
+            Ok::<_, ApiError>(rows)
+        })
+    };
+    let mut rows = if config.random_batch_ttl.is_zero() {
+        fetch().await?
+    } else {
+        // everyone asking for the same thing within the TTL shares one batch
+        let (batch, age) = state
+            .random_batches
+            .get_or_fetch(BatchKey::new(&filter), config.random_batch_ttl, fetch)
+            .await?;
+        let mut rows = batch.as_ref().clone();
+        if age > config.like_staleness {
+            state
+                .db_metrics
+                .timed("refresh_likes", refresh_likes(&state.read_db, &mut rows))
+                .await?;
+        }
+        rows
+    };
+    state.like_buffer.overlay(&mut rows);
+    if p.dedupe {
+        collapse_duplicates(&mut rows);
+    }
+    if let Some((user_id, since)) = history_since {
+        let ids: Vec<i64> = rows.iter().filter_map(|s| s.id).collect();
+        let seen = state
+            .fetch_log
+            .seen(&state.read_db, user_id, &ids, since)
+            .await?;
+        // stable, so the curated share and the random order survive
+        rows.sort_by_key(|s| s.id.is_some_and(|id| seen.contains(&id)));
+        rows.truncate(limit as usize);
+    }
+    let followed = match requester {
+        Some(steamid) if p.seed.is_none() => {
+            p.favorite_builders
+                || !state
+                    .contracts
+                    .partners(&state.read_db, steamid as i64)
+                    .await?
+                    .is_empty()
+        }
+        _ => false,
+    };
+    if followed && let Some(steamid) = requester {
+        let wanted = (limit * FOLLOWED_BUILDERS_MAX_SHARE / 100)
+            .max(1)
+            .min(limit);
+        let mut favored = state
+            .db_metrics
+            .timed("followed_builders", async {
+                let mut favored = fetch_followed_builders(
+                    &state.read_db,
+                    &config,
+                    &filter,
+                    steamid as i64,
+                    p.favorite_builders,
+                    wanted,
+                )
+                .await?;
+                load_reactions(&state.read_db, &mut favored).await?;
+                load_contributors(&state.read_db, &mut favored).await?;
+                load_attributes(&state.read_db, &mut favored).await?;
+                Ok::<_, sqlx::Error>(favored)
+            })
+            .await?;
+        state.like_buffer.overlay(&mut favored);
+        // they go first; the random pick fills the rest
+        rows.retain(|s| !favored.iter().any(|f| f.id == s.id));
+        favored.append(&mut rows);
+        rows = favored;
+        rows.truncate(limit as usize);
+    }
+    // per request rather than in the shared batch, so they stay current
+    if p.creator_stats {
+        load_creator_stats(&state.read_db, &mut rows).await?;
+    }
+
+    let total_matching = if include_total {
+        Some(
+            state
+                .db_metrics
+                .timed(
+                    "random_count",
+                    count_random_matches(&state.read_db, &config, &filter),
+                )
+                .await?,
+        )
+    } else {
+        None
+    };
+    Ok((rows, total_matching))
+}
+
+// Keeps the most-liked of each content_hash, in the place of the first one
+// picked; the rest are dropped, so the answer can come up short of `limit`.
+fn collapse_duplicates(rows: &mut Vec<Structure>) {
+    let mut kept: HashMap<i64, usize> = HashMap::new();
+    let mut collapsed: Vec<Structure> = Vec::with_capacity(rows.len());
+    for row in rows.drain(..) {
+        let Some(hash) = row.content_hash else {
+            collapsed.push(row);
+            continue;
+        };
+        match kept.get(&hash) {
+            Some(&index) if collapsed[index].likes < row.likes => collapsed[index] = row,
+            Some(_) => {}
+            None => {
+                kept.insert(hash, collapsed.len());
+                collapsed.push(row);
+            }
+        }
+    }
+    *rows = collapsed;
+}
+
+// Notes served structures for last_fetched_at (FETCH_FLUSH_SECONDS)
+pub fn note_fetched(state: &AppState, rows: &[Structure]) {
+    if !state.config().fetch_flush_interval.is_zero() {
+        state
+            .fetch_log
+            .record(rows.iter().filter_map(|s| s.id), now_millis());
+    }
+}
+
+// Notes what `steamid` was served for their fetch history (FETCH_HISTORY_DAYS)
+fn note_served_to(state: &AppState, steamid: u64, rows: &[Structure]) {
+    if state.config().fetch_history_days > 0 {
+        state.fetch_log.record_history(
+            steamid as i64,
+            rows.iter().filter_map(|s| s.id),
+            now_millis(),
+        );
+    }
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+// Writes the fetch times get_random noted every FETCH_FLUSH_SECONDS, and the
+// fetch history (FETCH_HISTORY_DAYS) along with them.
+pub async fn flush_fetches(state: AppState) {
+    loop {
+        let interval = state.config().fetch_flush_interval;
+        // still polled when off, so fetches noted before a reload turned it off get written
+        tokio::time::sleep(if interval.is_zero() {
+            Duration::from_secs(60)
+        } else {
+            interval
+        })
+        .await;
+        if state.fetch_log.is_empty() {
+            continue;
+        }
+        let flushed = state
+            .db_metrics
+            .timed("flush_fetches", state.fetch_log.flush(&state.db))
+            .await;
+        match flushed {
+            Ok(count) => tracing::debug!("fetch_log flushed structures={}", count),
+            Err(e) => tracing::error!("fetch_log flush failed error={}", e),
+        }
+        let days = state.config().fetch_history_days;
+        if days > 0 {
+            let cutoff = now_millis() - days as i64 * MILLIS_IN_DAY;
+            if let Err(e) = prune_history(&state.db, cutoff).await {
+                tracing::error!("fetch_history prune failed error={}", e);
+            }
+        }
+    }
+}
+
+// Moves cold structures to structures_archive every hour (ARCHIVE_COLD_AFTER_DAYS)
+pub async fn archive_cold_structures(state: AppState) {
+    const BATCH: i64 = 1000;
+    loop {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        let days = state.config().archive_cold_after_days;
+        if days == 0 {
+            continue;
+        }
+        let cutoff = now_millis() - days as i64 * MILLIS_IN_DAY;
+        // batches keep each write transaction short
+        let mut archived = 0;
+        loop {
+            let result = state
+                .db_metrics
+                .timed("archive_cold", archive_cold(&state.db, cutoff, BATCH))
+                .await;
+            match result {
+                Ok(count) => {
+                    archived += count;
+                    if count < BATCH as u64 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("archive_cold failed error={}", e);
+                    break;
+                }
+            }
+        }
+        if archived > 0 {
+            tracing::info!("archive_cold archived={}", archived);
+        }
+    }
+}
+
+pub async fn refresh_random_samples(state: AppState) {
+    loop {
+        let interval = state.config().random_sample_refresh;
+        if interval.is_zero() {
+            // disabled; check again later in case a reload turns it on
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        }
+        tokio::time::sleep(interval).await;
+
+        let config = state.config();
+        let started = Instant::now();
+        // a scene nobody fetched for two intervals is dropped instead of reloaded
+        let result = state
+            .db_metrics
+            .timed(
+                "refresh_samples",
+                state.random_samples.refresh(
+                    &state.read_db,
+                    config.current_season,
+                    config.primary_appid() as i64,
+                    interval * 2,
+                ),
+            )
+            .await;
+        match result {
+            Ok(()) => tracing::info!(
+                "random_samples refreshed scenes={} duration_ms={}",
+                state.random_samples.len(),
+                started.elapsed().as_millis()
+            ),
+            Err(e) => tracing::error!("random_samples refresh failed error={}", e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NearbyParams {
+    pub scene: String,
+    x: f32,
+    y: f32,
+    z: f32,
+    radius: f32,
+    pub map_id: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+// Structures within `radius` of a point, nearest first.
+pub async fn get_nearby(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    SteamApp(appid): SteamApp,
+    QueryParams(p): QueryParams<NearbyParams>,
+) -> Result<Json<Vec<Structure>>, AppError> {
+    let config = state.config();
+
+    let interval = state.rate_limit(config.nearby_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last) = state.nearby_rate_limiter.get(&steamid)
+        && last.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("nearby", steamid);
+        return Err(AppError::RateLimited(
+            "You are requesting nearby structures too frequently.".into(),
+            interval.saturating_sub(last.elapsed()),
+        ));
+    }
+    state.nearby_rate_limiter.insert(steamid, Instant::now());
+
+    let invalid = if p.scene.len() > config.max_scene_length {
+        Some(format!(
+            "scene must be <= {} characters",
+            config.max_scene_length
+        ))
+    } else if ![p.x, p.y, p.z].iter().all(|v| v.is_finite()) {
+        Some("x, y and z must be finite numbers".to_string())
+    } else if !(p.radius > 0.0 && p.radius <= config.nearby_max_radius) {
+        Some(format!(
+            "radius must be > 0 and <= {}",
+            config.nearby_max_radius
+        ))
+    } else {
+        None
+    };
+    if let Some(message) = invalid {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, message).into());
+    }
+
+    let limit = p
+        .limit
+        .unwrap_or(config.default_random_limit_for_map(p.map_id))
+        .clamp(0, config.max_requested_structs);
+    let sphere = Sphere {
+        x: p.x,
+        y: p.y,
+        z: p.z,
+        radius: p.radius,
+    };
+
+    let mut conditions = vec![
+        "scene = ?",
+        "deleted = 0",
+        "season_id = ?",
+        Sphere::CONDITION,
+        NOT_SHADOW_BANNED,
+    ];
+    if p.map_id.is_some() {
+        conditions.push("map_id = ?");
+    }
+    if config.separate_appids {
+        conditions.push("COALESCE(app_id, ?) = ?");
+    }
+    let created_after = config
+        .structure_ttl_for_map(p.map_id)
+        .map(|ttl| now_millis().saturating_sub(ttl.as_millis() as i64));
+    if created_after.is_some() {
+        conditions.push(PINNED_OR_CREATED_AFTER);
+    }
+    let sql = format!(
+        "SELECT * FROM structures WHERE {} \
+         ORDER BY (pos_x - ?) * (pos_x - ?) + (pos_y - ?) * (pos_y - ?) + (pos_z - ?) * (pos_z - ?) \
+         LIMIT ?",
+        conditions.join(" AND ")
+    );
+
+    let mut query = sqlx::query_as::<_, Structure>(&sql)
+        .bind(&p.scene)
+        .bind(config.current_season);
+    for value in sphere.binds() {
+        query = query.bind(value);
+    }
+    query = query.bind(steamid as i64);
+    if let Some(map_id) = p.map_id {
+        query = query.bind(map_id);
+    }
+    if config.separate_appids {
+        query = query.bind(config.primary_appid() as i64).bind(appid as i64);
+    }
+    if let Some(created_after) = created_after {
+        query = query.bind(created_after);
+    }
+    for value in [p.x, p.x, p.y, p.y, p.z, p.z] {
+        query = query.bind(value);
+    }
+    query = query.bind(limit);
+
+    let mut rows = state
+        .db_metrics
+        .timed("nearby", async {
+            let mut rows = query.fetch_all(&state.read_db).await?;
+            load_reactions(&state.read_db, &mut rows).await?;
+            load_contributors(&state.read_db, &mut rows).await?;
+            load_attributes(&state.read_db, &mut rows).await?;
+            Ok::<_, sqlx::Error>(rows)
+        })
+        .await?;
+    state.like_buffer.overlay(&mut rows);
+
+    Ok(Json(rows))
+}
+
+const USER_STRUCTURES_PAGE_DEFAULT: i64 = 50;
+const USER_STRUCTURES_PAGE_MAX: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct UserStructuresParams {
+    pub scene: Option<String>,
+    pub before_id: Option<i64>, // cursor: pass the previous page's next_before_id
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserStructuresPage<T> {
+    pub structures: Vec<T>,
+    pub next_before_id: Option<i64>,
+}
+
+impl<T> UserStructuresPage<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> UserStructuresPage<U> {
+        UserStructuresPage {
+            structures: self.structures.into_iter().map(f).collect(),
+            next_before_id: self.next_before_id,
+        }
+    }
+}
+
+// One builder's live structures in the current season, newest first, for a
+// "more from this builder" view. Public like the scene list; a shadow-banned
+// builder's list is empty.
+pub async fn get_user_structures(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    QueryParams(p): QueryParams<UserStructuresParams>,
+) -> Result<Json<UserStructuresPage<Structure>>, AppError> {
+    let limit = p
+        .limit
+        .unwrap_or(USER_STRUCTURES_PAGE_DEFAULT)
+        .clamp(1, USER_STRUCTURES_PAGE_MAX);
+
+    // one extra row tells whether another page exists
+    let sql = format!(
+        r#"SELECT * FROM structures
+           WHERE user_id = ? AND deleted = 0 AND season_id = ? AND {NOT_SHADOW_BANNED}
+             AND (? IS NULL OR scene = ?) AND (? IS NULL OR id < ?)
+           ORDER BY id DESC LIMIT ?"#
+    );
+    let mut rows = state
+        .db_metrics
+        .timed("user_structures", async {
+            let mut rows: Vec<Structure> = sqlx::query_as(&sql)
+                .bind(user_id)
+                .bind(state.config().current_season)
+                .bind(0_i64)
+                .bind(&p.scene)
+                .bind(&p.scene)
+                .bind(p.before_id)
+                .bind(p.before_id)
+                .bind(limit + 1)
+                .fetch_all(&state.read_db)
+                .await?;
+            load_reactions(&state.read_db, &mut rows).await?;
+            load_contributors(&state.read_db, &mut rows).await?;
+            load_attributes(&state.read_db, &mut rows).await?;
+            Ok::<_, sqlx::Error>(rows)
+        })
+        .await?;
+    state.like_buffer.overlay(&mut rows);
+
+    let next_before_id = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().and_then(|s| s.id)
+    } else {
+        None
+    };
+    Ok(Json(UserStructuresPage {
+        structures: rows,
+        next_before_id,
+    }))
+}
+
+pub async fn report_usage(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let interval = state.rate_limit(state.config().usage_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last) = state.usage_rate_limiter.get(&(steamid, id))
+        && last.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("usage", steamid);
+        return Err(AppError::RateLimited(
+            "You are reporting usage of this structure too frequently.".into(),
+            interval.saturating_sub(last.elapsed()),
+        ));
+    }
+    state
+        .usage_rate_limiter
+        .insert((steamid, id), Instant::now());
+
+    // Owners walking over their own builds are not counted
+    let updated = sqlx::query(
+        "UPDATE structures SET uses = uses + 1 WHERE id = ? AND deleted = 0 AND user_id <> ?",
+    )
+    .bind(id)
+    .bind(steamid as i64)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        let exists: Option<i64> =
+            sqlx::query_scalar("SELECT 1 FROM structures WHERE id = ? AND deleted = 0")
+                .bind(id)
+                .fetch_optional(&state.db)
+                .await?;
+        if exists.is_none() {
+            return Err(AppError::NotFound("Structure not found"));
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Owner soft-delete; can be undone with restore_structure for USER_RESTORE_WINDOW_SECONDS.
+pub async fn delete_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query(
+        r#"UPDATE structures
+           SET deleted = 1, deleted_at = strftime('%s','now')*1000, deleted_by = 'owner'
+           WHERE id = ? AND user_id = ? AND deleted = 0"#,
+    )
+    .bind(id)
+    .bind(steamid as i64)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(AppError::NotFound("Structure not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Moves, turns or re-ropes one of the owner's structures in place, keeping its
+// id, likes and created_at. Edits share POST_STRUCTURE_RATE_LIMIT with uploads
+// and go through the same density, own-distance and plausibility checks, the
+// row itself left out of the counts; with PLAUSIBILITY_CHECKS=flag an
+// implausible edit is saved but held for review like an upload. Each one that
+// changes something is recorded in structure_edits as field -> [old, new].
+pub async fn edit_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    JsonBody(edit): JsonBody<StructureEdit>,
+) -> Result<Json<Structure>, AppError> {
+    let expected_version = if_match_version(&headers)?;
+    let config = state.config();
+    let interval = state.rate_limit(config.post_structure_rate_limit);
+    if !state.rate_limit_exempt(steamid)
+        && let Some(last_post_time) = state.post_structure_rate_limiter.get(&steamid)
+        && last_post_time.elapsed() < interval
+    {
+        state.limit_stats.rate_limited("edit_structure", steamid);
+        return Err(AppError::RateLimited(
+            "You are editing structures too frequently.".into(),
+            interval.saturating_sub(last_post_time.elapsed()),
+        ));
+    }
+    state
+        .post_structure_rate_limiter
+        .insert(steamid, Instant::now());
+
+    let mut tx = state.db_metrics.begin("writer", &state.db).await?;
+    let current: Option<Structure> =
+        sqlx::query_as("SELECT * FROM structures WHERE id = ? AND user_id = ? AND deleted = 0")
+            .bind(id)
+            .bind(steamid as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let Some(current) = current else {
+        return Err(AppError::NotFound("Structure not found"));
+    };
+    if current.version != expected_version {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "The structure was changed elsewhere (now version {}); fetch it again before editing.",
+                current.version
+            ),
+        )
+        .with_code("version_conflict")
+        .into());
+    }
+
+    let mut edited = NewStructure::from(&current);
+    let changes = edit.apply(&mut edited);
+    let mut rec = if changes.is_empty() {
+        current
+    } else {
+        if let Err(e) = check_spacing(&mut tx, &config, steamid, &edited, Some(id)).await {
+            return Err(e.app_error(&edited, &config));
+        }
+        let review_reason = match config.plausibility_checks {
+            PlausibilityMode::Off => None,
+            mode => match placement_problem(&edited, &config) {
+                Some(problem) if mode == PlausibilityMode::Reject => {
+                    return Err(StoreError::Implausible(problem).app_error(&edited, &config));
+                }
+                problem => problem,
+            },
+        };
+        let s = &edited;
+        let mut rec: Structure = sqlx::query_as(
+            r#"UPDATE structures SET
+                   pos_x = ?, pos_y = ?, pos_z = ?,
+                   rot_x = ?, rot_y = ?, rot_z = ?, rot_w = ?,
+                   rope_start_x = ?, rope_start_y = ?, rope_start_z = ?,
+                   rope_end_x = ?, rope_end_y = ?, rope_end_z = ?,
+                   rope_length = ?,
+                   rope_flying_rotation_x = ?, rope_flying_rotation_y = ?, rope_flying_rotation_z = ?,
+                   rope_anchor_rotation_x = ?, rope_anchor_rotation_y = ?,
+                   rope_anchor_rotation_z = ?, rope_anchor_rotation_w = ?,
+                   antigrav = ?,
+                   content_hash = ?,
+                   updated_at = strftime('%s','now')*1000,
+                   version = version + 1
+               WHERE id = ?
+               RETURNING *"#,
+        )
+        .bind(s.pos_x)
+        .bind(s.pos_y)
+        .bind(s.pos_z)
+        .bind(s.rot_x)
+        .bind(s.rot_y)
+        .bind(s.rot_z)
+        .bind(s.rot_w)
+        .bind(s.rope_start_x)
+        .bind(s.rope_start_y)
+        .bind(s.rope_start_z)
+        .bind(s.rope_end_x)
+        .bind(s.rope_end_y)
+        .bind(s.rope_end_z)
+        .bind(s.rope_length)
+        .bind(s.rope_flying_rotation_x)
+        .bind(s.rope_flying_rotation_y)
+        .bind(s.rope_flying_rotation_z)
+        .bind(s.rope_anchor_rotation_x)
+        .bind(s.rope_anchor_rotation_y)
+        .bind(s.rope_anchor_rotation_z)
+        .bind(s.rope_anchor_rotation_w)
+        .bind(s.antigrav)
+        .bind(s.content_hash())
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO structure_edits (structure_id, user_id, changes, edited_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(steamid as i64)
+        .bind(serde_json::json!(changes).to_string())
+        .bind(rec.updated_at)
+        .execute(&mut *tx)
+        .await?;
+        // out of every fetch until a moderator approves it, as for an upload
+        if let Some(reason) = review_reason {
+            tracing::info!(
+                "review held structure_id={} user_id={} reason={}",
+                id,
+                steamid,
+                reason
+            );
+            sqlx::query(
+                r#"UPDATE structures
+                   SET deleted = 1, deleted_at = strftime('%s','now')*1000, deleted_by = 'review',
+                       review_reason = ?, review_auto_approve_at = NULL
+                   WHERE id = ?"#,
+            )
+            .bind(&reason)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            rec.under_review = Some(true);
+        }
+        rec
+    };
+    tx.commit().await?;
+
+    load_reactions(&state.read_db, std::slice::from_mut(&mut rec)).await?;
+    load_contributors(&state.read_db, std::slice::from_mut(&mut rec)).await?;
+    load_attributes(&state.read_db, std::slice::from_mut(&mut rec)).await?;
+    state.like_buffer.overlay(std::slice::from_mut(&mut rec));
+    if !changes.is_empty() {
+        tracing::info!(
+            "structure_edit structure_id={} user_id={} fields={}",
+            id,
+            steamid,
+            changes.keys().copied().collect::<Vec<_>>().join(",")
+        );
+        if rec.under_review.is_none() {
+            state.events.publish_scene(
+                &rec.scene,
+                SceneEvent::StructureEdited {
+                    structure: Box::new(rec.without_upload_info()),
+                },
+            );
+        }
+    }
+    Ok(Json(rec))
+}
+
+// The version a PATCH was based on, from If-Match: `3`, `"3"` or `W/"3"`
+fn if_match_version(headers: &HeaderMap) -> Result<i64, AppError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Err(ApiError::new(
+            StatusCode::PRECONDITION_REQUIRED,
+            "Send the structure's version in If-Match.",
+        )
+        .with_code("version_required")
+        .into());
+    };
+    value
+        .to_str()
+        .ok()
+        .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "If-Match must hold the structure's version.",
+            )
+            .into()
+        })
+}
+
+// Exempts one of the owner's structures from pruning and STRUCTURE_TTL_DAYS,
+// up to MAX_PINNED_PER_SCENE per scene and season.
+pub async fn pin_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let max_pinned = state.config().max_pinned_per_scene;
+    let mut tx = state.db.begin().await?;
+
+    let target: Option<(String, i64, bool)> = sqlx::query_as(
+        "SELECT scene, season_id, pinned FROM structures WHERE id = ? AND user_id = ? AND deleted = 0",
+    )
+    .bind(id)
+    .bind(steamid as i64)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((scene, season, pinned)) = target else {
+        return Err(AppError::NotFound("Structure not found"));
+    };
+    if pinned {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let pinned_in_scene: i64 = sqlx::query_scalar(
+        r#"SELECT COUNT(*) FROM structures
+           WHERE user_id = ? AND scene = ? AND season_id = ? AND deleted = 0 AND pinned = 1"#,
+    )
+    .bind(steamid as i64)
+    .bind(&scene)
+    .bind(season)
+    .fetch_one(&mut *tx)
+    .await?;
+    if pinned_in_scene >= max_pinned {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("You can pin at most {max_pinned} structures per scene."),
+        )
+        .with_code("pin_limit")
+        .into());
+    }
+
+    sqlx::query("UPDATE structures SET pinned = 1 WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unpin_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let found = sqlx::query(
+        "UPDATE structures SET pinned = 0 WHERE id = ? AND user_id = ? AND deleted = 0",
+    )
+    .bind(id)
+    .bind(steamid as i64)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+
+    if found == 0 {
+        return Err(AppError::NotFound("Structure not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Undoes the owner's own recent delete; moderator removals stay removed. The
+// structure comes back only while the scene has room under the per-scene cap,
+// and a pinned one only while MAX_PINNED_PER_SCENE allows, so pruning always
+// finds an unpinned structure to drop.
+pub async fn restore_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let config = state.config();
+    let window_ms = config.user_restore_window.as_millis() as i64;
+    let mut tx = state.db.begin().await?;
+
+    let target: Option<(String, i64, i32, bool)> = sqlx::query_as(
+        r#"SELECT scene, season_id, map_id, pinned FROM structures
+           WHERE id = ? AND user_id = ? AND deleted = 1 AND deleted_by = 'owner'
+             AND deleted_at >= strftime('%s','now')*1000 - ?"#,
+    )
+    .bind(id)
+    .bind(steamid as i64)
+    .bind(window_ms)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((scene, season, map_id, pinned)) = target else {
+        return Err(AppError::NotFound(
+            "No recently deleted structure of yours with this id",
+        ));
+    };
+
+    let (live, live_pinned): (i64, i64) = sqlx::query_as(
+        r#"SELECT COUNT(*), COALESCE(SUM(pinned), 0) FROM structures
+           WHERE user_id = ? AND scene = ? AND season_id = ? AND deleted = 0"#,
+    )
+    .bind(steamid as i64)
+    .bind(&scene)
+    .bind(season)
+    .fetch_one(&mut *tx)
+    .await?;
+    let limit = config.max_user_structs_for_map(map_id);
+    if live >= limit {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("You already have {limit} structures in this scene; delete one first."),
+        )
+        .with_code("scene_full")
+        .into());
+    }
+    if pinned && live_pinned >= config.max_pinned_per_scene {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "You can pin at most {} structures per scene; unpin one first.",
+                config.max_pinned_per_scene
+            ),
+        )
+        .with_code("pin_limit")
+        .into());
+    }
+
+    sqlx::query(
+        "UPDATE structures SET deleted = 0, deleted_at = NULL, deleted_by = NULL WHERE id = ?",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
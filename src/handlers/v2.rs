@@ -0,0 +1,336 @@
+// /api/v2 payloads.
+//
+// v2 groups the flat pos_*/rot_*/rope_* columns of v1 into vectors and a rope
+// object. Only the shapes differ: the handlers below convert at the edge and
+// call the v1 handlers, so rate limits, validation and storage stay shared.
+// Endpoints whose payloads did not change are routed to the v1 handlers as-is.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::{
+    favorites::{self, FavoritesPage, FavoritesParams},
+    fetch_sessions,
+    likes::{self, LikeBody, LikeTotals},
+    structures::{
+        self, NearbyParams, PostResponse, RandomParams, RandomResponse, UserStructuresPage,
+        UserStructuresParams,
+    },
+};
+use crate::{
+    auth::{SteamApp, VerifiedUser},
+    error::AppError,
+    extract::{JsonBody, QueryParams},
+    model::{CreatorStats, NewStructure, PrunePolicy, Structure, StructureEdit},
+    state::AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rope {
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+    pub length: f32,
+    pub flying_rotation: [f32; 3],
+    pub anchor_rotation: [f32; 4],
+}
+
+#[derive(Debug, Serialize)]
+pub struct StructureV2 {
+    pub id: Option<i64>,
+    pub created_at: Option<i64>,
+    pub user_id: i64,
+    pub username: String,
+    pub map_id: i32,
+    pub scene: String,
+    pub segment: i32,
+    pub prefab: String,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub rope: Rope,
+    pub antigrav: bool,
+    pub likes: i32,
+    pub uses: i64,
+    pub pinned: bool,
+    pub reactions: BTreeMap<String, i64>,
+    pub contributors: Vec<i64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_slots: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pruned_structure_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_uploads_today: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    pub version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<CreatorStats>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewStructureV2 {
+    pub username: String,
+    pub map_id: i32,
+    pub scene: String,
+    pub segment: i32,
+    pub prefab: String,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub rope: Rope,
+    #[serde(default)]
+    pub antigrav: bool,
+    #[serde(default)]
+    pub contributors: Vec<u64>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub prune_policy: Option<PrunePolicy>,
+}
+
+impl From<Structure> for StructureV2 {
+    fn from(s: Structure) -> Self {
+        Self {
+            id: s.id,
+            created_at: s.created_at,
+            user_id: s.user_id,
+            username: s.username,
+            map_id: s.map_id,
+            scene: s.scene,
+            segment: s.segment,
+            prefab: s.prefab,
+            position: [s.pos_x, s.pos_y, s.pos_z],
+            rotation: [s.rot_x, s.rot_y, s.rot_z, s.rot_w],
+            rope: Rope {
+                start: [s.rope_start_x, s.rope_start_y, s.rope_start_z],
+                end: [s.rope_end_x, s.rope_end_y, s.rope_end_z],
+                length: s.rope_length,
+                flying_rotation: [
+                    s.rope_flying_rotation_x,
+                    s.rope_flying_rotation_y,
+                    s.rope_flying_rotation_z,
+                ],
+                anchor_rotation: [
+                    s.rope_anchor_rotation_x,
+                    s.rope_anchor_rotation_y,
+                    s.rope_anchor_rotation_z,
+                    s.rope_anchor_rotation_w,
+                ],
+            },
+            antigrav: s.antigrav,
+            likes: s.likes,
+            uses: s.uses,
+            pinned: s.pinned,
+            reactions: s.reactions,
+            contributors: s.contributors,
+            attributes: s.attributes,
+            remaining_slots: s.remaining_slots,
+            pruned_structure_id: s.pruned_structure_id,
+            remaining_uploads_today: s.remaining_uploads_today,
+            updated_at: s.updated_at,
+            version: s.version,
+            creator: s.creator,
+        }
+    }
+}
+
+// Vectors and the rope are replaced as a whole
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructureEditV2 {
+    pub position: Option<[f32; 3]>,
+    pub rotation: Option<[f32; 4]>,
+    pub rope: Option<Rope>,
+    pub antigrav: Option<bool>,
+}
+
+impl From<StructureEditV2> for StructureEdit {
+    fn from(edit: StructureEditV2) -> Self {
+        let position = edit.position.map(|[x, y, z]| (x, y, z));
+        let rotation = edit.rotation.map(|[x, y, z, w]| (x, y, z, w));
+        let rope = edit.rope;
+        Self {
+            pos_x: position.map(|p| p.0),
+            pos_y: position.map(|p| p.1),
+            pos_z: position.map(|p| p.2),
+            rot_x: rotation.map(|r| r.0),
+            rot_y: rotation.map(|r| r.1),
+            rot_z: rotation.map(|r| r.2),
+            rot_w: rotation.map(|r| r.3),
+            rope_start_x: rope.as_ref().map(|r| r.start[0]),
+            rope_start_y: rope.as_ref().map(|r| r.start[1]),
+            rope_start_z: rope.as_ref().map(|r| r.start[2]),
+            rope_end_x: rope.as_ref().map(|r| r.end[0]),
+            rope_end_y: rope.as_ref().map(|r| r.end[1]),
+            rope_end_z: rope.as_ref().map(|r| r.end[2]),
+            rope_length: rope.as_ref().map(|r| r.length),
+            rope_flying_rotation_x: rope.as_ref().map(|r| r.flying_rotation[0]),
+            rope_flying_rotation_y: rope.as_ref().map(|r| r.flying_rotation[1]),
+            rope_flying_rotation_z: rope.as_ref().map(|r| r.flying_rotation[2]),
+            rope_anchor_rotation_x: rope.as_ref().map(|r| r.anchor_rotation[0]),
+            rope_anchor_rotation_y: rope.as_ref().map(|r| r.anchor_rotation[1]),
+            rope_anchor_rotation_z: rope.as_ref().map(|r| r.anchor_rotation[2]),
+            rope_anchor_rotation_w: rope.as_ref().map(|r| r.anchor_rotation[3]),
+            antigrav: edit.antigrav,
+        }
+    }
+}
+
+impl From<NewStructureV2> for NewStructure {
+    fn from(s: NewStructureV2) -> Self {
+        let [pos_x, pos_y, pos_z] = s.position;
+        let [rot_x, rot_y, rot_z, rot_w] = s.rotation;
+        let Rope {
+            start: [rope_start_x, rope_start_y, rope_start_z],
+            end: [rope_end_x, rope_end_y, rope_end_z],
+            length: rope_length,
+            flying_rotation:
+                [
+                    rope_flying_rotation_x,
+                    rope_flying_rotation_y,
+                    rope_flying_rotation_z,
+                ],
+            anchor_rotation:
+                [
+                    rope_anchor_rotation_x,
+                    rope_anchor_rotation_y,
+                    rope_anchor_rotation_z,
+                    rope_anchor_rotation_w,
+                ],
+        } = s.rope;
+        Self {
+            username: s.username,
+            map_id: s.map_id,
+            scene: s.scene,
+            segment: s.segment,
+            prefab: s.prefab,
+            pos_x,
+            pos_y,
+            pos_z,
+            rot_x,
+            rot_y,
+            rot_z,
+            rot_w,
+            rope_start_x,
+            rope_start_y,
+            rope_start_z,
+            rope_end_x,
+            rope_end_y,
+            rope_end_z,
+            rope_length,
+            rope_flying_rotation_x,
+            rope_flying_rotation_y,
+            rope_flying_rotation_z,
+            rope_anchor_rotation_x,
+            rope_anchor_rotation_y,
+            rope_anchor_rotation_z,
+            rope_anchor_rotation_w,
+            antigrav: s.antigrav,
+            contributors: s.contributors,
+            attributes: s.attributes,
+            prune_policy: s.prune_policy,
+        }
+    }
+}
+
+pub async fn post_structure(
+    state: State<AppState>,
+    user: VerifiedUser,
+    app: SteamApp,
+    JsonBody(s): JsonBody<NewStructureV2>,
+) -> Result<Response, AppError> {
+    let posted = structures::post_structure(state, user, app, JsonBody(s.into())).await?;
+    Ok(into_v2(posted))
+}
+
+pub async fn edit_structure(
+    state: State<AppState>,
+    user: VerifiedUser,
+    id: Path<i64>,
+    headers: HeaderMap,
+    JsonBody(edit): JsonBody<StructureEditV2>,
+) -> Result<Json<StructureV2>, AppError> {
+    let Json(edited) =
+        structures::edit_structure(state, user, id, headers, JsonBody(edit.into())).await?;
+    Ok(Json(edited.into()))
+}
+
+pub async fn get_queued_post(
+    state: State<AppState>,
+    user: VerifiedUser,
+    guid: Path<String>,
+) -> Result<Response, AppError> {
+    let posted = structures::get_queued_post(state, user, guid).await?;
+    Ok(into_v2(posted))
+}
+
+fn into_v2(posted: PostResponse) -> Response {
+    match posted {
+        PostResponse::Stored(stored) => Json(StructureV2::from(*stored)).into_response(),
+        queued => queued.into_response(),
+    }
+}
+
+pub async fn get_random(
+    state: State<AppState>,
+    user: VerifiedUser,
+    app: SteamApp,
+    headers: HeaderMap,
+    params: QueryParams<RandomParams>,
+) -> Result<Json<RandomResponse<StructureV2>>, AppError> {
+    let Json(rows) = structures::get_random(state, user, app, headers, params).await?;
+    Ok(Json(rows.map(StructureV2::from)))
+}
+
+// Always answers with the new totals
+pub async fn like_structure(
+    State(state): State<AppState>,
+    VerifiedUser(steamid): VerifiedUser,
+    Path(id): Path<i64>,
+    JsonBody(body): JsonBody<LikeBody>,
+) -> Result<Json<LikeTotals>, AppError> {
+    Ok(Json(likes::apply_like(&state, steamid, id, body).await?))
+}
+
+pub async fn get_user_structures(
+    state: State<AppState>,
+    user_id: Path<i64>,
+    params: QueryParams<UserStructuresParams>,
+) -> Result<Json<UserStructuresPage<StructureV2>>, AppError> {
+    let Json(page) = structures::get_user_structures(state, user_id, params).await?;
+    Ok(Json(page.map(StructureV2::from)))
+}
+
+pub async fn list_favorites(
+    state: State<AppState>,
+    user: VerifiedUser,
+    params: QueryParams<FavoritesParams>,
+) -> Result<Json<FavoritesPage<StructureV2>>, AppError> {
+    let Json(page) = favorites::list_favorites(state, user, params).await?;
+    Ok(Json(page.map(StructureV2::from)))
+}
+
+pub async fn get_nearby(
+    state: State<AppState>,
+    user: VerifiedUser,
+    app: SteamApp,
+    params: QueryParams<NearbyParams>,
+) -> Result<Json<Vec<StructureV2>>, AppError> {
+    let Json(rows) = structures::get_nearby(state, user, app, params).await?;
+    Ok(Json(rows.into_iter().map(StructureV2::from).collect()))
+}
+
+pub async fn get_fetch_session(
+    state: State<AppState>,
+    user: VerifiedUser,
+    session_id: Path<String>,
+) -> Result<Json<Vec<StructureV2>>, AppError> {
+    let Json(rows) = fetch_sessions::get_fetch_session(state, user, session_id).await?;
+    Ok(Json(rows.into_iter().map(StructureV2::from).collect()))
+}
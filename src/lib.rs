@@ -0,0 +1,65 @@
+// Peak Stranding server.
+//
+// The binary in main.rs only sets up logging and calls `run`. Everything else
+// lives here so tests and other binaries can build the same state and router:
+//
+// - config: settings from the environment and the config file
+// - state: AppState, shared by every handler and background task
+// - auth: Steam credential checks and the extractors built on them
+// - model: structure rows and upload payloads
+// - db: connection pools, schema and migrations, the queries and dumps
+// - handlers: the HTTP routes, grouped by what they serve
+// - maintenance: the read-only switch for backups and migrations
+// - client_version: refusing mod installs older than MIN_CLIENT_VERSION
+// - private_server: the password or invite code of private servers
+// - selfcheck: the checks that run before the server listens
+// - server: startup, background tasks and the listeners
+// - server_events: scheduled community events (like multipliers, relaxed caps)
+// - tenants: more communities in the same process, under /t/<slug>
+// - mock_steam: a fake Steam ticket check for tests and local development
+
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const MILLIS_IN_DAY: i64 = 86_400_000;
+
+mod access_log;
+mod adaptive_limits;
+mod auth;
+mod batches;
+mod client_ip;
+mod client_version;
+pub mod config;
+mod contracts;
+pub mod db;
+mod db_metrics;
+mod discord;
+mod error;
+mod events;
+mod extract;
+mod fetch_log;
+pub mod handlers;
+mod like_buffer;
+mod limit_stats;
+mod load_shed;
+mod maintenance;
+#[cfg(any(test, feature = "mock-steam"))]
+pub mod mock_steam;
+mod model;
+mod plausibility;
+mod post_queue;
+mod private_server;
+mod request_timeout;
+mod samples;
+mod selfcheck;
+mod server;
+mod server_events;
+pub mod state;
+mod steam;
+mod tenants;
+#[cfg(test)]
+mod tests;
+mod versioning;
+
+pub use config::Config;
+pub use handlers::build_router;
+pub use server::{run, start};
+pub use state::AppState;
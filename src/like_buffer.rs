@@ -0,0 +1,261 @@
+// Buffered likes.
+//
+// Without a buffer every like runs its checks and six writes in a transaction
+// of its own. With LIKE_FLUSH_SECONDS set, like_structure still checks the
+// like but only adds it up here per (liker, structure, reaction); a background
+// task then writes everything gathered since the last flush in one
+// transaction, with one statement per table. Daily budgets count the pending
+// likes too, and fetches add them onto the counts they serve, so a like shows
+// up right away rather than after the next flush. Likes still waiting here
+// are lost if the process stops.
+
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+use crate::{
+    db::queries::credit_contributors,
+    model::{Reaction, Structure},
+};
+
+#[derive(Debug)]
+pub struct PendingLike {
+    pub owner_id: i64,
+    pub scene: String,
+    pub prefab: String,
+    pub owner_username: Option<String>,
+    // likes given, against the budgets, and what they are worth to the
+    // structure (more during a like_multiplier event)
+    pub count: i64,
+    pub credited: i64,
+}
+
+type Key = (i64, i64, Reaction); // (liker, structure, reaction)
+
+// What one flush added to a structure for one reaction, summed over likers
+#[derive(Debug)]
+pub struct FlushedLike {
+    pub structure_id: i64,
+    pub reaction: Reaction,
+    pub count: i64,
+    // the structure's total after the flush, and what the flush added over all reactions
+    pub likes: i64,
+    pub added: i64,
+    pub owner_id: i64,
+    pub scene: String,
+    pub prefab: String,
+    pub owner_username: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct LikeBuffer {
+    pending: Mutex<HashMap<Key, PendingLike>>,
+}
+
+impl LikeBuffer {
+    pub fn add(&self, liker: i64, structure_id: i64, reaction: Reaction, like: PendingLike) {
+        let mut pending = self.pending.lock().unwrap();
+        pending
+            .entry((liker, structure_id, reaction))
+            .and_modify(|existing| {
+                existing.count += like.count;
+                existing.credited += like.credited;
+            })
+            .or_insert(like);
+    }
+
+    // Likes `liker` has waiting here: in total, and to structures of `owner_id`
+    pub fn pending_from(&self, liker: i64, owner_id: i64) -> (i64, i64) {
+        let pending = self.pending.lock().unwrap();
+        pending
+            .iter()
+            .filter(|((from, _, _), _)| *from == liker)
+            .fold((0, 0), |(total, to_owner), (_, like)| {
+                let to_owner = to_owner
+                    + if like.owner_id == owner_id {
+                        like.count
+                    } else {
+                        0
+                    };
+                (total + like.count, to_owner)
+            })
+    }
+
+    // What the likes waiting here add to one structure's count
+    pub fn pending_on(&self, structure_id: i64) -> i64 {
+        let pending = self.pending.lock().unwrap();
+        pending
+            .iter()
+            .filter(|((_, id, _), _)| *id == structure_id)
+            .map(|(_, like)| like.credited)
+            .sum()
+    }
+
+    // Adds the likes waiting here onto the counts of `rows`
+    pub fn overlay(&self, rows: &mut [Structure]) {
+        let pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return;
+        }
+        let positions: HashMap<i64, usize> = rows
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.id.map(|id| (id, i)))
+            .collect();
+        for ((_, structure_id, reaction), like) in pending.iter() {
+            if let Some(&i) = positions.get(structure_id) {
+                rows[i].likes += like.credited as i32;
+                *rows[i]
+                    .reactions
+                    .entry(reaction.as_str().to_string())
+                    .or_default() += like.credited;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+
+    // Writes every pending like. On failure they go back into the buffer for
+    // the next flush. Likes on structures deleted in the meantime are dropped.
+    pub async fn flush(&self, db: &SqlitePool) -> Result<Vec<FlushedLike>, sqlx::Error> {
+        let taken = std::mem::take(&mut *self.pending.lock().unwrap());
+        if taken.is_empty() {
+            return Ok(Vec::new());
+        }
+        match write(db, &taken).await {
+            Ok(flushed) => Ok(flushed),
+            Err(e) => {
+                for ((liker, structure_id, reaction), like) in taken {
+                    self.add(liker, structure_id, reaction, like);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn write(
+    db: &SqlitePool,
+    pending: &HashMap<Key, PendingLike>,
+) -> Result<Vec<FlushedLike>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let mut per_structure: HashMap<i64, i64> = HashMap::new();
+    for ((_, structure_id, _), like) in pending {
+        *per_structure.entry(*structure_id).or_default() += like.credited;
+    }
+    let deltas: Vec<_> = per_structure
+        .iter()
+        .map(|(id, count)| json!([id, count]))
+        .collect();
+    let live: HashMap<i64, i64> = sqlx::query_as(
+        r#"UPDATE structures SET likes = likes + json_extract(d.value, '$[1]')
+           FROM json_each(?) AS d
+           WHERE structures.id = json_extract(d.value, '$[0]') AND structures.deleted = 0
+           RETURNING structures.id, structures.likes"#,
+    )
+    .bind(json!(deltas).to_string())
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+    let pending: Vec<(&Key, &PendingLike)> = pending
+        .iter()
+        .filter(|((_, structure_id, _), _)| live.contains_key(structure_id))
+        .collect();
+
+    let mut per_reaction: HashMap<(i64, Reaction), (i64, &PendingLike)> = HashMap::new();
+    let mut per_pair: HashMap<(i64, i64, i64), i64> = HashMap::new();
+    let mut per_user: HashMap<i64, (i64, i64)> = HashMap::new(); // (sent, received)
+    for ((liker, structure_id, reaction), like) in &pending {
+        per_reaction
+            .entry((*structure_id, *reaction))
+            .or_insert((0, like))
+            .0 += like.credited;
+        *per_pair
+            .entry((*liker, like.owner_id, *structure_id))
+            .or_default() += like.count;
+        per_user.entry(*liker).or_default().0 += like.count;
+        per_user.entry(like.owner_id).or_default().1 += like.credited;
+    }
+
+    let reactions: Vec<_> = per_reaction
+        .iter()
+        .map(|((id, reaction), (count, _))| json!([id, reaction.as_str(), count]))
+        .collect();
+    sqlx::query(
+        r#"INSERT INTO structure_reactions (structure_id, kind, count)
+           SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]'), json_extract(value, '$[2]')
+           FROM json_each(?) WHERE true
+           ON CONFLICT(structure_id, kind) DO UPDATE SET count = count + excluded.count;"#,
+    )
+    .bind(json!(reactions).to_string())
+    .execute(&mut *tx)
+    .await?;
+
+    let ledger: Vec<_> = per_pair
+        .iter()
+        .map(|((liker, owner, id), count)| json!([liker, owner, id, count]))
+        .collect();
+    sqlx::query(
+        r#"INSERT INTO likes_ledger (liker_id, owner_id, structure_id, count, created_at)
+           SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]'),
+                  json_extract(value, '$[2]'), json_extract(value, '$[3]'),
+                  strftime('%s','now')*1000
+           FROM json_each(?);"#,
+    )
+    .bind(json!(ledger).to_string())
+    .execute(&mut *tx)
+    .await?;
+
+    let users: BTreeSet<i64> = per_user.keys().copied().collect();
+    sqlx::query(
+        r#"INSERT OR IGNORE INTO users (user_id, upload_banned, likes_received, likes_send)
+           SELECT value, 0, 0, 0 FROM json_each(?);"#,
+    )
+    .bind(json!(users).to_string())
+    .execute(&mut *tx)
+    .await?;
+    let user_deltas: Vec<_> = per_user
+        .iter()
+        .map(|(user, (sent, received))| json!([user, sent, received]))
+        .collect();
+    sqlx::query(
+        r#"UPDATE users SET likes_send = likes_send + json_extract(d.value, '$[1]'),
+                            likes_received = likes_received + json_extract(d.value, '$[2]')
+           FROM json_each(?) AS d
+           WHERE users.user_id = json_extract(d.value, '$[0]')"#,
+    )
+    .bind(json!(user_deltas).to_string())
+    .execute(&mut *tx)
+    .await?;
+
+    let live_deltas: Vec<(i64, i64)> = per_structure
+        .iter()
+        .filter(|(id, _)| live.contains_key(id))
+        .map(|(id, count)| (*id, *count))
+        .collect();
+    credit_contributors(&mut tx, &live_deltas).await?;
+
+    tx.commit().await?;
+
+    Ok(per_reaction
+        .into_iter()
+        .map(|((structure_id, reaction), (count, like))| FlushedLike {
+            structure_id,
+            reaction,
+            count,
+            likes: live[&structure_id],
+            added: per_structure[&structure_id],
+            owner_id: like.owner_id,
+            scene: like.scene.clone(),
+            prefab: like.prefab.clone(),
+            owner_username: like.owner_username.clone(),
+        })
+        .collect())
+}
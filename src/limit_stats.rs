@@ -0,0 +1,105 @@
+// Rate limit statistics for GET /admin/v1/stats/limits.
+//
+// Counts 429 answers per route and likes trimmed or refused by the like
+// limits since startup, and keeps the last RECENT_CAPACITY rate-limited
+// requests to rank the players hitting the limits most. Meant for tuning the
+// limits, so everything is in memory and starts over on restart.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const RECENT_CAPACITY: usize = 1024;
+const TOP_PLAYERS: usize = 20;
+
+#[derive(Debug)]
+pub struct LimitStats {
+    started_at: i64,
+    rate_limited: DashMap<&'static str, u64>,
+    like_clamps: DashMap<&'static str, u64>,
+    // steam ids of the latest rate-limited requests, oldest first
+    recent: Mutex<VecDeque<u64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitStatsResponse {
+    pub since: i64, // epoch millis
+    pub rate_limited: BTreeMap<&'static str, u64>,
+    pub like_clamps: BTreeMap<&'static str, u64>,
+    // what the configured intervals are multiplied by right now (ADAPTIVE_RATE_LIMITS)
+    pub rate_limit_factor: f64,
+    pub recent_window: usize,
+    pub top_rate_limited: Vec<RateLimitedPlayer>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct RateLimitedPlayer {
+    pub steam_id: u64,
+    pub count: usize,
+}
+
+impl Default for LimitStats {
+    fn default() -> Self {
+        Self {
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default(),
+            rate_limited: DashMap::new(),
+            like_clamps: DashMap::new(),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+        }
+    }
+}
+
+impl LimitStats {
+    // A request answered with 429
+    pub fn rate_limited(&self, route: &'static str, steamid: u64) {
+        *self.rate_limited.entry(route).or_default() += 1;
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(steamid);
+    }
+
+    // A like whose count was cut down (or refused) by `reason`
+    pub fn like_clamped(&self, reason: &'static str) {
+        *self.like_clamps.entry(reason).or_default() += 1;
+    }
+
+    pub fn snapshot(&self, rate_limit_factor: f64) -> LimitStatsResponse {
+        let recent = self.recent.lock().unwrap();
+        let mut per_player: HashMap<u64, usize> = HashMap::new();
+        for steamid in recent.iter() {
+            *per_player.entry(*steamid).or_default() += 1;
+        }
+        let mut top_rate_limited: Vec<RateLimitedPlayer> = per_player
+            .into_iter()
+            .map(|(steam_id, count)| RateLimitedPlayer { steam_id, count })
+            .collect();
+        top_rate_limited.sort_by(|a, b| b.count.cmp(&a.count).then(a.steam_id.cmp(&b.steam_id)));
+        top_rate_limited.truncate(TOP_PLAYERS);
+
+        LimitStatsResponse {
+            since: self.started_at,
+            rate_limited: self
+                .rate_limited
+                .iter()
+                .map(|e| (*e.key(), *e.value()))
+                .collect(),
+            like_clamps: self
+                .like_clamps
+                .iter()
+                .map(|e| (*e.key(), *e.value()))
+                .collect(),
+            rate_limit_factor,
+            recent_window: recent.len(),
+            top_rate_limited,
+        }
+    }
+}
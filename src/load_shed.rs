@@ -0,0 +1,62 @@
+// Concurrency caps with load shedding.
+//
+// MAX_CONCURRENT_REQUESTS caps the player routes as a whole and
+// MAX_CONCURRENT_FETCHES the random, nearby and fetch session routes of both
+// API versions together. A request that finds its cap full is answered 503
+// with code `overloaded` and a Retry-After right away, instead of queueing
+// for one of the few SQLite connections behind everyone else.
+
+use axum::{Router, error_handling::HandleErrorLayer, http::StatusCode, routing::MethodRouter};
+use std::time::Duration;
+use tower::{
+    BoxError, ServiceBuilder, limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer,
+};
+
+use crate::{error::ApiError, state::AppState};
+
+// Requests are short; by then the burst has usually drained
+const OVERLOADED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+// One cap, shared by every route it is applied to; 0 means no cap
+#[derive(Clone)]
+pub struct LoadCap(Option<GlobalConcurrencyLimitLayer>);
+
+impl LoadCap {
+    pub fn new(max: usize) -> Self {
+        Self((max > 0).then(|| GlobalConcurrencyLimitLayer::new(max)))
+    }
+
+    pub fn route(&self, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+        let Some(limit) = &self.0 else {
+            return route;
+        };
+        route.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(overloaded))
+                .layer(LoadShedLayer::new())
+                .layer(limit.clone()),
+        )
+    }
+
+    pub fn router(&self, router: Router<AppState>) -> Router<AppState> {
+        let Some(limit) = &self.0 else {
+            return router;
+        };
+        router.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(overloaded))
+                .layer(LoadShedLayer::new())
+                .layer(limit.clone()),
+        )
+    }
+}
+
+// LoadShed's only error is Overloaded
+async fn overloaded(_: BoxError) -> ApiError {
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "The server is busy, try again in a moment.",
+    )
+    .with_code("overloaded")
+    .with_retry_after(OVERLOADED_RETRY_AFTER)
+}
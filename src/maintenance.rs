@@ -0,0 +1,38 @@
+// Read-only (maintenance) mode.
+//
+// While it is on, players keep fetching, but every request that would write
+// (uploads, likes, pins, deletes, ...) is answered 503 with code `read_only`,
+// so operators can back up or move the database without taking the server
+// down. READ_ONLY sets it at startup and on reload when the setting changed;
+// PUT /admin/v1/maintenance?read_only=... flips it at runtime. Admin routes
+// and background flushes of work accepted before the switch are unaffected.
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+use crate::{error::ApiError, state::AppState};
+
+// Clients back off for this long before trying a write again
+const READ_ONLY_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// Middleware for the player routes
+pub async fn guard(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    // creating a fetch session only picks rows and keeps them in memory
+    let reads = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || req.uri().path().ends_with("/fetch-sessions");
+    if !reads && state.read_only() {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The server is under maintenance: browsing works, but uploads and likes are paused for a few minutes.",
+        )
+        .with_code("read_only")
+        .with_retry_after(READ_ONLY_RETRY_AFTER)
+        .into_response();
+    }
+    next.run(req).await
+}
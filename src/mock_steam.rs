@@ -0,0 +1,171 @@
+// Stand-in for Steam's ISteamUserAuth/AuthenticateUserTicket,
+// ISteamUser/CheckAppOwnership and ISteamUser/GetPlayerSummaries.
+//
+// Lets the Steam auth path run without a Web API key or network access: the
+// tests serve it on a local port, and a server built with the mock-steam
+// feature mounts it at /mock/steam (set STEAM_API_URL to
+// http://localhost:3000/mock/steam). The ticket decides the answer:
+//
+// - `valid:<steamid>` is accepted for any app, `valid:<steamid>:<appid>` only
+//   for that app id ("Ticket for other app" otherwise)
+// - `expired` and `invalid` get Steam's error answers
+// - `bad_json` gets a 200 whose body is not JSON
+// - `unavailable` gets a 503, `bad_key` a 403
+//
+// Anything else is rejected as an invalid ticket. The API key `rejected` gets
+// a 403 whatever the ticket, like a revoked key. Ownership checks and player
+// summaries want the key in x-webapi-key; every account owns every app, except
+// those put in `unowned`, and is called `Player <steamid>`.
+
+use axum::{
+    Form, Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use dashmap::DashSet;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Debug, Default)]
+pub struct MockSteam {
+    calls: AtomicUsize,
+    // accounts CheckAppOwnership says don't own the app
+    pub unowned: DashSet<u64>,
+}
+
+impl MockSteam {
+    // Calls answered so far, retries included
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Deserialize)]
+struct TicketForm {
+    key: String,
+    appid: u64,
+    ticket: String,
+}
+
+pub fn router<S>(mock: Arc<MockSteam>) -> Router<S> {
+    Router::new()
+        .route(
+            "/ISteamUserAuth/AuthenticateUserTicket/v1/",
+            post(authenticate_user_ticket),
+        )
+        .route(
+            "/ISteamUser/CheckAppOwnership/v2/",
+            get(check_app_ownership),
+        )
+        .route(
+            "/ISteamUser/GetPlayerSummaries/v2/",
+            get(get_player_summaries),
+        )
+        .with_state(mock)
+}
+
+// Counts the call and checks the x-webapi-key header
+fn key_accepted(mock: &MockSteam, headers: &HeaderMap) -> bool {
+    mock.calls.fetch_add(1, Ordering::Relaxed);
+    headers
+        .get("x-webapi-key")
+        .and_then(|key| key.to_str().ok())
+        .is_some_and(|key| key != "rejected")
+}
+
+#[derive(Deserialize)]
+struct OwnershipQuery {
+    steamid: u64,
+}
+
+async fn check_app_ownership(
+    State(mock): State<Arc<MockSteam>>,
+    headers: HeaderMap,
+    Query(query): Query<OwnershipQuery>,
+) -> Response {
+    if !key_accepted(&mock, &headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    Json(json!({
+        "appownership": {
+            "ownsapp": !mock.unowned.contains(&query.steamid),
+            "permanent": true,
+            "result": "OK",
+        }
+    }))
+    .into_response()
+}
+
+async fn authenticate_user_ticket(
+    State(mock): State<Arc<MockSteam>>,
+    Form(form): Form<TicketForm>,
+) -> Response {
+    mock.calls.fetch_add(1, Ordering::Relaxed);
+    if form.key == "rejected" {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    match form.ticket.split(':').collect::<Vec<_>>().as_slice() {
+        ["valid", steamid] => accepted(steamid),
+        ["valid", steamid, appid] if *appid == form.appid.to_string() => accepted(steamid),
+        ["valid", _, _] => rejected(101, "Ticket for other app"),
+        ["expired"] => rejected(102, "Ticket has expired"),
+        ["bad_json"] => (StatusCode::OK, "<html>Steam</html>").into_response(),
+        ["unavailable"] => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        ["bad_key"] => StatusCode::FORBIDDEN.into_response(),
+        _ => rejected(101, "Invalid ticket"),
+    }
+}
+
+#[derive(Deserialize)]
+struct SummariesQuery {
+    steamids: u64,
+}
+
+async fn get_player_summaries(
+    State(mock): State<Arc<MockSteam>>,
+    headers: HeaderMap,
+    Query(query): Query<SummariesQuery>,
+) -> Response {
+    if !key_accepted(&mock, &headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    Json(json!({
+        "response": {
+            "players": [{
+                "steamid": query.steamids.to_string(),
+                "personaname": format!("Player {}", query.steamids),
+            }]
+        }
+    }))
+    .into_response()
+}
+
+fn accepted(steamid: &str) -> Response {
+    Json(json!({
+        "response": {
+            "params": {
+                "result": "OK",
+                "steamid": steamid,
+                "ownersteamid": steamid,
+                "vacbanned": false,
+                "publisherbanned": false,
+            }
+        }
+    }))
+    .into_response()
+}
+
+fn rejected(errorcode: i64, errordesc: &str) -> Response {
+    Json(json!({
+        "response": {
+            "error": { "errorcode": errorcode, "errordesc": errordesc }
+        }
+    }))
+    .into_response()
+}
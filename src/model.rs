@@ -0,0 +1,676 @@
+// Structures as stored and as uploaded.
+//
+// Structure is the row shape shared by every endpoint; NewStructure is what a
+// client sends and gets validated against the config limits before storing.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::{config::Config, error::AppError};
+
+// in-game structure representation in the database
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Structure {
+    // DB-managed
+    pub id: Option<i64>,         // AUTOINCREMENT PK
+    pub created_at: Option<i64>, // epoch millis (seconds actually)
+
+    // getting that from steam
+    pub user_id: i64,
+
+    // from client
+    pub username: String,
+    pub map_id: i32,
+    pub scene: String,
+    pub segment: i32,
+    pub prefab: String,
+
+    pub pos_x: f32,
+    pub pos_y: f32,
+    pub pos_z: f32,
+
+    pub rot_x: f32,
+    pub rot_y: f32,
+    pub rot_z: f32,
+    pub rot_w: f32,
+
+    pub rope_start_x: f32,
+    pub rope_start_y: f32,
+    pub rope_start_z: f32,
+
+    pub rope_end_x: f32,
+    pub rope_end_y: f32,
+    pub rope_end_z: f32,
+
+    pub rope_length: f32,
+
+    pub rope_flying_rotation_x: f32,
+    pub rope_flying_rotation_y: f32,
+    pub rope_flying_rotation_z: f32,
+
+    pub rope_anchor_rotation_x: f32,
+    pub rope_anchor_rotation_y: f32,
+    pub rope_anchor_rotation_z: f32,
+    pub rope_anchor_rotation_w: f32,
+
+    pub antigrav: bool,
+
+    pub likes: i32,
+
+    // epoch millis of the owner's last edit (PATCH); None until then
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+
+    // bumped by every edit; PATCH must send back the one it was based on
+    #[sqlx(default)]
+    #[serde(default)]
+    pub version: i64,
+
+    // see `content_hash`; NULL on rows archived before the column existed
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub content_hash: Option<i64>,
+
+    // passive usage reports (climbed, used) from other players
+    #[serde(default)]
+    pub uses: i64,
+
+    // exempt from per-scene pruning and STRUCTURE_TTL_DAYS
+    #[sqlx(default)]
+    #[serde(default)]
+    pub pinned: bool,
+
+    // per-kind breakdown of `likes`, filled from structure_reactions where needed
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: BTreeMap<String, i64>,
+
+    // co-op builders credited next to user_id, filled from structure_contributors where needed
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub contributors: Vec<i64>,
+
+    // PREFAB_ATTRIBUTES values, filled from structure_attributes where needed
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+
+    // upload answers only: the uploader's free slots left in the scene, and the
+    // oldest structure pruned to make room for this one
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_slots: Option<i64>,
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruned_structure_id: Option<i64>,
+    // uploads the uploader has left today under DAILY_UPLOAD_QUOTA
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_uploads_today: Option<i64>,
+    // held back by PLAUSIBILITY_CHECKS until a moderator approves it
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub under_review: Option<bool>,
+    // random fetches with creator_stats=true only: the builder's standing
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creator: Option<CreatorStats>,
+}
+
+// What the mod shows next to a builder's name: "built by X, 120 likes over 14 builds"
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CreatorStats {
+    pub likes_received: i64,
+    // live structures, every scene and season
+    pub structures: i64,
+}
+
+// Reaction kinds a like can carry; every kind also counts toward `likes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reaction {
+    #[default]
+    ThumbsUp,
+    Heart,
+    Star,
+}
+
+impl Reaction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reaction::ThumbsUp => "thumbs_up",
+            Reaction::Heart => "heart",
+            Reaction::Star => "star",
+        }
+    }
+}
+
+// Which of a player's structures makes room when an upload goes over
+// MAX_USER_STRUCTS_SAVED_PER_SCENE. Ties fall back to the oldest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrunePolicy {
+    #[default]
+    Oldest,
+    LeastLiked,
+    // never fetched counts as fetched when uploaded
+    LeastRecentlyFetched,
+}
+
+impl PrunePolicy {
+    pub fn order_by(self) -> &'static str {
+        match self {
+            PrunePolicy::Oldest => "created_at ASC, id ASC",
+            PrunePolicy::LeastLiked => "likes ASC, created_at ASC, id ASC",
+            PrunePolicy::LeastRecentlyFetched => {
+                "COALESCE(last_fetched_at, created_at) ASC, created_at ASC, id ASC"
+            }
+        }
+    }
+}
+
+// Value types a [prefab_attributes.<prefab>] schema can declare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttributeType {
+    Bool,
+    Int,
+    Float,
+    String,
+}
+
+impl AttributeType {
+    fn accepts(self, value: &serde_json::Value) -> bool {
+        match self {
+            AttributeType::Bool => value.is_boolean(),
+            AttributeType::Int => value.is_i64(),
+            AttributeType::Float => value.is_number(),
+            AttributeType::String => value
+                .as_str()
+                .is_some_and(|text| text.chars().count() <= MAX_ATTRIBUTE_STRING_LENGTH),
+        }
+    }
+}
+
+impl FromStr for PrunePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "oldest" => Ok(PrunePolicy::Oldest),
+            "least_liked" => Ok(PrunePolicy::LeastLiked),
+            "least_recently_fetched" => Ok(PrunePolicy::LeastRecentlyFetched),
+            other => Err(format!(
+                "unknown prune policy {other:?} (expected oldest, least_liked or least_recently_fetched)"
+            )),
+        }
+    }
+}
+
+// in-game structure representation we receive as the payload for POST request
+#[derive(Debug, Deserialize)]
+pub struct NewStructure {
+    pub username: String,
+    pub map_id: i32,
+    pub scene: String,
+    pub segment: i32,
+    pub prefab: String,
+    pub pos_x: f32,
+    pub pos_y: f32,
+    pub pos_z: f32,
+    pub rot_x: f32,
+    pub rot_y: f32,
+    pub rot_z: f32,
+    pub rot_w: f32,
+    pub rope_start_x: f32,
+    pub rope_start_y: f32,
+    pub rope_start_z: f32,
+    pub rope_end_x: f32,
+    pub rope_end_y: f32,
+    pub rope_end_z: f32,
+    pub rope_length: f32,
+    pub rope_flying_rotation_x: f32,
+    pub rope_flying_rotation_y: f32,
+    pub rope_flying_rotation_z: f32,
+    pub rope_anchor_rotation_x: f32,
+    pub rope_anchor_rotation_y: f32,
+    pub rope_anchor_rotation_z: f32,
+    pub rope_anchor_rotation_w: f32,
+    pub antigrav: bool,
+    // steamids of the other players in the lobby that built it
+    #[serde(default)]
+    pub contributors: Vec<u64>,
+    // extra properties declared for the prefab in PREFAB_ATTRIBUTES
+    #[serde(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+    // overrides PRUNE_POLICY for this upload
+    #[serde(default)]
+    pub prune_policy: Option<PrunePolicy>,
+}
+
+// Grid that `content_hash` snaps positions (game units) and rotation
+// components to before hashing
+pub const CONTENT_HASH_EPSILON: f32 = 0.25;
+const CONTENT_HASH_ROTATION_STEP: f32 = 0.05;
+
+// FNV-1a over what a build looks like: map, scene, prefab, antigrav, and its
+// position, rope and rotation snapped to a grid. Copies of a default-rotation
+// ladder at the same spot hash alike; two builds on either side of a grid
+// line can still hash apart. Stable across builds, so it can be stored.
+fn hash_content(
+    map_id: i32,
+    scene: &str,
+    prefab: &str,
+    antigrav: bool,
+    positions: [f32; 10],
+    rotation: [f32; 4],
+) -> i64 {
+    let snap = |value: f32, step: f32| ((value / step).round() as i32).to_le_bytes();
+    let mut bytes = Vec::with_capacity(128);
+    bytes.extend_from_slice(&map_id.to_le_bytes());
+    bytes.extend_from_slice(scene.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(prefab.as_bytes());
+    bytes.push(0);
+    bytes.push(antigrav as u8);
+    for value in positions {
+        bytes.extend_from_slice(&snap(value, CONTENT_HASH_EPSILON));
+    }
+    for value in rotation {
+        bytes.extend_from_slice(&snap(value, CONTENT_HASH_ROTATION_STEP));
+    }
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    }) as i64
+}
+
+// Pose and rope fields an owner can change in place; absent ones stay as they are
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructureEdit {
+    pub pos_x: Option<f32>,
+    pub pos_y: Option<f32>,
+    pub pos_z: Option<f32>,
+    pub rot_x: Option<f32>,
+    pub rot_y: Option<f32>,
+    pub rot_z: Option<f32>,
+    pub rot_w: Option<f32>,
+    pub rope_start_x: Option<f32>,
+    pub rope_start_y: Option<f32>,
+    pub rope_start_z: Option<f32>,
+    pub rope_end_x: Option<f32>,
+    pub rope_end_y: Option<f32>,
+    pub rope_end_z: Option<f32>,
+    pub rope_length: Option<f32>,
+    pub rope_flying_rotation_x: Option<f32>,
+    pub rope_flying_rotation_y: Option<f32>,
+    pub rope_flying_rotation_z: Option<f32>,
+    pub rope_anchor_rotation_x: Option<f32>,
+    pub rope_anchor_rotation_y: Option<f32>,
+    pub rope_anchor_rotation_z: Option<f32>,
+    pub rope_anchor_rotation_w: Option<f32>,
+    pub antigrav: Option<bool>,
+}
+
+impl StructureEdit {
+    // Writes the edit into `s` and returns what actually changed, as
+    // field -> [old, new] for the edit log
+    pub fn apply(&self, s: &mut NewStructure) -> BTreeMap<&'static str, serde_json::Value> {
+        let mut changes = BTreeMap::new();
+        let fields = [
+            ("pos_x", self.pos_x, &mut s.pos_x),
+            ("pos_y", self.pos_y, &mut s.pos_y),
+            ("pos_z", self.pos_z, &mut s.pos_z),
+            ("rot_x", self.rot_x, &mut s.rot_x),
+            ("rot_y", self.rot_y, &mut s.rot_y),
+            ("rot_z", self.rot_z, &mut s.rot_z),
+            ("rot_w", self.rot_w, &mut s.rot_w),
+            ("rope_start_x", self.rope_start_x, &mut s.rope_start_x),
+            ("rope_start_y", self.rope_start_y, &mut s.rope_start_y),
+            ("rope_start_z", self.rope_start_z, &mut s.rope_start_z),
+            ("rope_end_x", self.rope_end_x, &mut s.rope_end_x),
+            ("rope_end_y", self.rope_end_y, &mut s.rope_end_y),
+            ("rope_end_z", self.rope_end_z, &mut s.rope_end_z),
+            ("rope_length", self.rope_length, &mut s.rope_length),
+            (
+                "rope_flying_rotation_x",
+                self.rope_flying_rotation_x,
+                &mut s.rope_flying_rotation_x,
+            ),
+            (
+                "rope_flying_rotation_y",
+                self.rope_flying_rotation_y,
+                &mut s.rope_flying_rotation_y,
+            ),
+            (
+                "rope_flying_rotation_z",
+                self.rope_flying_rotation_z,
+                &mut s.rope_flying_rotation_z,
+            ),
+            (
+                "rope_anchor_rotation_x",
+                self.rope_anchor_rotation_x,
+                &mut s.rope_anchor_rotation_x,
+            ),
+            (
+                "rope_anchor_rotation_y",
+                self.rope_anchor_rotation_y,
+                &mut s.rope_anchor_rotation_y,
+            ),
+            (
+                "rope_anchor_rotation_z",
+                self.rope_anchor_rotation_z,
+                &mut s.rope_anchor_rotation_z,
+            ),
+            (
+                "rope_anchor_rotation_w",
+                self.rope_anchor_rotation_w,
+                &mut s.rope_anchor_rotation_w,
+            ),
+        ];
+        for (name, value, field) in fields {
+            if let Some(value) = value
+                && value != *field
+            {
+                changes.insert(name, serde_json::json!([*field, value]));
+                *field = value;
+            }
+        }
+        if let Some(antigrav) = self.antigrav
+            && antigrav != s.antigrav
+        {
+            changes.insert("antigrav", serde_json::json!([s.antigrav, antigrav]));
+            s.antigrav = antigrav;
+        }
+        changes
+    }
+}
+
+// Column limits from the structures table CHECK constraints
+pub const MAX_USERNAME_LENGTH: usize = 50;
+pub const MAX_PREFAB_LENGTH: usize = 50;
+
+// Co-op lobbies are small; more than this is not a lobby
+pub const MAX_CONTRIBUTORS: usize = 8;
+
+pub const MAX_ATTRIBUTE_STRING_LENGTH: usize = 200;
+
+impl NewStructure {
+    pub fn content_hash(&self) -> i64 {
+        hash_content(
+            self.map_id,
+            &self.scene,
+            &self.prefab,
+            self.antigrav,
+            [
+                self.pos_x,
+                self.pos_y,
+                self.pos_z,
+                self.rope_start_x,
+                self.rope_start_y,
+                self.rope_start_z,
+                self.rope_end_x,
+                self.rope_end_y,
+                self.rope_end_z,
+                self.rope_length,
+            ],
+            [self.rot_x, self.rot_y, self.rot_z, self.rot_w],
+        )
+    }
+
+    // Catches what the table constraints would otherwise turn into a 500.
+    // Lengths count characters, like SQLite's length().
+    pub fn validate(&self, config: &Config) -> Result<(), AppError> {
+        let invalid = |field: &'static str, message: String| AppError::Validation(field, message);
+        let length_between = |field: &'static str, value: &str, min: usize, max: usize| {
+            let length = value.chars().count();
+            if (min..=max).contains(&length) {
+                Ok(())
+            } else if min == 0 {
+                Err(invalid(
+                    field,
+                    format!("{field} must be at most {max} characters"),
+                ))
+            } else {
+                Err(invalid(
+                    field,
+                    format!("{field} must be between {min} and {max} characters"),
+                ))
+            }
+        };
+
+        length_between("username", &self.username, 0, MAX_USERNAME_LENGTH)?;
+        length_between("scene", &self.scene, 1, config.max_scene_length)?;
+        length_between("prefab", &self.prefab, 1, MAX_PREFAB_LENGTH)?;
+        if !(0..=config.max_segment).contains(&self.segment) {
+            return Err(invalid(
+                "segment",
+                format!("segment must be between 0 and {}", config.max_segment),
+            ));
+        }
+        if self.contributors.len() > MAX_CONTRIBUTORS {
+            return Err(invalid(
+                "contributors",
+                format!("contributors must list at most {MAX_CONTRIBUTORS} players"),
+            ));
+        }
+        if self
+            .contributors
+            .iter()
+            .any(|&id| id == 0 || id > i64::MAX as u64)
+        {
+            return Err(invalid(
+                "contributors",
+                "contributors must be steamids".into(),
+            ));
+        }
+        if !self.attributes.is_empty() {
+            let schema = config.prefab_attributes.get(&self.prefab);
+            for (key, value) in &self.attributes {
+                let Some(kind) = schema.and_then(|schema| schema.get(key)) else {
+                    return Err(invalid(
+                        "attributes",
+                        format!("{} has no attribute {key:?}", self.prefab),
+                    ));
+                };
+                if !kind.accepts(value) {
+                    let expected = match kind {
+                        AttributeType::Bool => "a boolean".to_string(),
+                        AttributeType::Int => "an integer".to_string(),
+                        AttributeType::Float => "a number".to_string(),
+                        AttributeType::String => {
+                            format!("a string of at most {MAX_ATTRIBUTE_STRING_LENGTH} characters")
+                        }
+                    };
+                    return Err(invalid(
+                        "attributes",
+                        format!("attribute {key:?} must be {expected}"),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&Structure> for NewStructure {
+    fn from(s: &Structure) -> Self {
+        Self {
+            username: s.username.clone(),
+            map_id: s.map_id,
+            scene: s.scene.clone(),
+            segment: s.segment,
+            prefab: s.prefab.clone(),
+            pos_x: s.pos_x,
+            pos_y: s.pos_y,
+            pos_z: s.pos_z,
+            rot_x: s.rot_x,
+            rot_y: s.rot_y,
+            rot_z: s.rot_z,
+            rot_w: s.rot_w,
+            rope_start_x: s.rope_start_x,
+            rope_start_y: s.rope_start_y,
+            rope_start_z: s.rope_start_z,
+            rope_end_x: s.rope_end_x,
+            rope_end_y: s.rope_end_y,
+            rope_end_z: s.rope_end_z,
+            rope_length: s.rope_length,
+            rope_flying_rotation_x: s.rope_flying_rotation_x,
+            rope_flying_rotation_y: s.rope_flying_rotation_y,
+            rope_flying_rotation_z: s.rope_flying_rotation_z,
+            rope_anchor_rotation_x: s.rope_anchor_rotation_x,
+            rope_anchor_rotation_y: s.rope_anchor_rotation_y,
+            rope_anchor_rotation_z: s.rope_anchor_rotation_z,
+            rope_anchor_rotation_w: s.rope_anchor_rotation_w,
+            antigrav: s.antigrav,
+            contributors: Vec::new(),
+            attributes: s.attributes.clone(),
+            prune_policy: None,
+        }
+    }
+}
+
+impl Structure {
+    pub fn content_hash(&self) -> i64 {
+        hash_content(
+            self.map_id,
+            &self.scene,
+            &self.prefab,
+            self.antigrav,
+            [
+                self.pos_x,
+                self.pos_y,
+                self.pos_z,
+                self.rope_start_x,
+                self.rope_start_y,
+                self.rope_start_z,
+                self.rope_end_x,
+                self.rope_end_y,
+                self.rope_end_z,
+                self.rope_length,
+            ],
+            [self.rot_x, self.rot_y, self.rot_z, self.rot_w],
+        )
+    }
+
+    // A fresh upload as other players see it, without the uploader's quota
+    pub fn without_upload_info(&self) -> Self {
+        Self {
+            remaining_slots: None,
+            pruned_structure_id: None,
+            remaining_uploads_today: None,
+            under_review: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn insert_query() -> &'static str {
+        r#"
+        INSERT INTO structures (
+            user_id,
+            username,
+            map_id, scene, segment, prefab,
+            pos_x, pos_y, pos_z,
+            rot_x, rot_y, rot_z, rot_w,
+            rope_start_x, rope_start_y, rope_start_z,
+            rope_end_x,   rope_end_y,   rope_end_z,
+            rope_length,
+            rope_flying_rotation_x, rope_flying_rotation_y, rope_flying_rotation_z,
+            rope_anchor_rotation_x, rope_anchor_rotation_y, rope_anchor_rotation_z, rope_anchor_rotation_w,
+            antigrav,
+            content_hash,
+            season_id,
+            app_id,
+            created_at
+        ) VALUES (
+            ?, ?, ?, ?, ?, ?,
+            ?, ?, ?,
+            ?, ?, ?, ?,
+            ?, ?, ?,
+            ?, ?, ?,
+            ?,
+            ?, ?, ?,
+            ?, ?, ?, ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            strftime('%s','now')*1000
+        ) RETURNING *;
+        "#
+    }
+
+    // like insert_query, but keeps id, timestamps and counters from an export dump
+    pub fn import_query() -> &'static str {
+        r#"
+        INSERT INTO structures (
+            id, created_at,
+            user_id,
+            username,
+            map_id, scene, segment, prefab,
+            pos_x, pos_y, pos_z,
+            rot_x, rot_y, rot_z, rot_w,
+            rope_start_x, rope_start_y, rope_start_z,
+            rope_end_x,   rope_end_y,   rope_end_z,
+            rope_length,
+            rope_flying_rotation_x, rope_flying_rotation_y, rope_flying_rotation_z,
+            rope_anchor_rotation_x, rope_anchor_rotation_y, rope_anchor_rotation_z, rope_anchor_rotation_w,
+            antigrav,
+            likes, deleted, season_id, uses,
+            deleted_at, deleted_by, app_id, pinned, last_fetched_at,
+            origin_server, origin_id, content_hash, updated_at, version
+        ) VALUES (
+            ?, COALESCE(?, strftime('%s','now')*1000),
+            ?, ?, ?, ?, ?, ?,
+            ?, ?, ?,
+            ?, ?, ?, ?,
+            ?, ?, ?,
+            ?, ?, ?,
+            ?,
+            ?, ?, ?,
+            ?, ?, ?, ?,
+            ?,
+            ?, ?, ?, ?,
+            ?, ?, ?, ?, ?,
+            ?, ?, ?, ?, ?
+        );
+        "#
+    }
+}
+
+// Sphere around a point in a scene, for proximity filters on pos_x/pos_y/pos_z.
+#[derive(Debug, Clone, Copy)]
+pub struct Sphere {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub radius: f32,
+}
+
+impl Sphere {
+    // Bounding box first so the (scene, pos_x) index narrows the scan, then the exact distance.
+    pub const CONDITION: &'static str = "pos_x BETWEEN ? AND ? AND pos_y BETWEEN ? AND ? AND pos_z BETWEEN ? AND ? \
+        AND (pos_x - ?) * (pos_x - ?) + (pos_y - ?) * (pos_y - ?) + (pos_z - ?) * (pos_z - ?) <= ?";
+
+    // Values for the placeholders in CONDITION, in order
+    pub fn binds(&self) -> [f32; 13] {
+        let Sphere { x, y, z, radius } = *self;
+        [
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            z - radius,
+            z + radius,
+            x,
+            x,
+            y,
+            y,
+            z,
+            z,
+            radius * radius,
+        ]
+    }
+}
@@ -0,0 +1,109 @@
+// Plausibility checks on uploads.
+//
+// Catches placements a modified client produces and the game never would: a
+// rope much shorter or far longer than the gap it spans, a position outside
+// the map's [maps.<map_id>] bounds, or a burst of uploads from one player
+// spread over several segments within seconds. PLAUSIBILITY_CHECKS picks what
+// happens to such an upload: `flag` stores it soft-deleted with
+// deleted_by = 'review' so it stays out of every fetch until a moderator
+// approves it, `reject` refuses it with code `implausible`, `off` skips the
+// checks.
+
+use std::str::FromStr;
+
+use crate::{config::Config, handlers::structures::now_millis, model::NewStructure};
+
+// Ropes sag and stretch a little, but not to this degree
+const ROPE_MIN_RATIO: f32 = 0.9;
+const ROPE_MAX_RATIO: f32 = 4.0;
+// Absorbs float noise and the game's anchor offsets on very short ropes
+const ROPE_SLACK: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlausibilityMode {
+    #[default]
+    Off,
+    Flag,
+    Reject,
+}
+
+impl FromStr for PlausibilityMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" => Ok(PlausibilityMode::Off),
+            "flag" => Ok(PlausibilityMode::Flag),
+            "reject" => Ok(PlausibilityMode::Reject),
+            other => Err(format!(
+                "unknown plausibility mode {other:?} (expected off, flag or reject)"
+            )),
+        }
+    }
+}
+
+// What is wrong with the structure itself, if anything
+pub fn placement_problem(s: &NewStructure, config: &Config) -> Option<String> {
+    let position = [s.pos_x, s.pos_y, s.pos_z];
+    if position.iter().any(|v| !v.is_finite()) || !s.rope_length.is_finite() {
+        return Some("non-finite coordinates".into());
+    }
+
+    // structures without a rope send zeros
+    if s.rope_length > 0.0 {
+        let span = ((s.rope_end_x - s.rope_start_x).powi(2)
+            + (s.rope_end_y - s.rope_start_y).powi(2)
+            + (s.rope_end_z - s.rope_start_z).powi(2))
+        .sqrt();
+        if s.rope_length + ROPE_SLACK < span * ROPE_MIN_RATIO
+            || s.rope_length > span * ROPE_MAX_RATIO + ROPE_SLACK
+        {
+            return Some(format!(
+                "rope_length {:.1} for a span of {span:.1}",
+                s.rope_length
+            ));
+        }
+    }
+
+    if let Some((min, max)) = config.map_bounds(s.map_id) {
+        let outside = position
+            .iter()
+            .zip(min.iter().zip(max.iter()))
+            .any(|(v, (lo, hi))| v < lo || v > hi);
+        if outside {
+            return Some(format!("position outside the bounds of map {}", s.map_id));
+        }
+    }
+    None
+}
+
+// Too many uploads from one player over several segments in the burst window
+pub async fn burst_problem(
+    conn: &mut sqlx::SqliteConnection,
+    config: &Config,
+    steamid: u64,
+    segment: i32,
+) -> Result<Option<String>, sqlx::Error> {
+    if config.plausibility_burst_posts == 0 {
+        return Ok(None);
+    }
+    let since = now_millis() - config.plausibility_burst_window.as_millis() as i64;
+    let (posts, other_segments): (i64, i64) = sqlx::query_as(
+        r#"SELECT COUNT(*), COUNT(DISTINCT CASE WHEN segment != ? THEN segment END)
+           FROM structures WHERE user_id = ? AND created_at >= ?"#,
+    )
+    .bind(segment)
+    .bind(steamid as i64)
+    .bind(since)
+    .fetch_one(&mut *conn)
+    .await?;
+    if posts >= config.plausibility_burst_posts && other_segments > 0 {
+        return Ok(Some(format!(
+            "{} uploads over {} segments in {}s",
+            posts + 1,
+            other_segments + 1,
+            config.plausibility_burst_window.as_secs()
+        )));
+    }
+    Ok(None)
+}
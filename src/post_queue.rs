@@ -0,0 +1,119 @@
+// Queued structure uploads.
+//
+// With POST_QUEUE_CAPACITY set, post_structure only validates an upload and
+// hands it to a single writer task, answering 202 with a client_guid. The
+// writer stores whatever has piled up in one transaction (a savepoint per
+// upload, so one rejected upload doesn't undo the others). Clients poll the
+// client_guid for the outcome, which is kept for a while after it is known.
+
+use axum::http::StatusCode;
+use dashmap::DashMap;
+use std::time::Duration;
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    error::ApiError,
+    model::{NewStructure, Structure},
+};
+
+// how long a finished upload can still be polled
+const RESULT_TTL: Duration = Duration::from_secs(600);
+
+pub struct QueuedPost {
+    pub guid: String,
+    pub steamid: u64,
+    pub appid: u64,
+    pub structure: NewStructure,
+}
+
+#[derive(Debug)]
+struct Pending {
+    steamid: u64,
+    // None until the writer got to it
+    result: Option<Result<Box<Structure>, ApiError>>,
+    finished_at: Option<Instant>,
+}
+
+pub enum PostStatus {
+    Queued,
+    Done(Result<Box<Structure>, ApiError>),
+}
+
+#[derive(Debug)]
+pub struct PostQueue {
+    sender: mpsc::Sender<QueuedPost>,
+    pending: DashMap<String, Pending>,
+}
+
+impl PostQueue {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<QueuedPost>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let queue = Self {
+            sender,
+            pending: DashMap::new(),
+        };
+        (queue, receiver)
+    }
+
+    // Hands the upload to the writer and returns the client_guid to poll
+    pub fn enqueue(
+        &self,
+        steamid: u64,
+        appid: u64,
+        structure: NewStructure,
+    ) -> Result<String, ApiError> {
+        let guid = format!("{:032x}", fastrand::u128(..));
+        let post = QueuedPost {
+            guid: guid.clone(),
+            steamid,
+            appid,
+            structure,
+        };
+        // registered first so a fast writer never finishes an unknown guid
+        self.pending.insert(
+            guid.clone(),
+            Pending {
+                steamid,
+                result: None,
+                finished_at: None,
+            },
+        );
+        if self.sender.try_send(post).is_err() {
+            self.pending.remove(&guid);
+            return Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many uploads are waiting to be stored.",
+            )
+            .with_retry_after(Duration::from_secs(1)));
+        }
+        Ok(guid)
+    }
+
+    // Only the uploader can see how their upload went
+    pub fn status(&self, guid: &str, steamid: u64) -> Option<PostStatus> {
+        let pending = self.pending.get(guid)?;
+        if pending.steamid != steamid {
+            return None;
+        }
+        Some(match &pending.result {
+            None => PostStatus::Queued,
+            Some(result) => PostStatus::Done(result.clone()),
+        })
+    }
+
+    pub fn finish(&self, guid: &str, result: Result<Box<Structure>, ApiError>) {
+        if let Some(mut pending) = self.pending.get_mut(guid) {
+            pending.result = Some(result);
+            pending.finished_at = Some(Instant::now());
+        }
+    }
+
+    // Drops outcomes nobody polled within RESULT_TTL
+    pub fn sweep(&self) {
+        self.pending.retain(|_, pending| {
+            pending
+                .finished_at
+                .is_none_or(|finished| finished.elapsed() < RESULT_TTL)
+        });
+    }
+}
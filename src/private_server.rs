@@ -0,0 +1,61 @@
+// Private servers (SERVER_PASSWORD, INVITE_ONLY).
+//
+// Small groups can keep a server to themselves without a reverse proxy in
+// front. Every player request then has to carry X-Server-Password on top of
+// its Steam credential: either the shared SERVER_PASSWORD, or an invite code
+// minted through the admin API, which can be revoked on its own when one
+// person should lose access. Anything else is answered 401 with code
+// `private_server` before it reaches a handler. Server info stays reachable
+// so the mod can find out that it needs to ask for a password.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    db::queries::invite_is_live,
+    error::{ApiError, AppError},
+    state::AppState,
+};
+
+pub static PASSWORD_HEADER: HeaderName = HeaderName::from_static("x-server-password");
+
+// Middleware for the player routes
+pub async fn guard(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let config = state.config();
+    if !config.is_private() || req.uri().path().ends_with("/server-info") {
+        return next.run(req).await;
+    }
+    let sent = req
+        .headers()
+        .get(&PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let Some(sent) = sent else {
+        return rejected("This server is private. Enter its password or your invite code.");
+    };
+    if config.server_password.as_deref() == Some(sent) {
+        return next.run(req).await;
+    }
+    match invite_is_live(&state.read_db, sent).await {
+        Ok(true) => next.run(req).await,
+        Ok(false) => {
+            tracing::warn!(
+                "private_server called result=rejected path={}",
+                req.uri().path()
+            );
+            rejected("Wrong password or invite code for this server.")
+        }
+        Err(e) => AppError::from(e).into_response(),
+    }
+}
+
+fn rejected(message: &str) -> Response {
+    ApiError::new(StatusCode::UNAUTHORIZED, message)
+        .with_code("private_server")
+        .into_response()
+}
@@ -0,0 +1,41 @@
+// Server-side budget for player requests (REQUEST_TIMEOUT_SECONDS).
+//
+// A request still running when the budget runs out is answered 503 with code
+// `timeout`. Its handler future is dropped on the spot: a pending connection
+// acquire gives up, and an open sqlx Transaction rolls back as it drops, so
+// a stuck request can't keep holding one of the few SQLite connections.
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::ApiError, state::AppState};
+
+pub async fn limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let budget = state.config().request_timeout;
+    if budget.is_zero() {
+        return next.run(req).await;
+    }
+    let method = req.method().clone();
+    let url = req.uri().to_string();
+    match tokio::time::timeout(budget, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(
+                "request_timeout method={} url={} budget_ms={}",
+                method.as_str(),
+                url,
+                budget.as_millis()
+            );
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The server took too long to answer.",
+            )
+            .with_code("timeout")
+            .into_response()
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// Candidate lists for the random structure fetch.
+//
+// The SQL path of get_random ranks every live structure of a scene on every
+// request. With RANDOM_SAMPLE_REFRESH_SECONDS set, the ids and the few columns
+// the ranking needs are kept here per (scene, map_id) instead; get_random picks
+// its ids in memory and only loads those rows. A list is built on the first
+// fetch of its scene, reloaded by a background task while the scene keeps
+// being fetched, and dropped once it goes quiet.
+
+use dashmap::DashMap;
+use sqlx::{FromRow, SqlitePool};
+use std::{cmp::Reverse, collections::HashMap, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+use crate::db::queries::{MAX_USAGE_WEIGHT, QuotaCounter, RandomFilter};
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Candidate {
+    id: i64,
+    user_id: i64,
+    segment: i32,
+    prefab: String,
+    likes: i32,
+    uses: i64,
+    created_at: i64,
+    pinned: bool,
+    // with legacy rows counted as the primary app
+    app_id: i64,
+    // the owner's shadow ban as of the last load
+    shadow_banned: bool,
+}
+
+type Key = (String, Option<i32>);
+
+#[derive(Debug)]
+struct Entry {
+    season: i64,
+    candidates: Arc<Vec<Candidate>>,
+    last_used: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct SampleCache {
+    entries: DashMap<Key, Entry>,
+}
+
+impl SampleCache {
+    // The scene's list, loaded now if it is missing or from another season
+    pub async fn candidates(
+        &self,
+        db: &SqlitePool,
+        scene: &str,
+        map_id: Option<i32>,
+        season: i64,
+        primary_appid: i64,
+    ) -> Result<Arc<Vec<Candidate>>, sqlx::Error> {
+        let key = (scene.to_string(), map_id);
+        if let Some(mut entry) = self.entries.get_mut(&key)
+            && entry.season == season
+        {
+            entry.last_used = Instant::now();
+            return Ok(entry.candidates.clone());
+        }
+
+        let candidates = Arc::new(load(db, &key, season, primary_appid).await?);
+        self.entries.insert(
+            key,
+            Entry {
+                season,
+                candidates: candidates.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(candidates)
+    }
+
+    // Reloads every list fetched within `idle` and forgets the others
+    pub async fn refresh(
+        &self,
+        db: &SqlitePool,
+        season: i64,
+        primary_appid: i64,
+        idle: Duration,
+    ) -> Result<(), sqlx::Error> {
+        self.entries
+            .retain(|_, entry| entry.last_used.elapsed() < idle);
+        let keys: Vec<Key> = self.entries.iter().map(|e| e.key().clone()).collect();
+        for key in keys {
+            let candidates = Arc::new(load(db, &key, season, primary_appid).await?);
+            if let Some(mut entry) = self.entries.get_mut(&key) {
+                entry.season = season;
+                entry.candidates = candidates;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+async fn load(
+    db: &SqlitePool,
+    (scene, map_id): &Key,
+    season: i64,
+    primary_appid: i64,
+) -> Result<Vec<Candidate>, sqlx::Error> {
+    let map_condition = if map_id.is_some() {
+        "AND map_id = ?"
+    } else {
+        ""
+    };
+    let query = format!(
+        r#"
+        SELECT id, user_id, segment, prefab, likes, uses, created_at, pinned,
+               COALESCE(app_id, ?) AS app_id,
+               user_id IN (SELECT user_id FROM users WHERE shadow_banned = 1) AS shadow_banned
+        FROM structures
+        WHERE scene = ? AND deleted = 0 AND season_id = ? {map_condition}
+        "#
+    );
+    let mut query = sqlx::query_as::<_, Candidate>(&query)
+        .bind(primary_appid)
+        .bind(scene)
+        .bind(season);
+    if let Some(map_id) = map_id {
+        query = query.bind(map_id);
+    }
+    query.fetch_all(db).await
+}
+
+// Picks ids the way the SQL path orders rows: the curated share goes to the
+// most-liked structures (after LIKES_DECAY_DAYS), the rest spreads over (user, segment) groups before
+// any group gets a second pick, with a random order that favours used ones.
+// Prefabs over their quota are passed over, and picks past the prefab share
+// go last.
+pub fn sample(candidates: &[Candidate], filter: &RandomFilter<'_>) -> Vec<i64> {
+    let mut eligible: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|c| filter.app_id.is_none_or(|app_id| c.app_id == app_id))
+        .filter(|c| !filter.exclude_prefabs.contains(&c.prefab))
+        .filter(|c| !c.shadow_banned || filter.viewer == Some(c.user_id))
+        .filter(|c| {
+            filter
+                .created_after
+                .is_none_or(|after| c.pinned || c.created_at >= after)
+        })
+        .collect();
+    let limit = filter.limit.max(0) as usize;
+
+    let mut quotas = QuotaCounter::new(filter.prefab_quotas);
+    let mut curated: Vec<&Candidate> = eligible.iter().copied().filter(|c| c.likes > 0).collect();
+    match filter.likes_decay {
+        Some(decay) => curated.sort_by(|a, b| {
+            let weight = |c: &Candidate| decay.weight(c.likes, c.created_at);
+            weight(b).total_cmp(&weight(a)).then(a.id.cmp(&b.id))
+        }),
+        None => curated.sort_by_key(|c| (Reverse(c.likes), c.id)),
+    }
+    let mut picked: Vec<i64> = curated
+        .into_iter()
+        .filter(|c| quotas.take(&c.prefab))
+        .take((filter.curated_limit.max(0) as usize).min(limit))
+        .map(|c| c.id)
+        .collect();
+    eligible.retain(|c| !picked.contains(&c.id));
+
+    fastrand::shuffle(&mut eligible);
+    let mut group_sizes: HashMap<(i64, i32), usize> = HashMap::new();
+    let mut prefab_sizes: HashMap<&str, i64> = HashMap::new();
+    let mut ranked: Vec<(bool, usize, f64, i64)> = eligible
+        .iter()
+        .filter(|c| quotas.take(&c.prefab))
+        .map(|c| {
+            let rank = group_sizes.entry((c.user_id, c.segment)).or_default();
+            *rank += 1;
+            let prefab_rank = prefab_sizes.entry(&c.prefab).or_default();
+            *prefab_rank += 1;
+            let over_share = filter
+                .prefab_share
+                .is_some_and(|share| *prefab_rank > share);
+            let weight = 1.0 + c.uses.clamp(0, MAX_USAGE_WEIGHT - 1) as f64;
+            (over_share, *rank, fastrand::f64() / weight, c.id)
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.total_cmp(&b.2)));
+
+    let remaining = limit.saturating_sub(picked.len());
+    picked.extend(ranked.into_iter().take(remaining).map(|(_, _, _, id)| id));
+    picked
+}
@@ -0,0 +1,92 @@
+// Startup self-check.
+//
+// Runs once before the server listens, so a broken setup stops the start
+// with a message saying what to fix instead of surfacing later as 500s or
+// every player failing to log in. Checks that the schema has everything the
+// handlers use, that the database takes writes, that WAL is on (readers
+// would block behind the writer otherwise) and that Steam accepts the API
+// key. Steam being unreachable only warns: an outage shouldn't keep the
+// server down once Steam is back. STARTUP_SELF_CHECK = false skips it all.
+
+use anyhow::bail;
+use reqwest::Client;
+use sqlx::SqlitePool;
+
+use crate::{auth::AuthProviderKind, config::Config, db, steam};
+
+// Steam rejects it as an invalid ticket, which is all the key check needs
+const PROBE_TICKET: &str = "peakstranding-self-check";
+
+pub async fn run(
+    config: &Config,
+    db: &SqlitePool,
+    http: &Client,
+    steam_key: &str,
+) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+
+    let missing = db::schema::missing_schema(db).await?;
+    if !missing.is_empty() {
+        problems.push(format!(
+            "the database at DATABASE_URL lacks {}; restore a complete backup or remove the damaged file and let the server create a fresh one",
+            missing.join(", ")
+        ));
+    }
+
+    if let Err(e) = probe_write(db).await {
+        problems.push(format!(
+            "the database at DATABASE_URL does not take writes ({e}); check that the file and its directory are writable by this user and that the disk isn't full"
+        ));
+    }
+
+    if !db::is_in_memory(config) {
+        let mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(db)
+            .await?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            problems.push(format!(
+                "the database runs in {mode} journal mode instead of WAL; WAL needs shared memory next to the file, so keep DATABASE_URL off network filesystems"
+            ));
+        }
+    }
+
+    if config.auth_provider == AuthProviderKind::Steam {
+        match steam::authenticate_ticket(
+            http,
+            &config.steam_api_url,
+            steam_key,
+            config.primary_appid(),
+            PROBE_TICKET,
+            config.steam_partner_fallback,
+        )
+        .await
+        {
+            Ok(_) => {}
+            Err(e) if e.key_rejected => problems.push(format!(
+                "{}; check STEAM_WEB_API_KEY (a publisher key also needs STEAM_PARTNER_FALLBACK or a partner STEAM_API_URL)",
+                e.message
+            )),
+            Err(e) => tracing::warn!(
+                "self_check steam_unreachable error={}; starting anyway",
+                e.message
+            ),
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("startup self-check failed:\n- {}", problems.join("\n- "));
+    }
+    tracing::info!("self_check passed");
+    Ok(())
+}
+
+// A write that is rolled back, so nothing is left behind
+async fn probe_write(db: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO notification_cursors (user_id, last_read_id) VALUES (-1, 0)",
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.rollback().await
+}
@@ -0,0 +1,237 @@
+// Startup: loads the config, opens the database, spawns the background tasks
+// and serves the router over TCP (optionally TLS) or a unix socket. SIGHUP
+// reloads the config in place.
+
+use anyhow::Context;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use reqwest::Client;
+use std::{
+    env,
+    net::SocketAddr,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::{
+    adaptive_limits::adjust_rate_limits,
+    auth,
+    config::{Config, ListenAddr},
+    db::{self, checkpoint::checkpoint_wal, open_read_pool},
+    handlers::{
+        admin::auto_approve_held_uploads,
+        build_router,
+        federation::pull_upstream,
+        likes::flush_likes,
+        stats::{daily_summary, stats_rollup},
+        structures::{
+            archive_cold_structures, flush_fetches, refresh_random_samples, write_queued_posts,
+        },
+    },
+    selfcheck,
+    state::AppState,
+    tenants,
+};
+
+pub async fn run() -> anyhow::Result<()> {
+    let config = Arc::new(Config::load()?);
+    let steam_key = env::var("STEAM_WEB_API_KEY").expect("STEAM_WEB_API_KEY missing");
+    let http = http_client(&config)?;
+
+    let (state, app) = start_community(config.clone(), None, &http, &steam_key).await?;
+    let mut states = vec![state];
+    let mut tenant_apps = Vec::new();
+    for slug in &config.tenants {
+        let tenant_config = Arc::new(Config::load_tenant(slug)?);
+        let (tenant_state, tenant_app) =
+            start_community(tenant_config, Some(slug), &http, &steam_key).await?;
+        tracing::info!("tenant {} serving under /t/{}", slug, slug);
+        states.push(tenant_state);
+        tenant_apps.push((slug.clone(), tenant_app));
+    }
+    let app = tenants::router(app, tenant_apps);
+
+    // SIGHUP re-reads the config file without dropping the auth cache
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            for state in &states {
+                let tenant = state.tenant.as_deref().unwrap_or("-");
+                match state.reload_config() {
+                    Ok(ignored) => tracing::info!(
+                        "config_reload called trigger=sighup tenant={} result=OK ignored={:?}",
+                        tenant,
+                        ignored
+                    ),
+                    Err(e) => tracing::error!(
+                        "config_reload called trigger=sighup tenant={} result=error error={:#}",
+                        tenant,
+                        e
+                    ),
+                }
+            }
+        }
+    });
+
+    let tls = load_tls_config(&config).await?;
+
+    let listener = bind_listener(&config)?;
+    tracing::info!(
+        "Server listening on {} ({})",
+        config.listen,
+        if tls.is_some() { "https" } else { "http" }
+    );
+    serve(app, listener, tls).await?;
+
+    Ok(())
+}
+
+// The main community's state and router, with its background tasks running
+// but nothing listening; for the benchmarks in benches/
+pub async fn start(config: Arc<Config>) -> anyhow::Result<(AppState, Router)> {
+    let steam_key = env::var("STEAM_WEB_API_KEY").unwrap_or_default();
+    let http = http_client(&config)?;
+    start_community(config, None, &http, &steam_key).await
+}
+
+// Opens the database of one community (the main one, or `tenant`), checks
+// it, and spawns its background tasks. Returns its state and router.
+async fn start_community(
+    config: Arc<Config>,
+    tenant: Option<&str>,
+    http: &Client,
+    steam_key: &str,
+) -> anyhow::Result<(AppState, Router)> {
+    let db = db::open(&config).await?;
+    let read_db = open_read_pool(&config, &db).await?;
+
+    if config.startup_integrity_check {
+        db::integrity::repair_on_start(&db).await?;
+    }
+    if config.startup_self_check {
+        selfcheck::run(&config, &db, http, steam_key).await?;
+    }
+    let (mut state, post_receiver) = AppState::new(
+        config.clone(),
+        Config::load,
+        db,
+        read_db,
+        http.clone(),
+        auth::provider(config.auth_provider, http.clone(), steam_key.to_string()),
+        steam_key.to_string(),
+    );
+    state.tenant = tenant.map(String::from);
+
+    let app = build_router(state.clone());
+
+    tokio::spawn(daily_summary(state.clone()));
+    tokio::spawn(stats_rollup(state.clone()));
+    tokio::spawn(refresh_random_samples(state.clone()));
+    tokio::spawn(flush_likes(state.clone()));
+    tokio::spawn(flush_fetches(state.clone()));
+    tokio::spawn(archive_cold_structures(state.clone()));
+    tokio::spawn(auto_approve_held_uploads(state.clone()));
+    tokio::spawn(pull_upstream(state.clone()));
+    tokio::spawn(adjust_rate_limits(state.clone()));
+    tokio::spawn(checkpoint_wal(state.clone()));
+    if let Some(receiver) = post_receiver {
+        tokio::spawn(write_queued_posts(state.clone(), receiver));
+    }
+    Ok((state, app))
+}
+
+// TLS is optional: both paths set -> HTTPS, neither -> plain HTTP.
+pub async fn load_tls_config(config: &Config) -> anyhow::Result<Option<RustlsConfig>> {
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => {
+            // reqwest already links rustls with ring; make it the process-wide provider
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let tls = RustlsConfig::from_pem_file(cert, key)
+                .await
+                .with_context(|| format!("failed to load TLS cert {cert} / key {key}"))?;
+            Ok(Some(tls))
+        }
+        (None, None) => Ok(None),
+        _ => anyhow::bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    }
+}
+
+const HTTP_TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+// Client for Steam and Discord. Idle connections are kept so repeated ticket
+// checks skip the TCP and TLS handshakes. rustls is picked explicitly because
+// the default native-tls backend is built without ALPN and so never gets h2.
+pub fn http_client(config: &Config) -> reqwest::Result<Client> {
+    let mut builder = Client::builder()
+        .use_rustls_tls()
+        .pool_max_idle_per_host(config.http_pool_max_idle)
+        .pool_idle_timeout(config.http_pool_idle_timeout)
+        .tcp_keepalive(HTTP_TCP_KEEPALIVE)
+        .timeout(Duration::from_secs(5));
+    if !config.http2 {
+        builder = builder.http1_only();
+    }
+    builder.build()
+}
+
+pub enum BoundListener {
+    Tcp(std::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+pub fn bind_listener(config: &Config) -> anyhow::Result<BoundListener> {
+    match &config.listen {
+        ListenAddr::Tcp(addr) => {
+            let listener = std::net::TcpListener::bind(addr)
+                .with_context(|| format!("failed to bind {addr}"))?;
+            listener.set_nonblocking(true)?;
+            Ok(BoundListener::Tcp(listener))
+        }
+        ListenAddr::Unix(path) => {
+            // A socket file left behind by a previous run would make bind fail
+            if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+            }
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("failed to bind {}", path.display()))?;
+            if let Some(mode) = config.unix_socket_mode {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                    .with_context(|| format!("failed to chmod {}", path.display()))?;
+            }
+            Ok(BoundListener::Unix(listener))
+        }
+    }
+}
+
+// Every listener speaks HTTP/1.1 and HTTP/2: h2 is negotiated via ALPN under
+// TLS, and plaintext connections may start with the h2c preface, so a client
+// can multiplex fetches and posts over one connection. TCP listeners hand
+// the peer address to the router for client_ip.
+pub async fn serve(
+    app: Router,
+    listener: BoundListener,
+    tls: Option<RustlsConfig>,
+) -> anyhow::Result<()> {
+    match (listener, tls) {
+        (BoundListener::Tcp(listener), Some(tls)) => {
+            axum_server::from_tcp_rustls(listener, tls)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?
+        }
+        (BoundListener::Tcp(listener), None) => {
+            axum::serve(
+                tokio::net::TcpListener::from_std(listener)?,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?
+        }
+        (BoundListener::Unix(listener), None) => axum::serve(listener, app).await?,
+        (BoundListener::Unix(_), Some(_)) => {
+            anyhow::bail!("TLS is not supported on unix socket listeners")
+        }
+    }
+    Ok(())
+}
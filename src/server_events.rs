@@ -0,0 +1,171 @@
+// Scheduled community events (server_events table).
+//
+// Operators schedule events through the admin API instead of editing the
+// config: a like multiplier (double-likes weekends), relaxed caps (the
+// per-scene structure cap, the daily upload quota and the like budgets
+// multiplied), or a featured scene. Handlers ask for the events running at
+// request time. The upcoming ones are kept in memory and reread every
+// EVENTS_REFRESH, or right away when the admin API changes them, so an event
+// starts and ends on time without a query per request.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::{config::Config, handlers::structures::now_millis};
+
+const EVENTS_REFRESH: Duration = Duration::from_secs(30);
+
+// Largest multiplier an event may set
+pub const MAX_EVENT_MULTIPLIER: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // every like counts `multiplier` times toward likes and likes_received
+    LikeMultiplier,
+    // upload and like caps are `multiplier` times as high
+    RelaxedCaps,
+    // `scene` is marked as featured in the scene list
+    FeaturedScene,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::LikeMultiplier => "like_multiplier",
+            EventKind::RelaxedCaps => "relaxed_caps",
+            EventKind::FeaturedScene => "featured_scene",
+        }
+    }
+}
+
+impl TryFrom<String> for EventKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "like_multiplier" => Ok(EventKind::LikeMultiplier),
+            "relaxed_caps" => Ok(EventKind::RelaxedCaps),
+            "featured_scene" => Ok(EventKind::FeaturedScene),
+            other => Err(format!("unknown event kind {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServerEvent {
+    pub id: i64,
+    pub name: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub kind: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiplier: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scene: Option<String>,
+    // epoch millis
+    pub starts_at: i64,
+    pub ends_at: i64,
+}
+
+impl ServerEvent {
+    fn running_at(&self, now: i64) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+pub const EVENT_COLUMNS: &str = "id, name, kind, multiplier, scene, starts_at, ends_at";
+
+// The events running at one moment
+#[derive(Debug, Default)]
+pub struct ActiveEvents {
+    pub events: Vec<ServerEvent>,
+}
+
+impl ActiveEvents {
+    // Overlapping events of one kind don't stack; the largest multiplier wins
+    fn multiplier(&self, kind: EventKind) -> i64 {
+        self.events
+            .iter()
+            .filter(|event| event.kind == kind)
+            .filter_map(|event| event.multiplier)
+            .max()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn like_multiplier(&self) -> i64 {
+        self.multiplier(EventKind::LikeMultiplier)
+    }
+
+    pub fn is_featured(&self, scene: &str) -> bool {
+        self.events.iter().any(|event| {
+            event.kind == EventKind::FeaturedScene && event.scene.as_deref() == Some(scene)
+        })
+    }
+
+    // `config` with its caps raised by a running relaxed_caps event; limits
+    // that are off (0) stay off
+    pub fn relax(&self, config: Arc<Config>) -> Arc<Config> {
+        let factor = self.multiplier(EventKind::RelaxedCaps);
+        if factor == 1 {
+            return config;
+        }
+        let relaxed = |cap: i64| cap.saturating_mul(factor);
+        let mut config = (*config).clone();
+        config.max_user_structs_saved_per_scene = relaxed(config.max_user_structs_saved_per_scene);
+        for overrides in config.map_overrides.values_mut() {
+            overrides.max_user_structs_saved_per_scene =
+                overrides.max_user_structs_saved_per_scene.map(relaxed);
+        }
+        config.daily_upload_quota = relaxed(config.daily_upload_quota);
+        config.like_daily_budget = relaxed(config.like_daily_budget);
+        config.like_target_daily_cap = relaxed(config.like_target_daily_cap);
+        Arc::new(config)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventCache {
+    // events that haven't ended yet, and when they were read
+    upcoming: RwLock<Option<(Instant, Arc<Vec<ServerEvent>>)>>,
+}
+
+impl EventCache {
+    // Events that haven't ended yet, earliest first
+    pub async fn upcoming(&self, db: &SqlitePool) -> Result<Arc<Vec<ServerEvent>>, sqlx::Error> {
+        if let Some((read_at, events)) = &*self.upcoming.read().await
+            && read_at.elapsed() < EVENTS_REFRESH
+        {
+            return Ok(events.clone());
+        }
+        let events: Arc<Vec<ServerEvent>> = Arc::new(
+            sqlx::query_as(&format!(
+                "SELECT {EVENT_COLUMNS} FROM server_events WHERE ends_at > ? ORDER BY starts_at, id"
+            ))
+            .bind(now_millis())
+            .fetch_all(db)
+            .await?,
+        );
+        *self.upcoming.write().await = Some((Instant::now(), events.clone()));
+        Ok(events)
+    }
+
+    pub async fn active(&self, db: &SqlitePool) -> Result<ActiveEvents, sqlx::Error> {
+        let now = now_millis();
+        let events = self.upcoming(db).await?;
+        Ok(ActiveEvents {
+            events: events
+                .iter()
+                .filter(|event| event.running_at(now))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    // After the admin API changed the table
+    pub async fn invalidate(&self) {
+        *self.upcoming.write().await = None;
+    }
+}
@@ -0,0 +1,220 @@
+// Shared server state.
+//
+// AppState is cloned into every handler and background task; everything
+// mutable in it sits behind an Arc. The config lives in an ArcSwap so SIGHUP
+// can replace it without a restart.
+
+use arc_swap::ArcSwap;
+use dashmap::{DashMap, DashSet};
+use reqwest::Client;
+use sqlx::SqlitePool;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{RwLock, mpsc},
+    time::Instant,
+};
+
+use crate::{
+    adaptive_limits::RateLimitScale,
+    auth::{AuthError, AuthProvider},
+    batches::BatchCache,
+    config::Config,
+    contracts::ContractCache,
+    db::queries::{GlobalStatsResponse, PrefabStatsResponse, SceneStatsResponse},
+    db_metrics::DbMetrics,
+    discord::DiscordNotifier,
+    events::EventHub,
+    fetch_log::FetchLog,
+    like_buffer::LikeBuffer,
+    limit_stats::LimitStats,
+    post_queue::{PostQueue, QueuedPost},
+    samples::SampleCache,
+    server_events::EventCache,
+    steam::Breaker,
+};
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry<T> {
+    pub value: T,
+    pub expires_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    // the single writer connection; transactions and anything that modifies data
+    pub db: SqlitePool,
+    // query_only connections for reads that can run next to the writer under WAL
+    pub read_db: SqlitePool,
+    // ticket -> (steam_id, app_id)
+    pub cache: Arc<DashMap<String, (u64, u64)>>,
+    // ticket -> when and why the auth provider rejected it
+    pub rejected_tickets: Arc<DashMap<String, (Instant, AuthError)>>,
+    // (steam_id, app_id) -> (owns the app, checked at)
+    pub ownership_cache: Arc<DashMap<(u64, u64), (bool, Instant)>>,
+    // Steam Web API lookups outside ticket checks, which have their own
+    pub steam_breaker: Arc<Breaker>,
+    pub http: Client,
+    pub auth: Arc<dyn AuthProvider>,
+    pub steam_key: String,
+    pub config: Arc<ArcSwap<Config>>,
+    pub config_loader: fn() -> anyhow::Result<Config>,
+    // the [tenants.<slug>] community this state serves; None for the main one
+    pub tenant: Option<String>,
+    // maintenance mode: writes are refused while set (READ_ONLY)
+    pub read_only: Arc<AtomicBool>,
+    // RATE_LIMIT_EXEMPT_STEAMIDS, plus changes through the admin API
+    pub rate_limit_exempt: Arc<DashSet<u64>>,
+    // multiplies the per-user rate limit intervals (ADAPTIVE_RATE_LIMITS)
+    pub rate_limit_scale: Arc<RateLimitScale>,
+    pub post_structure_rate_limiter: Arc<DashMap<u64, Instant>>,
+    pub get_structure_rate_limiter: Arc<DashMap<u64, Instant>>,
+    pub post_like_rate_limiter: Arc<DashMap<u64, Instant>>,
+    // keyed by (user, structure): reporting different structures is never throttled
+    pub usage_rate_limiter: Arc<DashMap<(u64, i64), Instant>>,
+    pub nearby_rate_limiter: Arc<DashMap<u64, Instant>>,
+    pub global_stats_rate_limiter: Arc<DashMap<u64, Instant>>,
+    pub user_stats_rate_limiter: Arc<DashMap<u64, Instant>>,
+    pub global_stats_cache: Arc<RwLock<Option<CacheEntry<GlobalStatsResponse>>>>,
+    // (scene, season) -> stats; empty scenes are not cached
+    pub scene_stats_cache: Arc<DashMap<(String, i64), CacheEntry<SceneStatsResponse>>>,
+    pub prefab_stats_cache: Arc<RwLock<Option<CacheEntry<PrefabStatsResponse>>>>,
+    pub random_samples: Arc<SampleCache>,
+    pub random_batches: Arc<BatchCache>,
+    // session id -> structure ids picked for a lobby (FETCH_SESSION_TTL_SECONDS)
+    pub fetch_sessions: Arc<DashMap<String, CacheEntry<Vec<i64>>>>,
+    // set when uploads go through the background writer (POST_QUEUE_CAPACITY)
+    pub post_queue: Option<Arc<PostQueue>>,
+    pub like_buffer: Arc<LikeBuffer>,
+    pub fetch_log: Arc<FetchLog>,
+    pub limit_stats: Arc<LimitStats>,
+    pub db_metrics: Arc<DbMetrics>,
+    // (scene, season) -> (live structures, counted at)
+    pub scene_sizes: Arc<DashMap<(String, i64), (i64, Instant)>>,
+    pub events: Arc<EventHub>,
+    // scheduled community events (server_events table)
+    pub server_events: Arc<EventCache>,
+    // active strand contracts (contracts table)
+    pub contracts: Arc<ContractCache>,
+    pub discord: DiscordNotifier,
+}
+
+impl AppState {
+    // Fresh caches and limiters around the given pools. With
+    // POST_QUEUE_CAPACITY set, also returns the receiving end of the upload
+    // queue for write_queued_posts.
+    pub fn new(
+        config: Arc<Config>,
+        config_loader: fn() -> anyhow::Result<Config>,
+        db: SqlitePool,
+        read_db: SqlitePool,
+        http: Client,
+        auth: Arc<dyn AuthProvider>,
+        steam_key: String,
+    ) -> (Self, Option<mpsc::Receiver<QueuedPost>>) {
+        let (post_queue, post_receiver) = match config.post_queue_capacity {
+            0 => (None, None),
+            capacity => {
+                let (queue, receiver) = PostQueue::new(capacity);
+                (Some(Arc::new(queue)), Some(receiver))
+            }
+        };
+        let read_only = Arc::new(AtomicBool::new(config.read_only));
+        let rate_limit_exempt =
+            Arc::new(config.rate_limit_exempt_steamids.iter().copied().collect());
+        let config = Arc::new(ArcSwap::new(config));
+        let state = Self {
+            db,
+            read_db,
+            cache: Arc::new(DashMap::new()),
+            rejected_tickets: Arc::new(DashMap::new()),
+            ownership_cache: Arc::new(DashMap::new()),
+            steam_breaker: Arc::new(Breaker::default()),
+            http: http.clone(),
+            auth,
+            steam_key,
+            config: config.clone(),
+            config_loader,
+            tenant: None,
+            read_only,
+            rate_limit_exempt,
+            rate_limit_scale: Arc::new(RateLimitScale::default()),
+            post_structure_rate_limiter: Arc::new(DashMap::new()),
+            get_structure_rate_limiter: Arc::new(DashMap::new()),
+            post_like_rate_limiter: Arc::new(DashMap::new()),
+            usage_rate_limiter: Arc::new(DashMap::new()),
+            nearby_rate_limiter: Arc::new(DashMap::new()),
+            global_stats_rate_limiter: Arc::new(DashMap::new()),
+            user_stats_rate_limiter: Arc::new(DashMap::new()),
+            global_stats_cache: Arc::new(RwLock::new(None)),
+            scene_stats_cache: Arc::new(DashMap::new()),
+            prefab_stats_cache: Arc::new(RwLock::new(None)),
+            random_samples: Arc::new(SampleCache::default()),
+            random_batches: Arc::new(BatchCache::default()),
+            fetch_sessions: Arc::new(DashMap::new()),
+            post_queue,
+            like_buffer: Arc::new(LikeBuffer::default()),
+            fetch_log: Arc::new(FetchLog::default()),
+            limit_stats: Arc::new(LimitStats::default()),
+            db_metrics: Arc::new(DbMetrics::default()),
+            scene_sizes: Arc::new(DashMap::new()),
+            events: Arc::new(EventHub::default()),
+            server_events: Arc::new(EventCache::default()),
+            contracts: Arc::new(ContractCache::default()),
+            discord: DiscordNotifier::spawn(http, config),
+        };
+        (state, post_receiver)
+    }
+
+    // Current config snapshot; swapped atomically on reload
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    // Re-reads config and swaps it in, returning the restart-only keys that were ignored.
+    pub fn reload_config(&self) -> anyhow::Result<Vec<&'static str>> {
+        let running = self.config();
+        let mut next = match &self.tenant {
+            Some(slug) => Config::load_tenant(slug)?,
+            None => (self.config_loader)()?,
+        };
+        let ignored = next.keep_restart_only(&running);
+        // a runtime toggle survives reloads that leave READ_ONLY alone
+        if next.read_only != running.read_only {
+            self.set_read_only(next.read_only);
+        }
+        // same for the exemption list: a changed one replaces the runtime edits
+        if next.rate_limit_exempt_steamids != running.rate_limit_exempt_steamids {
+            self.rate_limit_exempt.clear();
+            for steamid in &next.rate_limit_exempt_steamids {
+                self.rate_limit_exempt.insert(*steamid);
+            }
+        }
+        self.config.store(Arc::new(next));
+        Ok(ignored)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limit_exempt(&self, steamid: u64) -> bool {
+        self.rate_limit_exempt.contains(&steamid)
+    }
+
+    // A configured per-user interval, scaled to the current load
+    pub fn rate_limit(&self, interval: Duration) -> Duration {
+        interval.mul_f64(self.rate_limit_scale.factor())
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        if self.read_only.swap(read_only, Ordering::Relaxed) != read_only {
+            tracing::warn!("maintenance read_only={}", read_only);
+        }
+    }
+}
@@ -0,0 +1,445 @@
+// Steam Web API calls: ticket authentication, the ownership check and
+// persona names.
+//
+// Tickets and the API key travel in a form-encoded POST body, or for the GET
+// methods the key in the x-webapi-key header, so they stay out of URLs, and
+// with them out of proxy and access logs. Transport errors, 5xx and 429
+// answers are retried a few times with backoff; anything else is final. A
+// Breaker in front of the calls stops sending traffic during a Steam outage.
+// Rejections carry a RejectReason so clients learn whether a new ticket helps.
+
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::{sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+const AUTHENTICATE_USER_TICKET_PATH: &str = "/ISteamUserAuth/AuthenticateUserTicket/v1/";
+const CHECK_APP_OWNERSHIP_PATH: &str = "/ISteamUser/CheckAppOwnership/v2/";
+const GET_PLAYER_SUMMARIES_PATH: &str = "/ISteamUser/GetPlayerSummaries/v2/";
+pub const PUBLIC_HOST: &str = "https://api.steampowered.com";
+// Same API for publisher keys; used as a fallback when the public host is down
+const PARTNER_HOST: &str = "https://partner.steam-api.com";
+// The Web API takes the key here as well as in a `key` parameter
+const KEY_HEADER: &str = "x-webapi-key";
+const MAX_ATTEMPTS: u32 = 3;
+// doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Debug, PartialEq)]
+pub enum TicketCheck {
+    Valid {
+        steamid: u64,
+    },
+    // Steam answered, but not with a usable ticket for this app
+    Rejected {
+        reason: RejectReason,
+        detail: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RejectReason {
+    // issued for a different app id
+    WrongApp,
+    // malformed or unknown to Steam
+    Invalid,
+    // was valid once: expired, already used or cancelled
+    Expired,
+}
+
+impl RejectReason {
+    // Steam only describes errors in prose, so this goes by the wording
+    pub fn classify(description: &str) -> Self {
+        let description = description.to_ascii_lowercase();
+        if description.contains("other app") || description.contains("appid") {
+            RejectReason::WrongApp
+        } else if ["expired", "used", "cancel", "revoked"]
+            .iter()
+            .any(|word| description.contains(word))
+        {
+            RejectReason::Expired
+        } else {
+            RejectReason::Invalid
+        }
+    }
+}
+
+// Steam could not be asked (after retries); says nothing about the ticket
+#[derive(Debug)]
+pub struct Unavailable {
+    pub message: String,
+    // Steam answered, but refused our API key; retrying won't help
+    pub key_rejected: bool,
+}
+
+impl Unavailable {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            key_rejected: false,
+        }
+    }
+}
+
+// Checks the ticket on `host` (the public Web API host unless STEAM_API_URL
+// says otherwise), and on the partner host as well when `partner_fallback` is
+// set and the first one is unavailable.
+pub async fn authenticate_ticket(
+    http: &Client,
+    host: &str,
+    key: &str,
+    appid: u64,
+    ticket: &str,
+    partner_fallback: bool,
+) -> Result<TicketCheck, Unavailable> {
+    match authenticate_at(http, host, key, appid, ticket).await {
+        Err(Unavailable { message, .. }) if partner_fallback => {
+            tracing::warn!("steam_auth falling back to partner host error={}", message);
+            authenticate_at(http, PARTNER_HOST, key, appid, ticket).await
+        }
+        result => result,
+    }
+}
+
+async fn authenticate_at(
+    http: &Client,
+    host: &str,
+    key: &str,
+    appid: u64,
+    ticket: &str,
+) -> Result<TicketCheck, Unavailable> {
+    #[derive(Deserialize)]
+    struct SteamResp {
+        response: SteamResponseInner,
+    }
+    #[derive(Deserialize)]
+    struct SteamResponseInner {
+        params: Option<SteamParams>,
+        error: Option<SteamError>,
+    }
+    #[derive(Deserialize)]
+    struct SteamParams {
+        result: String,
+        steamid: String,
+    }
+    #[derive(Deserialize)]
+    struct SteamError {
+        errorcode: i64,
+        errordesc: String,
+    }
+
+    let url = format!("{host}{AUTHENTICATE_USER_TICKET_PATH}");
+    let appid_text = appid.to_string();
+    let form = [("key", key), ("appid", &appid_text), ("ticket", ticket)];
+    let (resp, start) = send("steam_auth", host, || http.post(&url).form(&form)).await?;
+
+    let res: SteamResp = match resp.json().await {
+        Ok(j) => j,
+        Err(e) => {
+            tracing::warn!(
+                "steam_auth called result=bad_json host={} appid={} error={} duration_ms={}",
+                host,
+                appid,
+                e,
+                start.elapsed().as_millis()
+            );
+            // says nothing about the ticket
+            return Err(Unavailable::new(format!("unreadable answer: {e}")));
+        }
+    };
+
+    let params = match (res.response.params, res.response.error) {
+        (Some(params), _) => params,
+        (None, Some(error)) => {
+            let reason = RejectReason::classify(&error.errordesc);
+            tracing::warn!(
+                "steam_auth called result=error host={} appid={} errorcode={} errordesc={:?} reason={:?} duration_ms={}",
+                host,
+                appid,
+                error.errorcode,
+                error.errordesc,
+                reason,
+                start.elapsed().as_millis()
+            );
+            return Ok(TicketCheck::Rejected {
+                reason,
+                detail: error.errordesc,
+            });
+        }
+        (None, None) => {
+            return Ok(TicketCheck::Rejected {
+                reason: RejectReason::Invalid,
+                detail: "empty answer".into(),
+            });
+        }
+    };
+
+    if params.result != "OK" {
+        tracing::warn!(
+            "steam_auth called result={} host={} appid={} steamid={} duration_ms={}",
+            params.result,
+            host,
+            appid,
+            params.steamid,
+            start.elapsed().as_millis()
+        );
+        return Ok(TicketCheck::Rejected {
+            reason: RejectReason::Invalid,
+            detail: params.result,
+        });
+    }
+
+    let Ok(steamid) = params.steamid.parse::<u64>() else {
+        return Err(Unavailable::new("bad steamid"));
+    };
+    tracing::info!(
+        "steam_auth called result=OK host={} appid={} steamid={} duration_ms={}",
+        host,
+        appid,
+        steamid,
+        start.elapsed().as_millis()
+    );
+    Ok(TicketCheck::Valid { steamid })
+}
+
+// Whether the account owns the app, per ISteamUser/CheckAppOwnership. The
+// method only takes publisher keys on the partner host, so the public default
+// for `host` is swapped for it; any other STEAM_API_URL is used as is.
+pub async fn check_app_ownership(
+    http: &Client,
+    host: &str,
+    key: &str,
+    steamid: u64,
+    appid: u64,
+) -> Result<bool, Unavailable> {
+    #[derive(Deserialize)]
+    struct OwnershipResp {
+        appownership: AppOwnership,
+    }
+    #[derive(Deserialize)]
+    struct AppOwnership {
+        ownsapp: bool,
+        result: String,
+    }
+
+    let host = if host == PUBLIC_HOST {
+        PARTNER_HOST
+    } else {
+        host
+    };
+    let url = format!("{host}{CHECK_APP_OWNERSHIP_PATH}");
+    let query = [("steamid", steamid), ("appid", appid)];
+    let (resp, start) = send("steam_ownership", host, || {
+        http.get(&url).header(KEY_HEADER, key).query(&query)
+    })
+    .await?;
+
+    let res: OwnershipResp = resp.json().await.map_err(|e| {
+        tracing::warn!(
+            "steam_ownership called result=bad_json host={} steamid={} error={} duration_ms={}",
+            host,
+            steamid,
+            e,
+            start.elapsed().as_millis()
+        );
+        Unavailable::new(format!("unreadable answer: {e}"))
+    })?;
+    let owns = res.appownership.result == "OK" && res.appownership.ownsapp;
+    tracing::info!(
+        "steam_ownership called result={} host={} steamid={} owns={} duration_ms={}",
+        res.appownership.result,
+        host,
+        steamid,
+        owns,
+        start.elapsed().as_millis()
+    );
+    Ok(owns)
+}
+
+// The account's persona name, per ISteamUser/GetPlayerSummaries; None when
+// Steam doesn't know the account
+pub async fn player_name(
+    http: &Client,
+    host: &str,
+    key: &str,
+    steamid: u64,
+) -> Result<Option<String>, Unavailable> {
+    #[derive(Deserialize)]
+    struct SummariesResp {
+        response: SummariesInner,
+    }
+    #[derive(Deserialize)]
+    struct SummariesInner {
+        players: Vec<PlayerSummary>,
+    }
+    #[derive(Deserialize)]
+    struct PlayerSummary {
+        personaname: String,
+    }
+
+    let url = format!("{host}{GET_PLAYER_SUMMARIES_PATH}");
+    let query = [("steamids", steamid)];
+    let (resp, start) = send("steam_profile", host, || {
+        http.get(&url).header(KEY_HEADER, key).query(&query)
+    })
+    .await?;
+
+    let res: SummariesResp = resp.json().await.map_err(|e| {
+        tracing::warn!(
+            "steam_profile called result=bad_json host={} steamid={} error={} duration_ms={}",
+            host,
+            steamid,
+            e,
+            start.elapsed().as_millis()
+        );
+        Unavailable::new(format!("unreadable answer: {e}"))
+    })?;
+    let name = res
+        .response
+        .players
+        .into_iter()
+        .next()
+        .map(|p| p.personaname);
+    tracing::info!(
+        "steam_profile called result={} host={} steamid={} duration_ms={}",
+        if name.is_some() { "OK" } else { "not_found" },
+        host,
+        steamid,
+        start.elapsed().as_millis()
+    );
+    Ok(name)
+}
+
+// Sends what `request` builds, retrying transport errors, 5xx and 429 answers
+// with backoff. A 401 or 403 means Steam refused our key, which no retry fixes.
+// Returns the answer with the time its attempt started.
+async fn send(
+    call: &str,
+    host: &str,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<(Response, Instant), Unavailable> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut last_error = String::new();
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        let start = Instant::now();
+        let resp = match request().send().await {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::warn!(
+                    "{} called result=transport_error host={} attempt={} error={} duration_ms={}",
+                    call,
+                    host,
+                    attempt,
+                    e,
+                    start.elapsed().as_millis()
+                );
+                last_error = e.to_string();
+                continue;
+            }
+        };
+
+        let status = resp.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            tracing::warn!(
+                "{} called result=http_error host={} attempt={} status={} duration_ms={}",
+                call,
+                host,
+                attempt,
+                status.as_u16(),
+                start.elapsed().as_millis()
+            );
+            last_error = format!("steam answered {status}");
+            continue;
+        }
+        if status == StatusCode::FORBIDDEN || status == StatusCode::UNAUTHORIZED {
+            // our key: wrong key or not allowed on this host
+            tracing::warn!(
+                "{} called result=key_rejected host={} status={} duration_ms={}",
+                call,
+                host,
+                status.as_u16(),
+                start.elapsed().as_millis()
+            );
+            return Err(Unavailable {
+                message: format!("steam refused the API key ({status})"),
+                key_rejected: true,
+            });
+        }
+        return Ok((resp, start));
+    }
+
+    Err(Unavailable::new(last_error))
+}
+
+// Circuit breaker for Steam outages. After `threshold` unavailable answers in a
+// row it opens for `cooldown`, failing checks immediately instead of making
+// every request wait on timeouts. After the cooldown it is half-open: the
+// first call is let through as a probe and the rest are refused until the probe
+// settles it, a success closing it again and a failure re-opening it.
+#[derive(Debug, Default)]
+pub struct Breaker {
+    state: Mutex<BreakerState>,
+}
+
+// A probe whose request was dropped never reports back; after this long
+// another one is let through
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    // when the half-open probe was let through
+    probe_in_flight: Option<Instant>,
+}
+
+impl Breaker {
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        let now = Instant::now();
+        match state.open_until {
+            None => true,
+            Some(until) if now < until => false,
+            Some(_)
+                if state
+                    .probe_in_flight
+                    .is_some_and(|started| now.duration_since(started) < PROBE_TIMEOUT) =>
+            {
+                false
+            }
+            Some(_) => {
+                state.probe_in_flight = Some(now);
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        if state.open_until.is_some() {
+            tracing::info!("steam_breaker closed");
+        }
+        *state = BreakerState::default();
+    }
+
+    // threshold 0 disables the breaker
+    pub fn record_failure(&self, threshold: u32, cooldown: Duration) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        state.consecutive_failures += 1;
+        if threshold > 0 && state.consecutive_failures >= threshold {
+            tracing::warn!(
+                "steam_breaker opened failures={} cooldown_s={}",
+                state.consecutive_failures,
+                cooldown.as_secs()
+            );
+            state.open_until = Some(Instant::now() + cooldown);
+            state.probe_in_flight = None;
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Several communities in one process ([tenants.<slug>] config sections).
+//
+// Every tenant is a complete community of its own: its own database, caches,
+// limits, admin key and background tasks, served by its own router under
+// /t/<slug>. Clients that can't change the base URL send X-Tenant: <slug>
+// instead, and the request is routed as if it had the prefix. Requests with
+// neither reach the main community.
+
+use axum::{
+    Router,
+    extract::{OriginalUri, Request},
+    http::{HeaderName, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::error::ApiError;
+
+pub static TENANT_HEADER: HeaderName = HeaderName::from_static("x-tenant");
+
+// `main` with each tenant's router nested under /t/<slug>
+pub fn router(main: Router, tenants: Vec<(String, Router)>) -> Router {
+    if tenants.is_empty() {
+        return main;
+    }
+    let slugs: Arc<BTreeSet<String>> =
+        Arc::new(tenants.iter().map(|(slug, _)| slug.clone()).collect());
+    let app = tenants.into_iter().fold(main, |app, (slug, tenant)| {
+        app.nest(&format!("/t/{slug}"), tenant)
+    });
+    // rewriting has to happen before routing, so it wraps the whole router
+    let app = tower::ServiceBuilder::new()
+        .layer(middleware::from_fn(move |req, next| {
+            route_by_header(slugs.clone(), req, next)
+        }))
+        .service(app);
+    Router::new().fallback_service(app)
+}
+
+async fn route_by_header(slugs: Arc<BTreeSet<String>>, mut req: Request, next: Next) -> Response {
+    let Some(slug) = req.headers().get(&TENANT_HEADER) else {
+        return next.run(req).await;
+    };
+    let slug = slug.to_str().unwrap_or_default().trim().to_string();
+    if !slugs.contains(&slug) {
+        return ApiError::new(StatusCode::NOT_FOUND, "No such community on this server.")
+            .with_code("unknown_tenant")
+            .into_response();
+    }
+    let prefix = format!("/t/{slug}");
+    if !req.uri().path().starts_with(&format!("{prefix}/")) {
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |p| p.as_str())
+            .to_string();
+        match format!("{prefix}{path_and_query}").parse::<Uri>() {
+            Ok(uri) => {
+                // so the access log shows which community answered
+                req.extensions_mut().insert(OriginalUri(uri.clone()));
+                *req.uri_mut() = uri;
+            }
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    }
+    next.run(req).await
+}
@@ -1,20 +1,66 @@
 #![cfg(test)]
 
-use super::*;
 use axum::{
+    Json, Router,
     body::Body,
     http::{Method, Request, StatusCode},
 };
 use http_body_util::BodyExt;
+use reqwest::Client;
 use serde_json::{Value, json};
-use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::{
+    collections::BTreeMap,
+    env,
+    os::unix::fs::PermissionsExt,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_stream::StreamExt;
 use tower::ServiceExt;
 
+use crate::{
+    MILLIS_IN_DAY,
+    auth::{self, ADMIN_HEADER, AuthProviderKind, STEAM_HEADER},
+    client_ip::{self, Cidr},
+    config::{Config, ListenAddr, MapOverrides},
+    db::{
+        checkpoint, integrity, open_read_pool,
+        queries::{
+            NEWCOMER_REVIEW_REASON, archive_cold, auto_approve_reviews, prune_likes_ledger,
+            rollup_daily_stats, set_shadow_banned, set_upload_banned,
+        },
+        schema::apply_migrations,
+        sqlite_connect_options,
+    },
+    events::UserEvent,
+    handlers::federation,
+    handlers::{
+        build_router,
+        likes::flush_likes,
+        structures::{INCLUDE_TOTAL_HEADER, write_queued_posts},
+    },
+    mock_steam::{self, MockSteam},
+    model::PrunePolicy,
+    plausibility::PlausibilityMode,
+    selfcheck,
+    server::{BoundListener, bind_listener, http_client, load_tls_config, serve},
+    state::AppState,
+    steam, tenants,
+};
+
 const OWNER_TICKET: &str = "owner-ticket";
 const LIKER_TICKET: &str = "liker-ticket";
 const OTHER_TICKET: &str = "other-ticket";
 
+const ADMIN_KEY: &str = "admin-key";
+
+static TEST_CONFIG: OnceLock<Arc<Config>> = OnceLock::new();
+
+const TEST_APPID: u64 = 0;
 const OWNER_ID: u64 = 111;
 const LIKER_ID: u64 = 222;
 const OTHER_ID: u64 = 333;
@@ -26,7 +72,21 @@ struct TestContext {
 
 impl TestContext {
     async fn new() -> Self {
-        let config = shared_test_config();
+        Self::with_config(|_| {}).await
+    }
+
+    // Context whose state uses a tweaked copy of the shared test config
+    async fn with_config(customize: impl FnOnce(&mut Config)) -> Self {
+        Self::with_config_and_loader(customize, || Ok((*shared_test_config()).clone())).await
+    }
+
+    async fn with_config_and_loader(
+        customize: impl FnOnce(&mut Config),
+        config_loader: fn() -> anyhow::Result<Config>,
+    ) -> Self {
+        let mut config = (*shared_test_config()).clone();
+        customize(&mut config);
+        let config = Arc::new(config);
         let pool = SqlitePoolOptions::new()
             .max_connections(1)
             .connect("sqlite::memory:")
@@ -66,25 +126,30 @@ impl TestContext {
             .await
             .expect("failed to run migrations");
 
-        let cache = Arc::new(DashMap::new());
-        cache.insert(OWNER_TICKET.to_string(), OWNER_ID);
-        cache.insert(LIKER_TICKET.to_string(), LIKER_ID);
-        cache.insert(OTHER_TICKET.to_string(), OTHER_ID);
-
-        let state = AppState {
-            db: pool.clone(),
-            cache,
-            http: Client::builder().build().expect("failed to build client"),
-            steam_key: "test".to_string(),
-            config: config.clone(),
-            post_structure_rate_limiter: Arc::new(DashMap::new()),
-            get_structure_rate_limiter: Arc::new(DashMap::new()),
-            post_like_rate_limiter: Arc::new(DashMap::new()),
-            global_stats_rate_limiter: Arc::new(DashMap::new()),
-            user_stats_rate_limiter: Arc::new(DashMap::new()),
-            global_stats_cache: Arc::new(RwLock::new(None)),
-        };
+        let http = Client::builder().build().expect("failed to build client");
+        let auth = auth::provider(config.auth_provider, http.clone(), "test".to_string());
+        let (state, post_receiver) = AppState::new(
+            config,
+            config_loader,
+            pool.clone(),
+            pool.clone(),
+            http,
+            auth,
+            "test".to_string(),
+        );
+        state
+            .cache
+            .insert(OWNER_TICKET.to_string(), (OWNER_ID, TEST_APPID));
+        state
+            .cache
+            .insert(LIKER_TICKET.to_string(), (LIKER_ID, TEST_APPID));
+        state
+            .cache
+            .insert(OTHER_TICKET.to_string(), (OTHER_ID, TEST_APPID));
 
+        if let Some(receiver) = post_receiver {
+            tokio::spawn(write_queued_posts(state.clone(), receiver));
+        }
         let app = build_router(state.clone());
 
         Self { state, app }
@@ -144,6 +209,22 @@ impl TestContext {
             .expect("POST /like request failed")
     }
 
+    async fn get_nearby(&self, ticket: &str, query: &str) -> axum::http::Response<Body> {
+        let uri = format!("/api/v1/structures/nearby{query}");
+        self.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, ticket)
+                    .body(Body::empty())
+                    .expect("failed to build nearby request"),
+            )
+            .await
+            .expect("GET /nearby request failed")
+    }
+
     async fn get_global_stats(&self, ticket: &str) -> axum::http::Response<Body> {
         self.app
             .clone()
@@ -174,6 +255,105 @@ impl TestContext {
             .expect("GET /stats/me request failed")
     }
 
+    async fn user_request(
+        &self,
+        ticket: &str,
+        method: Method,
+        uri: &str,
+    ) -> axum::http::Response<Body> {
+        self.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, ticket)
+                    .body(Body::empty())
+                    .expect("failed to build user request"),
+            )
+            .await
+            .expect("user request failed")
+    }
+
+    async fn user_post(&self, ticket: &str, uri: &str, body: Value) -> axum::http::Response<Body> {
+        self.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, ticket)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .expect("failed to build user request"),
+            )
+            .await
+            .expect("user request failed")
+    }
+
+    async fn user_patch(
+        &self,
+        ticket: &str,
+        uri: &str,
+        if_match: &str,
+        body: Value,
+    ) -> axum::http::Response<Body> {
+        self.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::PATCH)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, ticket)
+                    .header("content-type", "application/json")
+                    .header("if-match", if_match)
+                    .body(Body::from(body.to_string()))
+                    .expect("failed to build user request"),
+            )
+            .await
+            .expect("user request failed")
+    }
+
+    async fn admin_request(
+        &self,
+        method: Method,
+        uri: &str,
+        admin_key: Option<&str>,
+        body: Body,
+    ) -> axum::http::Response<Body> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(key) = admin_key {
+            builder = builder.header(&ADMIN_HEADER, key);
+        }
+        self.app
+            .clone()
+            .oneshot(builder.body(body).expect("failed to build admin request"))
+            .await
+            .expect("admin request failed")
+    }
+
+    async fn export_dump(&self) -> String {
+        let response = self
+            .admin_request(
+                Method::GET,
+                "/admin/v1/export",
+                Some(ADMIN_KEY),
+                Body::empty(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response_text(response).await
+    }
+
+    // Serves the router on an ephemeral local port for clients that need a real socket
+    async fn spawn_server(&self) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(self.app.clone(), BoundListener::Tcp(listener), None));
+        port
+    }
+
     fn clear_post_rate_limit(&self, steam_id: u64) {
         self.state.post_structure_rate_limiter.remove(&steam_id);
     }
@@ -188,28 +368,133 @@ impl TestContext {
 }
 
 fn shared_test_config() -> Arc<Config> {
-    CONFIG
+    TEST_CONFIG
         .get_or_init(|| {
             Arc::new(Config {
-                steam_appid: 0,
+                steam_appids: vec![TEST_APPID],
+                separate_appids: false,
+                read_only: false,
+                startup_self_check: true,
+                startup_integrity_check: false,
+                slow_request_threshold: Duration::ZERO,
+                enable_post: true,
+                enable_likes: true,
+                enable_fetch: true,
+                min_client_version: None,
+                server_password: None,
+                invite_only: false,
+                whitelist_mode: false,
                 max_user_structs_saved_per_scene: 2,
+                prune_policy: PrunePolicy::Oldest,
+                max_pinned_per_scene: 1,
+                max_favorites_per_user: 200,
+                max_contracts_per_user: 20,
                 max_requested_structs: 4,
                 post_structure_rate_limit: Duration::from_millis(100),
+                new_player_burst_posts: 0,
+                daily_upload_quota: 0,
                 get_structure_rate_limit: Duration::from_millis(100),
                 post_like_rate_limit: Duration::from_millis(100),
+                rate_limit_exempt_steamids: Vec::new(),
+                adaptive_rate_limits: false,
+                adaptive_rate_limit_target: Duration::from_millis(50),
+                adaptive_rate_limit_min_factor: 0.5,
+                adaptive_rate_limit_max_factor: 4.0,
+                like_daily_budget: 500,
+                like_target_daily_cap: 100,
+                like_suspicious_threshold: 200,
+                likes_ledger_retention_days: 90,
+                usage_rate_limit: Duration::from_secs(30),
+                nearby_rate_limit: Duration::from_millis(100),
+                nearby_max_radius: 50.0,
+                density_radius: 5.0,
+                density_max_structures: 0,
+                min_own_structure_distance: 0.0,
+                plausibility_checks: PlausibilityMode::Off,
+                plausibility_burst_posts: 100,
+                plausibility_burst_window: Duration::from_secs(10),
+                newcomer_review_count: 0,
+                newcomer_auto_approve: Duration::from_secs(86_400),
+                duplicate_window: Duration::from_secs(300),
+                user_restore_window: Duration::from_secs(3600),
                 global_stats_rate_limit: Duration::from_millis(100),
                 user_stats_rate_limit: Duration::from_millis(100),
                 global_stats_cache_ttl: Duration::from_secs(600),
+                scene_stats_cache_ttl: Duration::from_secs(60),
                 default_random_limit: 3,
                 max_scene_length: 16,
+                max_segment: 10,
                 database_url: "sqlite::memory:".to_string(),
-                server_port: 0,
-                skip_steam_ticket_validation: true,
+                db_max_connections: 1,
+                sqlite_cache_size: -2000,
+                sqlite_mmap_size: 0,
+                sqlite_wal_autocheckpoint: 1000,
+                wal_checkpoint_interval: Duration::ZERO,
+                listen: ListenAddr::Tcp("127.0.0.1:0".to_string()),
+                unix_socket_mode: None,
+                trusted_proxies: Vec::new(),
+                cors_allowed_origins: Vec::new(),
+                cors_allowed_methods: vec!["GET".to_string()],
+                cors_allowed_headers: vec!["x-steam-auth".to_string(), "content-type".to_string()],
+                ws_max_subscriptions: 2,
+                max_body_bytes: 4096,
+                request_timeout: Duration::from_secs(30),
+                max_concurrent_requests: 0,
+                max_concurrent_fetches: 0,
+                random_sample_refresh: Duration::ZERO,
+                random_probe_min_rows: 0,
+                random_batch_ttl: Duration::ZERO,
+                like_staleness: Duration::from_secs(1),
+                fetch_session_ttl: Duration::from_secs(600),
+                post_queue_capacity: 0,
+                post_queue_batch_size: 64,
+                like_flush_interval: Duration::ZERO,
+                fetch_flush_interval: Duration::from_secs(60),
+                fetch_history_days: 0,
+                notification_digest_window: Duration::from_secs(86_400),
+                auth_provider: AuthProviderKind::Static,
+                auth_shared_secret: None,
+                steam_breaker_failures: 5,
+                steam_breaker_cooldown: Duration::from_secs(30),
+                steam_partner_fallback: false,
+                steam_api_url: steam::PUBLIC_HOST.to_string(),
+                http_pool_max_idle: 8,
+                http_pool_idle_timeout: Duration::from_secs(60),
+                http2: true,
+                require_app_ownership: false,
+                ownership_cache_ttl: Duration::from_secs(86_400),
+                resolve_steam_names: false,
+                profile_cache_ttl: Duration::from_secs(86_400),
+                admin_api_key: Some(ADMIN_KEY.to_string()),
+                server_id: None,
+                federation_keys: Vec::new(),
+                upstream_url: None,
+                upstream_key: None,
+                federation_pull_interval: Duration::from_secs(300),
+                tls_cert_path: None,
+                tls_key_path: None,
+                discord_webhook_url: None,
+                discord_like_milestone: 5,
+                like_milestones: vec![10, 100, 1000],
+                milestone_webhook_url: None,
+                curated_share_percent: 0,
+                likes_decay_days: 0,
+                prefab_fetch_quotas: BTreeMap::new(),
+                prefab_max_share_percent: 0,
+                current_season: 1,
+                structure_ttl_days: 0,
+                archive_cold_after_days: 0,
+                map_overrides: BTreeMap::new(),
+                prefab_attributes: BTreeMap::new(),
+                tenants: Vec::new(),
             })
         })
         .clone()
 }
 
+// Each payload gets its own position so separate uploads never look like duplicates.
+static NEXT_POS_X: AtomicU32 = AtomicU32::new(1);
+
 fn structure_payload(
     username: &str,
     scene: &str,
@@ -217,13 +502,14 @@ fn structure_payload(
     segment: i32,
     prefab: &str,
 ) -> Value {
+    let pos_x = NEXT_POS_X.fetch_add(1, Ordering::Relaxed) as f32;
     json!({
         "username": username,
         "map_id": map_id,
         "scene": scene,
         "segment": segment,
         "prefab": prefab,
-        "pos_x": 1.0,
+        "pos_x": pos_x,
         "pos_y": 2.0,
         "pos_z": 3.0,
         "rot_x": 0.0,
@@ -248,6 +534,16 @@ fn structure_payload(
     })
 }
 
+async fn response_text(response: axum::http::Response<Body>) -> String {
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("failed to collect body")
+        .to_bytes();
+    String::from_utf8(bytes.to_vec()).expect("body is not utf-8")
+}
+
 async fn response_json(response: axum::http::Response<Body>) -> Value {
     let bytes = response
         .into_body()
@@ -258,6 +554,7 @@ async fn response_json(response: axum::http::Response<Body>) -> Value {
     serde_json::from_slice(&bytes).expect("failed to parse json")
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn create_structure(
     ctx: &TestContext,
     ticket: &str,
@@ -305,6 +602,86 @@ async fn post_structure_blocks_when_rate_limited() {
     assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
 }
 
+#[tokio::test]
+async fn new_players_post_past_the_rate_limit_on_their_burst_credit_once() {
+    let ctx = TestContext::with_config(|config| {
+        config.new_player_burst_posts = 2;
+        config.post_structure_rate_limit = Duration::from_secs(60);
+    })
+    .await;
+    let post = |segment: i32| {
+        ctx.post_structure(
+            OWNER_TICKET,
+            structure_payload("Sam", "SceneBurst", 1, segment, "prefab_burst"),
+        )
+    };
+    for segment in 0..3 {
+        assert_eq!(post(segment).await.status(), StatusCode::OK);
+    }
+    assert_eq!(post(3).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // the credit does not come back once the limit has passed
+    ctx.clear_post_rate_limit(OWNER_ID);
+    assert_eq!(post(4).await.status(), StatusCode::OK);
+    assert_eq!(post(5).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    let used: i64 = sqlx::query_scalar("SELECT burst_posts_used FROM users WHERE user_id = ?")
+        .bind(OWNER_ID as i64)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(used, 2);
+}
+
+#[tokio::test]
+async fn daily_upload_quota_counts_uploads_per_utc_day() {
+    let ctx = TestContext::with_config(|config| config.daily_upload_quota = 2).await;
+    sqlx::query("INSERT INTO user_daily (user_id, day, uploads) VALUES (?, '2000-01-01', 9)")
+        .bind(OWNER_ID as i64)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let mut remaining = Vec::new();
+    for segment in 0..2 {
+        let payload = structure_payload("Sam", "SceneQuota", 1, segment, "prefab_quota");
+        let response = ctx.post_structure(OWNER_TICKET, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        remaining.push(response_json(response).await["remaining_uploads_today"].clone());
+        ctx.clear_post_rate_limit(OWNER_ID);
+    }
+    assert_eq!(remaining, vec![json!(1), json!(0)]);
+
+    let payload = structure_payload("Sam", "SceneQuota", 1, 2, "prefab_quota");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "daily_quota_exceeded");
+    let retry_after = body["retry_after"].as_u64().unwrap();
+    assert!(retry_after > 0 && retry_after <= 86_400);
+
+    let days: Vec<(String, i64)> =
+        sqlx::query_as("SELECT day, uploads FROM user_daily WHERE user_id = ?")
+            .bind(OWNER_ID as i64)
+            .fetch_all(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(days.len(), 1, "earlier days are dropped");
+    assert_eq!(days[0].1, 2);
+
+    // other players have their own quota
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneQuota",
+        1,
+        0,
+        "prefab_quota",
+    )
+    .await;
+}
+
 #[tokio::test]
 async fn post_structure_prunes_oldest_per_user_scene() {
     let ctx = TestContext::new().await;
@@ -335,399 +712,7203 @@ async fn post_structure_prunes_oldest_per_user_scene() {
 }
 
 #[tokio::test]
-async fn requests_missing_steam_header_are_rejected() {
-    let ctx = TestContext::new().await;
-    let payload = structure_payload("Sam", "SceneNoAuth", 1, 0, "prefab_noauth");
-    let response = ctx
-        .app
-        .clone()
-        .oneshot(
-            Request::builder()
-                .method(Method::POST)
-                .uri("/api/v1/structures")
-                .header("content-type", "application/json")
-                .body(Body::from(payload.to_string()))
-                .expect("failed to build unauthenticated request"),
-        )
-        .await
-        .expect("unauthenticated request failed");
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-}
-
-#[tokio::test]
-async fn get_random_applies_limits_and_filters() {
+async fn post_structure_reports_remaining_slots_and_pruned_structure() {
     let ctx = TestContext::new().await;
-    let users = [
-        (OWNER_TICKET, OWNER_ID, "Owner"),
-        (LIKER_TICKET, LIKER_ID, "Liker"),
-        (OTHER_TICKET, OTHER_ID, "Other"),
-    ];
-    let mut prefabs = Vec::new();
-    for (ticket, steam_id, prefix) in users {
-        for segment in 0..2 {
-            let prefab = format!("{prefix}_prefab_{segment}");
-            prefabs.push(prefab.clone());
-            let _ = create_structure(
-                &ctx,
-                ticket,
-                steam_id,
-                &format!("{prefix}_user"),
-                "SceneRandom",
-                1,
-                segment,
-                &prefab,
-            )
-            .await;
-        }
-    }
-
-    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneRandom").await;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = response_json(response).await;
-    let items = body.as_array().expect("array response");
-    assert_eq!(items.len(), ctx.state.config.default_random_limit as usize);
-    for item in items {
-        assert_eq!(item["scene"], "SceneRandom");
-    }
-
-    ctx.clear_get_rate_limit(OWNER_ID);
-    let response = ctx
-        .get_random(OWNER_TICKET, "?scene=SceneRandom&map_id=1&limit=10")
-        .await;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = response_json(response).await;
-    let items = body.as_array().expect("array response");
-    assert_eq!(items.len(), ctx.state.config.max_requested_structs as usize);
-    for item in items {
-        assert_eq!(item["map_id"].as_i64().unwrap(), 1);
+    let mut answers = Vec::new();
+    for segment in 0..3 {
+        let payload = structure_payload("Sam", "SceneQuota", 1, segment, "prefab_quota");
+        let response = ctx.post_structure(OWNER_TICKET, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        answers.push(response_json(response).await);
+        ctx.clear_post_rate_limit(OWNER_ID);
     }
 
-    ctx.clear_get_rate_limit(OWNER_ID);
-    let keep = prefabs.last().unwrap().clone();
-    let exclude = prefabs
-        .iter()
-        .filter(|name| **name != keep)
-        .cloned()
-        .collect::<Vec<_>>()
-        .join(",");
-    let response = ctx
-        .get_random(
-            OWNER_TICKET,
-            &format!("?scene=SceneRandom&map_id=1&limit=10&exclude_prefabs={exclude}"),
-        )
-        .await;
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = response_json(response).await;
-    let items = body.as_array().expect("array response");
-    assert_eq!(items.len(), 1);
-    assert_eq!(items[0]["prefab"].as_str().unwrap(), keep);
-
-    ctx.clear_get_rate_limit(OWNER_ID);
-    let too_long_scene = "X".repeat((ctx.state.config.max_scene_length + 1) as usize);
-    let response = ctx
-        .get_random(OWNER_TICKET, &format!("?scene={too_long_scene}"))
-        .await;
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(answers[0]["remaining_slots"], 1);
+    assert!(answers[0].get("pruned_structure_id").is_none());
+    assert_eq!(answers[1]["remaining_slots"], 0);
+    assert!(answers[1].get("pruned_structure_id").is_none());
+    assert_eq!(answers[2]["remaining_slots"], 0);
+    assert_eq!(answers[2]["pruned_structure_id"], answers[0]["id"]);
 }
 
 #[tokio::test]
-async fn get_random_enforces_rate_limit() {
+async fn prune_policy_can_spare_liked_structures() {
     let ctx = TestContext::new().await;
-    let _ = create_structure(
+    let liked = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "RateUser",
-        "SceneRate",
+        "Sam",
+        "ScenePolicy",
         1,
         0,
-        "prefab_rate",
+        "prefab_bridge",
+    )
+    .await;
+    let unliked = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Sam",
+        "ScenePolicy",
+        1,
+        1,
+        "prefab_ladder",
     )
     .await;
+    let response = ctx
+        .like_structure(LIKER_TICKET, liked, json!({ "count": 5 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    let first = ctx.get_random(OWNER_TICKET, "?scene=SceneRate").await;
-    assert_eq!(first.status(), StatusCode::OK);
-    let second = ctx.get_random(OWNER_TICKET, "?scene=SceneRate").await;
-    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    let mut payload = structure_payload("Sam", "ScenePolicy", 1, 2, "prefab_rope");
+    payload["prune_policy"] = json!("least_liked");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["pruned_structure_id"], unliked);
+
+    ctx.clear_post_rate_limit(OWNER_ID);
+
+    let mut payload = structure_payload("Sam", "ScenePolicy", 1, 3, "prefab_rope");
+    payload["prune_policy"] = json!("most_liked");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 }
 
 #[tokio::test]
-async fn global_stats_returns_values_and_uses_cache() {
-    let ctx = TestContext::new().await;
-    let _owner_structure = create_structure(
+async fn pinned_structures_survive_pruning_and_ttl() {
+    let ctx = TestContext::with_config(|config| config.structure_ttl_days = 1).await;
+    let pinned = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "OwnerStats",
-        "SceneStats",
+        "Sam",
+        "ScenePin",
         1,
         0,
-        "prefab_owner_stats",
+        "prefab_0",
     )
     .await;
-    let _liker_structure = create_structure(
+    let other = create_structure(
         &ctx,
-        LIKER_TICKET,
-        LIKER_ID,
-        "LikerStats",
-        "SceneStats",
+        OWNER_TICKET,
+        OWNER_ID,
+        "Sam",
+        "ScenePin",
         1,
         1,
-        "prefab_liker_stats",
+        "prefab_1",
     )
     .await;
-    let other_structure = create_structure(
-        &ctx,
-        OTHER_TICKET,
-        OTHER_ID,
-        "OtherStats",
-        "SceneStats",
-        1,
-        2,
-        "prefab_other_stats",
-    )
-    .await;
-
-    let now_ms = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("clock should be valid")
-        .as_millis() as i64;
-    let old_ms = now_ms - (2 * MILLIS_IN_DAY);
-    sqlx::query("UPDATE structures SET created_at = ? WHERE id = ?")
-        .bind(old_ms)
-        .bind(other_structure)
-        .execute(&ctx.state.db)
-        .await
-        .unwrap();
 
-    for (user_id, likes_send, likes_received) in [
-        (OWNER_ID, 2_i64, 9_i64),
-        (LIKER_ID, 5_i64, 3_i64),
-        (OTHER_ID, 10_i64, 1_i64),
-    ] {
-        sqlx::query("UPDATE users SET likes_send = ?, likes_received = ? WHERE user_id = ?")
-            .bind(likes_send)
-            .bind(likes_received)
-            .bind(user_id as i64)
-            .execute(&ctx.state.db)
+    let pin = |ticket: &'static str, id: i64| {
+        let ctx = &ctx;
+        async move {
+            ctx.user_request(
+                ticket,
+                Method::POST,
+                &format!("/api/v1/structures/{id}/pin"),
+            )
             .await
-            .unwrap();
-    }
+            .status()
+        }
+    };
+    assert_eq!(pin(LIKER_TICKET, pinned).await, StatusCode::NOT_FOUND);
+    assert_eq!(pin(OWNER_TICKET, pinned).await, StatusCode::NO_CONTENT);
+    // MAX_PINNED_PER_SCENE is 1 in the tests
+    assert_eq!(pin(OWNER_TICKET, other).await, StatusCode::CONFLICT);
 
-    let response = ctx.get_global_stats(OWNER_TICKET).await;
+    // pruning skips the pinned one even though it is the oldest
+    let payload = structure_payload("Sam", "ScenePin", 1, 2, "prefab_2");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
     assert_eq!(response.status(), StatusCode::OK);
-    let body = response_json(response).await;
-
-    assert_eq!(body["total_unique_players_all_time"].as_i64().unwrap(), 3);
-    assert_eq!(
-        body["total_structures_uploaded_all_time"].as_i64().unwrap(),
-        3
-    );
-    assert_eq!(body["total_likes_given_all_time"].as_i64().unwrap(), 17);
-    assert_eq!(body["total_unique_players_last_24h"].as_i64().unwrap(), 2);
-    assert_eq!(
-        body["total_structures_uploaded_last_24h"].as_i64().unwrap(),
-        2
-    );
-    assert_eq!(
-        body["server_version"].as_str().unwrap(),
-        env!("CARGO_PKG_VERSION")
-    );
-
-    let _ = create_structure(
-        &ctx,
-        OTHER_TICKET,
-        OTHER_ID,
-        "OtherStats",
-        "SceneStats",
-        1,
-        3,
-        "prefab_other_stats_2",
-    )
-    .await;
+    assert_eq!(response_json(response).await["pruned_structure_id"], other);
 
-    sqlx::query("UPDATE users SET likes_send = likes_send + 1 WHERE user_id = ?")
-        .bind(OTHER_ID as i64)
+    // and it is still served after STRUCTURE_TTL_DAYS
+    sqlx::query("UPDATE structures SET created_at = created_at - ? WHERE scene = 'ScenePin'")
+        .bind(2 * MILLIS_IN_DAY)
         .execute(&ctx.state.db)
         .await
         .unwrap();
+    let response = ctx.get_random(LIKER_TICKET, "?scene=ScenePin").await;
+    let body = response_json(response).await;
+    let rows = body.as_array().unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["id"], pinned);
+    assert_eq!(rows[0]["pinned"], true);
 
-    ctx.clear_global_stats_rate_limit(OWNER_ID);
-    let cached_response = ctx.get_global_stats(OWNER_TICKET).await;
-    assert_eq!(cached_response.status(), StatusCode::OK);
-    let cached_body = response_json(cached_response).await;
-    assert_eq!(cached_body, body);
-}
-
-#[tokio::test]
-async fn global_stats_enforces_rate_limit() {
-    let ctx = TestContext::new().await;
-    let first = ctx.get_global_stats(OWNER_TICKET).await;
-    assert_eq!(first.status(), StatusCode::OK);
-    let second = ctx.get_global_stats(OWNER_TICKET).await;
-    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = ctx
+        .user_request(
+            OWNER_TICKET,
+            Method::DELETE,
+            &format!("/api/v1/structures/{pinned}/pin"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    ctx.clear_get_rate_limit(LIKER_ID);
+    let response = ctx.get_random(LIKER_TICKET, "?scene=ScenePin").await;
+    assert_eq!(response_json(response).await, json!([]));
 }
 
 #[tokio::test]
-async fn user_stats_returns_values() {
+async fn random_fetches_record_last_fetched_at() {
     let ctx = TestContext::new().await;
-    let first = create_structure(
+    let fetched = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "OwnerUser",
-        "SceneUserStats",
+        "Sam",
+        "SceneFetch",
         1,
         0,
-        "prefab_user_a",
+        "prefab_0",
     )
     .await;
-    let _second = create_structure(
+    let response = ctx.get_random(LIKER_TICKET, "?scene=SceneFetch").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let unseen = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "OwnerUser",
-        "SceneUserStats",
+        "Sam",
+        "SceneFetch",
         1,
         1,
-        "prefab_user_b",
+        "prefab_1",
     )
     .await;
 
-    let now_ms = SystemTime::now()
+    assert!(!ctx.state.fetch_log.is_empty());
+    assert_eq!(ctx.state.fetch_log.flush(&ctx.state.db).await.unwrap(), 1);
+    let last_fetched: Vec<(i64, Option<i64>)> = sqlx::query_as(
+        "SELECT id, last_fetched_at FROM structures WHERE scene = 'SceneFetch' ORDER BY id",
+    )
+    .fetch_all(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(last_fetched[0].0, fetched);
+    assert!(last_fetched[0].1.is_some());
+    assert_eq!(last_fetched[1], (unseen, None));
+
+    let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("clock should be valid")
+        .unwrap()
         .as_millis() as i64;
-    let old_ms = now_ms - (2 * MILLIS_IN_DAY);
-    sqlx::query("UPDATE structures SET created_at = ? WHERE id = ?")
-        .bind(old_ms)
-        .bind(first)
-        .execute(&ctx.state.db)
-        .await
-        .unwrap();
-
-    sqlx::query("UPDATE users SET likes_received = ?, likes_send = ? WHERE user_id = ?")
-        .bind(9_i64)
-        .bind(4_i64)
-        .bind(OWNER_ID as i64)
-        .execute(&ctx.state.db)
-        .await
-        .unwrap();
-
-    let response = ctx.get_user_stats(OWNER_TICKET).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            &format!(
+                "/admin/v1/structures?scene=SceneFetch&not_fetched_since={}",
+                now - 60_000
+            ),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
     let body = response_json(response).await;
+    assert_eq!(body["structures"].as_array().unwrap().len(), 1);
+    assert_eq!(body["structures"][0]["id"], unseen);
 
-    assert_eq!(body["total_structures_uploaded"].as_i64().unwrap(), 2);
-    assert_eq!(body["structures_uploaded_last_24h"].as_i64().unwrap(), 1);
-    assert_eq!(body["total_likes_received"].as_i64().unwrap(), 9);
-    assert_eq!(body["total_likes_sent"].as_i64().unwrap(), 4);
+    // least_recently_fetched makes room with the structure nobody was shown
+    let mut payload = structure_payload("Sam", "SceneFetch", 1, 2, "prefab_2");
+    payload["prune_policy"] = json!("least_recently_fetched");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response_json(response).await["pruned_structure_id"], unseen);
 }
 
 #[tokio::test]
-async fn user_stats_enforces_rate_limit() {
-    let ctx = TestContext::new().await;
-    let first = ctx.get_user_stats(OWNER_TICKET).await;
-    assert_eq!(first.status(), StatusCode::OK);
-    let second = ctx.get_user_stats(OWNER_TICKET).await;
-    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+async fn fetch_history_serves_unseen_structures_first() {
+    let ctx = TestContext::with_config(|config| {
+        config.fetch_history_days = 7;
+        config.max_user_structs_saved_per_scene = 10;
+    })
+    .await;
+    let mut all = Vec::new();
+    for segment in 0..4 {
+        all.push(
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Sam",
+                "SceneHistory",
+                1,
+                segment,
+                "prefab_history",
+            )
+            .await,
+        );
+    }
+    let fetch = async || {
+        ctx.clear_get_rate_limit(LIKER_ID);
+        let response = ctx
+            .get_random(LIKER_TICKET, "?scene=SceneHistory&limit=2")
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut ids: Vec<i64> = response_json(response)
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    let first = fetch().await;
+    assert_eq!(first.len(), 2);
+    // the second fetch reads the history back from the table
+    ctx.state.fetch_log.flush(&ctx.state.db).await.unwrap();
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM fetch_history WHERE user_id = ?")
+        .bind(LIKER_ID as i64)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(stored, 2);
+    let second = fetch().await;
+    assert_eq!(second.len(), 2);
+    let mut both = [first, second].concat();
+    both.sort();
+    assert_eq!(both, all);
+
+    // other players have their own history
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let response = ctx
+        .get_random(OTHER_TICKET, "?scene=SceneHistory&limit=4")
+        .await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 4);
 }
 
 #[tokio::test]
-async fn like_structure_updates_counts_and_clamps() {
+async fn creator_stats_are_attached_on_request() {
     let ctx = TestContext::new().await;
-    let structure_id = create_structure(
+    let liked = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "Owner",
-        "SceneLike",
+        "Sam",
+        "SceneCreator",
         1,
         0,
-        "prefab_like",
+        "prefab_1",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Sam",
+        "SceneOther",
+        1,
+        0,
+        "prefab_1",
     )
     .await;
-
     let response = ctx
-        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 150 }))
+        .like_structure(LIKER_TICKET, liked, json!({ "count": 3 }))
         .await;
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    let likes = sqlx::query_scalar::<_, i64>("SELECT likes FROM structures WHERE id = ?")
-        .bind(structure_id)
-        .fetch_one(&ctx.state.db)
-        .await
-        .unwrap();
-    assert_eq!(likes, 100);
-
-    let (likes_send,) =
-        sqlx::query_as::<_, (i64,)>("SELECT likes_send FROM users WHERE user_id = ?")
-            .bind(LIKER_ID as i64)
-            .fetch_one(&ctx.state.db)
-            .await
-            .unwrap();
-    assert_eq!(likes_send, 100);
+    let rows = response_json(ctx.get_random(OTHER_TICKET, "?scene=SceneCreator").await).await;
+    assert!(rows[0].get("creator").is_none());
 
-    let (likes_received,) =
-        sqlx::query_as::<_, (i64,)>("SELECT likes_received FROM users WHERE user_id = ?")
-            .bind(OWNER_ID as i64)
-            .fetch_one(&ctx.state.db)
-            .await
-            .unwrap();
-    assert_eq!(likes_received, 100);
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let rows = response_json(
+        ctx.get_random(OTHER_TICKET, "?scene=SceneCreator&creator_stats=true")
+            .await,
+    )
+    .await;
+    assert_eq!(
+        rows[0]["creator"],
+        json!({ "likes_received": 3, "structures": 2 })
+    );
 }
 
 #[tokio::test]
-async fn like_structure_rejects_self_likes() {
-    let ctx = TestContext::new().await;
-    let structure_id = create_structure(
+async fn favorite_builders_are_always_included() {
+    let ctx = TestContext::with_config(|config| config.max_user_structs_saved_per_scene = 10).await;
+    for segment in 0..6 {
+        create_structure(
+            &ctx,
+            OTHER_TICKET,
+            OTHER_ID,
+            "Kim",
+            "SceneFav",
+            1,
+            segment,
+            "prefab_1",
+        )
+        .await;
+    }
+    let bookmarked = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "Owner",
-        "SceneSelf",
+        "Sam",
+        "SceneBook",
         1,
         0,
-        "prefab_self",
+        "prefab_1",
     )
     .await;
-
-    let response = ctx
-        .like_structure(OWNER_TICKET, structure_id, json!({ "count": 1 }))
-        .await;
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-}
-
-#[tokio::test]
-async fn like_structure_enforces_rate_limit() {
-    let ctx = TestContext::new().await;
-    let structure_id = create_structure(
+    let by_favorite = create_structure(
         &ctx,
         OWNER_TICKET,
         OWNER_ID,
-        "Owner",
-        "SceneLikeLimit",
+        "Sam",
+        "SceneFav",
         1,
         0,
-        "prefab_like_limit",
+        "prefab_1",
     )
     .await;
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("/api/v1/users/me/favorites/{bookmarked}"))
+                .header(&STEAM_HEADER, LIKER_TICKET)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    let first = ctx
-        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 1 }))
-        .await;
-    assert_eq!(first.status(), StatusCode::NO_CONTENT);
-    let second = ctx
-        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 1 }))
+    for _ in 0..5 {
+        ctx.clear_get_rate_limit(LIKER_ID);
+        let rows = response_json(
+            ctx.get_random(
+                LIKER_TICKET,
+                "?scene=SceneFav&limit=2&favorite_builders=true",
+            )
+            .await,
+        )
         .await;
-    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        let rows = rows.as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["id"], by_favorite);
+        assert_ne!(rows[1]["id"], by_favorite);
+    }
 }
 
 #[tokio::test]
-async fn like_structure_fails_for_missing_structure() {
-    let ctx = TestContext::new().await;
-    let response = ctx
-        .like_structure(LIKER_TICKET, 999, json!({ "count": 1 }))
+async fn contracts_link_both_players_once_both_offer() {
+    let ctx = TestContext::with_config(|config| config.max_user_structs_saved_per_scene = 10).await;
+    for segment in 0..6 {
+        create_structure(
+            &ctx,
+            LIKER_TICKET,
+            LIKER_ID,
+            "Lee",
+            "SceneContract",
+            1,
+            segment,
+            "prefab_1",
+        )
         .await;
-    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let partners = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Sam",
+        "SceneContract",
+        1,
+        0,
+        "prefab_1",
+    )
+    .await;
+    let send = async |method: Method, ticket: &str, uri: String| {
+        ctx.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, ticket)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    };
+
+    let response = send(
+        Method::POST,
+        OWNER_TICKET,
+        format!("/api/v1/contracts/{OWNER_ID}"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(
+        Method::POST,
+        OWNER_TICKET,
+        format!("/api/v1/contracts/{OTHER_ID}"),
+    )
+    .await;
+    assert_eq!(
+        response_json(response).await,
+        json!({ "partner_id": OTHER_ID, "status": "offered" })
+    );
+    let listed =
+        response_json(send(Method::GET, OTHER_TICKET, "/api/v1/contracts".to_string()).await).await;
+    assert_eq!(listed["contracts"][0]["partner_id"], OWNER_ID);
+    assert_eq!(listed["contracts"][0]["status"], "received");
+    let response = send(
+        Method::POST,
+        OTHER_TICKET,
+        format!("/api/v1/contracts/{OWNER_ID}"),
+    )
+    .await;
+    assert_eq!(response_json(response).await["status"], "active");
+
+    // the partner's structures lead the other's fetches
+    for _ in 0..3 {
+        ctx.clear_get_rate_limit(OTHER_ID);
+        let rows = response_json(
+            ctx.get_random(OTHER_TICKET, "?scene=SceneContract&limit=2")
+                .await,
+        )
+        .await;
+        assert_eq!(rows[0]["id"], partners);
+        assert_ne!(rows[1]["id"], partners);
+    }
+
+    // and likes on them reach the other partner too
+    let mut events = ctx.state.events.subscribe_user(OTHER_ID);
+    let response = ctx
+        .like_structure(LIKER_TICKET, partners, json!({ "count": 2 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    match events.try_recv().unwrap() {
+        UserEvent::PartnerLikeReceived {
+            partner_id,
+            structure_id,
+            count,
+            likes,
+            ..
+        } => assert_eq!(
+            (partner_id, structure_id, count, likes),
+            (OWNER_ID as i64, partners, 2, 2)
+        ),
+        other => panic!("unexpected event {other:?}"),
+    }
+
+    let uri = format!("/api/v1/contracts/{OWNER_ID}");
+    let response = send(Method::DELETE, OTHER_TICKET, uri.clone()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(Method::DELETE, OTHER_TICKET, uri).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let listed =
+        response_json(send(Method::GET, OWNER_TICKET, "/api/v1/contracts".to_string()).await).await;
+    assert_eq!(listed["contracts"], json!([]));
+}
+
+#[tokio::test]
+async fn builders_structures_are_listed_newest_first_without_a_ticket() {
+    let ctx = TestContext::new().await;
+    let mut ids = Vec::new();
+    for (scene, segment) in [("SceneA", 0), ("SceneA", 1), ("SceneB", 0), ("SceneB", 1)] {
+        ids.push(
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Sam",
+                scene,
+                1,
+                segment,
+                "prefab_1",
+            )
+            .await,
+        );
+    }
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Kim",
+        "SceneA",
+        1,
+        0,
+        "prefab_1",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET deleted = 1 WHERE id = ?")
+        .bind(ids[3])
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let list = async |uri: String| {
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response_json(response).await
+    };
+    let listed = |page: &Value| -> Vec<i64> {
+        page["structures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect()
+    };
+
+    let page = list(format!("/api/v1/users/{OWNER_ID}/structures?limit=2")).await;
+    assert_eq!(listed(&page), vec![ids[2], ids[1]]);
+    let next = page["next_before_id"].as_i64().unwrap();
+    let page = list(format!(
+        "/api/v1/users/{OWNER_ID}/structures?limit=2&before_id={next}"
+    ))
+    .await;
+    assert_eq!(listed(&page), vec![ids[0]]);
+    assert_eq!(page["next_before_id"], Value::Null);
+
+    let page = list(format!("/api/v2/users/{OWNER_ID}/structures?scene=SceneB")).await;
+    assert_eq!(listed(&page), vec![ids[2]]);
+    assert!(page["structures"][0]["position"].is_array());
+}
+
+#[tokio::test]
+async fn favorites_are_capped_and_listed_newest_first() {
+    let ctx = TestContext::with_config(|config| config.max_favorites_per_user = 2).await;
+    let mut ids = Vec::new();
+    for (scene, segment) in [("SceneA", 0), ("SceneA", 1), ("SceneB", 0)] {
+        ids.push(
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Sam",
+                scene,
+                1,
+                segment,
+                "prefab_1",
+            )
+            .await,
+        );
+    }
+    let send = async |method: Method, uri: String| {
+        ctx.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(&STEAM_HEADER, LIKER_TICKET)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    };
+    let favorite = async |id: i64| {
+        send(Method::POST, format!("/api/v1/users/me/favorites/{id}"))
+            .await
+            .status()
+    };
+    let listed = async |uri: &str| -> Vec<i64> {
+        response_json(send(Method::GET, uri.to_string()).await).await["structures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect()
+    };
+
+    assert_eq!(favorite(ids[0]).await, StatusCode::NO_CONTENT);
+    assert_eq!(favorite(ids[1]).await, StatusCode::NO_CONTENT);
+    assert_eq!(favorite(ids[1]).await, StatusCode::NO_CONTENT);
+    assert_eq!(favorite(ids[2]).await, StatusCode::CONFLICT);
+    assert_eq!(favorite(999_999).await, StatusCode::NOT_FOUND);
+    assert_eq!(
+        listed("/api/v1/users/me/favorites").await,
+        vec![ids[1], ids[0]]
+    );
+
+    // a deleted structure drops out and frees its slot
+    sqlx::query("UPDATE structures SET deleted = 1 WHERE id = ?")
+        .bind(ids[1])
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(favorite(ids[2]).await, StatusCode::NO_CONTENT);
+    let page = response_json(
+        send(
+            Method::GET,
+            "/api/v2/users/me/favorites?limit=1".to_string(),
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(page["structures"][0]["id"], ids[2]);
+    assert!(page["structures"][0]["position"].is_array());
+    let next = page["next_before"].as_i64().unwrap();
+    assert_eq!(
+        listed(&format!("/api/v1/users/me/favorites?before={next}")).await,
+        vec![ids[0]]
+    );
+
+    let response = send(
+        Method::DELETE,
+        format!("/api/v1/users/me/favorites/{}", ids[0]),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(
+        Method::DELETE,
+        format!("/api/v1/users/me/favorites/{}", ids[0]),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(listed("/api/v1/users/me/favorites").await, vec![ids[2]]);
+}
+
+#[tokio::test]
+async fn requests_missing_steam_header_are_rejected() {
+    let ctx = TestContext::new().await;
+    let payload = structure_payload("Sam", "SceneNoAuth", 1, 0, "prefab_noauth");
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/api/v1/structures")
+                .header("content-type", "application/json")
+                .body(Body::from(payload.to_string()))
+                .expect("failed to build unauthenticated request"),
+        )
+        .await
+        .expect("unauthenticated request failed");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn get_random_applies_limits_and_filters() {
+    let ctx = TestContext::new().await;
+    let users = [
+        (OWNER_TICKET, OWNER_ID, "Owner"),
+        (LIKER_TICKET, LIKER_ID, "Liker"),
+        (OTHER_TICKET, OTHER_ID, "Other"),
+    ];
+    let mut prefabs = Vec::new();
+    for (ticket, steam_id, prefix) in users {
+        for segment in 0..2 {
+            let prefab = format!("{prefix}_prefab_{segment}");
+            prefabs.push(prefab.clone());
+            let _ = create_structure(
+                &ctx,
+                ticket,
+                steam_id,
+                &format!("{prefix}_user"),
+                "SceneRandom",
+                1,
+                segment,
+                &prefab,
+            )
+            .await;
+        }
+    }
+
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneRandom").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let items = body.as_array().expect("array response");
+    assert_eq!(
+        items.len(),
+        ctx.state.config().default_random_limit as usize
+    );
+    for item in items {
+        assert_eq!(item["scene"], "SceneRandom");
+    }
+
+    ctx.clear_get_rate_limit(OWNER_ID);
+    let response = ctx
+        .get_random(OWNER_TICKET, "?scene=SceneRandom&map_id=1&limit=10")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let items = body.as_array().expect("array response");
+    assert_eq!(
+        items.len(),
+        ctx.state.config().max_requested_structs as usize
+    );
+    for item in items {
+        assert_eq!(item["map_id"].as_i64().unwrap(), 1);
+    }
+
+    ctx.clear_get_rate_limit(OWNER_ID);
+    let keep = prefabs.last().unwrap().clone();
+    let exclude = prefabs
+        .iter()
+        .filter(|name| **name != keep)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(",");
+    let response = ctx
+        .get_random(
+            OWNER_TICKET,
+            &format!("?scene=SceneRandom&map_id=1&limit=10&exclude_prefabs={exclude}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let items = body.as_array().expect("array response");
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["prefab"].as_str().unwrap(), keep);
+
+    ctx.clear_get_rate_limit(OWNER_ID);
+    let too_long_scene = "X".repeat((ctx.state.config().max_scene_length + 1) as usize);
+    let response = ctx
+        .get_random(OWNER_TICKET, &format!("?scene={too_long_scene}"))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn seeded_random_fetches_agree_across_players() {
+    let ctx = TestContext::new().await;
+    for (ticket, steam_id) in [
+        (OWNER_TICKET, OWNER_ID),
+        (LIKER_TICKET, LIKER_ID),
+        (OTHER_TICKET, OTHER_ID),
+    ] {
+        for segment in 0..2 {
+            create_structure(
+                &ctx,
+                ticket,
+                steam_id,
+                "Builder",
+                "SceneSeed",
+                1,
+                segment,
+                &format!("prefab_{steam_id}_{segment}"),
+            )
+            .await;
+        }
+    }
+
+    let fetch = |ticket: &'static str, steam_id: u64, seed: &'static str| {
+        let ctx = &ctx;
+        async move {
+            ctx.clear_get_rate_limit(steam_id);
+            let response = ctx
+                .get_random(ticket, &format!("?scene=SceneSeed&limit=4&seed={seed}"))
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response)
+                .await
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["id"].as_i64().unwrap())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let host = fetch(OWNER_TICKET, OWNER_ID, "lobby-1").await;
+    assert_eq!(host.len(), 4);
+    assert_eq!(fetch(LIKER_TICKET, LIKER_ID, "lobby-1").await, host);
+    assert_eq!(fetch(OTHER_TICKET, OTHER_ID, "lobby-1").await, host);
+    assert_ne!(fetch(OWNER_TICKET, OWNER_ID, "lobby-2").await, host);
+}
+
+#[tokio::test]
+async fn fetch_sessions_serve_one_snapshot_to_the_lobby() {
+    let ctx = TestContext::new().await;
+    for (ticket, steam_id) in [(OWNER_TICKET, OWNER_ID), (LIKER_TICKET, LIKER_ID)] {
+        for segment in 0..2 {
+            create_structure(
+                &ctx,
+                ticket,
+                steam_id,
+                "Builder",
+                "SceneSession",
+                1,
+                segment,
+                &format!("prefab_{steam_id}_{segment}"),
+            )
+            .await;
+        }
+    }
+
+    let response = ctx
+        .user_post(
+            OWNER_TICKET,
+            "/api/v1/fetch-sessions",
+            json!({ "scene": "SceneSession", "limit": 3 }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let created = response_json(response).await;
+    assert_eq!(created["size"], 3);
+    assert_eq!(created["expires_in"], 600);
+    let session_id = created["session_id"].as_str().unwrap();
+
+    // uploads after the pick don't change what the lobby sees
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Late",
+        "SceneSession",
+        1,
+        0,
+        "prefab_late",
+    )
+    .await;
+
+    let ids = |body: Value| {
+        body.as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let response = ctx
+        .user_request(
+            LIKER_TICKET,
+            Method::GET,
+            &format!("/api/v1/fetch-sessions/{session_id}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let liker = ids(response_json(response).await);
+    assert_eq!(liker.len(), 3);
+    let response = ctx
+        .user_request(
+            OTHER_TICKET,
+            Method::GET,
+            &format!("/api/v2/fetch-sessions/{session_id}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert!(body[0]["position"].is_array());
+    assert_eq!(ids(body), liker);
+
+    let response = ctx
+        .user_request(OTHER_TICKET, Method::GET, "/api/v1/fetch-sessions/unknown")
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn get_random_enforces_rate_limit() {
+    let ctx = TestContext::new().await;
+    let _ = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "RateUser",
+        "SceneRate",
+        1,
+        0,
+        "prefab_rate",
+    )
+    .await;
+
+    let first = ctx.get_random(OWNER_TICKET, "?scene=SceneRate").await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = ctx.get_random(OWNER_TICKET, "?scene=SceneRate").await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[derive(Clone, Default)]
+struct LogCapture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogCapture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LogCapture {
+    fn request_lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains("request user_id="))
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn access_log_writes_one_line_per_request() {
+    let ctx = TestContext::new().await;
+    let capture = LogCapture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let first = ctx.get_random(OWNER_TICKET, "?scene=SceneLog").await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = ctx.get_random(OWNER_TICKET, "?scene=SceneLog").await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    let anonymous = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/structures?scene=SceneLog")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+    let lines = capture.request_lines();
+    assert_eq!(lines.len(), 3, "{lines:#?}");
+    let url = "url=/api/v1/structures?scene=SceneLog";
+    assert!(lines[0].contains(" INFO "), "{}", lines[0]);
+    assert!(
+        lines[0].contains(&format!("user_id={OWNER_ID} method=GET {url} status=200")),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[1].contains(" WARN "), "{}", lines[1]);
+    assert!(
+        lines[1].contains(&format!("user_id={OWNER_ID} method=GET {url} status=429")),
+        "{}",
+        lines[1]
+    );
+    assert!(lines[1].ends_with("code=rate_limited"), "{}", lines[1]);
+    assert!(
+        lines[2].contains(&format!("user_id=- method=GET {url} status=401")),
+        "{}",
+        lines[2]
+    );
+    assert!(
+        lines[2].ends_with("code=missing_credential"),
+        "{}",
+        lines[2]
+    );
+}
+
+#[test]
+fn client_ip_skips_trusted_proxies_in_forwarded_chain() {
+    use axum::{extract::ConnectInfo, http::HeaderMap};
+    use std::net::{IpAddr, SocketAddr};
+
+    let trusted: Vec<Cidr> = ["10.0.0.0/8", "127.0.0.1", "fd00::/8"]
+        .iter()
+        .map(|cidr| cidr.parse().unwrap())
+        .collect();
+    let peer = |addr: &str| ConnectInfo(addr.parse::<SocketAddr>().unwrap());
+    let forwarded = |value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    };
+    let ip = |addr: &str| Some(addr.parse::<IpAddr>().unwrap());
+
+    let chain = forwarded("198.51.100.1, 203.0.113.7, 10.1.2.3");
+    // an untrusted peer is the client, whatever it claims
+    assert_eq!(
+        client_ip::resolve(&trusted, Some(&peer("192.0.2.9:5000")), &chain),
+        ip("192.0.2.9")
+    );
+    // behind two trusted hops the first untrusted one from the right wins
+    assert_eq!(
+        client_ip::resolve(&trusted, Some(&peer("127.0.0.1:5000")), &chain),
+        ip("203.0.113.7")
+    );
+    assert_eq!(
+        client_ip::resolve(
+            &trusted,
+            Some(&peer("[::ffff:10.0.0.1]:5000")),
+            &forwarded("[2001:db8::5]:4711")
+        ),
+        ip("2001:db8::5")
+    );
+    assert_eq!(
+        client_ip::resolve(
+            &trusted,
+            Some(&peer("[fd00::1]:5000")),
+            &forwarded("not-an-ip, 10.9.9.9")
+        ),
+        ip("10.9.9.9")
+    );
+    assert_eq!(
+        client_ip::resolve(&[], Some(&peer("127.0.0.1:5000")), &chain),
+        ip("127.0.0.1")
+    );
+    // unix socket peers are local proxies
+    assert_eq!(
+        client_ip::resolve(&[], None, &forwarded("203.0.113.7")),
+        ip("203.0.113.7")
+    );
+    assert_eq!(client_ip::resolve(&[], None, &HeaderMap::new()), None);
+
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!(
+        "0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(ip("8.8.8.8").unwrap())
+    );
+    assert!(
+        !"10.0.0.0/8"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(ip("11.0.0.1").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn access_log_names_the_forwarded_client() {
+    let ctx = TestContext::with_config(|config| {
+        config.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    let capture = LogCapture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    for peer in ["127.0.0.1:40000", "192.0.2.9:40000"] {
+        let mut request = Request::builder()
+            .uri("/api/v1/scenes")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(axum::extract::ConnectInfo(
+            peer.parse::<std::net::SocketAddr>().unwrap(),
+        ));
+        let response = ctx.app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let lines = capture.request_lines();
+    assert_eq!(lines.len(), 2, "{lines:#?}");
+    assert!(lines[0].ends_with(" ip=203.0.113.7"), "{}", lines[0]);
+    assert!(lines[1].ends_with(" ip=192.0.2.9"), "{}", lines[1]);
+}
+
+#[tokio::test]
+async fn slow_requests_are_logged_with_db_and_steam_time() {
+    let ctx = TestContext::with_config(|config| {
+        // every request counts as slow
+        config.slow_request_threshold = Duration::from_nanos(1);
+    })
+    .await;
+    let capture = LogCapture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneSlow").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut next = (*ctx.state.config()).clone();
+    next.slow_request_threshold = Duration::from_secs(60);
+    ctx.state.config.store(Arc::new(next));
+    ctx.clear_get_rate_limit(OWNER_ID);
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneSlow").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let lines = capture.request_lines();
+    assert_eq!(lines.len(), 2, "{lines:#?}");
+    assert!(lines[0].contains(" WARN "), "{}", lines[0]);
+    assert!(lines[0].contains("url=/api/v1/structures?scene=SceneSlow status=200"));
+    assert!(lines[0].contains(" slow=true db_ms="), "{}", lines[0]);
+    assert!(
+        lines[0].contains(" db_calls=1 steam_ms=0 steam_calls=0"),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[1].contains(" INFO "), "{}", lines[1]);
+    assert!(!lines[1].contains("slow="), "{}", lines[1]);
+}
+
+#[tokio::test]
+async fn global_stats_returns_values_and_uses_cache() {
+    let ctx = TestContext::new().await;
+    let _owner_structure = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "OwnerStats",
+        "SceneStats",
+        1,
+        0,
+        "prefab_owner_stats",
+    )
+    .await;
+    let _liker_structure = create_structure(
+        &ctx,
+        LIKER_TICKET,
+        LIKER_ID,
+        "LikerStats",
+        "SceneStats",
+        1,
+        1,
+        "prefab_liker_stats",
+    )
+    .await;
+    let other_structure = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "OtherStats",
+        "SceneStats",
+        1,
+        2,
+        "prefab_other_stats",
+    )
+    .await;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should be valid")
+        .as_millis() as i64;
+    let old_ms = now_ms - (2 * MILLIS_IN_DAY);
+    sqlx::query("UPDATE structures SET created_at = ? WHERE id = ?")
+        .bind(old_ms)
+        .bind(other_structure)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    for (user_id, likes_send, likes_received) in [
+        (OWNER_ID, 2_i64, 9_i64),
+        (LIKER_ID, 5_i64, 3_i64),
+        (OTHER_ID, 10_i64, 1_i64),
+    ] {
+        sqlx::query("UPDATE users SET likes_send = ?, likes_received = ? WHERE user_id = ?")
+            .bind(likes_send)
+            .bind(likes_received)
+            .bind(user_id as i64)
+            .execute(&ctx.state.db)
+            .await
+            .unwrap();
+    }
+
+    let response = ctx.get_global_stats(OWNER_TICKET).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+
+    assert_eq!(body["total_unique_players_all_time"].as_i64().unwrap(), 3);
+    assert_eq!(
+        body["total_structures_uploaded_all_time"].as_i64().unwrap(),
+        3
+    );
+    assert_eq!(body["total_likes_given_all_time"].as_i64().unwrap(), 17);
+    assert_eq!(body["total_unique_players_last_24h"].as_i64().unwrap(), 2);
+    assert_eq!(
+        body["total_structures_uploaded_last_24h"].as_i64().unwrap(),
+        2
+    );
+    assert_eq!(
+        body["server_version"].as_str().unwrap(),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let _ = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "OtherStats",
+        "SceneStats",
+        1,
+        3,
+        "prefab_other_stats_2",
+    )
+    .await;
+
+    sqlx::query("UPDATE users SET likes_send = likes_send + 1 WHERE user_id = ?")
+        .bind(OTHER_ID as i64)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    ctx.clear_global_stats_rate_limit(OWNER_ID);
+    let cached_response = ctx.get_global_stats(OWNER_TICKET).await;
+    assert_eq!(cached_response.status(), StatusCode::OK);
+    let cached_body = response_json(cached_response).await;
+    assert_eq!(cached_body, body);
+}
+
+#[tokio::test]
+async fn global_stats_enforces_rate_limit() {
+    let ctx = TestContext::new().await;
+    let first = ctx.get_global_stats(OWNER_TICKET).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = ctx.get_global_stats(OWNER_TICKET).await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn user_stats_returns_values() {
+    let ctx = TestContext::new().await;
+    let first = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "OwnerUser",
+        "SceneUserStats",
+        1,
+        0,
+        "prefab_user_a",
+    )
+    .await;
+    let _second = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "OwnerUser",
+        "SceneUserStats",
+        1,
+        1,
+        "prefab_user_b",
+    )
+    .await;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should be valid")
+        .as_millis() as i64;
+    let old_ms = now_ms - (2 * MILLIS_IN_DAY);
+    sqlx::query("UPDATE structures SET created_at = ? WHERE id = ?")
+        .bind(old_ms)
+        .bind(first)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    sqlx::query("UPDATE users SET likes_received = ?, likes_send = ? WHERE user_id = ?")
+        .bind(9_i64)
+        .bind(4_i64)
+        .bind(OWNER_ID as i64)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let response = ctx.get_user_stats(OWNER_TICKET).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+
+    assert_eq!(body["total_structures_uploaded"].as_i64().unwrap(), 2);
+    assert_eq!(body["structures_uploaded_last_24h"].as_i64().unwrap(), 1);
+    assert_eq!(body["total_likes_received"].as_i64().unwrap(), 9);
+    assert_eq!(body["total_likes_sent"].as_i64().unwrap(), 4);
+}
+
+#[tokio::test]
+async fn user_stats_enforces_rate_limit() {
+    let ctx = TestContext::new().await;
+    let first = ctx.get_user_stats(OWNER_TICKET).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let second = ctx.get_user_stats(OWNER_TICKET).await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn like_structure_updates_counts_and_clamps() {
+    let ctx = TestContext::new().await;
+    let structure_id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneLike",
+        1,
+        0,
+        "prefab_like",
+    )
+    .await;
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 150 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let likes = sqlx::query_scalar::<_, i64>("SELECT likes FROM structures WHERE id = ?")
+        .bind(structure_id)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 100);
+
+    let (likes_send,) =
+        sqlx::query_as::<_, (i64,)>("SELECT likes_send FROM users WHERE user_id = ?")
+            .bind(LIKER_ID as i64)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(likes_send, 100);
+
+    let (likes_received,) =
+        sqlx::query_as::<_, (i64,)>("SELECT likes_received FROM users WHERE user_id = ?")
+            .bind(OWNER_ID as i64)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(likes_received, 100);
+}
+
+#[tokio::test]
+async fn like_structure_rejects_self_likes() {
+    let ctx = TestContext::new().await;
+    let structure_id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneSelf",
+        1,
+        0,
+        "prefab_self",
+    )
+    .await;
+
+    let response = ctx
+        .like_structure(OWNER_TICKET, structure_id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn coop_contributors_are_returned_and_credited_with_likes() {
+    let ctx = TestContext::new().await;
+
+    let mut payload = structure_payload("Owner", "SceneCoop", 1, 0, "prefab_coop");
+    payload["contributors"] = json!((1..=9).collect::<Vec<u64>>());
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    ctx.clear_post_rate_limit(OWNER_ID);
+
+    // the uploader and repeats are dropped
+    let mut payload = structure_payload("Owner", "SceneCoop", 1, 0, "prefab_coop");
+    payload["contributors"] = json!([OTHER_ID, OWNER_ID, OTHER_ID]);
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["contributors"], json!([OTHER_ID]));
+    let structure_id = body["id"].as_i64().unwrap();
+
+    let response = ctx.get_random(LIKER_TICKET, "?scene=SceneCoop").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body[0]["contributors"], json!([OTHER_ID]));
+
+    // contributors cannot like what they helped build
+    let response = ctx
+        .like_structure(OTHER_TICKET, structure_id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 3 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let received: Vec<(i64, i64)> = sqlx::query_as(
+        "SELECT user_id, likes_received FROM users WHERE user_id IN (?, ?) ORDER BY user_id",
+    )
+    .bind(OWNER_ID as i64)
+    .bind(OTHER_ID as i64)
+    .fetch_all(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(received, vec![(OWNER_ID as i64, 3), (OTHER_ID as i64, 3)]);
+}
+
+#[tokio::test]
+async fn notifications_list_likes_until_acknowledged() {
+    let ctx = TestContext::new().await;
+    let structure_id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneInbox",
+        1,
+        0,
+        "prefab_inbox",
+    )
+    .await;
+    for (ticket, count) in [(LIKER_TICKET, 3), (OTHER_TICKET, 2)] {
+        let response = ctx
+            .like_structure(ticket, structure_id, json!({ "count": count }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let inbox = |ticket: &'static str, query: &'static str| {
+        let ctx = &ctx;
+        async move {
+            let response = ctx
+                .user_request(
+                    ticket,
+                    Method::GET,
+                    &format!("/api/v1/users/me/notifications{query}"),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+
+    let page = inbox(LIKER_TICKET, "").await;
+    assert_eq!(page["unread"], 0);
+
+    let page = inbox(OWNER_TICKET, "?limit=1").await;
+    assert_eq!(page["unread"], 2);
+    assert_eq!(page["unread_likes"], 5);
+    assert_eq!(page["has_more"], true);
+    let first = &page["notifications"][0];
+    assert_eq!(first["liker_id"], LIKER_ID);
+    assert_eq!(first["structure_id"], structure_id);
+    assert_eq!(first["scene"], "SceneInbox");
+    assert_eq!(first["prefab"], "prefab_inbox");
+    assert_eq!(first["count"], 3);
+    let cursor = page["cursor"].as_i64().unwrap();
+
+    let response = ctx
+        .user_post(
+            OWNER_TICKET,
+            "/api/v1/users/me/notifications/read",
+            json!({ "cursor": cursor }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let page = inbox(OWNER_TICKET, "").await;
+    assert_eq!(page["unread"], 1);
+    assert_eq!(page["notifications"][0]["liker_id"], OTHER_ID);
+    assert_eq!(page["has_more"], false);
+
+    // acknowledging an older cursor doesn't bring read likes back
+    let response = ctx
+        .user_post(
+            OWNER_TICKET,
+            "/api/v1/users/me/notifications/read",
+            json!({ "cursor": 0 }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(inbox(OWNER_TICKET, "").await["unread"], 1);
+    assert_eq!(inbox(OWNER_TICKET, "?after=0").await["unread"], 2);
+}
+
+#[tokio::test]
+async fn notification_digest_rolls_likes_up_per_structure() {
+    let ctx = TestContext::new().await;
+    let mut ids = Vec::new();
+    for prefab in ["prefab_digest_a", "prefab_digest_b"] {
+        ids.push(
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Owner",
+                "SceneDigest",
+                1,
+                0,
+                prefab,
+            )
+            .await,
+        );
+    }
+    for (ticket, liker, id, count) in [
+        (LIKER_TICKET, LIKER_ID, ids[0], 3),
+        (OTHER_TICKET, OTHER_ID, ids[0], 2),
+        (LIKER_TICKET, LIKER_ID, ids[1], 1),
+    ] {
+        ctx.state.post_like_rate_limiter.remove(&liker);
+        let response = ctx
+            .like_structure(ticket, id, json!({ "count": count }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let digest = |query: &'static str| {
+        let ctx = &ctx;
+        async move {
+            let response = ctx
+                .user_request(
+                    OWNER_TICKET,
+                    Method::GET,
+                    &format!("/api/v1/users/me/notifications/digest{query}"),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+
+    let page = digest("").await;
+    assert_eq!(page["unread"], 3);
+    assert_eq!(page["unread_likes"], 6);
+    assert_eq!(page["has_more"], false);
+    let digests = page["digests"].as_array().unwrap();
+    assert_eq!(digests.len(), 2);
+    assert_eq!(digests[0]["structure_id"], ids[0]);
+    assert_eq!(digests[0]["prefab"], "prefab_digest_a");
+    assert_eq!(digests[0]["count"], 5);
+    assert_eq!(digests[0]["likers"], 2);
+    assert_eq!(digests[1]["count"], 1);
+
+    // acknowledging the first page leaves only the second structure
+    let page = digest("?limit=1").await;
+    assert_eq!(page["has_more"], true);
+    let response = ctx
+        .user_post(
+            OWNER_TICKET,
+            "/api/v1/users/me/notifications/read",
+            json!({ "cursor": page["cursor"] }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let page = digest("").await;
+    assert_eq!(page["digests"].as_array().unwrap().len(), 1);
+    assert_eq!(page["digests"][0]["structure_id"], ids[1]);
+    assert_eq!(page["unread_likes"], 1);
+}
+
+#[tokio::test]
+async fn like_structure_enforces_rate_limit() {
+    let ctx = TestContext::new().await;
+    let structure_id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneLikeLimit",
+        1,
+        0,
+        "prefab_like_limit",
+    )
+    .await;
+
+    let first = ctx
+        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(first.status(), StatusCode::NO_CONTENT);
+    let second = ctx
+        .like_structure(LIKER_TICKET, structure_id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn like_structure_fails_for_missing_structure() {
+    let ctx = TestContext::new().await;
+    let response = ctx
+        .like_structure(LIKER_TICKET, 999, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn admin_routes_require_admin_key() {
+    let ctx = TestContext::new().await;
+    let missing = ctx
+        .admin_request(Method::GET, "/admin/v1/export", None, Body::empty())
+        .await;
+    assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+    let wrong = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/export",
+            Some("not-the-key"),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(wrong.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn export_then_import_round_trips_into_fresh_database() {
+    let source = TestContext::new().await;
+    let liked = create_structure(
+        &source,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneExport",
+        1,
+        0,
+        "prefab_export_a",
+    )
+    .await;
+    let removed = create_structure(
+        &source,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneExport",
+        2,
+        1,
+        "prefab_export_b",
+    )
+    .await;
+    let response = source
+        .like_structure(LIKER_TICKET, liked, json!({ "count": 7 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    sqlx::query("UPDATE structures SET deleted = 1 WHERE id = ?")
+        .bind(removed)
+        .execute(&source.state.db)
+        .await
+        .unwrap();
+
+    let dump = source.export_dump().await;
+    let lines: Vec<Value> = dump
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("export line is json"))
+        .collect();
+    assert_eq!(lines.iter().filter(|l| l["table"] == "users").count(), 3);
+    assert_eq!(
+        lines.iter().filter(|l| l["table"] == "structures").count(),
+        2
+    );
+
+    let target = TestContext::new().await;
+    let response = target
+        .admin_request(
+            Method::POST,
+            "/admin/v1/import",
+            Some(ADMIN_KEY),
+            Body::from(dump.clone()),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary = response_json(response).await;
+    assert_eq!(summary["users"].as_i64().unwrap(), 3);
+    assert_eq!(summary["structures"].as_i64().unwrap(), 2);
+
+    assert_eq!(target.export_dump().await, dump);
+}
+
+#[tokio::test]
+async fn import_rejects_malformed_lines_atomically() {
+    let ctx = TestContext::new().await;
+    let body = format!(
+        "{}\nnot json\n",
+        json!({ "table": "users", "user_id": 5, "upload_banned": false, "likes_received": 0, "likes_send": 0 })
+    );
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            "/admin/v1/import",
+            Some(ADMIN_KEY),
+            Body::from(body),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "bad_request");
+    assert!(body["message"].as_str().unwrap().starts_with("line 2:"));
+
+    let users = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users")
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(users, 0);
+}
+
+#[tokio::test]
+async fn serves_https_when_tls_paths_are_configured() {
+    let ctx = TestContext::new().await;
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("failed to generate certificate");
+    let dir = std::env::temp_dir().join(format!("peakstranding-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.signing_key.serialize_pem()).unwrap();
+
+    let mut config = (*ctx.state.config()).clone();
+    config.tls_cert_path = Some(cert_path.to_string_lossy().into_owned());
+    config.tls_key_path = Some(key_path.to_string_lossy().into_owned());
+    let tls = load_tls_config(&config)
+        .await
+        .expect("failed to load TLS config");
+    assert!(tls.is_some());
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(serve(ctx.app.clone(), BoundListener::Tcp(listener), tls));
+
+    let client = Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{port}/api/v1/stats/me"))
+        .header(&STEAM_HEADER, OWNER_TICKET)
+        .send()
+        .await
+        .expect("HTTPS request failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+
+    config.tls_key_path = None;
+    assert!(load_tls_config(&config).await.is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn plaintext_listener_accepts_h2c_and_http1() {
+    let ctx = TestContext::new().await;
+    let port = ctx.spawn_server().await;
+    let url = format!("http://127.0.0.1:{port}/api/v1/stats/me");
+
+    let h2c = Client::builder().http2_prior_knowledge().build().unwrap();
+    for _ in 0..2 {
+        ctx.state.user_stats_rate_limiter.remove(&OWNER_ID);
+        let response = h2c
+            .get(&url)
+            .header(&STEAM_HEADER, OWNER_TICKET)
+            .send()
+            .await
+            .expect("h2c request failed");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    }
+
+    ctx.state.user_stats_rate_limiter.remove(&OWNER_ID);
+    let config = Config {
+        http2: false,
+        ..(*ctx.state.config()).clone()
+    };
+    let response = http_client(&config)
+        .unwrap()
+        .get(&url)
+        .header(&STEAM_HEADER, OWNER_TICKET)
+        .send()
+        .await
+        .expect("HTTP/1.1 request failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_11);
+}
+
+#[test]
+fn listen_addr_parses_tcp_and_unix_targets() {
+    assert_eq!(
+        "0.0.0.0:3000".parse::<ListenAddr>(),
+        Ok(ListenAddr::Tcp("0.0.0.0:3000".to_string()))
+    );
+    assert_eq!(
+        "unix:/run/peakstranding.sock".parse::<ListenAddr>(),
+        Ok(ListenAddr::Unix("/run/peakstranding.sock".into()))
+    );
+    assert!("unix:".parse::<ListenAddr>().is_err());
+}
+
+#[tokio::test]
+async fn serves_over_unix_socket_with_configured_mode() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let ctx = TestContext::new().await;
+    let dir = std::env::temp_dir().join(format!("peakstranding-uds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket_path = dir.join("server.sock");
+    // a stale socket from a previous run must not block startup
+    drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+
+    let mut config = (*ctx.state.config()).clone();
+    config.listen = ListenAddr::Unix(socket_path.clone());
+    config.unix_socket_mode = Some(0o660);
+    let listener = bind_listener(&config).expect("failed to bind unix socket");
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o660);
+    tokio::spawn(serve(ctx.app.clone(), listener, None));
+
+    let mut stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+    let request = format!(
+        "GET /api/v1/stats/me HTTP/1.1\r\nHost: localhost\r\nX-Steam-Auth: {OWNER_TICKET}\r\nConnection: close\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+async fn cors_preflight(ctx: &TestContext, origin: &str) -> axum::http::Response<Body> {
+    ctx.app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/v1/stats/global")
+                .header("origin", origin)
+                .header("access-control-request-method", "GET")
+                .header("access-control-request-headers", "x-steam-auth")
+                .body(Body::empty())
+                .expect("failed to build preflight request"),
+        )
+        .await
+        .expect("preflight request failed")
+}
+
+#[tokio::test]
+async fn cors_is_disabled_by_default() {
+    let ctx = TestContext::new().await;
+    let response = cors_preflight(&ctx, "https://stats.example.com").await;
+    assert!(
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn cors_allows_only_configured_origins() {
+    let ctx = TestContext::with_config(|config| {
+        config.cors_allowed_origins = vec!["https://stats.example.com".to_string()];
+    })
+    .await;
+
+    let allowed = cors_preflight(&ctx, "https://stats.example.com").await;
+    assert_eq!(
+        allowed.headers()["access-control-allow-origin"],
+        "https://stats.example.com"
+    );
+    assert_eq!(allowed.headers()["access-control-allow-methods"], "GET");
+    assert!(
+        allowed.headers()["access-control-allow-headers"]
+            .to_str()
+            .unwrap()
+            .contains("x-steam-auth")
+    );
+
+    let other = cors_preflight(&ctx, "https://evil.example.com").await;
+    assert!(other.headers().get("access-control-allow-origin").is_none());
+}
+
+fn config_from(toml_text: &str, env_vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+    let file = toml::from_str::<toml::Table>(toml_text).expect("test toml is valid");
+    let env_vars: Vec<(String, String)> = env_vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Config::from_sources(file, &|key| {
+        env_vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    })
+}
+
+#[test]
+fn config_reads_file_values_and_env_overrides() {
+    let config = config_from(
+        r#"
+        max_requested_structs = 200
+        default_random_limit = 20
+        post_like_rate_limit = 3
+        cors_allowed_origins = ["https://a.example", "https://b.example"]
+        listen = "unix:/run/ps.sock"
+        unix_socket_mode = "660"
+        "#,
+        &[("DEFAULT_RANDOM_LIMIT", "25")],
+    )
+    .expect("config should load");
+
+    assert_eq!(config.max_requested_structs, 200);
+    assert_eq!(config.default_random_limit, 25);
+    assert_eq!(config.post_like_rate_limit, Duration::from_secs(3));
+    assert_eq!(
+        config.cors_allowed_origins,
+        vec!["https://a.example", "https://b.example"]
+    );
+    assert_eq!(config.listen, ListenAddr::Unix("/run/ps.sock".into()));
+    assert_eq!(config.unix_socket_mode, Some(0o660));
+    // untouched keys keep their defaults
+    assert_eq!(config.steam_appids, vec![3527290]);
+    assert_eq!(config.max_user_structs_saved_per_scene, 100);
+}
+
+#[test]
+fn config_rejects_invalid_and_unknown_values() {
+    let bad_env = config_from("", &[("MAX_REQUESTED_STRUCTS", "lots")]).unwrap_err();
+    assert!(bad_env.to_string().contains("MAX_REQUESTED_STRUCTS"));
+
+    let unknown = config_from("max_requested_struct = 10", &[]).unwrap_err();
+    assert!(unknown.to_string().contains("max_requested_struct"));
+
+    let inconsistent =
+        config_from("max_requested_structs = 10\ndefault_random_limit = 50", &[]).unwrap_err();
+    assert!(inconsistent.to_string().contains("DEFAULT_RANDOM_LIMIT"));
+
+    let half_tls = config_from("tls_cert_path = \"/etc/cert.pem\"", &[]).unwrap_err();
+    assert!(half_tls.to_string().contains("TLS_KEY_PATH"));
+}
+
+#[tokio::test]
+async fn sqlite_tuning_applies_to_pooled_connections() {
+    let config = config_from(
+        r#"
+        database_url = "sqlite::memory:"
+        sqlite_cache_size = -65536
+        sqlite_wal_autocheckpoint = 4000
+        "#,
+        &[("DB_MAX_CONNECTIONS", "2")],
+    )
+    .expect("config should load");
+    assert_eq!(config.db_max_connections, 2);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .connect_with(sqlite_connect_options(&config).unwrap())
+        .await
+        .unwrap();
+    let cache_size: i64 = sqlx::query_scalar("PRAGMA cache_size")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    let autocheckpoint: i64 = sqlx::query_scalar("PRAGMA wal_autocheckpoint")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(cache_size, -65536);
+    assert_eq!(autocheckpoint, 4000);
+
+    let zero = config_from("db_max_connections = 0", &[]).unwrap_err();
+    assert!(zero.to_string().contains("DB_MAX_CONNECTIONS"));
+}
+
+#[tokio::test]
+async fn read_pool_is_query_only_and_sees_committed_writes() {
+    let dir = std::env::temp_dir().join(format!("peakstranding-rw-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rw.db");
+    let _ = std::fs::remove_file(&path);
+    let mut config = (*shared_test_config()).clone();
+    config.database_url = format!("sqlite://{}?mode=rwc", path.display());
+    config.db_max_connections = 2;
+
+    let writer = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(sqlite_connect_options(&config).unwrap())
+        .await
+        .unwrap();
+    sqlx::query("CREATE TABLE notes (body TEXT)")
+        .execute(&writer)
+        .await
+        .unwrap();
+    let reader = open_read_pool(&config, &writer).await.unwrap();
+
+    sqlx::query("INSERT INTO notes VALUES ('hello')")
+        .execute(&writer)
+        .await
+        .unwrap();
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM notes")
+        .fetch_one(&reader)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+
+    let denied = sqlx::query("INSERT INTO notes VALUES ('nope')")
+        .execute(&reader)
+        .await
+        .unwrap_err();
+    assert!(denied.to_string().contains("readonly"), "{denied}");
+
+    reader.close().await;
+    writer.close().await;
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn wal_checkpoint_truncates_the_wal_file() {
+    let dir = std::env::temp_dir().join(format!("peakstranding-wal-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("wal.db");
+    let wal = dir.join("wal.db-wal");
+    let _ = std::fs::remove_file(&path);
+    let mut config = (*shared_test_config()).clone();
+    config.database_url = format!("sqlite://{}?mode=rwc", path.display());
+    // only the explicit checkpoint empties the WAL
+    config.sqlite_wal_autocheckpoint = 0;
+
+    let writer = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(sqlite_connect_options(&config).unwrap())
+        .await
+        .unwrap();
+    sqlx::query("CREATE TABLE notes (body TEXT)")
+        .execute(&writer)
+        .await
+        .unwrap();
+    for _ in 0..20 {
+        sqlx::query("INSERT INTO notes VALUES (randomblob(4096))")
+            .execute(&writer)
+            .await
+            .unwrap();
+    }
+    let grown = std::fs::metadata(&wal).unwrap().len();
+    assert!(grown > 0);
+
+    let result = checkpoint::checkpoint(&writer).await.unwrap();
+    assert!(!result.busy);
+    assert_eq!(result.wal_bytes_before, Some(grown));
+    assert_eq!(result.wal_bytes_after, Some(0));
+    assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+    writer.close().await;
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+fn reloaded_test_config() -> anyhow::Result<Config> {
+    let mut config = (*shared_test_config()).clone();
+    config.default_random_limit = 1;
+    config.database_url = "sqlite://elsewhere.db".to_string();
+    Ok(config)
+}
+
+#[tokio::test]
+async fn route_switches_turn_features_off() {
+    let ingest = TestContext::with_config(|config| config.enable_fetch = false).await;
+    let id = create_structure(
+        &ingest,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneSwitch",
+        1,
+        0,
+        "prefab_switch",
+    )
+    .await;
+    let response = ingest.get_random(LIKER_TICKET, "?scene=SceneSwitch").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response_json(response).await["code"], "feature_disabled");
+    let response = ingest
+        .user_request(
+            LIKER_TICKET,
+            Method::GET,
+            "/api/v2/structures/nearby?scene=SceneSwitch&x=0&y=0&z=0",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = ingest.like_structure(LIKER_TICKET, id, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mirror = TestContext::with_config(|config| {
+        config.enable_post = false;
+        config.enable_likes = false;
+    })
+    .await;
+    let response = mirror
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Owner", "SceneSwitch", 1, 0, "prefab_switch"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response_json(response).await["code"], "feature_disabled");
+    let response = mirror.like_structure(LIKER_TICKET, 1, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = mirror.get_random(LIKER_TICKET, "?scene=SceneSwitch").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn fetches_over_the_concurrency_cap_are_shed() {
+    let ctx = TestContext::with_config(|config| config.max_concurrent_fetches = 1).await;
+    // the only connection is taken, so the first fetch waits for it
+    let conn = ctx.state.db.acquire().await.unwrap();
+    let waiting = tokio::spawn({
+        let app = ctx.app.clone();
+        async move {
+            app.oneshot(
+                Request::builder()
+                    .uri("/api/v1/structures?scene=SceneShed")
+                    .header(&STEAM_HEADER, OWNER_TICKET)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = ctx
+        .user_request(
+            OTHER_TICKET,
+            Method::GET,
+            "/api/v2/structures/nearby?scene=SceneShed&x=0&y=0&z=0&radius=5",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "1");
+    assert_eq!(response_json(response).await["code"], "overloaded");
+
+    drop(conn);
+    assert_eq!(waiting.await.unwrap().status(), StatusCode::OK);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneShed").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn requests_over_the_timeout_give_their_connection_back() {
+    let ctx =
+        TestContext::with_config(|config| config.request_timeout = Duration::from_millis(200))
+            .await;
+    let conn = ctx.state.db.acquire().await.unwrap();
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Sam", "SceneTimeout", 1, 0, "prefab_a"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response_json(response).await["code"], "timeout");
+    drop(conn);
+
+    // nothing of the abandoned upload was kept
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneTimeout").await;
+    assert_eq!(response_json(response).await, json!([]));
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Sam", "SceneTimeout", 1, 0, "prefab_a"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn read_only_mode_refuses_writes_but_serves_fetches() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneMaintenance",
+        1,
+        0,
+        "prefab_maintenance",
+    )
+    .await;
+
+    let response = ctx
+        .admin_request(
+            Method::PUT,
+            "/admin/v1/maintenance?read_only=true",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["read_only"], true);
+
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Owner", "SceneMaintenance", 1, 1, "prefab_later"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key("retry-after"));
+    assert_eq!(response_json(response).await["code"], "read_only");
+    let response = ctx.like_structure(LIKER_TICKET, id, json!({})).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = ctx
+        .get_random(LIKER_TICKET, "?scene=SceneMaintenance")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+    ctx.clear_get_rate_limit(LIKER_ID);
+    let response = ctx
+        .user_post(
+            LIKER_TICKET,
+            "/api/v1/fetch-sessions",
+            json!({ "scene": "SceneMaintenance" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/maintenance",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response_json(response).await["read_only"], true);
+    ctx.admin_request(
+        Method::PUT,
+        "/admin/v1/maintenance?read_only=false",
+        Some(ADMIN_KEY),
+        Body::empty(),
+    )
+    .await;
+    let response = ctx.like_structure(LIKER_TICKET, id, json!({})).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn admin_reload_swaps_config_but_keeps_restart_only_values() {
+    let ctx = TestContext::with_config_and_loader(|_| {}, reloaded_test_config).await;
+    for segment in 0..2 {
+        let _ = create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Owner",
+            "SceneReload",
+            1,
+            segment,
+            &format!("prefab_reload_{segment}"),
+        )
+        .await;
+    }
+
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            "/admin/v1/reload",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["restart_required"], json!(["DATABASE_URL"]));
+
+    let config = ctx.state.config();
+    assert_eq!(config.default_random_limit, 1);
+    assert_eq!(config.database_url, "sqlite::memory:");
+
+    // handlers pick up the new default without a restart
+    let response = ctx.get_random(LIKER_TICKET, "?scene=SceneReload").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn admin_reload_keeps_old_config_when_new_one_is_invalid() {
+    let ctx = TestContext::with_config_and_loader(
+        |_| {},
+        || anyhow::bail!("MAX_SCENE_LENGTH must be at least 1"),
+    )
+    .await;
+    let before = ctx.state.config();
+
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            "/admin/v1/reload",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(Arc::ptr_eq(&before, &ctx.state.config()));
+}
+
+type TestSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn ws_connect_as(port: u16, ticket: &str) -> TestSocket {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let mut request = format!("ws://127.0.0.1:{port}/api/v1/ws")
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(&STEAM_HEADER, ticket.parse().unwrap());
+    let (socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("websocket handshake failed");
+    socket
+}
+
+async fn ws_exchange(socket: &mut TestSocket, command: Option<Value>) -> Value {
+    use futures_util::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    if let Some(command) = command {
+        socket
+            .send(Message::Text(command.to_string().into()))
+            .await
+            .unwrap();
+    }
+    let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("timed out waiting for websocket message")
+        .expect("websocket closed")
+        .expect("websocket error");
+    serde_json::from_str(message.to_text().unwrap()).expect("websocket message is json")
+}
+
+#[tokio::test]
+async fn websocket_pushes_posts_and_likes_for_subscribed_scenes() {
+    let ctx = TestContext::new().await;
+    let port = ctx.spawn_server().await;
+    let mut socket = ws_connect_as(port, OTHER_TICKET).await;
+
+    let ack = ws_exchange(
+        &mut socket,
+        Some(json!({ "action": "subscribe", "scenes": ["SceneLive"] })),
+    )
+    .await;
+    assert_eq!(
+        ack,
+        json!({ "type": "subscribed", "scenes": ["SceneLive"] })
+    );
+
+    // other scenes stay silent
+    let _ = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneQuiet",
+        1,
+        0,
+        "prefab_quiet",
+    )
+    .await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneLive",
+        1,
+        0,
+        "prefab_live",
+    )
+    .await;
+    let posted = ws_exchange(&mut socket, None).await;
+    assert_eq!(posted["type"], "structure_posted");
+    assert_eq!(posted["structure"]["id"].as_i64().unwrap(), id);
+    assert_eq!(posted["structure"]["prefab"], "prefab_live");
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "count": 3 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let liked = ws_exchange(&mut socket, None).await;
+    assert_eq!(
+        liked,
+        json!({ "type": "structure_liked", "id": id, "scene": "SceneLive", "likes": 3, "count": 3, "reaction": "thumbs_up" })
+    );
+}
+
+#[tokio::test]
+async fn websocket_limits_subscriptions_per_connection() {
+    let ctx = TestContext::new().await;
+    let port = ctx.spawn_server().await;
+    let mut socket = ws_connect_as(port, OWNER_TICKET).await;
+
+    let reply = ws_exchange(
+        &mut socket,
+        Some(json!({ "action": "subscribe", "scenes": ["A", "B", "C"] })),
+    )
+    .await;
+    assert_eq!(reply["type"], "error");
+
+    let reply = ws_exchange(
+        &mut socket,
+        Some(json!({ "action": "unsubscribe", "scenes": ["A"] })),
+    )
+    .await;
+    assert_eq!(reply, json!({ "type": "subscribed", "scenes": ["B"] }));
+}
+
+#[tokio::test]
+async fn user_events_stream_likes_on_own_structures() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneSse",
+        1,
+        0,
+        "prefab_sse",
+    )
+    .await;
+
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/api/v1/users/me/events")
+                .header(&STEAM_HEADER, OWNER_TICKET)
+                .body(Body::empty())
+                .expect("failed to build events request"),
+        )
+        .await
+        .expect("GET /users/me/events request failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[axum::http::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let mut body = response.into_body();
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "count": 2 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+        .await
+        .expect("no event within timeout")
+        .expect("stream ended")
+        .expect("stream errored");
+    let text = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+    assert!(text.starts_with("event: like_received\n"), "{text}");
+    let data = text
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .expect("event has data");
+    let event: Value = serde_json::from_str(data).unwrap();
+    assert_eq!(
+        event,
+        json!({
+            "type": "like_received",
+            "structure_id": id,
+            "scene": "SceneSse",
+            "prefab": "prefab_sse",
+            "reaction": "thumbs_up",
+            "count": 2,
+            "likes": 2
+        })
+    );
+}
+
+#[tokio::test]
+async fn like_milestone_posts_discord_embed() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let webhook = Router::new().route(
+        "/hook",
+        axum::routing::post(move |Json(body): Json<Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send(body).ok();
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let ctx = TestContext::with_config(|config| {
+        config.discord_webhook_url = Some(format!("http://127.0.0.1:{port}/hook"));
+    })
+    .await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneHook",
+        1,
+        0,
+        "prefab_hook",
+    )
+    .await;
+
+    // 3 likes stays under the milestone of 5, the next 3 cross it
+    for _ in 0..2 {
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        let response = ctx
+            .like_structure(LIKER_TICKET, id, json!({ "count": 3 }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not called")
+        .unwrap();
+    let embeds = message["embeds"].as_array().unwrap();
+    assert_eq!(embeds.len(), 1);
+    assert_eq!(embeds[0]["title"], "A structure reached 6 likes");
+    assert_eq!(
+        embeds[0]["description"],
+        "prefab_hook by Owner in SceneHook"
+    );
+
+    // decayed below the milestone and liked past it again: claimed already
+    sqlx::query("UPDATE structures SET likes = 4 WHERE id = ?")
+        .bind(id)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    ctx.state.post_like_rate_limiter.remove(&OTHER_ID);
+    let response = ctx
+        .like_structure(OTHER_TICKET, id, json!({ "count": 3 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn like_milestones_fire_once_direct_and_buffered() {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let webhook = Router::new().route(
+        "/milestones",
+        axum::routing::post(move |Json(body): Json<Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send(body).ok();
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { axum::serve(listener, webhook).await });
+
+    let ctx = TestContext::with_config(|config| {
+        config.like_milestones = vec![5, 10];
+        config.milestone_webhook_url = Some(format!("http://127.0.0.1:{port}/milestones"));
+    })
+    .await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneMilestone",
+        1,
+        0,
+        "prefab_milestone",
+    )
+    .await;
+    let mut events = ctx.state.events.subscribe_user(OWNER_ID);
+    let like = async |ticket: &str, steam_id: u64, body: Value| {
+        ctx.state.post_like_rate_limiter.remove(&steam_id);
+        let response = ctx.like_structure(ticket, id, body).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    };
+
+    // written at once: 6 passes 5, one more passes nothing
+    like(LIKER_TICKET, LIKER_ID, json!({ "count": 6 })).await;
+    like(LIKER_TICKET, LIKER_ID, json!({ "count": 1 })).await;
+    let hook = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not called")
+        .unwrap();
+    assert_eq!(
+        hook,
+        json!({
+            "structure_id": id,
+            "owner_id": OWNER_ID,
+            "scene": "SceneMilestone",
+            "prefab": "prefab_milestone",
+            "milestone": 5,
+            "likes": 6
+        })
+    );
+
+    // buffered: two reactions flushed together cross 10 once
+    let mut next = (*ctx.state.config()).clone();
+    next.like_flush_interval = Duration::from_millis(50);
+    ctx.state.config.store(Arc::new(next));
+    tokio::spawn(flush_likes(ctx.state.clone()));
+    like(LIKER_TICKET, LIKER_ID, json!({ "count": 2 })).await;
+    like(
+        OTHER_TICKET,
+        OTHER_ID,
+        json!({ "count": 2, "reaction": "heart" }),
+    )
+    .await;
+    let hook = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("webhook not called")
+        .unwrap();
+    assert_eq!(hook["milestone"], 10);
+    assert_eq!(hook["likes"], 11);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(rx.try_recv().is_err());
+
+    let mut reached = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let UserEvent::LikeMilestone { milestone, .. } = event {
+            reached.push(milestone);
+        }
+    }
+    assert_eq!(reached, vec![5, 10]);
+}
+
+#[tokio::test]
+async fn scene_stats_count_live_structures_builders_and_prefabs() {
+    let ctx = TestContext::new().await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneCount",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneCount",
+        1,
+        1,
+        "rope",
+    )
+    .await;
+    let other = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneCount",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneElsewhere",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+
+    let response = ctx
+        .user_request(LIKER_TICKET, Method::GET, "/api/v1/scenes/SceneCount/stats")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response_json(response).await,
+        json!({
+            "scene": "SceneCount",
+            "season": 1,
+            "total_structures": 3,
+            "unique_builders": 2,
+            "prefabs": { "ladder": 2, "rope": 1 },
+        })
+    );
+
+    let response = ctx
+        .user_request(
+            OTHER_TICKET,
+            Method::DELETE,
+            &format!("/api/v1/structures/{other}"),
+        )
+        .await;
+    assert!(response.status().is_success());
+    // served from the cache until it expires
+    let response = ctx
+        .user_request(LIKER_TICKET, Method::GET, "/api/v1/scenes/SceneCount/stats")
+        .await;
+    assert_eq!(response_json(response).await["total_structures"], 3);
+    ctx.state.scene_stats_cache.clear();
+    let response = ctx
+        .user_request(LIKER_TICKET, Method::GET, "/api/v2/scenes/SceneCount/stats")
+        .await;
+    let body = response_json(response).await;
+    assert_eq!(body["total_structures"], 2);
+    assert_eq!(body["unique_builders"], 1);
+    assert_eq!(body["prefabs"], json!({ "ladder": 1, "rope": 1 }));
+
+    let response = ctx
+        .user_request(LIKER_TICKET, Method::GET, "/api/v1/scenes/SceneEmpty/stats")
+        .await;
+    assert_eq!(response_json(response).await["total_structures"], 0);
+    assert_eq!(
+        ctx.state.scene_stats_cache.len(),
+        1,
+        "empty scenes are not cached"
+    );
+}
+
+#[tokio::test]
+async fn scenes_are_listed_by_name_in_pages_without_a_ticket() {
+    let ctx = TestContext::new().await;
+    for scene in ["SceneB", "SceneA", "SceneC"] {
+        create_structure(&ctx, OWNER_TICKET, OWNER_ID, "Owner", scene, 1, 0, "ladder").await;
+    }
+    let newest = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneA",
+        1,
+        0,
+        "rope",
+    )
+    .await;
+    let deleted = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneGone",
+        1,
+        0,
+        "rope",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET deleted = 1 WHERE id = ?")
+        .bind(deleted)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    let newest_at: i64 = sqlx::query_scalar("SELECT created_at FROM structures WHERE id = ?")
+        .bind(newest)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let list = |uri: &'static str| {
+        let app = ctx.app.clone();
+        async move {
+            let response = app
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+
+    let page = list("/api/v1/scenes?limit=2").await;
+    assert_eq!(
+        page["scenes"],
+        json!([
+            { "scene": "SceneA", "structures": 2, "last_activity": newest_at, "featured": false },
+            { "scene": "SceneB", "structures": 1, "last_activity": page["scenes"][1]["last_activity"], "featured": false },
+        ])
+    );
+    assert_eq!(page["next_after"], "SceneB");
+
+    let page = list("/api/v1/scenes?limit=2&after=SceneB").await;
+    let scenes: Vec<&str> = page["scenes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["scene"].as_str().unwrap())
+        .collect();
+    assert_eq!(scenes, vec!["SceneC"]);
+    assert_eq!(page["next_after"], Value::Null);
+}
+
+#[tokio::test]
+async fn prefab_stats_average_likes_overall_and_per_scene_without_a_ticket() {
+    let ctx = TestContext::new().await;
+    let liked = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneA",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneA",
+        1,
+        0,
+        "rope",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneB",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    let deleted = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneB",
+        1,
+        0,
+        "rope",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET likes = 3 WHERE id = ?")
+        .bind(liked)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE structures SET deleted = 1, likes = 9 WHERE id = ?")
+        .bind(deleted)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/stats/prefabs")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response_json(response).await,
+        json!({
+            "season": 1,
+            "prefabs": {
+                "ladder": { "structures": 2, "avg_likes": 1.5 },
+                "rope": { "structures": 1, "avg_likes": 0.0 },
+            },
+            "scenes": {
+                "SceneA": {
+                    "ladder": { "structures": 1, "avg_likes": 3.0 },
+                    "rope": { "structures": 1, "avg_likes": 0.0 },
+                },
+                "SceneB": {
+                    "ladder": { "structures": 1, "avg_likes": 0.0 },
+                },
+            },
+        })
+    );
+    assert!(ctx.state.prefab_stats_cache.read().await.is_some());
+}
+
+#[tokio::test]
+async fn daily_stats_rollup_aggregates_days_and_scenes() {
+    let ctx = TestContext::new().await;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let today_start = now_ms - now_ms % MILLIS_IN_DAY;
+
+    let mut ids = Vec::new();
+    for (ticket, user_id, scene) in [
+        (OWNER_TICKET, OWNER_ID, "SceneA"),
+        (OWNER_TICKET, OWNER_ID, "SceneB"),
+        (OTHER_TICKET, OTHER_ID, "SceneA"),
+    ] {
+        ctx.clear_post_rate_limit(user_id);
+        ids.push(
+            create_structure(
+                &ctx,
+                ticket,
+                user_id,
+                "Builder",
+                scene,
+                1,
+                0,
+                "prefab_daily",
+            )
+            .await,
+        );
+    }
+    // first structure was posted yesterday
+    sqlx::query("UPDATE structures SET created_at = ? WHERE id = ?")
+        .bind(today_start - 1000)
+        .bind(ids[0])
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    // yesterday's snapshot saw 4 likes in total, now there are 10
+    sqlx::query(
+        "INSERT INTO stats_daily (day, likes_given_total) VALUES (date(? / 1000, 'unixepoch'), 4)",
+    )
+    .bind(today_start - 1000)
+    .execute(&ctx.state.db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO users (user_id, likes_send) VALUES (?, 10)")
+        .bind(LIKER_ID as i64)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    rollup_daily_stats(&ctx.state.db, now_ms).await.unwrap();
+    // pruning after the rollup must not shrink the recorded day
+    sqlx::query("DELETE FROM structures WHERE id = ?")
+        .bind(ids[2])
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    rollup_daily_stats(&ctx.state.db, now_ms).await.unwrap();
+
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/stats/daily?days=7")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let days = body["days"].as_array().unwrap();
+    assert_eq!(days.len(), 2);
+
+    assert_eq!(days[0]["structures_posted"], 1);
+    assert_eq!(days[0]["scenes"], json!({ "SceneA": 1 }));
+    assert_eq!(days[1]["structures_posted"], 2);
+    assert_eq!(days[1]["unique_users"], 2);
+    assert_eq!(days[1]["likes_given"], 6);
+    assert_eq!(days[1]["scenes"], json!({ "SceneA": 1, "SceneB": 1 }));
+}
+
+#[tokio::test]
+async fn likes_ledger_keeps_only_the_retention_window() {
+    let ctx = TestContext::with_config(|config| config.likes_ledger_retention_days = 7).await;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    for days_ago in [30, 8, 6, 0] {
+        sqlx::query(
+            r#"INSERT INTO likes_ledger (liker_id, owner_id, structure_id, count, created_at)
+               VALUES (?, ?, 1, 1, ?)"#,
+        )
+        .bind(LIKER_ID as i64)
+        .bind(OWNER_ID as i64)
+        .bind(now_ms - days_ago * MILLIS_IN_DAY)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    }
+    let remaining = |ctx: &TestContext| {
+        let db = ctx.state.db.clone();
+        async move {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM likes_ledger")
+                .fetch_one(&db)
+                .await
+                .unwrap()
+        }
+    };
+
+    let pruned = prune_likes_ledger(&ctx.state.db, &ctx.state.config(), now_ms)
+        .await
+        .unwrap();
+    assert_eq!(pruned, 2);
+    assert_eq!(remaining(&ctx).await, 2);
+
+    // never shorter than the archiver looks back
+    let mut config = (*ctx.state.config()).clone();
+    config.likes_ledger_retention_days = 1;
+    config.archive_cold_after_days = 10;
+    let pruned = prune_likes_ledger(&ctx.state.db, &config, now_ms)
+        .await
+        .unwrap();
+    assert_eq!(pruned, 0);
+    config.archive_cold_after_days = 0;
+    let pruned = prune_likes_ledger(&ctx.state.db, &config, now_ms)
+        .await
+        .unwrap();
+    assert_eq!(pruned, 1);
+    assert_eq!(remaining(&ctx).await, 1);
+}
+
+#[tokio::test]
+async fn get_random_mixes_in_top_liked_when_curation_enabled() {
+    let ctx = TestContext::with_config(|config| config.curated_share_percent = 50).await;
+    let mut ids = Vec::new();
+    for (ticket, steam_id) in [
+        (OWNER_TICKET, OWNER_ID),
+        (LIKER_TICKET, LIKER_ID),
+        (OTHER_TICKET, OTHER_ID),
+    ] {
+        for segment in 0..2 {
+            ids.push(
+                create_structure(
+                    &ctx,
+                    ticket,
+                    steam_id,
+                    "Builder",
+                    "SceneCurated",
+                    1,
+                    segment,
+                    "prefab_curated",
+                )
+                .await,
+            );
+        }
+    }
+    let best = ids[3];
+    for (id, likes) in [(best, 50), (ids[1], 10)] {
+        sqlx::query("UPDATE structures SET likes = ? WHERE id = ?")
+            .bind(likes)
+            .bind(id)
+            .execute(&ctx.state.db)
+            .await
+            .unwrap();
+    }
+
+    // limit 2 at 50% leaves one curated slot, which always holds the best build
+    for _ in 0..5 {
+        ctx.clear_get_rate_limit(OWNER_ID);
+        let response = ctx
+            .get_random(OWNER_TICKET, "?scene=SceneCurated&limit=2")
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_json(response).await;
+        let returned: Vec<i64> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(returned.len(), 2);
+        assert!(returned.contains(&best));
+        assert_ne!(returned[0], returned[1]);
+    }
+}
+
+#[tokio::test]
+async fn likes_decay_lets_fresh_builds_overtake_old_favourites() {
+    for (strategy, decay_days, expected) in [
+        ("query", 0, "old"),
+        ("query", 1, "fresh"),
+        ("sample", 1, "fresh"),
+        ("probe", 1, "fresh"),
+    ] {
+        let ctx = TestContext::with_config(|config| {
+            config.curated_share_percent = 100;
+            config.likes_decay_days = decay_days;
+            match strategy {
+                "sample" => config.random_sample_refresh = Duration::from_secs(3600),
+                "probe" => config.random_probe_min_rows = 1,
+                _ => {}
+            }
+        })
+        .await;
+        let old = create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Builder",
+            "SceneDecay",
+            1,
+            0,
+            "prefab_a",
+        )
+        .await;
+        let fresh = create_structure(
+            &ctx,
+            LIKER_TICKET,
+            LIKER_ID,
+            "Builder",
+            "SceneDecay",
+            1,
+            1,
+            "prefab_a",
+        )
+        .await;
+        // 50 likes a month ago count for less than 20 today
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock should be valid")
+            .as_millis() as i64;
+        let month_ago = now_ms - 30 * MILLIS_IN_DAY;
+        for (id, likes, created_at) in [(old, 50, month_ago), (fresh, 20, now_ms)] {
+            sqlx::query("UPDATE structures SET likes = ?, created_at = ? WHERE id = ?")
+                .bind(likes)
+                .bind(created_at)
+                .bind(id)
+                .execute(&ctx.state.db)
+                .await
+                .unwrap();
+        }
+
+        let rows = response_json(
+            ctx.get_random(OTHER_TICKET, "?scene=SceneDecay&limit=1")
+                .await,
+        )
+        .await;
+        let picked = rows[0]["id"].as_i64().unwrap();
+        let expected_id = if expected == "old" { old } else { fresh };
+        assert_eq!(picked, expected_id, "{strategy} decay_days={decay_days}");
+        if picked == old {
+            assert_eq!(rows[0]["likes"], 50);
+        }
+    }
+}
+
+#[tokio::test]
+async fn prefab_quotas_cap_heavy_prefabs_in_every_random_strategy() {
+    for strategy in ["query", "sample", "probe"] {
+        let ctx = TestContext::with_config(|config| {
+            config.prefab_fetch_quotas = BTreeMap::from([("prefab_giant".to_string(), 1)]);
+            config.curated_share_percent = 50;
+            match strategy {
+                "sample" => config.random_sample_refresh = Duration::from_secs(3600),
+                "probe" => config.random_probe_min_rows = 1,
+                _ => {}
+            }
+        })
+        .await;
+        let mut owners = [
+            (OWNER_TICKET, OWNER_ID),
+            (LIKER_TICKET, LIKER_ID),
+            (OTHER_TICKET, OTHER_ID),
+        ]
+        .iter()
+        .cycle();
+        for (segment, prefab) in [
+            "prefab_giant",
+            "prefab_giant",
+            "prefab_giant",
+            "prefab_a",
+            "prefab_b",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let (ticket, steam_id) = owners.next().unwrap();
+            let id = create_structure(
+                &ctx,
+                ticket,
+                *steam_id,
+                "Builder",
+                "SceneQuota",
+                1,
+                segment as i32,
+                prefab,
+            )
+            .await;
+            // curation would pick the giants first without the quota
+            if prefab == "prefab_giant" {
+                sqlx::query("UPDATE structures SET likes = 10 WHERE id = ?")
+                    .bind(id)
+                    .execute(&ctx.state.db)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let rows = response_json(
+            ctx.get_random(OTHER_TICKET, "?scene=SceneQuota&limit=4")
+                .await,
+        )
+        .await;
+        let mut prefabs: Vec<&str> = rows
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["prefab"].as_str().unwrap())
+            .collect();
+        prefabs.sort();
+        if strategy == "probe" {
+            // probes may miss rows, but never take a second giant
+            let giants = prefabs.iter().filter(|p| **p == "prefab_giant").count();
+            assert_eq!(giants, 1, "{prefabs:?}");
+        } else {
+            assert_eq!(
+                prefabs,
+                ["prefab_a", "prefab_b", "prefab_giant"],
+                "{strategy}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn prefab_share_spreads_random_fetches_over_prefabs() {
+    for strategy in ["query", "sample", "probe"] {
+        let ctx = TestContext::with_config(|config| {
+            config.prefab_max_share_percent = 25;
+            config.max_user_structs_saved_per_scene = 20;
+            match strategy {
+                "sample" => config.random_sample_refresh = Duration::from_secs(3600),
+                "probe" => config.random_probe_min_rows = 1,
+                _ => {}
+            }
+        })
+        .await;
+        let prefabs = ["prefab_a"; 6].into_iter().chain(["prefab_b", "prefab_c"]);
+        for (segment, prefab) in prefabs.enumerate() {
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Builder",
+                "SceneShare",
+                1,
+                segment as i32,
+                prefab,
+            )
+            .await;
+        }
+
+        let rows = response_json(
+            ctx.get_random(OTHER_TICKET, "?scene=SceneShare&limit=4")
+                .await,
+        )
+        .await;
+        let mut prefabs: Vec<&str> = rows
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["prefab"].as_str().unwrap())
+            .collect();
+        prefabs.sort();
+        if strategy == "probe" {
+            // probes may miss rows, but the slots still get filled
+            assert_eq!(prefabs.len(), 4, "{prefabs:?}");
+        } else {
+            // one of each first, then the share is exceeded to fill the fetch
+            assert_eq!(
+                prefabs,
+                ["prefab_a", "prefab_a", "prefab_b", "prefab_c"],
+                "{strategy}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn season_rollover_hides_then_archives_old_structures() {
+    let ctx = TestContext::new().await;
+    for segment in 0..2 {
+        create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Owner",
+            "SceneSeason",
+            1,
+            segment,
+            "prefab_old",
+        )
+        .await;
+    }
+
+    let mut next = (*ctx.state.config()).clone();
+    next.current_season = 2;
+    ctx.state.config.store(Arc::new(next));
+
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneSeason").await;
+    assert_eq!(response_json(response).await, json!([]));
+
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneSeason",
+        1,
+        0,
+        "prefab_new",
+    )
+    .await;
+
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            "/admin/v1/seasons/rollover",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response_json(response).await,
+        json!({ "season": 2, "archived": 2 })
+    );
+
+    let remaining: Vec<i64> = sqlx::query_scalar("SELECT id FROM structures")
+        .fetch_all(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(remaining, vec![id]);
+    let archived: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM structures_archive WHERE prefab = 'prefab_old'")
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(archived, 2);
+    let open_seasons: Vec<i64> =
+        sqlx::query_scalar("SELECT id FROM seasons WHERE ended_at IS NULL")
+            .fetch_all(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(open_seasons, vec![2]);
+}
+
+#[tokio::test]
+async fn cold_structures_are_archived_and_can_be_restored() {
+    let ctx = TestContext::new().await;
+    let mut payload = structure_payload("Owner", "SceneCold", 1, 0, "prefab_cold");
+    payload["contributors"] = json!([OTHER_ID]);
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    let cold = response_json(response).await["id"].as_i64().unwrap();
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let liked = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneCold",
+        1,
+        1,
+        "prefab_liked",
+    )
+    .await;
+
+    // both were built long ago; only one got a like since
+    sqlx::query("UPDATE structures SET created_at = created_at - ?")
+        .bind(90 * MILLIS_IN_DAY)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    let response = ctx
+        .like_structure(LIKER_TICKET, liked, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let cutoff = now - 30 * MILLIS_IN_DAY;
+    assert_eq!(archive_cold(&ctx.state.db, cutoff, 100).await.unwrap(), 1);
+
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneCold").await;
+    let body = response_json(response).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["id"], liked);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/structures?scene=SceneCold&include_archived=true",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    let body = response_json(response).await;
+    assert_eq!(body["structures"][0]["id"], liked);
+    assert_eq!(body["structures"][0]["archived"], false);
+    assert_eq!(body["structures"][1]["id"], cold);
+    assert_eq!(body["structures"][1]["archived"], true);
+
+    let uri = format!("/admin/v1/structures/{cold}/unarchive");
+    let response = ctx
+        .admin_request(Method::POST, &uri, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = ctx
+        .admin_request(Method::POST, &uri, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // back with its contributors, and not cold again right away
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneCold").await;
+    let body = response_json(response).await;
+    assert_eq!(body.as_array().unwrap().len(), 2);
+    let restored = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["id"] == cold)
+        .unwrap();
+    assert_eq!(restored["contributors"], json!([OTHER_ID]));
+    assert_eq!(archive_cold(&ctx.state.db, cutoff, 100).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn implausible_uploads_wait_for_review_or_are_rejected() {
+    let ctx = TestContext::with_config(|config| {
+        config.plausibility_checks = PlausibilityMode::Flag;
+        config.plausibility_burst_posts = 3;
+        config.plausibility_burst_window = Duration::from_secs(60);
+        config.map_overrides.insert(
+            1,
+            MapOverrides {
+                bounds_min: Some([-1e9, -100.0, -100.0]),
+                bounds_max: Some([1e9, 100.0, 100.0]),
+                ..Default::default()
+            },
+        );
+    })
+    .await;
+    let post = async |payload: Value| {
+        ctx.clear_post_rate_limit(OWNER_ID);
+        let response = ctx.post_structure(OWNER_TICKET, payload).await;
+        (response.status(), response_json(response).await)
+    };
+
+    let (status, body) = post(structure_payload("Owner", "SceneCheat", 1, 0, "prefab_ok")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("under_review").is_none());
+
+    // a 500 m rope across a 2 m gap, and a ladder high above the map
+    let mut payload = structure_payload("Owner", "SceneCheat", 1, 0, "prefab_rope");
+    payload["rope_length"] = json!(500.0);
+    let (status, body) = post(payload).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["under_review"], true);
+    let rope = body["id"].as_i64().unwrap();
+    let mut payload = structure_payload("Owner", "SceneCheat", 1, 0, "prefab_high");
+    payload["pos_y"] = json!(5000.0);
+    let (_, body) = post(payload).await;
+    let high = body["id"].as_i64().unwrap();
+
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneCheat").await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/review",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    let body = response_json(response).await;
+    assert_eq!(body["structures"][0]["id"], high);
+    assert_eq!(
+        body["structures"][0]["review_reason"],
+        "position outside the bounds of map 1"
+    );
+    assert_eq!(body["structures"][1]["id"], rope);
+    assert_eq!(
+        body["structures"][1]["review_reason"],
+        "rope_length 500.0 for a span of 1.7"
+    );
+
+    let review = async |id: i64, verdict: &str| {
+        ctx.admin_request(
+            Method::POST,
+            &format!("/admin/v1/review/{id}/{verdict}"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await
+        .status()
+    };
+    assert_eq!(review(rope, "approve").await, StatusCode::NO_CONTENT);
+    assert_eq!(review(high, "reject").await, StatusCode::NO_CONTENT);
+    assert_eq!(review(high, "approve").await, StatusCode::NOT_FOUND);
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneCheat").await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 2);
+
+    // a fourth upload within the window, in another segment
+    let (_, body) = post(structure_payload("Owner", "SceneCheat", 1, 1, "prefab_far")).await;
+    assert_eq!(body["under_review"], true);
+
+    let mut next = (*ctx.state.config()).clone();
+    next.plausibility_checks = PlausibilityMode::Reject;
+    next.plausibility_burst_posts = 0;
+    ctx.state.config.store(Arc::new(next));
+    let mut payload = structure_payload("Owner", "SceneCheat", 1, 0, "prefab_short");
+    payload["rope_end_x"] = json!(50.0);
+    let (status, body) = post(payload).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "implausible");
+}
+
+#[tokio::test]
+async fn newcomer_uploads_wait_for_approval_or_the_timer() {
+    let ctx = TestContext::with_config(|config| {
+        config.newcomer_review_count = 1;
+        config.newcomer_auto_approve = Duration::from_secs(3600);
+    })
+    .await;
+    let post = async |prefab: &str| {
+        ctx.clear_post_rate_limit(OWNER_ID);
+        let payload = structure_payload("Owner", "SceneNewcomer", 1, 0, prefab);
+        let response = ctx.post_structure(OWNER_TICKET, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response_json(response).await
+    };
+    let random = async || {
+        ctx.clear_get_rate_limit(OTHER_ID);
+        let response = ctx.get_random(OTHER_TICKET, "?scene=SceneNewcomer").await;
+        response_json(response).await.as_array().unwrap().len()
+    };
+
+    // nothing of theirs went live yet, so both wait
+    let first = post("prefab_first").await;
+    let second = post("prefab_second").await;
+    assert_eq!(first["under_review"], true);
+    assert_eq!(second["under_review"], true);
+    assert_eq!(random().await, 0);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/review",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    let body = response_json(response).await;
+    assert_eq!(
+        body["structures"][0]["review_reason"],
+        NEWCOMER_REVIEW_REASON
+    );
+    let auto_approve_at = body["structures"][0]["review_auto_approve_at"]
+        .as_i64()
+        .unwrap();
+
+    let uri = format!("/admin/v1/review/{}/approve", first["id"]);
+    let response = ctx
+        .admin_request(Method::POST, &uri, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let third = post("prefab_third").await;
+    assert!(third.get("under_review").is_none());
+    assert_eq!(random().await, 2);
+
+    // the timer releases the other one
+    assert_eq!(
+        auto_approve_reviews(&ctx.state.db, auto_approve_at - 1)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        auto_approve_reviews(&ctx.state.db, auto_approve_at)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(random().await, 3);
+}
+
+#[tokio::test]
+async fn shadow_banned_structures_only_reach_their_owner() {
+    let ctx = TestContext::with_config(|config| config.nearby_max_radius = 1e9).await;
+    set_shadow_banned(&ctx.state.db, OWNER_ID as i64, true)
+        .await
+        .unwrap();
+    let hidden = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneShadow",
+        1,
+        0,
+        "prefab_hidden",
+    )
+    .await;
+    let shown = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneShadow",
+        1,
+        0,
+        "prefab_shown",
+    )
+    .await;
+
+    let ids = async |ticket: &str, steam_id: u64, nearby: bool| {
+        ctx.clear_get_rate_limit(steam_id);
+        ctx.state.nearby_rate_limiter.remove(&steam_id);
+        let response = if nearby {
+            ctx.get_nearby(ticket, "?scene=SceneShadow&x=0&y=0&z=0&radius=1000000")
+                .await
+        } else {
+            ctx.get_random(ticket, "?scene=SceneShadow").await
+        };
+        let mut ids: Vec<i64> = response_json(response)
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+    for nearby in [false, true] {
+        assert_eq!(
+            ids(OWNER_TICKET, OWNER_ID, nearby).await,
+            vec![hidden, shown]
+        );
+        assert_eq!(ids(OTHER_TICKET, OTHER_ID, nearby).await, vec![shown]);
+    }
+
+    set_shadow_banned(&ctx.state.db, OWNER_ID as i64, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        ids(OTHER_TICKET, OTHER_ID, false).await,
+        vec![hidden, shown]
+    );
+}
+
+#[test]
+fn config_parses_per_map_overrides() {
+    let config = config_from(
+        r#"
+        default_random_limit = 20
+
+        [maps.3]
+        default_random_limit = 5
+        structure_ttl_days = 14
+        "#,
+        &[],
+    )
+    .expect("config should load");
+    assert_eq!(config.default_random_limit_for_map(Some(3)), 5);
+    assert_eq!(config.default_random_limit_for_map(Some(4)), 20);
+    assert_eq!(config.default_random_limit_for_map(None), 20);
+    assert_eq!(config.max_user_structs_for_map(3), 100);
+    assert_eq!(
+        config.structure_ttl_for_map(Some(3)),
+        Some(Duration::from_secs(14 * 86_400))
+    );
+    assert_eq!(config.structure_ttl_for_map(None), None);
+
+    let typo = config_from("[maps.3]\ndefault_limit = 5", &[]).unwrap_err();
+    assert!(format!("{typo:#}").contains("maps.3"));
+    let too_big = config_from("[maps.3]\ndefault_random_limit = 1000", &[]).unwrap_err();
+    assert!(too_big.to_string().contains("maps.3.default_random_limit"));
+}
+
+#[tokio::test]
+async fn prefab_attributes_are_validated_stored_and_served() {
+    let parsed = config_from(
+        r#"
+        [prefab_attributes.sign]
+        text = "string"
+        glow = "bool"
+        "#,
+        &[],
+    )
+    .expect("config should load");
+    let bad = config_from("[prefab_attributes.sign]\ntext = \"color\"", &[]).unwrap_err();
+    assert!(format!("{bad:#}").contains("prefab_attributes"));
+    let ctx = TestContext::with_config(|config| {
+        config.prefab_attributes = parsed.prefab_attributes.clone();
+    })
+    .await;
+
+    let mut payload = structure_payload("Sam", "SceneAttr", 1, 0, "sign");
+    payload["attributes"] = json!({ "text": "Summit this way", "glow": true });
+    let response = ctx.post_structure(OWNER_TICKET, payload.clone()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response_json(response).await["attributes"],
+        json!({ "glow": true, "text": "Summit this way" })
+    );
+
+    for (attributes, ticket) in [
+        (json!({ "colour": "red" }), LIKER_TICKET),
+        (json!({ "glow": "yes" }), OTHER_TICKET),
+    ] {
+        payload["attributes"] = attributes;
+        let response = ctx.post_structure(ticket, payload.clone()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response_json(response).await["field"], "attributes");
+    }
+    // prefabs without a schema take none
+    let mut rope = structure_payload("Sam", "SceneAttr", 1, 0, "rope");
+    rope["attributes"] = json!({ "glow": true });
+    ctx.clear_post_rate_limit(LIKER_ID);
+    let response = ctx.post_structure(LIKER_TICKET, rope).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneAttr").await;
+    let body = response_json(response).await;
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["attributes"]["text"], "Summit this way");
+}
+
+#[test]
+fn tenant_sections_override_the_file_and_the_environment() {
+    let file = r#"
+        admin_api_key = "main-key"
+        max_requested_structs = 100
+
+        [tenants.eu-discord]
+        database_url = "sqlite://eu.db?mode=rwc"
+        admin_api_key = "eu-key"
+        max_requested_structs = 50
+        "#;
+    let env = [("DATABASE_URL", "sqlite://main.db?mode=rwc")];
+    let main = config_from(file, &env).expect("config should load");
+    assert_eq!(main.tenants, vec!["eu-discord".to_string()]);
+    assert_eq!(main.max_requested_structs, 100);
+
+    let table = toml::from_str::<toml::Table>(file).unwrap();
+    let lookup = |key: &str| {
+        env.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.to_string())
+    };
+    let eu = Config::tenant_from_sources(&table, &lookup, "eu-discord").unwrap();
+    assert_eq!(eu.database_url, "sqlite://eu.db?mode=rwc");
+    assert_eq!(eu.admin_api_key.as_deref(), Some("eu-key"));
+    assert_eq!(eu.max_requested_structs, 50);
+    assert!(eu.tenants.is_empty());
+
+    for (section, error) in [
+        ("max_requested_structs = 5", "needs its own database_url"),
+        (
+            "database_url = \"sqlite://x.db\"\nlisten = \"0.0.0.0:1\"",
+            "listen cannot be set per tenant",
+        ),
+        (
+            "database_url = \"sqlite://main.db?mode=rwc\"",
+            "shares its database_url",
+        ),
+    ] {
+        let err = config_from(&format!("[tenants.eu]\n{section}"), &env).unwrap_err();
+        assert!(format!("{err:#}").contains(error), "{err:#}");
+    }
+    let err = config_from("[tenants.EU]\ndatabase_url = \"sqlite://x.db\"", &[]).unwrap_err();
+    assert!(err.to_string().contains("invalid tenant slug"));
+}
+
+#[tokio::test]
+async fn tenants_keep_their_structures_apart_by_path_or_header() {
+    let main = TestContext::new().await;
+    let eu = TestContext::new().await;
+    let app = tenants::router(
+        build_router(main.state.clone()),
+        vec![("eu".to_string(), build_router(eu.state.clone()))],
+    );
+    let send = |uri: &str, tenant: Option<&str>, body: Option<Value>| {
+        let mut builder = Request::builder()
+            .uri(uri)
+            .header(&STEAM_HEADER, OWNER_TICKET);
+        if let Some(tenant) = tenant {
+            builder = builder.header("x-tenant", tenant);
+        }
+        let request = match body {
+            Some(body) => builder
+                .method(Method::POST)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        };
+        app.clone().oneshot(request.unwrap())
+    };
+
+    let payload = structure_payload("Sam", "SceneTenant", 1, 0, "sign");
+    let response = send("/t/eu/api/v1/structures", None, Some(payload))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send("/api/v1/structures?scene=SceneTenant", Some("eu"), None)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+    main.clear_get_rate_limit(OWNER_ID);
+    let response = send("/api/v1/structures?scene=SceneTenant", None, None)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await, json!([]));
+
+    let response = send("/api/v1/structures", Some("us"), None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response_json(response).await["code"], "unknown_tenant");
+}
+
+#[tokio::test]
+async fn map_overrides_apply_to_fetch_limit_and_ttl() {
+    let ctx = TestContext::with_config(|config| {
+        config.map_overrides.insert(
+            7,
+            MapOverrides {
+                default_random_limit: Some(1),
+                structure_ttl_days: Some(1),
+                ..Default::default()
+            },
+        );
+    })
+    .await;
+    let mut ids = Vec::new();
+    for (ticket, steam_id) in [(OWNER_TICKET, OWNER_ID), (LIKER_TICKET, LIKER_ID)] {
+        for map_id in [1, 7] {
+            ids.push(
+                create_structure(
+                    &ctx,
+                    ticket,
+                    steam_id,
+                    "Builder",
+                    "SceneMaps",
+                    map_id,
+                    0,
+                    "prefab_map",
+                )
+                .await,
+            );
+        }
+    }
+    // one map 7 structure is past its TTL; the same age is fine on map 1
+    sqlx::query("UPDATE structures SET created_at = created_at - ? WHERE id IN (?, ?)")
+        .bind(2 * MILLIS_IN_DAY)
+        .bind(ids[0])
+        .bind(ids[1])
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let fresh = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Builder",
+        "SceneMaps",
+        7,
+        0,
+        "prefab_map",
+    )
+    .await;
+
+    let response = ctx
+        .get_random(OTHER_TICKET, "?scene=SceneMaps&map_id=7&limit=4")
+        .await;
+    let body = response_json(response).await;
+    let mut returned: Vec<i64> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["id"].as_i64().unwrap())
+        .collect();
+    returned.sort();
+    assert_eq!(returned, vec![ids[3], fresh]);
+
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let response = ctx
+        .get_random(OTHER_TICKET, "?scene=SceneMaps&map_id=7")
+        .await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 1);
+
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let response = ctx
+        .get_random(OTHER_TICKET, "?scene=SceneMaps&map_id=1")
+        .await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn reactions_are_counted_per_kind_and_in_likes() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneReact",
+        1,
+        0,
+        "prefab_react",
+    )
+    .await;
+
+    for body in [
+        json!({ "count": 2 }),
+        json!({ "count": 3, "reaction": "heart" }),
+        json!({ "reaction": "heart" }),
+    ] {
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        let response = ctx.like_structure(LIKER_TICKET, id, body).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "reaction": "clown" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneReact").await;
+    let body = response_json(response).await;
+    assert_eq!(body[0]["likes"], 6);
+    assert_eq!(body[0]["reactions"], json!({ "thumbs_up": 2, "heart": 4 }));
+}
+
+#[tokio::test]
+async fn dedupe_fetches_collapse_identical_builds_to_the_most_liked() {
+    let ctx = TestContext::new().await;
+    let first =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneDup", [1.0, 2.0, 3.0]).await;
+    let liked =
+        create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneDup", [1.05, 2.0, 3.0]).await;
+    let third =
+        create_structure_at(&ctx, OTHER_TICKET, OTHER_ID, "SceneDup", [0.98, 2.0, 3.0]).await;
+    let apart =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneDup", [5.0, 2.0, 3.0]).await;
+    sqlx::query("UPDATE structures SET likes = 5 WHERE id = ?")
+        .bind(liked)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let hashes: Vec<Option<i64>> = sqlx::query_scalar(
+        "SELECT content_hash FROM structures WHERE id IN (?, ?, ?, ?) ORDER BY id",
+    )
+    .bind(first)
+    .bind(liked)
+    .bind(third)
+    .bind(apart)
+    .fetch_all(&ctx.state.db)
+    .await
+    .unwrap();
+    assert!(hashes.iter().all(Option::is_some));
+    assert_eq!(hashes[0], hashes[1]);
+    assert_eq!(hashes[0], hashes[2]);
+    assert_ne!(hashes[0], hashes[3]);
+
+    let ids = |body: Value| {
+        let mut ids: Vec<i64> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+    let response = ctx
+        .get_random(OWNER_TICKET, "?scene=SceneDup&limit=4")
+        .await;
+    assert_eq!(response_json(response).await.as_array().unwrap().len(), 4);
+    ctx.clear_get_rate_limit(OWNER_ID);
+    let response = ctx
+        .get_random(OWNER_TICKET, "?scene=SceneDup&limit=4&dedupe=true")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(ids(response_json(response).await), vec![liked, apart]);
+}
+
+#[tokio::test]
+async fn usage_reports_count_once_per_window_and_skip_owner() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneUsage",
+        1,
+        0,
+        "prefab_rope",
+    )
+    .await;
+    let report = |ticket: &'static str, id: i64| {
+        ctx.app.clone().oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("/api/v1/structures/{id}/usage"))
+                .header(&STEAM_HEADER, ticket)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    assert_eq!(
+        report(LIKER_TICKET, id).await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        report(LIKER_TICKET, id).await.unwrap().status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        report(OTHER_TICKET, id).await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        report(OWNER_TICKET, id).await.unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        report(OTHER_TICKET, 999).await.unwrap().status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let response = ctx.get_random(LIKER_TICKET, "?scene=SceneUsage").await;
+    let body = response_json(response).await;
+    assert_eq!(body[0]["uses"], 2);
+}
+
+#[tokio::test]
+async fn post_structure_returns_existing_row_for_recent_duplicate() {
+    let ctx = TestContext::new().await;
+    let payload = structure_payload("Sam", "SceneDup", 1, 0, "prefab_dup");
+
+    let first = response_json(ctx.post_structure(OWNER_TICKET, payload.clone()).await).await;
+    ctx.clear_post_rate_limit(OWNER_ID);
+
+    let mut nudged = payload.clone();
+    nudged["pos_y"] = json!(2.005);
+    let second = response_json(ctx.post_structure(OWNER_TICKET, nudged).await).await;
+    assert_eq!(second["id"], first["id"]);
+    ctx.clear_post_rate_limit(OWNER_ID);
+
+    let mut moved = payload.clone();
+    moved["pos_y"] = json!(2.5);
+    let third = response_json(ctx.post_structure(OWNER_TICKET, moved).await).await;
+    assert_ne!(third["id"], first["id"]);
+
+    // other players building the same thing in the same spot are not duplicates
+    let other = response_json(ctx.post_structure(OTHER_TICKET, payload).await).await;
+    assert_ne!(other["id"], first["id"]);
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM structures")
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+}
+
+async fn create_structure_at(
+    ctx: &TestContext,
+    ticket: &str,
+    steam_id: u64,
+    scene: &str,
+    pos: [f32; 3],
+) -> i64 {
+    let mut payload = structure_payload("Builder", scene, 1, 0, "prefab_spot");
+    payload["pos_x"] = json!(pos[0]);
+    payload["pos_y"] = json!(pos[1]);
+    payload["pos_z"] = json!(pos[2]);
+    let response = ctx.post_structure(ticket, payload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    ctx.clear_post_rate_limit(steam_id);
+    response_json(response).await["id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn nearby_returns_structures_within_radius_nearest_first() {
+    let ctx = TestContext::new().await;
+    let far =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneNear", [100.0, 0.0, 0.0]).await;
+    let near =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneNear", [3.0, 4.0, 0.0]).await;
+    let nearest =
+        create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneNear", [1.0, 0.0, 1.0]).await;
+    // inside the bounding box but outside the sphere
+    let corner =
+        create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneNear", [4.0, 4.0, 4.0]).await;
+
+    let response = ctx
+        .get_nearby(OTHER_TICKET, "?scene=SceneNear&x=0&y=0&z=0&radius=5")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let ids: Vec<i64> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![nearest, near]);
+    assert!(!ids.contains(&far) && !ids.contains(&corner));
+
+    ctx.state.nearby_rate_limiter.remove(&OTHER_ID);
+    let response = ctx
+        .get_nearby(OTHER_TICKET, "?scene=SceneNear&x=0&y=0&z=0&radius=500")
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn nearby_leaves_out_structures_past_the_ttl() {
+    let ctx = TestContext::with_config(|config| config.structure_ttl_days = 1).await;
+    let expired =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneOld", [1.0, 0.0, 0.0]).await;
+    let fresh =
+        create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneOld", [2.0, 0.0, 0.0]).await;
+    sqlx::query("UPDATE structures SET created_at = created_at - ? WHERE id = ?")
+        .bind(2 * MILLIS_IN_DAY)
+        .bind(expired)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let response = ctx
+        .get_nearby(OTHER_TICKET, "?scene=SceneOld&x=0&y=0&z=0&radius=5")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    let ids: Vec<i64> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![fresh]);
+}
+
+#[tokio::test]
+async fn post_structure_rejects_placements_in_crowded_spots() {
+    let ctx = TestContext::with_config(|config| config.density_max_structures = 2).await;
+    create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneDense", [0.0, 0.0, 0.0]).await;
+    create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneDense", [1.0, 0.0, 0.0]).await;
+
+    let mut crowded = structure_payload("Other", "SceneDense", 1, 0, "prefab_spot");
+    crowded["pos_x"] = json!(0.5);
+    crowded["pos_y"] = json!(0.0);
+    crowded["pos_z"] = json!(0.0);
+    let response = ctx.post_structure(OTHER_TICKET, crowded.clone()).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    ctx.clear_post_rate_limit(OTHER_ID);
+
+    // other segments and spots further away are unaffected
+    crowded["segment"] = json!(1);
+    let response = ctx.post_structure(OTHER_TICKET, crowded).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    ctx.clear_post_rate_limit(OTHER_ID);
+    create_structure_at(&ctx, OTHER_TICKET, OTHER_ID, "SceneDense", [10.0, 0.0, 0.0]).await;
+}
+
+#[tokio::test]
+async fn post_structure_enforces_min_distance_between_own_structures() {
+    let ctx = TestContext::with_config(|config| config.min_own_structure_distance = 3.0).await;
+    create_structure_at(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "SceneSpacing",
+        [0.0, 0.0, 0.0],
+    )
+    .await;
+
+    let mut close = structure_payload("Owner", "SceneSpacing", 1, 0, "prefab_spot");
+    close["pos_x"] = json!(2.0);
+    close["pos_y"] = json!(0.0);
+    close["pos_z"] = json!(0.0);
+    let response = ctx.post_structure(OWNER_TICKET, close.clone()).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    ctx.clear_post_rate_limit(OWNER_ID);
+
+    // a different prefab, or another player, may stand right there
+    let mut other_prefab = close.clone();
+    other_prefab["prefab"] = json!("prefab_other");
+    let response = ctx.post_structure(OWNER_TICKET, other_prefab).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = ctx.post_structure(OTHER_TICKET, close).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn queued_posts_are_stored_by_the_writer_and_can_be_polled() {
+    let ctx = TestContext::with_config(|config| {
+        config.post_queue_capacity = 8;
+        config.density_max_structures = 1;
+    })
+    .await;
+    let poll = |ticket: &'static str, guid: String| {
+        let ctx = &ctx;
+        async move {
+            let uri = format!("/api/v1/structures/queued/{guid}");
+            for _ in 0..100 {
+                let response = ctx.user_request(ticket, Method::GET, &uri).await;
+                if response.status() != StatusCode::ACCEPTED {
+                    return response;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("upload {guid} was never written");
+        }
+    };
+
+    let mut payload = structure_payload("Owner", "SceneQueue", 1, 0, "prefab_q");
+    payload["pos_x"] = json!(0.0);
+    let response = ctx.post_structure(OWNER_TICKET, payload.clone()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body = response_json(response).await;
+    assert_eq!(body["status"], "queued");
+    let guid = body["client_guid"].as_str().unwrap().to_string();
+
+    let response = poll(OWNER_TICKET, guid.clone()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stored = response_json(response).await;
+    assert_eq!(stored["scene"], "SceneQueue");
+    let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM structures WHERE id = ?")
+        .bind(stored["id"].as_i64().unwrap())
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+
+    // only the uploader can poll, in either API version
+    let response = poll(LIKER_TICKET, guid.clone()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = ctx
+        .user_request(
+            OWNER_TICKET,
+            Method::GET,
+            &format!("/api/v2/structures/queued/{guid}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["position"][1], 2.0);
+
+    // rejections are reported the way a direct post would answer them
+    payload["username"] = json!("Liker");
+    let response = ctx.post_structure(LIKER_TICKET, payload).await;
+    let guid = response_json(response).await["client_guid"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = poll(LIKER_TICKET, guid).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(response_json(response).await["code"], "too_crowded");
+}
+
+#[tokio::test]
+async fn admin_structure_browser_filters_and_paginates() {
+    let ctx = TestContext::new().await;
+    let mut owner_ids = Vec::new();
+    for scene in ["SceneMod1", "SceneMod2", "SceneMod3"] {
+        owner_ids.push(
+            create_structure(
+                &ctx,
+                OWNER_TICKET,
+                OWNER_ID,
+                "Owner",
+                scene,
+                1,
+                0,
+                "prefab_mod",
+            )
+            .await,
+        );
+    }
+    let liked = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneMod1",
+        1,
+        0,
+        "prefab_mod",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET likes = 9, deleted = 1 WHERE id = ?")
+        .bind(liked)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let page = |query: String| {
+        let ctx = &ctx;
+        async move {
+            let response = ctx
+                .admin_request(
+                    Method::GET,
+                    &format!("/admin/v1/structures{query}"),
+                    Some(ADMIN_KEY),
+                    Body::empty(),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+    let ids = |body: &Value| -> Vec<i64> {
+        body["structures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect()
+    };
+
+    let first = page(format!("?user_id={OWNER_ID}&limit=2")).await;
+    assert_eq!(ids(&first), vec![owner_ids[2], owner_ids[1]]);
+    let cursor = first["next_before_id"].as_i64().unwrap();
+    let second = page(format!("?user_id={OWNER_ID}&limit=2&before_id={cursor}")).await;
+    assert_eq!(ids(&second), vec![owner_ids[0]]);
+    assert!(second["next_before_id"].is_null());
+
+    let flagged = page("?deleted=true&min_likes=5&scene=SceneMod1".to_string()).await;
+    assert_eq!(ids(&flagged), vec![liked]);
+    assert_eq!(flagged["structures"][0]["deleted"], true);
+}
+
+#[tokio::test]
+async fn admin_like_abuse_flags_reciprocal_pairs_and_unfetched_likes() {
+    let ctx = TestContext::with_config(|config| config.fetch_history_days = 7).await;
+    let owners = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Sam",
+        "SceneRing",
+        1,
+        0,
+        "prefab_1",
+    )
+    .await;
+    let likers = create_structure(
+        &ctx,
+        LIKER_TICKET,
+        LIKER_ID,
+        "Lee",
+        "SceneElsewhere",
+        1,
+        0,
+        "prefab_1",
+    )
+    .await;
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneRing").await;
+    assert_eq!(response_json(response).await[0]["id"], owners);
+    ctx.state.fetch_log.flush(&ctx.state.db).await.unwrap();
+
+    for (ticket, steam_id, id, count) in [
+        (LIKER_TICKET, LIKER_ID, owners, 3),
+        (OWNER_TICKET, OWNER_ID, likers, 4),
+        (OTHER_TICKET, OTHER_ID, owners, 5),
+    ] {
+        ctx.state.post_like_rate_limiter.remove(&steam_id);
+        let response = ctx
+            .like_structure(ticket, id, json!({ "count": count }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/likes/suspicious?min_likes=3",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response_json(response).await;
+    assert_eq!(
+        report["reciprocal"],
+        json!([{ "user_a": OWNER_ID, "user_b": LIKER_ID, "a_to_b": 4, "b_to_a": 3 }])
+    );
+    // the other player was served the structure they liked
+    assert_eq!(
+        report["unfetched"],
+        json!([
+            { "liker_id": OWNER_ID, "likes": 4, "unfetched": 4 },
+            { "liker_id": LIKER_ID, "likes": 3, "unfetched": 3 }
+        ])
+    );
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/likes/suspicious?min_likes=4",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    let report = response_json(response).await;
+    assert_eq!(report["reciprocal"], json!([]));
+    assert_eq!(report["unfetched"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn admin_db_sizes_count_rows_and_measure_tables_and_indexes() {
+    let ctx = TestContext::new().await;
+    for segment in 0..2 {
+        create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Sam",
+            "SceneSize",
+            1,
+            segment,
+            "prefab_1",
+        )
+        .await;
+    }
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/db-stats",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response_json(response).await;
+    assert_eq!(report["tables"]["structures"]["rows"], 2);
+    assert_eq!(report["tables"]["favorites"]["rows"], 0);
+    assert!(report["tables"]["structures"]["bytes"].as_i64().unwrap() > 0);
+    assert_eq!(
+        report["indexes"]["idx_likes_ledger_liker"]["table"],
+        "likes_ledger"
+    );
+    assert!(report["indexes"]["idx_likes_ledger_liker"]["bytes"].is_i64());
+    let page_size = report["page_size"].as_i64().unwrap();
+    assert!(page_size > 0 && report["page_count"].as_i64().unwrap() > 0);
+    // the test database lives in memory
+    assert_eq!(report["file_bytes"], Value::Null);
+}
+
+#[tokio::test]
+async fn admin_heatmap_bins_positions_per_scene_as_json_or_csv() {
+    let ctx = TestContext::new().await;
+    create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneHeat", [1.0, 2.0, 3.0]).await;
+    create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneHeat", [9.5, 0.0, 9.9]).await;
+    create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneHeat", [-0.5, 12.0, 3.0]).await;
+    create_structure_at(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Scene,Other",
+        [25.0, 0.0, 0.0],
+    )
+    .await;
+    let deleted =
+        create_structure_at(&ctx, OTHER_TICKET, OTHER_ID, "SceneHeat", [1.0, 1.0, 1.0]).await;
+    sqlx::query("UPDATE structures SET deleted = 1 WHERE id = ?")
+        .bind(deleted)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/heatmap",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response_json(response).await,
+        json!({
+            "season": 1,
+            "cell": 10.0,
+            "scenes": {
+                "Scene,Other": [{ "x": 2, "y": 0, "z": 0, "count": 1 }],
+                "SceneHeat": [
+                    { "x": -1, "y": 1, "z": 0, "count": 1 },
+                    { "x": 0, "y": 0, "z": 0, "count": 2 },
+                ],
+            },
+        })
+    );
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/heatmap?cell=5&format=csv",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/csv");
+    assert_eq!(
+        response_text(response).await,
+        "scene,x,y,z,count\n\
+         \"Scene,Other\",5,0,0,1\n\
+         SceneHeat,-1,2,0,1\n\
+         SceneHeat,0,0,0,1\n\
+         SceneHeat,1,0,1,1\n"
+    );
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/heatmap?scene=SceneHeat&cell=0.5",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn admin_search_matches_substrings_likes_and_bounding_boxes() {
+    let ctx = TestContext::new().await;
+    let mut spawn_payload = structure_payload("LadderLord", "SceneFind", 1, 0, "Ladder_Tall");
+    spawn_payload["pos_x"] = json!(1.0);
+    let response = ctx.post_structure(OWNER_TICKET, spawn_payload).await;
+    let tower = response_json(response).await["id"].as_i64().unwrap();
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let mut far_payload = structure_payload("Other", "SceneFind", 1, 0, "Ladder_Short");
+    far_payload["pos_x"] = json!(500.0);
+    let response = ctx.post_structure(OTHER_TICKET, far_payload).await;
+    let far = response_json(response).await["id"].as_i64().unwrap();
+    let rope = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "LadderLord",
+        "SceneFind",
+        1,
+        0,
+        "Rope_Spool",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET likes = 12 WHERE id = ?")
+        .bind(tower)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+
+    let search = |query: &str| {
+        let ctx = &ctx;
+        let uri = format!("/admin/v1/structures/search{query}");
+        async move {
+            let response = ctx
+                .admin_request(Method::GET, &uri, Some(ADMIN_KEY), Body::empty())
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response_json(response).await;
+            body["structures"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["id"].as_i64().unwrap())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // trigram index, case-insensitive
+    assert_eq!(search("?prefab=LADDER").await, vec![far, tower]);
+    assert_eq!(search("?prefab=ladder&username=dderlo").await, vec![tower]);
+    // too short for trigrams
+    assert_eq!(search("?username=ot").await, vec![far]);
+    assert_eq!(search("?prefab=ladder&min_likes=10").await, vec![tower]);
+    assert_eq!(search("?prefab=ladder&max_likes=0").await, vec![far]);
+    assert_eq!(
+        search("?scene=SceneFind&prefab=ladder&min_x=0&max_x=10&min_y=0&max_z=5").await,
+        vec![tower]
+    );
+
+    // renames and deletions reach the index
+    sqlx::query("UPDATE structures SET prefab = 'Ladder_Rope' WHERE id = ?")
+        .bind(rope)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM structures WHERE id = ?")
+        .bind(far)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(search("?prefab=ladder").await, vec![rope, tower]);
+    let indexed: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM structure_search WHERE structure_search MATCH 'prefab : \"short\"'",
+    )
+    .fetch_one(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(indexed, 0);
+}
+
+#[tokio::test]
+async fn admin_purge_soft_deletes_user_structures_and_audits() {
+    let ctx = TestContext::new().await;
+    for scene in ["ScenePurgeA", "ScenePurgeB"] {
+        create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Griefer",
+            scene,
+            1,
+            0,
+            "prefab_grief",
+        )
+        .await;
+    }
+    let bystander = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "ScenePurgeA",
+        1,
+        0,
+        "prefab_ok",
+    )
+    .await;
+
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            &format!("/admin/v1/users/{OWNER_ID}/purge?scene=ScenePurgeA"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response_json(response).await, json!({ "purged": 1 }));
+
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            &format!("/admin/v1/users/{OWNER_ID}/purge"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response_json(response).await, json!({ "purged": 1 }));
+
+    let live: Vec<i64> = sqlx::query_scalar("SELECT id FROM structures WHERE deleted = 0")
+        .fetch_all(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(live, vec![bystander]);
+
+    let audit: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT action, target, details FROM admin_audit_log ORDER BY id")
+            .fetch_all(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(audit.len(), 2);
+    assert_eq!(audit[0].0, "purge_user");
+    assert_eq!(audit[0].1, OWNER_ID.to_string());
+    let details: Value = serde_json::from_str(&audit[0].2).unwrap();
+    assert_eq!(details, json!({ "scene": "ScenePurgeA", "purged": 1 }));
+}
+
+#[tokio::test]
+async fn upload_banned_users_get_403_until_unbanned() {
+    let ctx = TestContext::with_config(|config| {
+        config.post_structure_rate_limit = Duration::ZERO;
+    })
+    .await;
+    set_upload_banned(&ctx.state.db, OWNER_ID as i64, true)
+        .await
+        .unwrap();
+
+    let payload = structure_payload("Banned", "SceneBan", 1, 0, "prefab_ban");
+    let response = ctx.post_structure(OWNER_TICKET, payload.clone()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response_json(response).await["code"], "upload_banned");
+
+    set_upload_banned(&ctx.state.db, OWNER_ID as i64, false)
+        .await
+        .unwrap();
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let actions: Vec<String> = sqlx::query_scalar("SELECT action FROM admin_audit_log ORDER BY id")
+        .fetch_all(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(actions, vec!["ban_user", "unban_user"]);
+}
+
+#[tokio::test]
+async fn server_info_describes_features_and_limits_without_a_ticket() {
+    let ctx = TestContext::with_config(|config| {
+        config.enable_likes = false;
+        config.daily_upload_quota = 5;
+    })
+    .await;
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v2/server-info")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["server_version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["api_versions"], json!([1, 2]));
+    assert_eq!(body["features"]["likes"], false);
+    assert_eq!(body["features"]["uploads"], true);
+    assert_eq!(
+        body["limits"]["max_structures_per_fetch"],
+        ctx.state.config().max_requested_structs
+    );
+    assert_eq!(body["limits"]["daily_upload_quota"], 5);
+    assert_eq!(
+        body["limits"]["rate_limits"]["post_structure"],
+        ctx.state.config().post_structure_rate_limit.as_secs_f64()
+    );
+}
+
+#[tokio::test]
+async fn outdated_mods_are_refused_with_upgrade_required() {
+    let ctx = TestContext::with_config(|config| {
+        config.min_client_version = Some("1.4".parse().unwrap());
+    })
+    .await;
+    let request = |ticket: &str, version: Option<&str>, uri: &str| {
+        let mut builder = Request::builder().uri(uri).header(&STEAM_HEADER, ticket);
+        if let Some(version) = version {
+            builder = builder.header("x-mod-version", version);
+        }
+        ctx.app
+            .clone()
+            .oneshot(builder.body(Body::empty()).unwrap())
+    };
+
+    for version in [None, Some("1.3.9"), Some("garbage")] {
+        let response = request(OWNER_TICKET, version, "/api/v1/stats/me")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        let body = response_json(response).await;
+        assert_eq!(body["code"], "client_outdated");
+        assert!(body["message"].as_str().unwrap().contains("1.4"));
+    }
+    for (version, ticket) in [
+        ("1.4.0", OWNER_TICKET),
+        ("1.10", LIKER_TICKET),
+        ("v2.0.0-beta", OTHER_TICKET),
+    ] {
+        let response = request(ticket, Some(version), "/api/v1/stats/me")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{version}");
+    }
+
+    let response = request(OWNER_TICKET, None, "/api/v1/server-info")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["min_client_version"], "1.4");
+}
+
+#[tokio::test]
+async fn private_servers_want_the_password_or_a_live_invite() {
+    let ctx = TestContext::with_config(|config| {
+        config.server_password = Some("hunter2".into());
+    })
+    .await;
+    let request = |ticket: &str, password: Option<&str>, uri: &str| {
+        let mut builder = Request::builder().uri(uri).header(&STEAM_HEADER, ticket);
+        if let Some(password) = password {
+            builder = builder.header("x-server-password", password);
+        }
+        ctx.app
+            .clone()
+            .oneshot(builder.body(Body::empty()).unwrap())
+    };
+
+    for password in [None, Some("hunter3")] {
+        let response = request(OWNER_TICKET, password, "/api/v1/stats/me")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response_json(response).await["code"], "private_server");
+    }
+    let response = request(OWNER_TICKET, Some("hunter2"), "/api/v1/stats/me")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request(OWNER_TICKET, None, "/api/v1/server-info")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["private"], true);
+
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/admin/v1/invites")
+                .header(&ADMIN_HEADER, ADMIN_KEY)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "note": "liker" }).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let code = response_json(response).await["code"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let response = request(LIKER_TICKET, Some(&code), "/api/v1/stats/me")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = ctx
+        .admin_request(
+            Method::DELETE,
+            &format!("/admin/v1/invites/{code}"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = request(OTHER_TICKET, Some(&code), "/api/v1/stats/me")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/invites",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    let body = response_json(response).await;
+    assert_eq!(body["invites"][0]["note"], "liker");
+    assert!(body["invites"][0]["revoked_at"].is_i64());
+}
+
+#[tokio::test]
+async fn whitelist_mode_only_admits_allowlisted_players() {
+    let ctx = TestContext::with_config(|config| {
+        config.whitelist_mode = true;
+    })
+    .await;
+    let stats_me = || ctx.get_user_stats(OWNER_TICKET);
+    let owner_uri = format!("/admin/v1/allowlist/{OWNER_ID}");
+    let allowlist =
+        |method: Method| ctx.admin_request(method, &owner_uri, Some(ADMIN_KEY), Body::empty());
+
+    let response = stats_me().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response_json(response).await["code"], "not_allowlisted");
+
+    assert_eq!(
+        allowlist(Method::PUT).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(stats_me().await.status(), StatusCode::OK);
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/allowlist",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(
+        response_json(response).await["steam_ids"],
+        json!([OWNER_ID])
+    );
+
+    assert_eq!(
+        allowlist(Method::DELETE).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(stats_me().await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn motd_shows_only_the_announcements_running_now() {
+    let ctx = TestContext::new().await;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should be valid")
+        .as_millis() as i64;
+    let announce = |body: Value| {
+        ctx.app.clone().oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/admin/v1/announcements")
+                .header(&ADMIN_HEADER, ADMIN_KEY)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+
+    let mut ids = Vec::new();
+    for body in [
+        json!({ "message": "Maintenance tonight", "ends_at": now_ms + MILLIS_IN_DAY }),
+        json!({ "message": "Next week's event", "starts_at": now_ms + MILLIS_IN_DAY }),
+        json!({ "message": "Yesterday's event", "starts_at": now_ms - 2 * MILLIS_IN_DAY, "ends_at": now_ms - MILLIS_IN_DAY }),
+    ] {
+        let response = announce(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        ids.push(response_json(response).await["id"].as_i64().unwrap());
+    }
+    let response = announce(json!({ "message": "  " })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let motd = || {
+        ctx.app.clone().oneshot(
+            Request::builder()
+                .uri("/api/v1/motd")
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+    let body = response_json(motd().await.unwrap()).await;
+    assert_eq!(body["announcements"].as_array().unwrap().len(), 1);
+    assert_eq!(body["announcements"][0]["message"], "Maintenance tonight");
+
+    let response = ctx
+        .admin_request(
+            Method::DELETE,
+            &format!("/admin/v1/announcements/{}", ids[0]),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let body = response_json(motd().await.unwrap()).await;
+    assert_eq!(body["announcements"], json!([]));
+}
+
+#[tokio::test]
+async fn server_events_multiply_likes_and_feature_scenes_while_running() {
+    let ctx = TestContext::new().await;
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock should be valid")
+        .as_millis() as i64;
+    let schedule = |body: Value| {
+        ctx.app.clone().oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/admin/v1/server-events")
+                .header(&ADMIN_HEADER, ADMIN_KEY)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneEvent",
+        1,
+        0,
+        "prefab_event",
+    )
+    .await;
+
+    let response = schedule(json!({ "kind": "like_multiplier", "multiplier": 1, "starts_at": now_ms - 1000, "ends_at": now_ms + MILLIS_IN_DAY })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = schedule(json!({ "kind": "featured_scene", "starts_at": now_ms - 1000, "ends_at": now_ms + MILLIS_IN_DAY })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = schedule(json!({ "name": "Double likes weekend", "kind": "like_multiplier", "multiplier": 2, "starts_at": now_ms - 1000, "ends_at": now_ms + MILLIS_IN_DAY })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let double_likes = response_json(response).await["id"].as_i64().unwrap();
+    let response = schedule(json!({ "kind": "featured_scene", "scene": "SceneEvent", "starts_at": now_ms - 1000, "ends_at": now_ms + MILLIS_IN_DAY })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = schedule(json!({ "kind": "relaxed_caps", "multiplier": 3, "starts_at": now_ms + MILLIS_IN_DAY, "ends_at": now_ms + 2 * MILLIS_IN_DAY })).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let events = response_json(
+        ctx.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/server-events")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(events["events"].as_array().unwrap().len(), 3);
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let likes: i64 = sqlx::query_scalar("SELECT likes FROM structures WHERE id = ?")
+        .bind(id)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 2);
+    let sent: i64 = sqlx::query_scalar("SELECT likes_send FROM users WHERE user_id = ?")
+        .bind(LIKER_ID as i64)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(sent, 1);
+
+    let scenes = response_json(
+        ctx.app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/scenes")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(scenes["scenes"][0]["featured"], true);
+
+    let response = ctx
+        .admin_request(
+            Method::DELETE,
+            &format!("/admin/v1/server-events/{double_likes}"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let likes: i64 = sqlx::query_scalar("SELECT likes FROM structures WHERE id = ?")
+        .bind(id)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 3);
+}
+
+#[tokio::test]
+async fn owners_edit_pose_in_place_keeping_id_and_likes() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneEdit",
+        1,
+        0,
+        "sign",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET likes = 4 WHERE id = ?")
+        .bind(id)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    let (created_at, old_hash): (i64, i64) =
+        sqlx::query_as("SELECT created_at, content_hash FROM structures WHERE id = ?")
+            .bind(id)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+
+    let uri = format!("/api/v1/structures/{id}");
+    let response = ctx
+        .user_patch(OTHER_TICKET, &uri, "1", json!({ "rot_y": 0.7 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = ctx
+        .user_patch(OWNER_TICKET, &uri, "1", json!({ "likes": 100 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = ctx
+        .user_patch(
+            OWNER_TICKET,
+            &uri,
+            "1",
+            json!({ "pos_y": 8.0, "rot_y": 0.5, "rot_w": 1.0 }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["id"], id);
+    assert_eq!(body["likes"], 4);
+    assert_eq!(body["created_at"], created_at);
+    assert_eq!(body["pos_y"], 8.0);
+    assert_eq!(body["rot_y"], 0.5);
+    assert!(body["updated_at"].as_i64().is_some());
+
+    let (changes, new_hash): (String, i64) = sqlx::query_as(
+        r#"SELECT e.changes, s.content_hash FROM structure_edits e
+           JOIN structures s ON s.id = e.structure_id WHERE e.structure_id = ?"#,
+    )
+    .bind(id)
+    .fetch_one(&ctx.state.db)
+    .await
+    .unwrap();
+    // rot_w was already 1.0
+    assert_eq!(
+        serde_json::from_str::<Value>(&changes).unwrap(),
+        json!({ "pos_y": [2.0, 8.0], "rot_y": [0.0, 0.5] })
+    );
+    assert_ne!(new_hash, old_hash);
+
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let response = ctx
+        .user_patch(
+            OWNER_TICKET,
+            &format!("/api/v2/structures/{id}"),
+            "\"2\"",
+            json!({ "position": [1.0, 2.0, 3.0] }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["position"], json!([1.0, 2.0, 3.0]));
+    assert_eq!(body["rotation"], json!([0.0, 0.5, 0.0, 1.0]));
+    assert_eq!(body["likes"], 4);
+    assert_eq!(body["version"], 3);
+}
+
+#[tokio::test]
+async fn stale_edits_are_refused_with_a_version_conflict() {
+    let ctx = TestContext::new().await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneEdit",
+        1,
+        0,
+        "sign",
+    )
+    .await;
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneEdit").await;
+    assert_eq!(response_json(response).await[0]["version"], 1);
+
+    let uri = format!("/api/v1/structures/{id}");
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::PATCH)
+                .uri(&uri)
+                .header(&STEAM_HEADER, OWNER_TICKET)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "pos_x": 5.0 }).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+    assert_eq!(response_json(response).await["code"], "version_required");
+
+    // two devices both start from version 1
+    let response = ctx
+        .user_patch(OWNER_TICKET, &uri, "\"1\"", json!({ "pos_x": 5.0 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["version"], 2);
+    ctx.clear_post_rate_limit(OWNER_ID);
+    let response = ctx
+        .user_patch(OWNER_TICKET, &uri, "\"1\"", json!({ "pos_x": 9.0 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(response_json(response).await["code"], "version_conflict");
+
+    let pos_x: f32 = sqlx::query_scalar("SELECT pos_x FROM structures WHERE id = ?")
+        .bind(id)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(pos_x, 5.0);
+}
+
+#[tokio::test]
+async fn edits_go_through_the_upload_spacing_and_plausibility_checks() {
+    let ctx = TestContext::with_config(|config| {
+        config.density_max_structures = 2;
+        config.min_own_structure_distance = 3.0;
+        config.plausibility_checks = PlausibilityMode::Flag;
+    })
+    .await;
+    let near =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneEdits", [0.0, 0.0, 0.0]).await;
+    let far =
+        create_structure_at(&ctx, OWNER_TICKET, OWNER_ID, "SceneEdits", [10.0, 0.0, 0.0]).await;
+    create_structure_at(&ctx, LIKER_TICKET, LIKER_ID, "SceneEdits", [30.0, 0.0, 0.0]).await;
+    create_structure_at(&ctx, OTHER_TICKET, OTHER_ID, "SceneEdits", [32.0, 0.0, 0.0]).await;
+    let edit = async |id: i64, version: &str, body: Value| {
+        ctx.clear_post_rate_limit(OWNER_ID);
+        let response = ctx
+            .user_patch(
+                OWNER_TICKET,
+                &format!("/api/v1/structures/{id}"),
+                version,
+                body,
+            )
+            .await;
+        (response.status(), response_json(response).await)
+    };
+
+    // a nudge doesn't count the structure against itself
+    let (status, _) = edit(near, "1", json!({ "pos_x": 1.0 })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = edit(far, "1", json!({ "pos_x": 2.0 })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "too_close");
+    let (status, body) = edit(far, "1", json!({ "pos_x": 31.0 })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "too_crowded");
+
+    // saved, but out of every fetch until a moderator approves it
+    let (status, body) = edit(far, "1", json!({ "rope_length": 500.0 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["under_review"], true);
+    let (deleted_by, rope_length): (String, f32) =
+        sqlx::query_as("SELECT deleted_by, rope_length FROM structures WHERE id = ?")
+            .bind(far)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(deleted_by, "review");
+    assert_eq!(rope_length, 500.0);
+}
+
+#[tokio::test]
+async fn restores_respect_the_scene_cap_and_the_pin_limit() {
+    let ctx = TestContext::new().await;
+    let create = async |prefab: &str| {
+        let id = create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Owner",
+            "SceneRefill",
+            1,
+            0,
+            prefab,
+        )
+        .await;
+        ctx.clear_post_rate_limit(OWNER_ID);
+        id
+    };
+    let call = async |method: Method, uri: String| {
+        let response = ctx.user_request(OWNER_TICKET, method, &uri).await;
+        let status = response.status();
+        let code = match status {
+            StatusCode::NO_CONTENT => Value::Null,
+            _ => response_json(response).await["code"].clone(),
+        };
+        (status, code)
+    };
+
+    let pinned = create("prefab_a").await;
+    call(Method::POST, format!("/api/v1/structures/{pinned}/pin")).await;
+    call(Method::DELETE, format!("/api/v1/structures/{pinned}")).await;
+    let kept = create("prefab_b").await;
+    let extra = create("prefab_c").await;
+    let restore = format!("/api/v1/structures/{pinned}/restore");
+
+    // two live structures fill the scene
+    let (status, code) = call(Method::POST, restore.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(code, "scene_full");
+
+    // room again, but another pin now takes the only pin slot
+    call(Method::DELETE, format!("/api/v1/structures/{extra}")).await;
+    call(Method::POST, format!("/api/v1/structures/{kept}/pin")).await;
+    let (status, code) = call(Method::POST, restore.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(code, "pin_limit");
+
+    call(Method::DELETE, format!("/api/v1/structures/{kept}/pin")).await;
+    let (status, _) = call(Method::POST, restore).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn owners_and_admins_can_restore_deleted_structures() {
+    let ctx = TestContext::new().await;
+    let own = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneRestore",
+        1,
+        0,
+        "prefab_undo",
+    )
+    .await;
+    let other = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneRestore",
+        1,
+        0,
+        "prefab_other",
+    )
+    .await;
+
+    let response = ctx
+        .user_request(
+            OWNER_TICKET,
+            Method::DELETE,
+            &format!("/api/v1/structures/{other}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = ctx
+        .user_request(
+            OWNER_TICKET,
+            Method::DELETE,
+            &format!("/api/v1/structures/{own}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneRestore").await;
+    let ids: Vec<i64> = response_json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![other]);
+
+    let restore_own = format!("/api/v1/structures/{own}/restore");
+    let response = ctx
+        .user_request(OTHER_TICKET, Method::POST, &restore_own)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = ctx
+        .user_request(OWNER_TICKET, Method::POST, &restore_own)
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let deleted: (bool, Option<i64>, Option<String>) =
+        sqlx::query_as("SELECT deleted, deleted_at, deleted_by FROM structures WHERE id = ?")
+            .bind(own)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(deleted, (false, None, None));
+
+    // moderator removals can only be undone by an admin
+    let response = ctx
+        .admin_request(
+            Method::POST,
+            &format!("/admin/v1/users/{OWNER_ID}/purge"),
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = ctx
+        .user_request(OWNER_TICKET, Method::POST, &restore_own)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let admin_restore = format!("/admin/v1/structures/{own}/restore");
+    let response = ctx
+        .admin_request(Method::POST, &admin_restore, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = ctx
+        .admin_request(Method::POST, &admin_restore, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let audit: (String, String, String) = sqlx::query_as(
+        "SELECT action, target, details FROM admin_audit_log WHERE action = 'restore_structure'",
+    )
+    .fetch_one(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(audit.1, own.to_string());
+    let details: Value = serde_json::from_str(&audit.2).unwrap();
+    assert_eq!(details, json!({ "deleted_by": "admin" }));
+}
+
+#[tokio::test]
+async fn likes_can_answer_with_the_new_totals() {
+    for buffered in [false, true] {
+        let ctx = TestContext::with_config(|config| {
+            config.like_daily_budget = 10;
+            config.like_target_daily_cap = 0;
+            if buffered {
+                config.like_flush_interval = Duration::from_secs(3600);
+            }
+        })
+        .await;
+        let id = create_structure(
+            &ctx,
+            OWNER_TICKET,
+            OWNER_ID,
+            "Owner",
+            "SceneTotals",
+            1,
+            0,
+            "prefab_a",
+        )
+        .await;
+
+        let response = ctx.like_structure(LIKER_TICKET, id, json!({})).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        let response = ctx
+            .like_structure(
+                LIKER_TICKET,
+                id,
+                json!({ "count": 3, "return_totals": true }),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response_json(response).await,
+            json!({ "likes": 4, "remaining_daily_budget": 6, "remaining_for_owner": null })
+        );
+
+        // v2 always answers with them
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(format!("/api/v2/structures/{id}/like"))
+                    .header(&STEAM_HEADER, LIKER_TICKET)
+                    .header("content-type", "application/json")
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "buffered={buffered}");
+        assert_eq!(response_json(response).await["likes"], 5);
+    }
+}
+
+#[tokio::test]
+async fn like_budgets_trim_and_then_reject_likes() {
+    let ctx = TestContext::with_config(|config| {
+        config.like_daily_budget = 10;
+        config.like_target_daily_cap = 6;
+    })
+    .await;
+    let owned = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneBudget",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    let other = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneBudget",
+        1,
+        0,
+        "prefab_b",
+    )
+    .await;
+
+    let like = async |id: i64, count: i32| {
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        ctx.like_structure(LIKER_TICKET, id, json!({ "count": count }))
+            .await
+            .status()
+    };
+    assert_eq!(like(owned, 5).await, StatusCode::NO_CONTENT);
+    assert_eq!(like(owned, 5).await, StatusCode::NO_CONTENT); // trimmed to 1 by the target cap
+    assert_eq!(like(owned, 1).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(like(other, 10).await, StatusCode::NO_CONTENT); // trimmed to 4 by the budget
+    assert_eq!(like(other, 1).await, StatusCode::TOO_MANY_REQUESTS);
+
+    let likes: Vec<i64> = sqlx::query_scalar("SELECT likes FROM structures ORDER BY id")
+        .fetch_all(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, vec![6, 4]);
+    let ledger: Vec<(i64, i64)> =
+        sqlx::query_as("SELECT owner_id, count FROM likes_ledger WHERE liker_id = ? ORDER BY id")
+            .bind(LIKER_ID as i64)
+            .fetch_all(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(
+        ledger,
+        vec![
+            (OWNER_ID as i64, 5),
+            (OWNER_ID as i64, 1),
+            (OTHER_ID as i64, 4)
+        ]
+    );
+    let sent: i64 = sqlx::query_scalar("SELECT likes_send FROM users WHERE user_id = ?")
+        .bind(LIKER_ID as i64)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(sent, 10);
+}
+
+#[tokio::test]
+async fn buffered_likes_count_toward_budgets_and_are_written_on_flush() {
+    let ctx = TestContext::with_config(|config| {
+        config.like_flush_interval = Duration::from_secs(3600);
+        config.like_target_daily_cap = 6;
+    })
+    .await;
+    let owned = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneBuffer",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    let removed = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneBuffer",
+        1,
+        0,
+        "prefab_b",
+    )
+    .await;
+
+    let like = async |ticket: &str, steam_id: u64, id: i64, body: Value| {
+        ctx.state.post_like_rate_limiter.remove(&steam_id);
+        ctx.like_structure(ticket, id, body).await.status()
+    };
+    let liked = like(LIKER_TICKET, LIKER_ID, owned, json!({ "count": 5 })).await;
+    assert_eq!(liked, StatusCode::NO_CONTENT);
+    // trimmed to 1: the 5 still in the buffer count toward the target cap
+    let body = json!({ "count": 5, "reaction": "heart" });
+    assert_eq!(
+        like(LIKER_TICKET, LIKER_ID, owned, body).await,
+        StatusCode::NO_CONTENT
+    );
+    let body = json!({ "count": 1 });
+    assert_eq!(
+        like(LIKER_TICKET, LIKER_ID, owned, body.clone()).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        like(OTHER_TICKET, OTHER_ID, owned, body.clone()).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        like(LIKER_TICKET, LIKER_ID, removed, body).await,
+        StatusCode::NO_CONTENT
+    );
+
+    let likes = sqlx::query_scalar::<_, i64>("SELECT likes FROM structures WHERE id = ?")
+        .bind(owned)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 0);
+
+    // likes on a structure deleted before the flush are dropped
+    let response = ctx
+        .user_request(
+            OTHER_TICKET,
+            Method::DELETE,
+            &format!("/api/v1/structures/{removed}"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let flushed = ctx.state.like_buffer.flush(&ctx.state.db).await.unwrap();
+    assert_eq!(flushed.len(), 2); // thumbs_up and heart on the live structure
+    assert!(ctx.state.like_buffer.is_empty());
+
+    let likes = sqlx::query_scalar::<_, i64>("SELECT likes FROM structures WHERE id = ?")
+        .bind(owned)
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 7);
+    let reactions: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT kind, count FROM structure_reactions WHERE structure_id = ? ORDER BY kind",
+    )
+    .bind(owned)
+    .fetch_all(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(
+        reactions,
+        vec![("heart".to_string(), 1), ("thumbs_up".to_string(), 6)]
+    );
+    let ledger: Vec<(i64, i64)> = sqlx::query_as(
+        "SELECT liker_id, count FROM likes_ledger WHERE structure_id = ? ORDER BY liker_id",
+    )
+    .bind(owned)
+    .fetch_all(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_eq!(ledger, vec![(LIKER_ID as i64, 6), (OTHER_ID as i64, 1)]);
+    let users: Vec<(i64, i64, i64)> =
+        sqlx::query_as("SELECT user_id, likes_send, likes_received FROM users ORDER BY user_id")
+            .fetch_all(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(
+        users,
+        vec![
+            (OWNER_ID as i64, 0, 7),
+            (LIKER_ID as i64, 6, 0),
+            (OTHER_ID as i64, 1, 0)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admin_limit_stats_count_rate_limits_and_like_trims() {
+    let ctx = TestContext::with_config(|config| config.like_target_daily_cap = 3).await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneLimits",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+
+    for _ in 0..3 {
+        ctx.get_random(OTHER_TICKET, "?scene=SceneLimits").await;
+    }
+    ctx.get_random(LIKER_TICKET, "?scene=SceneLimits").await;
+    ctx.get_random(LIKER_TICKET, "?scene=SceneLimits").await;
+    let response = ctx
+        .like_structure(LIKER_TICKET, id, json!({ "count": 500 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+    let response = ctx.like_structure(LIKER_TICKET, id, json!({})).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let response = ctx
+        .admin_request(Method::GET, "/admin/v1/stats/limits", None, Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/limits",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats = response_json(response).await;
+    assert_eq!(stats["rate_limited"], json!({ "get_random": 3, "like": 1 }));
+    // 500 clamped to 100, then trimmed to the cap of 3; the next like is refused by it
+    assert_eq!(stats["like_clamps"], json!({ "count": 1, "target_cap": 2 }));
+    assert_eq!(stats["recent_window"], 4);
+    assert_eq!(
+        stats["top_rate_limited"],
+        json!([
+            { "steam_id": LIKER_ID, "count": 2 },
+            { "steam_id": OTHER_ID, "count": 2 }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn admin_db_stats_time_queries_and_connection_waits() {
+    let ctx = TestContext::new().await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneMetrics",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    // a ticket the auth cache hasn't seen yet
+    let response = ctx.get_random("555", "?scene=SceneMetrics").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/db",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response_json(response).await;
+    for query in ["store_structure", "random_fetch"] {
+        let timing = &body["queries"][query];
+        assert_eq!(timing["count"], 1, "{query}");
+        assert!(timing["p99_ms"].as_f64().unwrap() <= timing["max_ms"].as_f64().unwrap());
+    }
+    let writer = &body["pools"]["writer"];
+    assert_eq!(writer["acquire_wait"]["count"], 1);
+    assert_eq!(writer["max_connections"], 1);
+    assert!(body["pools"]["reader"]["size"].is_u64());
+    assert_eq!(body["auth_verify"]["count"], 1);
+}
+
+#[tokio::test]
+async fn exempt_players_skip_rate_limits() {
+    let ctx = TestContext::with_config(|config| {
+        config.get_structure_rate_limit = Duration::from_secs(60);
+        config.rate_limit_exempt_steamids = vec![OWNER_ID];
+    })
+    .await;
+    let fetch_twice = |ticket: &'static str| {
+        let ctx = &ctx;
+        async move {
+            let first = ctx.get_random(ticket, "?scene=SceneExempt").await;
+            assert_eq!(first.status(), StatusCode::OK);
+            ctx.get_random(ticket, "?scene=SceneExempt").await.status()
+        }
+    };
+    assert_eq!(fetch_twice(OWNER_TICKET).await, StatusCode::OK);
+    assert_eq!(
+        fetch_twice(OTHER_TICKET).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let uri = format!("/admin/v1/rate-limit-exempt/{OTHER_ID}");
+    let response = ctx
+        .admin_request(Method::PUT, &uri, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    ctx.clear_get_rate_limit(OTHER_ID);
+    assert_eq!(fetch_twice(OTHER_TICKET).await, StatusCode::OK);
+
+    let uri = format!("/admin/v1/rate-limit-exempt/{OWNER_ID}");
+    let response = ctx
+        .admin_request(Method::DELETE, &uri, Some(ADMIN_KEY), Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/rate-limit-exempt",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(
+        response_json(response).await,
+        json!({ "steam_ids": [OTHER_ID] })
+    );
+    ctx.clear_get_rate_limit(OWNER_ID);
+    assert_eq!(
+        fetch_twice(OWNER_TICKET).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn adaptive_rate_limits_follow_query_latency_within_bounds() {
+    let ctx = TestContext::with_config(|config| {
+        config.get_structure_rate_limit = Duration::from_millis(400);
+        config.adaptive_rate_limits = true;
+        config.adaptive_rate_limit_max_factor = 2.0;
+    })
+    .await;
+    let config = ctx.state.config();
+    let scale = &ctx.state.rate_limit_scale;
+
+    scale.adjust(&config, Some(Duration::from_secs(1)));
+    assert_eq!(scale.factor(), 1.5);
+    scale.adjust(&config, Some(Duration::from_secs(1)));
+    assert_eq!(scale.factor(), 2.0);
+    // within the target band nothing moves
+    scale.adjust(&config, Some(Duration::from_millis(40)));
+    assert_eq!(scale.factor(), 2.0);
+
+    // 400ms stretched to 800ms
+    let first = ctx.get_random(OTHER_TICKET, "?scene=SceneAdaptive").await;
+    assert_eq!(first.status(), StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneAdaptive").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    for _ in 0..4 {
+        scale.adjust(&config, None);
+    }
+    assert_eq!(scale.factor(), 0.5);
+    let response = ctx
+        .admin_request(
+            Method::GET,
+            "/admin/v1/stats/limits",
+            Some(ADMIN_KEY),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(response_json(response).await["rate_limit_factor"], 0.5);
+    let response = ctx.get_random(OTHER_TICKET, "?scene=SceneAdaptive").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut off = (*config).clone();
+    off.adaptive_rate_limits = false;
+    scale.adjust(&off, Some(Duration::from_secs(1)));
+    assert_eq!(scale.factor(), 1.0);
+}
+
+#[tokio::test]
+async fn post_requires_app_ownership_when_enabled() {
+    let ctx = TestContext::with_config(|config| config.require_app_ownership = true).await;
+    ctx.state
+        .ownership_cache
+        .insert((OWNER_ID, TEST_APPID), (true, tokio::time::Instant::now()));
+    ctx.state
+        .ownership_cache
+        .insert((OTHER_ID, TEST_APPID), (false, tokio::time::Instant::now()));
+
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Owner", "SceneOwned", 1, 0, "prefab_owned"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = ctx
+        .post_structure(
+            OTHER_TICKET,
+            structure_payload("Other", "SceneOwned", 1, 0, "prefab_owned"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM structures")
+        .fetch_one(&ctx.state.db)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+async fn post_uses_resolved_steam_name_when_enabled() {
+    let ctx = TestContext::with_config(|config| config.resolve_steam_names = true).await;
+    sqlx::query(
+        r#"INSERT INTO user_profiles (user_id, persona_name, fetched_at)
+           VALUES (?, 'RealOwner', strftime('%s','now')*1000)"#,
+    )
+    .bind(OWNER_ID as i64)
+    .execute(&ctx.state.db)
+    .await
+    .unwrap();
+
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Impostor", "SceneNames", 1, 0, "prefab_named"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response_json(response).await["username"], "RealOwner");
+}
+
+#[tokio::test]
+async fn separate_appids_keep_fetches_to_compatible_clients() {
+    const DEMO_APPID: u64 = 480;
+    const DEMO_TICKET: &str = "demo-ticket";
+    let ctx = TestContext::with_config(|config| {
+        config.steam_appids = vec![TEST_APPID, DEMO_APPID];
+        config.separate_appids = true;
+    })
+    .await;
+    ctx.state
+        .cache
+        .insert(DEMO_TICKET.to_string(), (OTHER_ID, DEMO_APPID));
+
+    let legacy = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneApps",
+        1,
+        0,
+        "prefab_old",
+    )
+    .await;
+    sqlx::query("UPDATE structures SET app_id = NULL WHERE id = ?")
+        .bind(legacy)
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    let release = create_structure(
+        &ctx,
+        LIKER_TICKET,
+        LIKER_ID,
+        "Liker",
+        "SceneApps",
+        1,
+        0,
+        "prefab_release",
+    )
+    .await;
+    let demo = create_structure(
+        &ctx,
+        DEMO_TICKET,
+        OTHER_ID,
+        "Demo",
+        "SceneApps",
+        1,
+        0,
+        "prefab_demo",
+    )
+    .await;
+
+    let fetch_ids = async |ticket: &str| {
+        let response = ctx.get_random(ticket, "?scene=SceneApps").await;
+        let mut ids: Vec<i64> = response_json(response)
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["id"].as_i64().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(fetch_ids(OWNER_TICKET).await, vec![legacy, release]);
+    assert_eq!(fetch_ids(DEMO_TICKET).await, vec![demo]);
+
+    let config = config_from("steam_appid = [3527290, 480]", &[]).expect("config should load");
+    assert_eq!(config.steam_appids, vec![3527290, 480]);
+    assert!(config_from("steam_appid = \"\"", &[]).is_err());
+}
+
+#[tokio::test]
+async fn static_auth_provider_checks_shared_secret() {
+    let ctx = TestContext::with_config(|config| {
+        config.auth_shared_secret = Some("s3cret".to_string());
+    })
+    .await;
+
+    let response = ctx.get_random("s3cret:444", "?scene=SceneAuth").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    for credential in ["wrong:444", "444"] {
+        let response = ctx.get_random(credential, "?scene=SceneAuth").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response_json(response).await["code"], "invalid_ticket");
+    }
+    // rejections are remembered, so the provider isn't asked again right away
+    assert!(ctx.state.rejected_tickets.contains_key("wrong:444"));
+    ctx.state.config.store(shared_test_config());
+    let response = ctx.get_random("444", "?scene=SceneAuth").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response_json(response).await["code"], "invalid_ticket");
+    ctx.state.rejected_tickets.clear();
+    ctx.state.get_structure_rate_limiter.clear();
+    let response = ctx.get_random("444", "?scene=SceneAuth").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let config = config_from("", &[("SKIP_STEAM_TICKET_VALIDATION", "true")]).unwrap();
+    assert_eq!(config.auth_provider, AuthProviderKind::Static);
+    let config = config_from("auth_provider = \"steam\"", &[]).unwrap();
+    assert_eq!(config.auth_provider, AuthProviderKind::Steam);
+    assert!(config_from("auth_provider = \"epic\"", &[]).is_err());
+}
+
+#[tokio::test]
+async fn federation_mirrors_upstream_structures_once() {
+    let upstream = TestContext::with_config(|config| {
+        config.server_id = Some("main".into());
+        config.federation_keys = vec!["mirror-key".into()];
+    })
+    .await;
+    let mut upstream_ids = Vec::new();
+    for (ticket, steam_id) in [(OWNER_TICKET, OWNER_ID), (LIKER_TICKET, LIKER_ID)] {
+        upstream_ids.push(
+            create_structure(
+                &upstream,
+                ticket,
+                steam_id,
+                "Builder",
+                "SceneFederated",
+                1,
+                0,
+                &format!("prefab_{steam_id}"),
+            )
+            .await,
+        );
+    }
+    // came from the mirror in the first place, so it must not go back there
+    sqlx::query("UPDATE structures SET origin_server = 'mirror', origin_id = 7 WHERE id = ?")
+        .bind(upstream_ids[1])
+        .execute(&upstream.state.db)
+        .await
+        .unwrap();
+
+    let response = upstream
+        .admin_request(Method::GET, "/federation/v1/changes", None, Body::empty())
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+    let app = upstream.app.clone();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let mirror = TestContext::with_config(|config| {
+        config.server_id = Some("mirror".into());
+        config.upstream_url = Some(upstream_url);
+        config.upstream_key = Some("mirror-key".into());
+    })
+    .await;
+    assert_eq!(federation::pull_once(&mirror.state).await.unwrap(), 1);
+    assert_eq!(federation::pull_once(&mirror.state).await.unwrap(), 0);
+
+    let (user_id, origin_server, origin_id): (i64, String, i64) = sqlx::query_as(
+        "SELECT user_id, origin_server, origin_id FROM structures WHERE scene = 'SceneFederated'",
+    )
+    .fetch_one(&mirror.state.db)
+    .await
+    .unwrap();
+    assert_eq!(user_id, OWNER_ID as i64);
+    assert_eq!(origin_server, "main");
+    assert_eq!(origin_id, upstream_ids[0]);
+
+    let response = mirror
+        .get_random(OTHER_TICKET, "?scene=SceneFederated")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let served = response_json(response).await;
+    assert_eq!(served.as_array().unwrap().len(), 1);
+    assert_eq!(served[0]["prefab"], format!("prefab_{OWNER_ID}"));
+}
+
+#[tokio::test]
+async fn federation_feed_leaves_out_shadow_banned_players() {
+    let upstream = TestContext::with_config(|config| {
+        config.server_id = Some("main".into());
+        config.federation_keys = vec!["mirror-key".into()];
+    })
+    .await;
+    create_structure(
+        &upstream,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Builder",
+        "SceneFederated",
+        1,
+        0,
+        "prefab_1",
+    )
+    .await;
+    set_shadow_banned(&upstream.state.db, OWNER_ID as i64, true)
+        .await
+        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+    let app = upstream.app.clone();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let mirror = TestContext::with_config(|config| {
+        config.server_id = Some("mirror".into());
+        config.upstream_url = Some(upstream_url);
+        config.upstream_key = Some("mirror-key".into());
+    })
+    .await;
+    assert_eq!(federation::pull_once(&mirror.state).await.unwrap(), 0);
+    let mirrored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM structures")
+        .fetch_one(&mirror.state.db)
+        .await
+        .unwrap();
+    assert_eq!(mirrored, 0);
+}
+
+#[tokio::test]
+async fn remote_import_merges_another_server_with_provenance() {
+    let shard = TestContext::new().await;
+    let liked = create_structure(
+        &shard,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneMerge",
+        1,
+        0,
+        "prefab_liked",
+    )
+    .await;
+    create_structure(
+        &shard,
+        LIKER_TICKET,
+        LIKER_ID,
+        "Liker",
+        "SceneMerge",
+        1,
+        0,
+        "prefab_other",
+    )
+    .await;
+    let response = shard
+        .like_structure(LIKER_TICKET, liked, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let shard_url = format!("http://{}", listener.local_addr().unwrap());
+    let app = shard.app.clone();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let ctx = TestContext::new().await;
+    // an unrelated local structure keeps the imported ids from lining up
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Local",
+        "SceneMerge",
+        1,
+        0,
+        "prefab_local",
+    )
+    .await;
+    let import = || async {
+        let response = ctx
+            .app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/admin/v1/import-remote")
+                    .header(&ADMIN_HEADER, ADMIN_KEY)
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        json!({ "url": shard_url, "key": ADMIN_KEY, "origin": "shard-a" })
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response_json(response).await
+    };
+
+    let summary = import().await;
+    assert_eq!(summary["structures"], 2);
+    assert_eq!(summary["skipped"], 0);
+    let (id, likes, origin_server, origin_id): (i64, i64, String, i64) = sqlx::query_as(
+        "SELECT id, likes, origin_server, origin_id FROM structures WHERE prefab = 'prefab_liked'",
+    )
+    .fetch_one(&ctx.state.db)
+    .await
+    .unwrap();
+    assert_ne!(id, liked);
+    assert_eq!(
+        (likes, origin_server.as_str(), origin_id),
+        (1, "shard-a", liked)
+    );
+    let likes_received: i64 =
+        sqlx::query_scalar("SELECT likes_received FROM users WHERE user_id = ?")
+            .bind(OWNER_ID as i64)
+            .fetch_one(&ctx.state.db)
+            .await
+            .unwrap();
+    assert_eq!(likes_received, 1);
+
+    let summary = import().await;
+    assert_eq!(summary["structures"], 0);
+    assert_eq!(summary["skipped"], 2);
+}
+
+// Serves the Steam stand-in on an ephemeral port; returns it with its base url
+async fn spawn_mock_steam() -> (Arc<MockSteam>, String) {
+    let mock = Arc::new(MockSteam::default());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let router: Router = mock_steam::router(mock.clone());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    (mock, format!("http://127.0.0.1:{port}"))
+}
+
+async fn steam_auth_context(steam_api_url: String) -> TestContext {
+    TestContext::with_config(|config| {
+        config.auth_provider = AuthProviderKind::Steam;
+        config.steam_api_url = steam_api_url;
+        config.steam_breaker_failures = 0;
+        config.get_structure_rate_limit = Duration::ZERO;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn steam_auth_caches_accepted_and_rejected_tickets() {
+    let (mock, url) = spawn_mock_steam().await;
+    let ctx = steam_auth_context(url).await;
+
+    for _ in 0..2 {
+        let response = ctx.get_random("valid:555", "?scene=SceneSteam").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(
+        mock.calls(),
+        1,
+        "the second request is served from the cache"
+    );
+    assert_eq!(
+        ctx.state.cache.get("valid:555").map(|entry| *entry),
+        Some((555, TEST_APPID))
+    );
+
+    for _ in 0..2 {
+        let response = ctx.get_random("expired", "?scene=SceneSteam").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response_json(response).await["code"], "ticket_expired");
+    }
+    assert_eq!(mock.calls(), 2, "rejections are remembered as well");
+
+    let response = ctx.get_random("nonsense", "?scene=SceneSteam").await;
+    assert_eq!(response_json(response).await["code"], "invalid_ticket");
+    let response = ctx.get_random("valid:555:999", "?scene=SceneSteam").await;
+    assert_eq!(response_json(response).await["code"], "wrong_app");
+}
+
+#[tokio::test]
+async fn startup_self_check_reports_what_to_fix() {
+    let (_, url) = spawn_mock_steam().await;
+    let ctx = steam_auth_context(url).await;
+    let config = ctx.state.config();
+    selfcheck::run(&config, &ctx.state.db, &ctx.state.http, "key")
+        .await
+        .expect("a fresh database and a working key pass");
+
+    sqlx::query("DROP INDEX idx_structures_prefab")
+        .execute(&ctx.state.db)
+        .await
+        .unwrap();
+    let error = selfcheck::run(&config, &ctx.state.db, &ctx.state.http, "rejected")
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("index idx_structures_prefab"), "{error}");
+    assert!(error.contains("STEAM_WEB_API_KEY"), "{error}");
+}
+
+#[tokio::test]
+async fn integrity_check_reports_orphans_and_reindexes_on_request() {
+    let ctx = TestContext::new().await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneIntegrity",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+
+    let check = |query: &'static str| {
+        let ctx = &ctx;
+        async move {
+            let response = ctx
+                .admin_request(
+                    Method::POST,
+                    &format!("/admin/v1/integrity-check{query}"),
+                    Some(ADMIN_KEY),
+                    Body::empty(),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+    let body = check("").await;
+    assert_eq!(body["ok"], true);
+    assert_eq!(body["problems"], json!([]));
+    assert_eq!(body["reindexed"], false);
+
+    // a milestone row whose structure is gone, as a crash without FK checks leaves
+    let mut conn = ctx.state.db.acquire().await.unwrap();
+    sqlx::query("PRAGMA foreign_keys = OFF")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO like_milestones (structure_id, milestone, reached_at) VALUES (999999, 10, 0)",
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    sqlx::query("PRAGMA foreign_keys = ON")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    drop(conn);
+
+    let body = check("?quick=true&reindex=true").await;
+    assert_eq!(body["ok"], false);
+    assert_eq!(body["problems"], json!([]));
+    assert_eq!(
+        body["foreign_key_violations"],
+        json!([{ "table": "like_milestones", "rowid": 1, "parent": "structures" }])
+    );
+    // the file itself is fine, so nothing to rebuild
+    assert_eq!(body["reindexed"], false);
+    integrity::repair_on_start(&ctx.state.db).await.unwrap();
+}
+
+#[tokio::test]
+async fn steam_auth_reports_outages_and_unreadable_answers() {
+    let (mock, url) = spawn_mock_steam().await;
+    let ctx = steam_auth_context(url).await;
+
+    // an unreadable answer says nothing about the ticket
+    let response = ctx.get_random("bad_json", "?scene=SceneSteam").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response_json(response).await["code"], "steam_unreachable");
+    assert!(!ctx.state.rejected_tickets.contains_key("bad_json"));
+
+    // 5xx answers are retried before giving up
+    let response = ctx.get_random("unavailable", "?scene=SceneSteam").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response_json(response).await["code"], "steam_unreachable");
+    assert_eq!(mock.calls(), 4);
+
+    let response = ctx.get_random("bad_key", "?scene=SceneSteam").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert!(!ctx.state.rejected_tickets.contains_key("bad_key"));
+
+    // nothing listens on a port that was just released
+    let port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let ctx = steam_auth_context(format!("http://127.0.0.1:{port}")).await;
+    let response = ctx.get_random("valid:555", "?scene=SceneSteam").await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response_json(response).await["code"], "steam_unreachable");
+}
+
+#[tokio::test]
+async fn ownership_checks_go_to_the_configured_steam_host() {
+    let (mock, url) = spawn_mock_steam().await;
+    let ctx = TestContext::with_config(|config| {
+        config.auth_provider = AuthProviderKind::Steam;
+        config.steam_api_url = url;
+        config.require_app_ownership = true;
+    })
+    .await;
+    mock.unowned.insert(556);
+
+    let response = ctx
+        .post_structure(
+            "valid:555",
+            structure_payload("Owner", "SceneOwned", 1, 0, "prefab_owned"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = ctx
+        .post_structure(
+            "valid:556",
+            structure_payload("Other", "SceneOwned", 1, 0, "prefab_owned"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // a ticket check and an ownership check each
+    assert_eq!(mock.calls(), 4);
+}
+
+#[tokio::test]
+async fn persona_names_come_from_the_configured_steam_host() {
+    let (mock, url) = spawn_mock_steam().await;
+    let ctx = TestContext::with_config(|config| {
+        config.auth_provider = AuthProviderKind::Steam;
+        config.steam_api_url = url;
+        config.resolve_steam_names = true;
+    })
+    .await;
+
+    for _ in 0..2 {
+        let response = ctx
+            .post_structure(
+                "valid:555",
+                structure_payload("Typed", "SceneNamed", 1, 0, "prefab_named"),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response_json(response).await["username"], "Player 555");
+        ctx.clear_post_rate_limit(555);
+    }
+    // one ticket check and one lookup; the name is kept in user_profiles
+    assert_eq!(mock.calls(), 2);
+}
+
+#[test]
+fn steam_breaker_opens_after_consecutive_failures() {
+    let breaker = steam::Breaker::default();
+    breaker.record_failure(2, Duration::from_secs(30));
+    assert!(breaker.allow());
+    breaker.record_success();
+    breaker.record_failure(2, Duration::from_secs(30));
+    assert!(breaker.allow(), "a success resets the failure count");
+    breaker.record_failure(2, Duration::from_secs(30));
+    assert!(!breaker.allow());
+    breaker.record_success();
+    assert!(breaker.allow());
+
+    // expired cooldown lets one probe through; the rest wait for its answer
+    let breaker = steam::Breaker::default();
+    breaker.record_failure(1, Duration::ZERO);
+    assert!(breaker.allow());
+    assert!(!breaker.allow(), "only one probe at a time");
+    breaker.record_failure(1, Duration::ZERO);
+    assert!(
+        breaker.allow(),
+        "a failed probe re-opens it for the next cooldown"
+    );
+    assert!(!breaker.allow());
+    breaker.record_success();
+    assert!(breaker.allow());
+    assert!(breaker.allow(), "a successful probe closes it");
+
+    let disabled = steam::Breaker::default();
+    for _ in 0..10 {
+        disabled.record_failure(0, Duration::from_secs(30));
+    }
+    assert!(disabled.allow());
+}
+
+#[test]
+fn steam_rejections_are_classified_by_description() {
+    use steam::RejectReason;
+    assert_eq!(
+        RejectReason::classify("Ticket for other app"),
+        RejectReason::WrongApp
+    );
+    assert_eq!(
+        RejectReason::classify("Ticket has expired"),
+        RejectReason::Expired
+    );
+    assert_eq!(
+        RejectReason::classify("Ticket already used"),
+        RejectReason::Expired
+    );
+    assert_eq!(
+        RejectReason::classify("Invalid ticket"),
+        RejectReason::Invalid
+    );
+}
+
+#[tokio::test]
+async fn missing_credential_has_its_own_code() {
+    let ctx = TestContext::new().await;
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/structures?scene=SceneAuth")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response_json(response).await,
+        json!({ "code": "missing_credential", "message": "X-Steam-Auth missing" })
+    );
+}
+
+#[tokio::test]
+async fn errors_use_the_json_envelope() {
+    let ctx = TestContext::with_config(|config| {
+        config.get_structure_rate_limit = Duration::from_secs(10);
+    })
+    .await;
+
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneErrors").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = ctx.get_random(OWNER_TICKET, "?scene=SceneErrors").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "10");
+    assert_eq!(
+        response_json(response).await,
+        json!({
+            "code": "rate_limited",
+            "message": "You are requesting structures too frequently.",
+            "retry_after": 10,
+        })
+    );
+
+    let response = ctx
+        .like_structure(LIKER_TICKET, 999_999, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "not_found");
+    assert!(body.get("retry_after").is_none());
+
+    let structure = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneErrors",
+        1,
+        0,
+        "prefab_self",
+    )
+    .await;
+    let response = ctx
+        .like_structure(OWNER_TICKET, structure, json!({ "count": 1 }))
+        .await;
+    assert_eq!(response_json(response).await["code"], "self_like");
+}
+
+#[tokio::test]
+async fn database_errors_answer_a_generic_internal_error() {
+    let ctx = TestContext::new().await;
+    ctx.state.db.close().await;
+
+    let response = ctx
+        .post_structure(
+            OWNER_TICKET,
+            structure_payload("Owner", "SceneDbDown", 1, 0, "prefab_db"),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        response_json(response).await,
+        json!({ "code": "internal", "message": "Database error." })
+    );
+}
+
+#[tokio::test]
+async fn api_v2_serves_nested_payloads_over_shared_storage() {
+    let ctx = TestContext::new().await;
+    let payload = json!({
+        "username": "Nested",
+        "map_id": 1,
+        "scene": "SceneV2",
+        "segment": 0,
+        "prefab": "prefab_v2",
+        "position": [1.0, 2.0, 3.0],
+        "rotation": [0.0, 0.0, 0.0, 1.0],
+        "rope": {
+            "start": [0.0, 0.0, 0.0],
+            "end": [1.0, 1.0, 1.0],
+            "length": 5.0,
+            "flying_rotation": [0.0, 0.0, 0.0],
+            "anchor_rotation": [0.0, 0.0, 0.0, 1.0]
+        }
+    });
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/api/v2/structures")
+                .header(&STEAM_HEADER, OWNER_TICKET)
+                .header("content-type", "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-api-version"], "2");
+    let body = response_json(response).await;
+    assert_eq!(body["position"], json!([1.0, 2.0, 3.0]));
+    assert_eq!(body["rope"]["length"], 5.0);
+    assert!(body.get("pos_x").is_none());
+
+    // the same row is visible to v1 clients in the flat shape
+    let response = ctx.get_random(LIKER_TICKET, "?scene=SceneV2").await;
+    assert_eq!(response.headers()["x-api-version"], "1");
+    let rows = response_json(response).await;
+    assert_eq!(rows[0]["pos_z"], 3.0);
+    assert_eq!(rows[0]["rope_end_x"], 1.0);
+
+    let response = ctx
+        .user_request(
+            OTHER_TICKET,
+            Method::GET,
+            "/api/v2/structures?scene=SceneV2",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let rows = response_json(response).await;
+    assert_eq!(rows[0]["rotation"], json!([0.0, 0.0, 0.0, 1.0]));
+}
+
+#[tokio::test]
+async fn api_version_header_must_match_the_path() {
+    let ctx = TestContext::new().await;
+    let request = |version: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri("/api/v1/stats/me")
+            .header(&STEAM_HEADER, OWNER_TICKET)
+            .header("x-api-version", version)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = ctx.app.clone().oneshot(request("v1")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-api-supported-versions"], "1, 2");
+
+    let response = ctx.app.clone().oneshot(request("2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response_json(response).await["code"], "version_mismatch");
+
+    let response = ctx.app.clone().oneshot(request("7")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response_json(response).await["code"], "unsupported_version");
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected_in_the_error_envelope() {
+    let ctx = TestContext::new().await;
+    let mut payload = structure_payload("Big", "SceneBig", 1, 0, "prefab_big");
+    payload["username"] = json!("x".repeat(8_000));
+    let body = payload.to_string();
+
+    // declared length is refused before the body is read
+    let response = ctx
+        .app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/api/v1/structures")
+                .header(&STEAM_HEADER, OWNER_TICKET)
+                .header("content-type", "application/json")
+                .header("content-length", body.len())
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response_json(response).await["code"], "payload_too_large");
+
+    // without a Content-Length the limit applies while reading
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response_json(response).await["code"], "payload_too_large");
+}
+
+#[tokio::test]
+async fn malformed_json_bodies_are_rejected_in_the_error_envelope() {
+    let ctx = TestContext::new().await;
+
+    let mut nested = json!(1);
+    for _ in 0..40 {
+        nested = json!([nested]);
+    }
+    let mut payload = structure_payload("Deep", "SceneDeep", 1, 0, "prefab_deep");
+    payload["extra"] = nested;
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = response_json(response).await;
+    assert_eq!(body["code"], "invalid_body");
+    assert!(body["message"].as_str().unwrap().contains("nested"));
+
+    let response = ctx
+        .post_structure(OWNER_TICKET, json!({ "username": "Sam" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response_json(response).await["code"], "invalid_body");
+
+    let response = ctx
+        .user_request(
+            OWNER_TICKET,
+            Method::GET,
+            "/api/v1/structures/nearby?scene=A",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response_json(response).await["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn post_structure_rejects_out_of_range_fields_with_422() {
+    let ctx = TestContext::new().await;
+    let cases = [
+        ("username", json!("u".repeat(51))),
+        ("prefab", json!("p".repeat(51))),
+        ("prefab", json!("")),
+        ("scene", json!("s".repeat(17))),
+        ("segment", json!(11)),
+        ("segment", json!(-1)),
+    ];
+    for (field, value) in cases {
+        let mut payload = structure_payload("Sam", "SceneFields", 1, 0, "prefab_fields");
+        payload[field] = value;
+        let response = ctx.post_structure(OWNER_TICKET, payload).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "{field}"
+        );
+        let body = response_json(response).await;
+        assert_eq!(body["code"], "invalid_field");
+        assert_eq!(body["field"], field);
+    }
+
+    // limits count characters, not bytes
+    let payload = structure_payload(&"é".repeat(50), "SceneFields", 1, 10, "prefab_fields");
+    let response = ctx.post_structure(OWNER_TICKET, payload).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn random_fetch_samples_from_cached_candidates_when_enabled() {
+    let ctx = TestContext::with_config(|config| {
+        config.random_sample_refresh = Duration::from_secs(60);
+    })
+    .await;
+    let first = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneSample",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    let second = create_structure(
+        &ctx,
+        LIKER_TICKET,
+        LIKER_ID,
+        "Liker",
+        "SceneSample",
+        1,
+        1,
+        "prefab_b",
+    )
+    .await;
+
+    let response = ctx
+        .get_random(OTHER_TICKET, "?scene=SceneSample&exclude_prefabs=prefab_b")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let ids: Vec<i64> = response_json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![first]);
+
+    // uploads after the list was built show up once it is refreshed
+    let third = create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneSample",
+        1,
+        2,
+        "prefab_c",
+    )
+    .await;
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let rows = response_json(ctx.get_random(OTHER_TICKET, "?scene=SceneSample").await).await;
+    assert_eq!(rows.as_array().unwrap().len(), 2);
+
+    let config = ctx.state.config();
+    ctx.state
+        .random_samples
+        .refresh(
+            &ctx.state.read_db,
+            config.current_season,
+            0,
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let rows = response_json(ctx.get_random(OTHER_TICKET, "?scene=SceneSample").await).await;
+    let mut ids: Vec<i64> = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["id"].as_i64().unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![first, second, third]);
+
+    // scenes nobody fetched within the idle window are dropped
+    ctx.state
+        .random_samples
+        .refresh(&ctx.state.read_db, config.current_season, 0, Duration::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(ctx.state.random_samples.len(), 0);
+}
+
+#[tokio::test]
+async fn random_fetch_reports_total_matching_when_asked() {
+    let ctx = TestContext::with_config(|config| {
+        config.get_structure_rate_limit = Duration::ZERO;
+    })
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneTotal",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneTotal",
+        1,
+        1,
+        "rope",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneTotal",
+        1,
+        0,
+        "ladder",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneTotal",
+        2,
+        0,
+        "ladder",
+    )
+    .await;
+
+    let fetch = |uri: &'static str, include_total: bool| {
+        let app = ctx.app.clone();
+        async move {
+            let mut request = Request::builder()
+                .uri(uri)
+                .header(&STEAM_HEADER, LIKER_TICKET);
+            if include_total {
+                request = request.header(&INCLUDE_TOTAL_HEADER, "true");
+            }
+            let response = app
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response_json(response).await
+        }
+    };
+
+    let body = fetch("/api/v1/structures?scene=SceneTotal&map_id=1&limit=1", true).await;
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+    assert_eq!(body["total_matching"], 3);
+
+    let body = fetch(
+        "/api/v1/structures?scene=SceneTotal&map_id=1&exclude_prefabs=rope",
+        true,
+    )
+    .await;
+    assert_eq!(body["items"].as_array().unwrap().len(), 2);
+    assert_eq!(body["total_matching"], 2);
+
+    let body = fetch("/api/v2/structures?scene=SceneTotal&limit=1", true).await;
+    assert_eq!(body["total_matching"], 4);
+    assert!(body["items"][0]["position"].is_array());
+
+    // without the header the rows come as a plain array, as before
+    let body = fetch("/api/v1/structures?scene=SceneTotal&limit=2", false).await;
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn random_fetch_probes_ids_in_scenes_over_the_threshold() {
+    let ctx = TestContext::with_config(|config| {
+        config.random_probe_min_rows = 2;
+    })
+    .await;
+    let mut created = Vec::new();
+    for (ticket, steam_id, segment) in [
+        (OWNER_TICKET, OWNER_ID, 0),
+        (OWNER_TICKET, OWNER_ID, 1),
+        (LIKER_TICKET, LIKER_ID, 0),
+    ] {
+        created.push(
+            create_structure(
+                &ctx,
+                ticket,
+                steam_id,
+                "Prober",
+                "SceneProbe",
+                1,
+                segment,
+                &format!("prefab_probe_{segment}"),
+            )
+            .await,
+        );
+    }
+    // rows of other scenes sit between and must never be returned
+    create_structure(
+        &ctx,
+        OTHER_TICKET,
+        OTHER_ID,
+        "Other",
+        "SceneElse",
+        1,
+        0,
+        "prefab_probe_0",
+    )
+    .await;
+
+    let rows = response_json(ctx.get_random(OTHER_TICKET, "?scene=SceneProbe").await).await;
+    let mut ids: Vec<i64> = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["id"].as_i64().unwrap())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(rows.as_array().unwrap().len(), ids.len());
+    assert!(!ids.is_empty());
+    assert!(ids.iter().all(|id| created.contains(id)));
+
+    ctx.clear_get_rate_limit(OTHER_ID);
+    let rows = response_json(
+        ctx.get_random(
+            OTHER_TICKET,
+            "?scene=SceneProbe&exclude_prefabs=prefab_probe_0",
+        )
+        .await,
+    )
+    .await;
+    assert!(
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .all(|row| row["prefab"] == "prefab_probe_1")
+    );
+}
+
+#[tokio::test]
+async fn random_fetches_within_the_batch_ttl_share_one_batch() {
+    let ctx = TestContext::with_config(|config| {
+        config.random_batch_ttl = Duration::from_secs(60);
+    })
+    .await;
+    let first = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneBatch",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneBatch",
+        1,
+        1,
+        "prefab_b",
+    )
+    .await;
+
+    let ids = |rows: Value| -> Vec<i64> {
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["id"].as_i64().unwrap())
+            .collect()
+    };
+    let batch = ids(response_json(
+        ctx.get_random(
+            OWNER_TICKET,
+            "?scene=SceneBatch&exclude_prefabs=prefab_c,prefab_d",
+        )
+        .await,
+    )
+    .await);
+    assert_eq!(batch.len(), 2);
+
+    // a later upload doesn't show up until the batch expires
+    let third = create_structure(
+        &ctx,
+        LIKER_TICKET,
+        LIKER_ID,
+        "Liker",
+        "SceneBatch",
+        1,
+        2,
+        "prefab_c",
+    )
+    .await;
+    let shared = ids(response_json(
+        ctx.get_random(
+            LIKER_TICKET,
+            "?scene=SceneBatch&exclude_prefabs=prefab_d,prefab_c",
+        )
+        .await,
+    )
+    .await);
+    assert_eq!(shared, batch);
+
+    // other exclusions are a different batch, picked fresh
+    let mut other = ids(response_json(
+        ctx.get_random(OTHER_TICKET, "?scene=SceneBatch&exclude_prefabs=prefab_b")
+            .await,
+    )
+    .await);
+    other.sort();
+    assert_eq!(other, vec![first, third]);
+}
+
+#[tokio::test]
+async fn fetches_serve_buffered_likes_and_reread_stale_batches() {
+    let ctx = TestContext::with_config(|config| {
+        config.random_batch_ttl = Duration::from_secs(60);
+        config.like_staleness = Duration::ZERO;
+        config.like_flush_interval = Duration::from_secs(3600);
+    })
+    .await;
+    let id = create_structure(
+        &ctx,
+        OWNER_TICKET,
+        OWNER_ID,
+        "Owner",
+        "SceneFresh",
+        1,
+        0,
+        "prefab_a",
+    )
+    .await;
+    let fetch = async || {
+        ctx.clear_get_rate_limit(OTHER_ID);
+        let rows = response_json(ctx.get_random(OTHER_TICKET, "?scene=SceneFresh").await).await;
+        assert_eq!(rows[0]["id"], id);
+        (rows[0]["likes"].clone(), rows[0]["reactions"].clone())
+    };
+    let like = async || {
+        ctx.state.post_like_rate_limiter.remove(&LIKER_ID);
+        let response = ctx
+            .like_structure(LIKER_TICKET, id, json!({ "count": 2 }))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    };
+    assert_eq!(fetch().await.0, 0);
+
+    // still in the buffer, but already counted
+    like().await;
+    assert_eq!(fetch().await, (json!(2), json!({ "thumbs_up": 2 })));
+    // written, and reread into the shared batch without counting twice
+    ctx.state.like_buffer.flush(&ctx.state.db).await.unwrap();
+    assert_eq!(fetch().await, (json!(2), json!({ "thumbs_up": 2 })));
+
+    // within the staleness bound the batch keeps the counts it was picked with
+    let mut next = (*ctx.state.config()).clone();
+    next.like_staleness = Duration::from_secs(60);
+    ctx.state.config.store(Arc::new(next));
+    like().await;
+    ctx.state.like_buffer.flush(&ctx.state.db).await.unwrap();
+    assert_eq!(fetch().await.0, 0);
 }
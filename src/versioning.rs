@@ -0,0 +1,97 @@
+// API version negotiation.
+//
+// Every version lives under its own prefix (/api/v1, /api/v2) and the path
+// decides what is served. Clients may also send X-Api-Version with the version
+// they were built against; a request that reaches a prefix it did not expect
+// is refused instead of being answered in a shape the client cannot read.
+// Responses always carry the served version and the versions this server
+// knows, so an old install can tell it is due for an update.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+
+pub static VERSION_HEADER: HeaderName = HeaderName::from_static("x-api-version");
+pub static SUPPORTED_HEADER: HeaderName = HeaderName::from_static("x-api-supported-versions");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    pub fn number(self) -> u8 {
+        match self {
+            ApiVersion::V1 => 1,
+            ApiVersion::V2 => 2,
+        }
+    }
+
+    // "2", "v2" and "V2" all name the same version
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let number = value
+            .strip_prefix(['v', 'V'])
+            .unwrap_or(value)
+            .parse::<u8>()
+            .ok()?;
+        Self::ALL.into_iter().find(|v| v.number() == number)
+    }
+}
+
+fn supported_list() -> String {
+    ApiVersion::ALL
+        .iter()
+        .map(|v| v.number().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Middleware for one version's routes; `served` is the version of the prefix.
+pub async fn negotiate(State(served): State<ApiVersion>, req: Request, next: Next) -> Response {
+    let requested = req
+        .headers()
+        .get(&VERSION_HEADER)
+        .map(|value| value.to_str().ok().and_then(ApiVersion::parse));
+
+    let mut response = match requested {
+        Some(None) => ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "unsupported API version; this server speaks {}",
+                supported_list()
+            ),
+        )
+        .with_code("unsupported_version")
+        .into_response(),
+        Some(Some(version)) if version != served => ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "X-Api-Version {} does not match the /api/v{} path",
+                version.number(),
+                served.number()
+            ),
+        )
+        .with_code("version_mismatch")
+        .into_response(),
+        _ => next.run(req).await,
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        &VERSION_HEADER,
+        HeaderValue::from(u16::from(served.number())),
+    );
+    if let Ok(value) = HeaderValue::from_str(&supported_list()) {
+        headers.insert(&SUPPORTED_HEADER, value);
+    }
+    response
+}